@@ -10,6 +10,7 @@ extern crate protobuf;
 #[macro_use]
 extern crate structopt;
 
+mod rate_limit;
 mod service;
 
 use std::io::{self, Read};
@@ -22,6 +23,7 @@ use grpcio::{Environment, ServerBuilder, Server};
 use structopt::StructOpt;
 
 use fero_proto::fero_grpc::create_fero;
+use rate_limit::RateLimiter;
 use service::*;
 
 #[derive(StructOpt)]
@@ -42,6 +44,12 @@ struct Opt {
     #[structopt(short = "r", long = "server-port", default_value = "50051")]
     /// The port of the server to make requests to.
     server_port: u16,
+    #[structopt(long = "timestamp-rate", default_value = "60")]
+    /// The number of timestamps each client may request per minute.
+    timestamp_rate: u32,
+    #[structopt(long = "timestamp-burst", default_value = "10")]
+    /// The number of timestamps each client may request at once.
+    timestamp_burst: u32,
 }
 
 fn create_bastion(
@@ -49,9 +57,11 @@ fn create_bastion(
     port: u16,
     server_address: &str,
     server_port: u16,
+    timestamp_limiter: RateLimiter,
 ) -> Result<Server, Error> {
+    let bastion = FeroBastion::new(server_address, server_port, timestamp_limiter);
     ServerBuilder::new(Arc::new(Environment::new(1)))
-        .register_service(create_fero(FeroBastion::new(server_address, server_port)))
+        .register_service(create_fero(bastion))
         .bind(address, port)
         .build()
         .map_err(|e| e.into())
@@ -67,6 +77,7 @@ pub fn main() -> Result<(), Error> {
         opts.port,
         &opts.server_address,
         opts.server_port,
+        RateLimiter::new(opts.timestamp_rate, opts.timestamp_burst),
     )?;
 
     bastion.start();
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Timestamping needs no approvals, so anyone who can reach the bastion can make the HSM sign.
+// This keeps any one client from monopolizing it.
+
+/// A token bucket per client, each holding up to `burst` requests and refilling at `rate` per
+/// minute.
+pub struct RateLimiter {
+    rate: u32,
+    burst: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32, burst: u32) -> RateLimiter {
+        RateLimiter {
+            rate,
+            burst,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token from `client`'s bucket at time `now`, returning whether there was one.
+    pub fn check(&self, client: &str, now: Instant) -> bool {
+        let per_second = f64::from(self.rate) / 60.0;
+        let burst = f64::from(self.burst);
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+
+        // Full buckets are indistinguishable from missing ones, so forget them.
+        buckets.retain(|_, bucket| {
+            bucket.tokens + elapsed_seconds(bucket.updated, now) * per_second < burst
+        });
+
+        let bucket = buckets.entry(client_host(client).to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens =
+            burst.min(bucket.tokens + elapsed_seconds(bucket.updated, now) * per_second);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn elapsed_seconds(since: Instant, now: Instant) -> f64 {
+    let elapsed = if now > since { now - since } else { Duration::from_secs(0) };
+    elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9
+}
+
+/// Strip the port from a gRPC peer such as `ipv4:192.0.2.1:40000`, so that each connection from
+/// the same host shares a bucket.
+fn client_host(peer: &str) -> &str {
+    match peer.rfind(':') {
+        Some(index) if peer.starts_with("ipv4:") || peer.starts_with("ipv6:") => &peer[..index],
+        _ => peer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts() {
+        assert_eq!(client_host("ipv4:192.0.2.1:40000"), "ipv4:192.0.2.1");
+        assert_eq!(client_host("ipv6:[2001:db8::1]:40000"), "ipv6:[2001:db8::1]");
+        assert_eq!(client_host("unix:/run/fero.sock"), "unix:/run/fero.sock");
+    }
+
+    #[test]
+    fn burst_then_refill() {
+        let limiter = RateLimiter::new(60, 3);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check("ipv4:192.0.2.1:40000", start));
+        }
+        assert!(!limiter.check("ipv4:192.0.2.1:40001", start));
+
+        // One token a second.
+        assert!(!limiter.check("ipv4:192.0.2.1:40000", start + Duration::from_millis(500)));
+        assert!(limiter.check("ipv4:192.0.2.1:40000", start + Duration::from_millis(1500)));
+        assert!(!limiter.check("ipv4:192.0.2.1:40000", start + Duration::from_millis(1600)));
+    }
+
+    #[test]
+    fn clients_are_independent() {
+        let limiter = RateLimiter::new(60, 1);
+        let now = Instant::now();
+
+        assert!(limiter.check("ipv4:192.0.2.1:40000", now));
+        assert!(!limiter.check("ipv4:192.0.2.1:40000", now));
+        assert!(limiter.check("ipv4:192.0.2.2:40000", now));
+    }
+
+    #[test]
+    fn idle_clients_are_forgotten() {
+        let limiter = RateLimiter::new(60, 2);
+        let start = Instant::now();
+
+        assert!(limiter.check("ipv4:192.0.2.1:40000", start));
+        assert!(limiter.check("ipv4:192.0.2.2:40000", start + Duration::from_secs(5)));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn zero_rate_never_refills() {
+        let limiter = RateLimiter::new(0, 1);
+        let start = Instant::now();
+
+        assert!(limiter.check("ipv4:192.0.2.1:40000", start));
+        assert!(!limiter.check("ipv4:192.0.2.1:40000", start + Duration::from_secs(3600)));
+    }
+}
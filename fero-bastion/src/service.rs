@@ -1,25 +1,32 @@
 use std::sync::Arc;
+use std::time::Instant;
 
 use chrono::prelude::*;
-use futures::Future;
-use grpcio::{self, ChannelBuilder, EnvBuilder, RpcContext, RpcStatus, UnarySink};
+use futures::{Future, Sink, Stream};
+use grpcio::{self, ChannelBuilder, EnvBuilder, RpcContext, RpcStatus, ServerStreamingSink,
+             UnarySink, WriteFlags};
 use protobuf::well_known_types::Timestamp;
 
 use fero_proto::fero::*;
 use fero_proto::fero_grpc::*;
+use rate_limit::RateLimiter;
 
 #[derive(Clone)]
 pub struct FeroBastion {
     client: Arc<FeroClient>,
+    timestamp_limiter: Arc<RateLimiter>,
 }
 
 impl FeroBastion {
-    pub fn new(address: &str, port: u16) -> FeroBastion {
+    pub fn new(address: &str, port: u16, timestamp_limiter: RateLimiter) -> FeroBastion {
         let env = Arc::new(EnvBuilder::new().build());
         let channel = ChannelBuilder::new(env).connect(&format!("{}:{}", address, port));
         let client = Arc::new(FeroClient::new(channel));
 
-        FeroBastion { client }
+        FeroBastion {
+            client,
+            timestamp_limiter: Arc::new(timestamp_limiter),
+        }
     }
 }
 
@@ -71,6 +78,29 @@ macro_rules! bastion_call_with_timestamp {
     )
 }
 
+macro_rules! bastion_stream_call {
+    ($func:ident, $req_ty:ty, $resp_ty:ty, $err_msg:expr) => (
+        fn $func(&self, ctx: RpcContext, req: $req_ty, sink: ServerStreamingSink<$resp_ty>) {
+            match self.client.$func(&req) {
+                Ok(responses) => ctx.spawn(
+                    sink.send_all(responses.map(|response| (response, WriteFlags::default())))
+                        .map(|_| ())
+                        .map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)),
+                ),
+                Err(err) => {
+                    info!("{}: {}", $err_msg, err);
+                    ctx.spawn(
+                        sink.fail(RpcStatus {
+                            status: grpcio::RpcStatusCode::PermissionDenied,
+                            details: Some($err_msg.to_string()),
+                        }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)),
+                    )
+                }
+            }
+        }
+    )
+}
+
 impl Fero for FeroBastion {
     bastion_call_with_timestamp!(
         sign_payload,
@@ -93,10 +123,142 @@ impl Fero for FeroBastion {
         "Failed to update user key weight"
     );
 
+    bastion_call_with_timestamp!(
+        sign_ssh_certificate,
+        SshCertificateRequest,
+        SshCertificateResponse,
+        "Failed to sign SSH certificate"
+    );
+
+    bastion_call_with_timestamp!(
+        issue_certificate,
+        CertificateRequest,
+        CertificateResponse,
+        "Failed to issue certificate"
+    );
+
+    bastion_call_with_timestamp!(
+        generate_crl,
+        CrlRequest,
+        CrlResponse,
+        "Failed to generate CRL"
+    );
+
+    bastion_call_with_timestamp!(
+        sign_jws,
+        JwsRequest,
+        JwsResponse,
+        "Failed to sign JWS"
+    );
+
+    bastion_call_with_timestamp!(
+        sign_tuf_metadata,
+        TufRequest,
+        TufResponse,
+        "Failed to sign TUF metadata"
+    );
+
+    bastion_call_with_timestamp!(
+        sign_cosign,
+        CosignRequest,
+        CosignResponse,
+        "Failed to sign for cosign"
+    );
+
+    bastion_call_with_timestamp!(
+        sign_release,
+        ReleaseRequest,
+        ReleaseResponse,
+        "Failed to sign Release file"
+    );
+
+    bastion_call_with_timestamp!(
+        sign_rpm,
+        RpmRequest,
+        RpmResponse,
+        "Failed to sign RPM"
+    );
+
+    fn timestamp(
+        &self,
+        ctx: RpcContext,
+        req: TimestampRequest,
+        sink: UnarySink<TimestampResponse>,
+    ) {
+        // Timestamps need no approvals, so they're limited per client instead.
+        let peer = ctx.peer();
+        if !self.timestamp_limiter.check(&peer, Instant::now()) {
+            info!("Rate limited timestamp request from {}", peer);
+            return ctx.spawn(
+                sink.fail(RpcStatus {
+                    status: grpcio::RpcStatusCode::ResourceExhausted,
+                    details: Some("Too many timestamp requests".to_string()),
+                }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)),
+            );
+        }
+
+        match self.client.timestamp(&req) {
+            Ok(response) => ctx.spawn(sink.success(response).map_err(move |err| {
+                error!("failed to reply {:?}: {:?}", req, err)
+            })),
+            Err(err) => {
+                info!("Failed to timestamp: {}", err);
+                ctx.spawn(
+                    sink.fail(RpcStatus {
+                        status: grpcio::RpcStatusCode::PermissionDenied,
+                        details: Some("Failed to timestamp".to_string()),
+                    }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)),
+                )
+            }
+        }
+    }
+
     bastion_call!(
         get_logs,
         LogRequest,
         LogResponse,
         "Failed to get audit logs"
     );
+
+    bastion_call!(
+        get_checkpoints,
+        CheckpointRequest,
+        CheckpointResponse,
+        "Failed to get audit log checkpoints"
+    );
+
+    bastion_call!(
+        get_inclusion_proof,
+        InclusionProofRequest,
+        InclusionProofResponse,
+        "Failed to get inclusion proof"
+    );
+
+    bastion_call!(
+        get_consistency_proof,
+        ConsistencyProofRequest,
+        ConsistencyProofResponse,
+        "Failed to get consistency proof"
+    );
+
+    bastion_stream_call!(
+        export_logs,
+        LogRequest,
+        LogExportRecord,
+        "Failed to export audit logs"
+    );
+
+    bastion_stream_call!(
+        stream_logs,
+        LogStreamRequest,
+        LogResponse,
+        "Failed to stream audit logs"
+    );
+
+    bastion_stream_call!(
+        follow_logs,
+        LogStreamRequest,
+        LogResponse,
+        "Failed to follow audit logs"
+    );
 }
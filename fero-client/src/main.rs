@@ -1,30 +1,60 @@
 extern crate byteorder;
+#[macro_use]
 extern crate failure;
 extern crate fero_proto;
+extern crate futures;
 extern crate grpcio;
 #[macro_use]
 extern crate log;
 extern crate loggerv;
+extern crate pem;
 extern crate protobuf;
 #[macro_use]
 extern crate structopt;
 
+use std::collections::HashMap;
+use std::fmt::Debug;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::num::ParseIntError;
 use std::path::PathBuf;
+use std::process::{self, Command};
+use std::str;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use byteorder::{BigEndian, WriteBytesExt};
 use failure::Error;
+use futures::Stream;
 use grpcio::{ChannelBuilder, EnvBuilder};
+use protobuf::{Message, ProtobufEnum};
 use protobuf::repeated::RepeatedField;
+use protobuf::well_known_types::Timestamp;
 use structopt::StructOpt;
 
-use fero_proto::fero::{Identification, LogRequest, SignRequest, SignRequest_SignatureType,
-                       ThresholdRequest, WeightRequest};
+use fero_proto::armor::armor_signature;
+use fero_proto::audit;
+use fero_proto::checkpoint::Checkpoint;
+use fero_proto::export::LogExport;
+use fero_proto::fero::{CertificateRequest, CheckpointRequest, ConsistencyProofRequest,
+                       ContainerImage, CosignRequest, CrlParams, CrlRequest, CrlRevocation,
+                       Identification, InclusionProofRequest, JwsRequest,
+                       JwsRequest_Serialization, LogEntry, LogEntry_OperationResult,
+                       LogEntry_OperationType, LogRequest, LogResponse, LogStreamRequest,
+                       ReleaseRequest, RpmRequest, SignRequest, SignRequest_SignatureType,
+                       SshCertificateOption, SshCertificateParams,
+                       SshCertificateParams_CertificateType,
+                       SshCertificateRequest, ThresholdRequest, TimestampRequest, TufRequest,
+                       WeightRequest};
 use fero_proto::fero_grpc::FeroClient;
+use fero_proto::hex;
 use fero_proto::log::FeroLogEntry;
+use fero_proto::merkle;
+use fero_proto::rpm::{self, Package};
+
+/// How long to wait before following the logs again after losing the server.
+const FOLLOW_RETRY_SECS: u64 = 5;
 
 #[derive(StructOpt)]
 #[structopt(name = "fero-client")]
@@ -59,6 +89,44 @@ struct SignCommand {
     #[structopt(long = "pkcs1")]
     /// Produce a PKCS1V1.5 signature, if set.
     rsa: bool,
+    #[structopt(long = "cms")]
+    /// Produce a detached CMS (PKCS#7) signature in DER form, if set.
+    cms: bool,
+    #[structopt(long = "git-commit")]
+    /// Treat the file as a raw git commit object, and output it with the signature embedded.
+    git_commit: bool,
+    #[structopt(long = "git-tag")]
+    /// Treat the file as a raw git tag object, and output it with the signature embedded.
+    git_tag: bool,
+    #[structopt(long = "minisign")]
+    /// Produce a minisign signature file, if set. The secret must be an Ed25519 key.
+    minisign: bool,
+    #[structopt(long = "authenticode")]
+    /// Treat the file as a PE image, and output it with an Authenticode signature appended.
+    authenticode: bool,
+    #[structopt(long = "kernel-module")]
+    /// Treat the file as a Linux kernel module, and output it with its signature appended.
+    kernel_module: bool,
+    #[structopt(long = "kernel-module-signature")]
+    /// Produce a detached kernel module signature, as `sign-file -d` does, if set.
+    kernel_module_signature: bool,
+}
+
+#[derive(StructOpt)]
+struct GpgCommand {
+    #[structopt(short = "k", long = "secret-key")]
+    /// The name of the secret key to sign with.
+    secret_key_name: String,
+    // No short option, since git passes gpg `-bsau`.
+    #[structopt(long = "signature", parse(from_os_str), raw(number_of_values = "1"))]
+    /// The user signatures to authorize signing.
+    signatures: Vec<PathBuf>,
+    #[structopt(long = "payload-output", parse(from_os_str))]
+    /// A file to copy the data being signed to, so that users can approve it. Once approvals are
+    /// given, the data being signed must match it.
+    payload_output: Option<PathBuf>,
+    /// The arguments git passes to gpg.
+    gpg_args: Vec<String>,
 }
 
 #[derive(StructOpt)]
@@ -119,11 +187,351 @@ struct WeightPayloadCommand {
     file: PathBuf,
 }
 
+#[derive(StructOpt)]
+struct SshCertificateParamsOpts {
+    #[structopt(short = "k", long = "secret-key")]
+    /// The name of the secret key to certify with.
+    secret_key_name: String,
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    /// The SSH public key to certify.
+    file: PathBuf,
+    #[structopt(long = "host")]
+    /// Issue a host certificate rather than a user certificate.
+    host: bool,
+    #[structopt(short = "I", long = "identity")]
+    /// The certificate's key identity.
+    key_id: String,
+    #[structopt(short = "n", long = "principal")]
+    /// User or host names the certificate is valid for.
+    principals: Vec<String>,
+    #[structopt(short = "z", long = "serial", default_value = "0")]
+    /// The certificate's serial number.
+    serial: u64,
+    #[structopt(long = "valid-after")]
+    /// The start of the validity period, in seconds since the epoch.
+    valid_after: u64,
+    #[structopt(long = "valid-before")]
+    /// The end of the validity period, in seconds since the epoch.
+    valid_before: u64,
+    #[structopt(short = "O", long = "critical-option")]
+    /// A critical option, as `name` or `name=value`.
+    critical_options: Vec<String>,
+    #[structopt(short = "E", long = "extension")]
+    /// An extension, as `name` or `name=value`.
+    extensions: Vec<String>,
+}
+
+#[derive(StructOpt)]
+struct SshCertificatePayloadCommand {
+    #[structopt(flatten)]
+    params: SshCertificateParamsOpts,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to output the payload into.
+    output: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct SshCertificateCommand {
+    #[structopt(flatten)]
+    params: SshCertificateParamsOpts,
+    #[structopt(short = "s", long = "signature", parse(from_os_str))]
+    /// The user signatures to authorize the operation.
+    signatures: Vec<PathBuf>,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to place the certificate in.
+    output: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct CertificateCommand {
+    #[structopt(short = "k", long = "secret-key")]
+    /// The name of the secret key to issue the certificate with.
+    secret_key_name: String,
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    /// The PKCS#10 certification request, which is also what users sign to approve it.
+    file: PathBuf,
+    #[structopt(short = "s", long = "signature", parse(from_os_str))]
+    /// The user signatures to authorize the operation.
+    signatures: Vec<PathBuf>,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to place the certificate in.
+    output: PathBuf,
+    #[structopt(long = "pem")]
+    /// Write the certificate as PEM rather than DER.
+    pem: bool,
+}
+
+#[derive(StructOpt)]
+struct CrlParamsOpts {
+    #[structopt(short = "k", long = "secret-key")]
+    /// The name of the secret key whose CRL to generate.
+    secret_key_name: String,
+    #[structopt(short = "r", long = "revoke", parse(try_from_str = "parse_revocation"))]
+    /// A certificate to revoke, as a hex serial number optionally followed by `:` and a CRLReason
+    /// code.
+    revocations: Vec<CrlRevocation>,
+    #[structopt(long = "validity-days", default_value = "7")]
+    /// How long until the next CRL is due.
+    validity_days: u32,
+}
+
+#[derive(StructOpt)]
+struct CrlPayloadCommand {
+    #[structopt(flatten)]
+    params: CrlParamsOpts,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to output the payload into.
+    output: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct CrlCommand {
+    #[structopt(flatten)]
+    params: CrlParamsOpts,
+    #[structopt(short = "s", long = "signature", parse(from_os_str))]
+    /// The user signatures to authorize the operation.
+    signatures: Vec<PathBuf>,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to place the CRL in.
+    output: PathBuf,
+    #[structopt(long = "pem")]
+    /// Write the CRL as PEM rather than DER.
+    pem: bool,
+}
+
+#[derive(StructOpt)]
+struct JwsCommand {
+    #[structopt(short = "k", long = "secret-key")]
+    /// The name of the secret key to sign with.
+    secret_key_name: String,
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    /// The JWS payload, which is also what users sign to approve it.
+    file: PathBuf,
+    #[structopt(short = "s", long = "signature", parse(from_os_str))]
+    /// The user signatures to authorize the operation.
+    signatures: Vec<PathBuf>,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to place the JWS in.
+    output: PathBuf,
+    #[structopt(long = "json")]
+    /// Use the flattened JSON serialization rather than the compact one.
+    json: bool,
+}
+
+#[derive(StructOpt)]
+struct TufCommand {
+    #[structopt(short = "k", long = "secret-key")]
+    /// The name of the secret key to sign with.
+    secret_key_name: String,
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    /// The canonical JSON `signed` object of the role's metadata, which is also what users sign
+    /// to approve it.
+    file: PathBuf,
+    #[structopt(short = "s", long = "signature", parse(from_os_str))]
+    /// The user signatures to authorize the operation.
+    signatures: Vec<PathBuf>,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to place the TUF signature object in.
+    output: PathBuf,
+    #[structopt(long = "key-output", parse(from_os_str))]
+    /// A file to place the secret's TUF public key object in, if given.
+    key_output: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct CosignPayloadCommand {
+    #[structopt(long = "image")]
+    /// The repository the image is published in.
+    docker_reference: String,
+    #[structopt(long = "digest")]
+    /// The image's manifest digest.
+    manifest_digest: String,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to output the payload into.
+    output: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct CosignCommand {
+    #[structopt(short = "k", long = "secret-key")]
+    /// The name of the secret key to sign with.
+    secret_key_name: String,
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    /// The blob to sign, which is also what users sign to approve it.
+    file: Option<PathBuf>,
+    #[structopt(long = "image")]
+    /// The repository of the container image to sign, instead of a blob.
+    docker_reference: Option<String>,
+    #[structopt(long = "digest")]
+    /// The manifest digest of the container image to sign.
+    manifest_digest: Option<String>,
+    #[structopt(short = "s", long = "signature", parse(from_os_str))]
+    /// The user signatures to authorize the operation.
+    signatures: Vec<PathBuf>,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to place the base64 signature in.
+    output: PathBuf,
+    #[structopt(long = "bundle", parse(from_os_str))]
+    /// A file to place the Sigstore bundle in, if given.
+    bundle: Option<PathBuf>,
+    #[structopt(long = "payload", parse(from_os_str))]
+    /// A file to place the simple signing payload of a container image in, if given.
+    payload: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct ReleaseCommand {
+    #[structopt(short = "k", long = "secret-key")]
+    /// The name of the secret key to sign with.
+    secret_key_name: String,
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    /// The Release file, which is also what users sign to approve it.
+    file: PathBuf,
+    #[structopt(short = "s", long = "signature", parse(from_os_str))]
+    /// The user signatures to authorize the operation.
+    signatures: Vec<PathBuf>,
+    #[structopt(long = "release-gpg", parse(from_os_str))]
+    /// The file to place the detached signature (Release.gpg) in.
+    release_gpg: PathBuf,
+    #[structopt(long = "in-release", parse(from_os_str))]
+    /// The file to place the cleartext-signed Release file (InRelease) in.
+    in_release: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct RpmPayloadCommand {
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    /// The RPM package to sign.
+    file: PathBuf,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to output the payload into.
+    output: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct RpmCommand {
+    #[structopt(short = "k", long = "secret-key")]
+    /// The name of the secret key to sign with.
+    secret_key_name: String,
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    /// The RPM package to sign.
+    file: PathBuf,
+    #[structopt(short = "s", long = "signature", parse(from_os_str))]
+    /// The user signatures to authorize the operation.
+    signatures: Vec<PathBuf>,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to place the signed package in.
+    output: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct TimestampCommand {
+    #[structopt(short = "q", long = "query", parse(from_os_str))]
+    /// The RFC 3161 timestamp query, such as from `openssl ts -query`.
+    query: PathBuf,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to place the timestamp response in.
+    output: PathBuf,
+}
+
 #[derive(StructOpt)]
 struct GetLogCommand {
     #[structopt(short = "s", long = "since")]
     /// Only retrieve logs created since this log index.
     since: i32,
+    #[structopt(long = "audit-key", parse(from_os_str))]
+    /// The audit secret's RSA public key, in PEM or DER form, to check checkpoints against.
+    audit_key: Option<PathBuf>,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// A file to export the logs to, for `verify-logs`.
+    output: Option<PathBuf>,
+    #[structopt(short = "f", long = "follow")]
+    /// Keep printing new log entries as they're logged.
+    follow: bool,
+}
+
+#[derive(StructOpt)]
+struct SearchLogsCommand {
+    #[structopt(short = "s", long = "since", default_value = "0")]
+    /// Only search logs created since this log index.
+    since: i32,
+    #[structopt(long = "until", default_value = "0")]
+    /// Only search logs up to this log index, rather than to the end of the log.
+    until: i32,
+    #[structopt(short = "t", long = "type", parse(try_from_str = "parse_operation_type"))]
+    /// Only find operations of this type, such as `sign` or `add-secret`.
+    operation_types: Vec<LogEntry_OperationType>,
+    #[structopt(short = "r", long = "result", parse(try_from_str = "parse_operation_result"))]
+    /// Only find operations with this result, `success` or `failure`.
+    results: Vec<LogEntry_OperationResult>,
+    #[structopt(short = "n", long = "name")]
+    /// Only find operations on this secret.
+    secret_key_name: Option<String>,
+    #[structopt(long = "after-time")]
+    /// Only find operations at or after this time, in seconds since the epoch.
+    after_time: Option<i64>,
+    #[structopt(long = "before-time")]
+    /// Only find operations before this time, in seconds since the epoch.
+    before_time: Option<i64>,
+    #[structopt(long = "page-size", default_value = "0")]
+    /// The number of entries to fetch at a time, or 0 for the server's default.
+    page_size: u32,
+}
+
+#[derive(StructOpt)]
+struct ExportLogsCommand {
+    #[structopt(short = "s", long = "since", default_value = "0")]
+    /// Only export logs created since this log index.
+    since: i32,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to write the export to.
+    output: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct ShowExportCommand {
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    /// The export, from `export-logs`.
+    file: PathBuf,
+    #[structopt(long = "audit-key", parse(from_os_str))]
+    /// The audit secret's RSA public key, in PEM or DER form, to check the checkpoint against.
+    audit_key: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct VerifyLogsCommand {
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    /// The logs, from `export-logs` or `get-logs --output`.
+    file: PathBuf,
+    #[structopt(short = "k", long = "key", parse(try_from_str = "parse_key"))]
+    /// A secret's HSM key, as NAME=ID, that its signing operations must use.
+    keys: Vec<(String, u16)>,
+}
+
+#[derive(StructOpt)]
+struct ProveInclusionCommand {
+    #[structopt(short = "i", long = "id")]
+    /// ID of the log entry.
+    id: i32,
+    #[structopt(short = "n", long = "tree-size", default_value = "0")]
+    /// Size of the log's Merkle tree to prove inclusion in, rather than the whole log.
+    tree_size: u64,
+    #[structopt(long = "audit-key", parse(from_os_str))]
+    /// The audit secret's RSA public key, in PEM or DER form, to check checkpoints against.
+    audit_key: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct ProveConsistencyCommand {
+    #[structopt(long = "first")]
+    /// Size of the smaller tree, such as that of a checkpoint seen before.
+    first: u64,
+    #[structopt(long = "second", default_value = "0")]
+    /// Size of the larger tree, rather than the whole log.
+    second: u64,
+    #[structopt(long = "audit-key", parse(from_os_str))]
+    /// The audit secret's RSA public key, in PEM or DER form, to check checkpoints against.
+    audit_key: Option<PathBuf>,
 }
 
 #[derive(StructOpt)]
@@ -131,6 +539,13 @@ enum FeroCommand {
     #[structopt(name = "sign")]
     /// Sign the given file.
     Sign(SignCommand),
+    #[structopt(
+        name = "gpg",
+        raw(setting = "structopt::clap::AppSettings::TrailingVarArg"),
+        raw(setting = "structopt::clap::AppSettings::AllowLeadingHyphen")
+    )]
+    /// Act as gpg for git's `gpg.program`, signing with fero.
+    Gpg(GpgCommand),
     #[structopt(name = "threshold-payload")]
     /// Generate a signable payload for a threshold request.
     ThresholdPayload(ThresholdPayloadCommand),
@@ -143,9 +558,66 @@ enum FeroCommand {
     #[structopt(name = "weight")]
     /// Update a given user's weight for a given secret key.
     Weight(WeightCommand),
+    #[structopt(name = "ssh-certificate-payload")]
+    /// Generate a signable payload for an SSH certificate request.
+    SshCertificatePayload(SshCertificatePayloadCommand),
+    #[structopt(name = "ssh-certificate")]
+    /// Certify an SSH public key.
+    SshCertificate(SshCertificateCommand),
+    #[structopt(name = "issue-certificate")]
+    /// Issue an X.509 certificate from a certification request.
+    IssueCertificate(CertificateCommand),
+    #[structopt(name = "crl-payload")]
+    /// Generate a signable payload for a CRL request.
+    CrlPayload(CrlPayloadCommand),
+    #[structopt(name = "crl")]
+    /// Revoke certificates and generate a new CRL.
+    Crl(CrlCommand),
+    #[structopt(name = "jws")]
+    /// Sign the given file as a JSON Web Signature.
+    Jws(JwsCommand),
+    #[structopt(name = "tuf")]
+    /// Sign TUF role metadata.
+    Tuf(TufCommand),
+    #[structopt(name = "cosign-payload")]
+    /// Generate a signable payload for a cosign container image request.
+    CosignPayload(CosignPayloadCommand),
+    #[structopt(name = "cosign")]
+    /// Sign a blob or container image for verification with cosign.
+    Cosign(CosignCommand),
+    #[structopt(name = "release")]
+    /// Sign a Debian repository Release file.
+    Release(ReleaseCommand),
+    #[structopt(name = "rpm-payload")]
+    /// Generate a signable payload for an RPM request.
+    RpmPayload(RpmPayloadCommand),
+    #[structopt(name = "rpm")]
+    /// Sign an RPM package's header.
+    Rpm(RpmCommand),
+    #[structopt(name = "timestamp")]
+    /// Request an RFC 3161 timestamp.
+    Timestamp(TimestampCommand),
     #[structopt(name = "get-logs")]
     /// Get the audit logs from the server.
     GetLogs(GetLogCommand),
+    #[structopt(name = "search-logs")]
+    /// Find audit log entries by type, result, secret or time.
+    SearchLogs(SearchLogsCommand),
+    #[structopt(name = "export-logs")]
+    /// Export the audit logs to a file which can be checked offline.
+    ExportLogs(ExportLogsCommand),
+    #[structopt(name = "show-export")]
+    /// Check and print an audit log export, offline.
+    ShowExport(ShowExportCommand),
+    #[structopt(name = "verify-logs")]
+    /// Check exported audit logs against the HSM's own log, offline.
+    VerifyLogs(VerifyLogsCommand),
+    #[structopt(name = "prove-inclusion")]
+    /// Check that a log entry is included in the audit log's Merkle tree.
+    ProveInclusion(ProveInclusionCommand),
+    #[structopt(name = "prove-consistency")]
+    /// Check that the audit log has only been appended to between two sizes.
+    ProveConsistency(ProveConsistencyCommand),
 }
 
 fn parse_hex(s: &str) -> Result<u64, ParseIntError> {
@@ -156,6 +628,69 @@ fn parse_hex(s: &str) -> Result<u64, ParseIntError> {
     }
 }
 
+/// Find a protobuf enum's value by its name, in any case and with `-` for `_`.
+fn parse_enum<E: ProtobufEnum + Debug>(s: &str) -> Result<E, Error> {
+    let name = s.replace('-', "_");
+    E::values()
+        .iter()
+        .find(|value| format!("{:?}", value).eq_ignore_ascii_case(&name))
+        .cloned()
+        .ok_or_else(|| format_err!("Unknown value {}", s))
+}
+
+fn parse_operation_type(s: &str) -> Result<LogEntry_OperationType, Error> {
+    parse_enum(s)
+}
+
+fn parse_operation_result(s: &str) -> Result<LogEntry_OperationResult, Error> {
+    parse_enum(s)
+}
+
+fn parse_key(s: &str) -> Result<(String, u16), Error> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(id)) if !name.is_empty() => {
+            let id = if id.starts_with("0x") {
+                u16::from_str_radix(&id[2..], 16)?
+            } else {
+                id.parse()?
+            };
+            Ok((String::from(name), id))
+        }
+        _ => bail!("Expected NAME=ID, found {}", s),
+    }
+}
+
+fn parse_revocation(s: &str) -> Result<CrlRevocation, Error> {
+    let mut parts = s.splitn(2, ':');
+    let serial = parts.next().unwrap_or("");
+    let serial = if serial.starts_with("0x") { &serial[2..] } else { serial };
+    if serial.is_empty() {
+        bail!("Missing serial number");
+    }
+
+    // Left-pad odd-length serials, so that they can be parsed a byte at a time.
+    let serial = if serial.len() % 2 == 1 {
+        format!("0{}", serial)
+    } else {
+        String::from(serial)
+    };
+    let serial = serial
+        .as_bytes()
+        .chunks(2)
+        .map(|byte| Ok(u8::from_str_radix(str::from_utf8(byte)?, 16)?))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut revocation = CrlRevocation::new();
+    revocation.set_serial(serial);
+    revocation.set_reason(match parts.next() {
+        Some(reason) => reason.parse()?,
+        None => 0,
+    });
+
+    Ok(revocation)
+}
+
 fn build_signatures(signature_files: &[PathBuf]) -> Result<Vec<Vec<u8>>, Error> {
     let mut signatures_contents = Vec::new();
 
@@ -169,6 +704,188 @@ fn build_signatures(signature_files: &[PathBuf]) -> Result<Vec<Vec<u8>>, Error>
     Ok(signatures_contents)
 }
 
+/// Find the value of one of the options `names` in gpg's arguments, whether it's given as
+/// `--name=value` or `--name value`.
+fn gpg_option<'a>(args: &'a [String], names: &[&str]) -> Option<&'a str> {
+    for (index, arg) in args.iter().enumerate() {
+        for name in names {
+            if arg == name {
+                return args.get(index + 1).map(|value| value.as_str());
+            } else if arg.starts_with(name) && arg[name.len()..].starts_with('=') {
+                return Some(&arg[name.len() + 1..]);
+            }
+        }
+    }
+
+    None
+}
+
+/// Whether gpg's arguments ask for a detached signature, as git does when signing, rather than
+/// verification.
+fn gpg_signing(args: &[String]) -> bool {
+    args.iter()
+        .any(|arg| arg == "-bsau" || arg == "-b" || arg == "--detach-sign")
+}
+
+/// The environment variables which pin the dates in a git commit or tag object, so that git
+/// produces the same object again once users have approved it.
+fn git_date_variables(object: &[u8]) -> Vec<String> {
+    let object = String::from_utf8_lossy(object);
+    let mut variables = Vec::new();
+    for line in object.lines().take_while(|line| !line.is_empty()) {
+        let variable = if line.starts_with("author ") {
+            "GIT_AUTHOR_DATE"
+        } else if line.starts_with("committer ") || line.starts_with("tagger ") {
+            "GIT_COMMITTER_DATE"
+        } else {
+            continue;
+        };
+
+        // The identity ends with the seconds since the epoch and the timezone offset.
+        let date = line.rsplitn(3, ' ').take(2).collect::<Vec<_>>();
+        if date.len() == 2 {
+            variables.push(format!("{}=\"{} {}\"", variable, date[1], date[0]));
+        }
+    }
+
+    variables
+}
+
+fn build_ssh_options(options: &[String]) -> Vec<SshCertificateOption> {
+    options
+        .iter()
+        .map(|option| {
+            let mut parts = option.splitn(2, '=');
+            let mut ssh_option = SshCertificateOption::new();
+            ssh_option.set_name(parts.next().unwrap_or("").to_string());
+            ssh_option.set_value(parts.next().unwrap_or("").to_string());
+            ssh_option
+        })
+        .collect()
+}
+
+fn build_ssh_params(opts: &SshCertificateParamsOpts) -> Result<SshCertificateParams, Error> {
+    let mut params = SshCertificateParams::new();
+    params.set_public_key({
+        let mut file = File::open(&opts.file)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        contents.trim().to_string()
+    });
+    if opts.host {
+        params.set_cert_type(SshCertificateParams_CertificateType::HOST);
+    } else {
+        params.set_cert_type(SshCertificateParams_CertificateType::USER);
+    }
+    params.set_serial(opts.serial);
+    params.set_key_id(opts.key_id.clone());
+    params.set_principals(RepeatedField::from_vec(opts.principals.clone()));
+    params.set_valid_after(opts.valid_after);
+    params.set_valid_before(opts.valid_before);
+    params.set_critical_options(RepeatedField::from_vec(build_ssh_options(&opts.critical_options)));
+    params.set_extensions(RepeatedField::from_vec(build_ssh_options(&opts.extensions)));
+
+    Ok(params)
+}
+
+/// Read an RSA public key in PEM or DER form.
+fn read_public_key(path: PathBuf) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    if data.starts_with(b"-----BEGIN") {
+        match pem::parse(&data) {
+            Ok(pem) => Ok(pem.contents),
+            Err(e) => bail!("Error parsing PEM: {}", e),
+        }
+    } else {
+        Ok(data)
+    }
+}
+
+/// The audit secret's public key, from `audit_key` if given, or else as the server reports it.
+fn audit_public_key(audit_key: Option<PathBuf>, server_key: &[u8]) -> Result<Vec<u8>, Error> {
+    match audit_key {
+        Some(path) => {
+            let public_key = read_public_key(path)?;
+            if public_key != server_key {
+                error!("The server's audit secret doesn't match the given audit key!");
+            }
+            Ok(public_key)
+        }
+        None => {
+            warn!("No audit key given; checking checkpoints against the server's key.");
+            Ok(server_key.to_vec())
+        }
+    }
+}
+
+/// Compare `root` with the signed checkpoint of the log's Merkle tree of size `size`, if there
+/// is one. Without one, nothing vouches for `root` but the server itself.
+fn check_signed_tree_root(
+    client: &FeroClient,
+    size: u64,
+    root: &[u8],
+    audit_key: Option<PathBuf>,
+) -> Result<(), Error> {
+    if size == 0 {
+        return Ok(());
+    }
+
+    let mut req = CheckpointRequest::new();
+    req.set_minIndex(size as i32 - 1);
+    let reply = client.get_checkpoints(&req)?;
+
+    let checkpoint = reply
+        .get_checkpoints()
+        .iter()
+        .map(Checkpoint::from)
+        .find(|checkpoint| checkpoint.log_id as u64 == size && !checkpoint.tree_root.is_empty());
+    match checkpoint {
+        Some(checkpoint) => {
+            checkpoint.verify(&audit_public_key(audit_key, reply.get_public_key())?)?;
+            if checkpoint.tree_root != root {
+                bail!("Tree root of size {} doesn't match its signed checkpoint!", size);
+            }
+            info!("Tree root of size {} matches its signed checkpoint.", size);
+        }
+        None => warn!("No signed checkpoint of a tree of size {}.", size),
+    }
+
+    Ok(())
+}
+
+/// Fetch the logs matching `req` a page at a time.
+fn stream_logs(client: &FeroClient, req: &LogStreamRequest) -> Result<Vec<LogEntry>, Error> {
+    let mut logs = Vec::new();
+    for page in client.stream_logs(req)?.wait() {
+        logs.extend(page?.take_logs().into_vec());
+    }
+
+    Ok(logs)
+}
+
+/// Read logs from a file written by either `export-logs` or `get-logs --output`.
+fn read_logs(path: PathBuf) -> Result<Vec<LogEntry>, Error> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    match LogExport::read_from(&mut &data[..]) {
+        Ok(export) => {
+            export.verify()?;
+            Ok(export.entries)
+        }
+        Err(_) => Ok(protobuf::parse_from_bytes::<LogResponse>(&data)?.take_logs().into_vec()),
+    }
+}
+
+fn build_crl_params(opts: &CrlParamsOpts) -> CrlParams {
+    let mut params = CrlParams::new();
+    params.set_revocations(RepeatedField::from_vec(opts.revocations.clone()));
+    params.set_validity_days(opts.validity_days);
+
+    params
+}
+
 pub fn main() -> Result<(), Error> {
     let opts = Opt::from_args();
 
@@ -186,11 +903,29 @@ pub fn main() -> Result<(), Error> {
 
             let mut req = SignRequest::new();
             req.set_identification(ident);
-            if sign_opts.rsa {
-                req.set_sigType(SignRequest_SignatureType::PKCS1V1_5);
-            } else {
-                req.set_sigType(SignRequest_SignatureType::PGP);
-            }
+            let sig_types = [
+                (sign_opts.rsa, SignRequest_SignatureType::PKCS1V1_5),
+                (sign_opts.cms, SignRequest_SignatureType::CMS),
+                (sign_opts.git_commit, SignRequest_SignatureType::GIT_COMMIT),
+                (sign_opts.git_tag, SignRequest_SignatureType::GIT_TAG),
+                (sign_opts.minisign, SignRequest_SignatureType::MINISIGN),
+                (sign_opts.authenticode, SignRequest_SignatureType::AUTHENTICODE),
+                (sign_opts.kernel_module, SignRequest_SignatureType::KERNEL_MODULE),
+                (
+                    sign_opts.kernel_module_signature,
+                    SignRequest_SignatureType::KERNEL_MODULE_SIGNATURE,
+                ),
+            ];
+            let requested = sig_types
+                .iter()
+                .filter(|&&(set, _)| set)
+                .map(|&(_, sig_type)| sig_type)
+                .collect::<Vec<_>>();
+            req.set_sigType(match requested.as_slice() {
+                [] => SignRequest_SignatureType::PGP,
+                [sig_type] => *sig_type,
+                _ => bail!("Only one signature type may be requested"),
+            });
             req.set_payload({
                 let mut file = File::open(sign_opts.file)?;
                 let mut contents = Vec::new();
@@ -202,6 +937,67 @@ pub fn main() -> Result<(), Error> {
             let mut output = File::create(sign_opts.output)?;
             output.write_all(&reply.get_payload().to_vec())?;
         }
+        FeroCommand::Gpg(gpg_opts) => {
+            // git also runs gpg.program to verify signatures, which is left to the real gpg.
+            if !gpg_signing(&gpg_opts.gpg_args) {
+                let status = Command::new("gpg").args(&gpg_opts.gpg_args).status()?;
+                process::exit(status.code().unwrap_or(1));
+            }
+
+            let mut payload = Vec::new();
+            io::stdin().read_to_end(&mut payload)?;
+
+            // git writes a new object each time, with the current time in it, so the payload is
+            // only written out once, when asking for approvals. When retrying with approvals, git
+            // has to be made to produce the very same object by pinning its dates.
+            let pin_dates = git_date_variables(&payload).join(" ");
+            if gpg_opts.signatures.is_empty() {
+                if let Some(payload_output) = gpg_opts.payload_output {
+                    File::create(&payload_output)?.write_all(&payload)?;
+                    bail!(
+                        "No approvals given; have users sign {} and retry with {}",
+                        payload_output.display(),
+                        pin_dates
+                    );
+                }
+                bail!("No approvals given; have users sign the payload and try again");
+            }
+            if let Some(payload_output) = gpg_opts.payload_output {
+                let mut approved = Vec::new();
+                File::open(&payload_output)?.read_to_end(&mut approved)?;
+                if approved != payload {
+                    bail!(
+                        "The object to sign doesn't match the approved payload in {}; retry with \
+                         the dates it was produced with pinned",
+                        payload_output.display()
+                    );
+                }
+            }
+
+            let mut ident = Identification::new();
+            ident.set_secretKeyName(gpg_opts.secret_key_name);
+            ident.set_signatures(RepeatedField::from_vec(build_signatures(&gpg_opts.signatures)?));
+
+            let mut req = SignRequest::new();
+            req.set_identification(ident);
+            req.set_sigType(SignRequest_SignatureType::PGP);
+            req.set_payload(payload);
+
+            let reply = client.sign_payload(&req)?;
+            io::stdout().write_all(armor_signature(reply.get_payload()).as_bytes())?;
+
+            // git checks for this status line to tell that a signature was made.
+            let status = format!(
+                "[GNUPG:] SIG_CREATED D 1 8 00 {} {}",
+                SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                gpg_option(&gpg_opts.gpg_args, &["-bsau", "-u", "--local-user"]).unwrap_or(""),
+            );
+            match gpg_option(&gpg_opts.gpg_args, &["--status-fd"]) {
+                Some("1") => println!("{}", status),
+                Some("2") => eprintln!("{}", status),
+                _ => {}
+            }
+        }
         FeroCommand::ThresholdPayload(threshold_opts) => {
             let mut payload = Vec::new();
             payload.write(threshold_opts.secret_key_name.as_bytes())?;
@@ -242,14 +1038,244 @@ pub fn main() -> Result<(), Error> {
 
             client.set_user_key_weight(&req).map(|_| ())?;
         }
+        FeroCommand::SshCertificatePayload(ssh_opts) => {
+            let payload = build_ssh_params(&ssh_opts.params)?.write_to_bytes()?;
+
+            let mut file = File::create(ssh_opts.output)?;
+            file.write_all(&payload)?;
+        }
+        FeroCommand::SshCertificate(ssh_opts) => {
+            let mut ident = Identification::new();
+            ident.set_secretKeyName(ssh_opts.params.secret_key_name.clone());
+            ident.set_signatures(RepeatedField::from_vec(build_signatures(&ssh_opts.signatures)?));
+
+            let mut req = SshCertificateRequest::new();
+            req.set_identification(ident);
+            req.set_params(build_ssh_params(&ssh_opts.params)?);
+
+            let reply = client.sign_ssh_certificate(&req)?;
+            let mut output = File::create(ssh_opts.output)?;
+            writeln!(output, "{}", reply.get_certificate())?;
+        }
+        FeroCommand::IssueCertificate(cert_opts) => {
+            let mut ident = Identification::new();
+            ident.set_secretKeyName(cert_opts.secret_key_name);
+            ident.set_signatures(RepeatedField::from_vec(build_signatures(&cert_opts.signatures)?));
+
+            let mut req = CertificateRequest::new();
+            req.set_identification(ident);
+            req.set_csr({
+                let mut file = File::open(cert_opts.file)?;
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents)?;
+                contents
+            });
+
+            let reply = client.issue_certificate(&req)?;
+            let mut output = File::create(cert_opts.output)?;
+            if cert_opts.pem {
+                output.write_all(pem::encode(&pem::Pem {
+                    tag: String::from("CERTIFICATE"),
+                    contents: reply.get_certificate().to_vec(),
+                }).as_bytes())?;
+            } else {
+                output.write_all(reply.get_certificate())?;
+            }
+        }
+        FeroCommand::CrlPayload(crl_opts) => {
+            let payload = build_crl_params(&crl_opts.params).write_to_bytes()?;
+
+            let mut file = File::create(crl_opts.output)?;
+            file.write_all(&payload)?;
+        }
+        FeroCommand::Crl(crl_opts) => {
+            let mut ident = Identification::new();
+            ident.set_secretKeyName(crl_opts.params.secret_key_name.clone());
+            ident.set_signatures(RepeatedField::from_vec(build_signatures(&crl_opts.signatures)?));
+
+            let mut req = CrlRequest::new();
+            req.set_identification(ident);
+            req.set_params(build_crl_params(&crl_opts.params));
+
+            let reply = client.generate_crl(&req)?;
+            let mut output = File::create(crl_opts.output)?;
+            if crl_opts.pem {
+                output.write_all(pem::encode(&pem::Pem {
+                    tag: String::from("X509 CRL"),
+                    contents: reply.get_crl().to_vec(),
+                }).as_bytes())?;
+            } else {
+                output.write_all(reply.get_crl())?;
+            }
+        }
+        FeroCommand::Jws(jws_opts) => {
+            let mut ident = Identification::new();
+            ident.set_secretKeyName(jws_opts.secret_key_name);
+            ident.set_signatures(RepeatedField::from_vec(build_signatures(&jws_opts.signatures)?));
+
+            let mut req = JwsRequest::new();
+            req.set_identification(ident);
+            req.set_payload({
+                let mut file = File::open(jws_opts.file)?;
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents)?;
+                contents
+            });
+            req.set_serialization(if jws_opts.json {
+                JwsRequest_Serialization::JSON
+            } else {
+                JwsRequest_Serialization::COMPACT
+            });
+
+            let reply = client.sign_jws(&req)?;
+            let mut output = File::create(jws_opts.output)?;
+            writeln!(output, "{}", reply.get_jws())?;
+        }
+        FeroCommand::Tuf(tuf_opts) => {
+            let mut ident = Identification::new();
+            ident.set_secretKeyName(tuf_opts.secret_key_name);
+            ident.set_signatures(RepeatedField::from_vec(build_signatures(&tuf_opts.signatures)?));
+
+            let mut req = TufRequest::new();
+            req.set_identification(ident);
+            req.set_metadata({
+                let mut file = File::open(tuf_opts.file)?;
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents)?;
+                contents
+            });
+
+            let reply = client.sign_tuf_metadata(&req)?;
+            // Neither field needs escaping, being hex.
+            let mut output = File::create(tuf_opts.output)?;
+            writeln!(
+                output,
+                r#"{{"keyid":"{}","sig":"{}"}}"#,
+                reply.get_keyid(),
+                reply.get_sig()
+            )?;
+            if let Some(key_output) = tuf_opts.key_output {
+                let mut output = File::create(key_output)?;
+                writeln!(output, "{}", reply.get_key())?;
+            }
+        }
+        FeroCommand::CosignPayload(cosign_opts) => {
+            let mut image = ContainerImage::new();
+            image.set_docker_reference(cosign_opts.docker_reference);
+            image.set_manifest_digest(cosign_opts.manifest_digest);
+
+            let mut file = File::create(cosign_opts.output)?;
+            file.write_all(&image.write_to_bytes()?)?;
+        }
+        FeroCommand::Cosign(cosign_opts) => {
+            let mut ident = Identification::new();
+            ident.set_secretKeyName(cosign_opts.secret_key_name);
+            ident.set_signatures(
+                RepeatedField::from_vec(build_signatures(&cosign_opts.signatures)?),
+            );
+
+            let mut req = CosignRequest::new();
+            req.set_identification(ident);
+            match (cosign_opts.file, cosign_opts.docker_reference, cosign_opts.manifest_digest) {
+                (Some(file), None, None) => req.set_blob({
+                    let mut file = File::open(file)?;
+                    let mut contents = Vec::new();
+                    file.read_to_end(&mut contents)?;
+                    contents
+                }),
+                (None, Some(docker_reference), Some(manifest_digest)) => {
+                    let mut image = ContainerImage::new();
+                    image.set_docker_reference(docker_reference);
+                    image.set_manifest_digest(manifest_digest);
+                    req.set_image(image);
+                }
+                _ => bail!("Either a file or both an image and a digest must be given"),
+            }
+
+            let reply = client.sign_cosign(&req)?;
+            let mut output = File::create(cosign_opts.output)?;
+            writeln!(output, "{}", reply.get_signature())?;
+            if let Some(bundle) = cosign_opts.bundle {
+                let mut output = File::create(bundle)?;
+                writeln!(output, "{}", reply.get_bundle())?;
+            }
+            if let Some(payload) = cosign_opts.payload {
+                let mut output = File::create(payload)?;
+                output.write_all(reply.get_payload())?;
+            }
+        }
+        FeroCommand::Release(release_opts) => {
+            let mut ident = Identification::new();
+            ident.set_secretKeyName(release_opts.secret_key_name);
+            ident.set_signatures(
+                RepeatedField::from_vec(build_signatures(&release_opts.signatures)?),
+            );
+
+            let mut req = ReleaseRequest::new();
+            req.set_identification(ident);
+            req.set_release({
+                let mut file = File::open(release_opts.file)?;
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents)?;
+                contents
+            });
+
+            let reply = client.sign_release(&req)?;
+            let mut output = File::create(release_opts.release_gpg)?;
+            output.write_all(reply.get_release_gpg().as_bytes())?;
+            let mut output = File::create(release_opts.in_release)?;
+            output.write_all(reply.get_in_release().as_bytes())?;
+        }
+        FeroCommand::RpmPayload(rpm_opts) => {
+            let mut package = Vec::new();
+            File::open(rpm_opts.file)?.read_to_end(&mut package)?;
+
+            let mut file = File::create(rpm_opts.output)?;
+            file.write_all(&rpm::approval_payload(rpm::header_blob(&package)?)?)?;
+        }
+        FeroCommand::Rpm(rpm_opts) => {
+            let mut ident = Identification::new();
+            ident.set_secretKeyName(rpm_opts.secret_key_name);
+            ident.set_signatures(RepeatedField::from_vec(build_signatures(&rpm_opts.signatures)?));
+
+            let mut package = Vec::new();
+            File::open(rpm_opts.file)?.read_to_end(&mut package)?;
+            let package = Package::parse(&package)?;
+
+            // Only the header is signed, so there's no need to send the whole package.
+            let mut req = RpmRequest::new();
+            req.set_identification(ident);
+            req.set_package(package.header().to_vec());
+
+            let reply = client.sign_rpm(&req)?;
+            let mut output = File::create(rpm_opts.output)?;
+            output.write_all(&package.with_signature(rpm::SIGTAG_RSA, reply.get_signature()))?;
+        }
+        FeroCommand::Timestamp(timestamp_opts) => {
+            let mut req = TimestampRequest::new();
+            req.set_request({
+                let mut file = File::open(timestamp_opts.query)?;
+                let mut contents = Vec::new();
+                file.read_to_end(&mut contents)?;
+                contents
+            });
+
+            let reply = client.timestamp(&req)?;
+            let mut output = File::create(timestamp_opts.output)?;
+            output.write_all(reply.get_response())?;
+        }
         FeroCommand::GetLogs(log_opts) => {
-            let mut req = LogRequest::new();
+            let mut req = LogStreamRequest::new();
             req.set_minIndex(log_opts.since);
 
-            let reply = client.get_logs(&req)?;
+            let mut reply = LogResponse::new();
+            reply.set_logs(RepeatedField::from_vec(stream_logs(&client, &req)?));
             for log in reply.get_logs() {
                 println!("{}", log);
             }
+            if let Some(output) = log_opts.output {
+                File::create(output)?.write_all(&reply.write_to_bytes()?)?;
+            }
 
             let logs = reply.get_logs().into_iter().map(FeroLogEntry::from).collect::<Vec<_>>();
             match FeroLogEntry::verify(&logs) {
@@ -262,8 +1288,318 @@ pub fn main() -> Result<(), Error> {
                 }
                 Err(e) => error!("Log verification failed!\nDetails: {}", e),
             }
+
+            let mut req = CheckpointRequest::new();
+            req.set_minIndex(log_opts.since);
+
+            let checkpoint_reply = client.get_checkpoints(&req)?;
+            let public_key =
+                audit_public_key(log_opts.audit_key, checkpoint_reply.get_public_key())?;
+
+            let mut checkpoints_ok = true;
+            for proto_checkpoint in checkpoint_reply.get_checkpoints() {
+                println!("{}", proto_checkpoint);
+
+                let checkpoint = Checkpoint::from(proto_checkpoint);
+                if let Err(e) = checkpoint.verify(&public_key) {
+                    error!("Checkpoint verification failed!\nDetails: {}", e);
+                    checkpoints_ok = false;
+                    continue;
+                }
+
+                let entry = reply.get_logs().iter().find(|log| log.id == checkpoint.log_id);
+                if let Some(entry) = entry {
+                    if entry.hash != checkpoint.log_hash {
+                        error!(
+                            "Log entry {} doesn't match its signed checkpoint!",
+                            checkpoint.log_id
+                        );
+                        checkpoints_ok = false;
+                    }
+                }
+
+                // The tree root can only be recomputed if we have the log from the start.
+                let size = checkpoint.log_id as usize;
+                let logs = reply.get_logs();
+                if !checkpoint.tree_root.is_empty()
+                    && logs.first().map(|log| log.id) == Some(1)
+                    && logs.len() >= size
+                {
+                    let leaves = logs[..size]
+                        .iter()
+                        .map(|log| merkle::leaf_hash(&log.hash))
+                        .collect::<Vec<_>>();
+                    if merkle::root(&leaves) != checkpoint.tree_root {
+                        error!(
+                            "The log's Merkle tree of size {} doesn't match its signed checkpoint!",
+                            size
+                        );
+                        checkpoints_ok = false;
+                    }
+                }
+            }
+            if checkpoints_ok {
+                info!(
+                    "Checkpoint verification OK ({} checkpoints).",
+                    checkpoint_reply.get_checkpoints().len()
+                );
+            }
+
+            if log_opts.follow {
+                let mut parent = reply.get_logs().last().cloned();
+
+                // Whenever the stream breaks, follow again from the last entry received.
+                loop {
+                    let mut req = LogStreamRequest::new();
+                    req.set_minIndex(parent.as_ref().map(|log| log.id).unwrap_or(log_opts.since));
+
+                    let result = client.follow_logs(&req).map_err(Error::from).and_then(|pages| {
+                        for page in pages.wait() {
+                            for log in page?.take_logs().into_vec() {
+                                println!("{}", log);
+                                if let Some(ref parent) = parent {
+                                    if FeroLogEntry::from(&log).hash(&parent.hash)? != log.hash {
+                                        error!(
+                                            "Log entry {} doesn't chain to entry {}!",
+                                            log.id, parent.id
+                                        );
+                                    }
+                                }
+                                parent = Some(log);
+                            }
+                        }
+                        Ok(())
+                    });
+                    match result {
+                        Ok(()) => warn!("The server stopped sending logs; following again."),
+                        Err(err) => warn!("Lost the log stream ({}); following again.", err),
+                    }
+                    thread::sleep(Duration::from_secs(FOLLOW_RETRY_SECS));
+                }
+            }
+        }
+        FeroCommand::SearchLogs(search_opts) => {
+            let mut req = LogStreamRequest::new();
+            req.set_minIndex(search_opts.since);
+            req.set_maxIndex(search_opts.until);
+            req.set_page_size(search_opts.page_size);
+            req.set_operation_types(search_opts.operation_types);
+            req.set_results(search_opts.results);
+            if let Some(secret_key_name) = search_opts.secret_key_name {
+                req.set_secret_name(secret_key_name);
+            }
+            if let Some(after_time) = search_opts.after_time {
+                let mut timestamp = Timestamp::new();
+                timestamp.set_seconds(after_time);
+                req.set_since(timestamp);
+            }
+            if let Some(before_time) = search_opts.before_time {
+                let mut timestamp = Timestamp::new();
+                timestamp.set_seconds(before_time);
+                req.set_until(timestamp);
+            }
+
+            let logs = stream_logs(&client, &req)?;
+            for log in &logs {
+                println!("{}", log);
+            }
+            info!("Found {} log entries.", logs.len());
+        }
+        FeroCommand::ExportLogs(export_opts) => {
+            let mut req = LogRequest::new();
+            req.set_minIndex(export_opts.since);
+
+            let records = client
+                .export_logs(&req)?
+                .wait()
+                .collect::<Result<Vec<_>, _>>()?;
+            let export = LogExport::from_records(records)?;
+            export.verify()?;
+            info!(
+                "Exported {} log entries, up to entry {}.",
+                export.header.get_entry_count(),
+                export.header.get_last_id()
+            );
+
+            export.write_to(&mut File::create(export_opts.output)?)?;
+        }
+        FeroCommand::ShowExport(show_opts) => {
+            let export = LogExport::read_from(&mut File::open(show_opts.file)?)?;
+
+            println!("{}", export.header);
+            for entry in &export.entries {
+                println!("{}", entry);
+            }
+
+            export.verify()?;
+            info!("Log export verification OK.");
+
+            if export.header.has_checkpoint() {
+                let checkpoint = Checkpoint::from(export.header.get_checkpoint());
+                match show_opts.audit_key {
+                    Some(audit_key) => {
+                        checkpoint.verify(&read_public_key(audit_key)?)?;
+                        info!("Checkpoint of log entry {} OK.", checkpoint.log_id);
+                    }
+                    None => warn!("No audit key given; the checkpoint's signature is unchecked."),
+                }
+            } else {
+                warn!("Log export has no signed checkpoint.");
+            }
+        }
+        FeroCommand::VerifyLogs(verify_opts) => {
+            let logs = read_logs(verify_opts.file)?;
+
+            let keys = verify_opts.keys.into_iter().collect::<HashMap<_, _>>();
+            let report = audit::audit_logs(&logs, &keys);
+
+            println!("entries: {}", report.entries);
+            println!("hsm_logs: {}", report.hsm_logs);
+            if let Some(anchor) = report.hsm_anchor {
+                println!("hsm_anchor: {}", anchor);
+            }
+            for finding in &report.findings {
+                println!("finding: {}", finding);
+            }
+
+            if !report.findings.is_empty() {
+                bail!("Log verification found {} problems", report.findings.len());
+            }
+            info!("Log verification OK.");
+        }
+        FeroCommand::ProveInclusion(proof_opts) => {
+            let mut req = InclusionProofRequest::new();
+            req.set_log_id(proof_opts.id);
+            req.set_tree_size(proof_opts.tree_size);
+
+            let reply = client.get_inclusion_proof(&req)?;
+            println!("{}", reply.get_entry());
+            println!("tree_size: {}", reply.get_tree_size());
+            println!("tree_root: {}", hex::encode(reply.get_tree_root()));
+
+            merkle::verify_inclusion(
+                &merkle::leaf_hash(&reply.get_entry().hash),
+                proof_opts.id as u64 - 1,
+                reply.get_tree_size(),
+                reply.get_proof(),
+                reply.get_tree_root(),
+            )?;
+            info!("Inclusion proof OK.");
+
+            check_signed_tree_root(
+                &client,
+                reply.get_tree_size(),
+                reply.get_tree_root(),
+                proof_opts.audit_key,
+            )?;
+        }
+        FeroCommand::ProveConsistency(proof_opts) => {
+            let mut req = ConsistencyProofRequest::new();
+            req.set_first(proof_opts.first);
+            req.set_second(proof_opts.second);
+
+            let reply = client.get_consistency_proof(&req)?;
+            println!("first: {} {}", reply.get_first(), hex::encode(reply.get_first_root()));
+            println!("second: {} {}", reply.get_second(), hex::encode(reply.get_second_root()));
+
+            merkle::verify_consistency(
+                reply.get_first(),
+                reply.get_second(),
+                reply.get_first_root(),
+                reply.get_second_root(),
+                reply.get_proof(),
+            )?;
+            info!("Consistency proof OK.");
+
+            for &(size, root) in &[
+                (reply.get_first(), reply.get_first_root()),
+                (reply.get_second(), reply.get_second_root()),
+            ] {
+                check_signed_tree_root(&client, size, root, proof_opts.audit_key.clone())?;
+            }
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpg_command(args: &[&str]) -> GpgCommand {
+        let mut argv = vec!["fero-client", "-a", "bastion", "gpg", "-k", "mysecret"];
+        argv.extend_from_slice(args);
+        match Opt::from_iter_safe(argv).unwrap().command {
+            FeroCommand::Gpg(gpg_opts) => gpg_opts,
+            _ => panic!("Not parsed as the gpg command"),
+        }
+    }
+
+    #[test]
+    fn git_signing_arguments() {
+        // What git 2.39 passes to gpg.program for `git commit -S` and `git tag -s`.
+        let gpg_opts = gpg_command(&[
+            "--payload-output",
+            "/tmp/git-payload",
+            "--signature",
+            "git-payload.sig.1",
+            "--signature",
+            "git-payload.sig.2",
+            "--status-fd=2",
+            "-bsau",
+            "ABCDEF",
+        ]);
+        assert_eq!(
+            gpg_opts.signatures,
+            vec![PathBuf::from("git-payload.sig.1"), PathBuf::from("git-payload.sig.2")]
+        );
+        assert_eq!(gpg_opts.payload_output, Some(PathBuf::from("/tmp/git-payload")));
+        assert_eq!(gpg_opts.gpg_args, vec!["--status-fd=2", "-bsau", "ABCDEF"]);
+        assert!(gpg_signing(&gpg_opts.gpg_args));
+        assert_eq!(gpg_option(&gpg_opts.gpg_args, &["-bsau", "-u"]), Some("ABCDEF"));
+        assert_eq!(gpg_option(&gpg_opts.gpg_args, &["--status-fd"]), Some("2"));
+
+        // A wrapper script may also separate git's arguments.
+        let gpg_opts = gpg_command(&["--", "--status-fd=2", "-bsau", "ABCDEF"]);
+        assert_eq!(gpg_opts.gpg_args, vec!["--status-fd=2", "-bsau", "ABCDEF"]);
+    }
+
+    #[test]
+    fn git_verification_arguments() {
+        let gpg_opts = gpg_command(&[
+            "--keyid-format=long",
+            "--status-fd=1",
+            "--verify",
+            "/tmp/.git_vtag_tmpfTQ4bC",
+            "-",
+        ]);
+        assert!(gpg_opts.signatures.is_empty());
+        assert_eq!(gpg_opts.gpg_args.len(), 5);
+        assert!(!gpg_signing(&gpg_opts.gpg_args));
+    }
+
+    #[test]
+    fn git_dates() {
+        let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                       author A U Thor <a@example.com> 1792349139 +0000\n\
+                       committer C O Mitter <c@example.com> 1792349200 -0700\n\
+                       \n\
+                       committer 1 +0000 in the message\n";
+        assert_eq!(
+            git_date_variables(commit),
+            vec![
+                "GIT_AUTHOR_DATE=\"1792349139 +0000\"",
+                "GIT_COMMITTER_DATE=\"1792349200 -0700\"",
+            ]
+        );
+
+        let tag = b"object d6c0ad523e3b31ed4a72c624b8c415cba9621224\n\
+                    type commit\n\
+                    tag v1\n\
+                    tagger T Agger <t@example.com> 1792349139 +0100\n\
+                    \n\
+                    v1\n";
+        assert_eq!(git_date_variables(tag), vec!["GIT_COMMITTER_DATE=\"1792349139 +0100\""]);
+    }
+}
@@ -0,0 +1,37 @@
+use base64;
+
+// See RFC 4880 section 6 for the ASCII armor format.
+
+const CRC24_INIT: u32 = 0xb7_04ce;
+const CRC24_POLY: u32 = 0x186_4cfb;
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc = CRC24_INIT;
+    for byte in data {
+        crc ^= u32::from(*byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x100_0000 != 0 {
+                crc ^= CRC24_POLY;
+            }
+        }
+    }
+
+    crc & 0xff_ffff
+}
+
+/// ASCII-armor a binary OpenPGP signature packet, as `gpg --armor` would.
+pub fn armor_signature(packet: &[u8]) -> String {
+    let mut out = String::from("-----BEGIN PGP SIGNATURE-----\n\n");
+    for line in base64::encode(packet).as_bytes().chunks(64) {
+        out.push_str(&String::from_utf8_lossy(line));
+        out.push('\n');
+    }
+
+    let crc = crc24(packet);
+    out.push('=');
+    out.push_str(&base64::encode(&[(crc >> 16) as u8, (crc >> 8) as u8, crc as u8]));
+    out.push_str("\n-----END PGP SIGNATURE-----\n");
+
+    out
+}
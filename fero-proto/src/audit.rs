@@ -0,0 +1,623 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+use failure::Error;
+use sha2::{Digest, Sha256};
+
+use fero::{LogEntry, LogEntry_OperationResult, LogEntry_OperationType};
+use log::{FeroLogEntry, HsmLogEntry};
+
+// Offline verification of an audit log, linking fero's own entries to the YubiHSM's log. See
+// Yubico's documentation of the YubiHSM 2 logs for how their digests are chained.
+
+const HSM_DIGEST_LENGTH: usize = 16;
+const HSM_COMMAND_SIGN_PKCS1: u8 = 0x47;
+const HSM_COMMAND_SIGN_EDDSA: u8 = 0x6a;
+const SIGN_RSA: &[u8] = &[HSM_COMMAND_SIGN_PKCS1];
+const SIGN_EDDSA: &[u8] = &[HSM_COMMAND_SIGN_EDDSA];
+const SIGN_ANY: &[u8] = &[HSM_COMMAND_SIGN_PKCS1, HSM_COMMAND_SIGN_EDDSA];
+/// The HSM responds to a successful command with the command's code with the high bit set.
+const HSM_RESPONSE_SUCCESS: u8 = 0x80;
+
+/// A single gap or inconsistency found in an audit log.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Finding {
+    /// The log doesn't start with the root entry, so the first entry can't be checked.
+    MissingRoot { first_id: i32 },
+    /// Log entry IDs aren't consecutive.
+    IdGap { expected: i32, found: i32 },
+    /// An entry's hash doesn't follow from its contents and its parent's hash.
+    BadHash { id: i32 },
+    /// HSM log indices aren't consecutive.
+    HsmIndexGap { id: i32, expected: u16, found: u16 },
+    /// An HSM log entry's digest doesn't follow from its contents and the previous digest.
+    BadHsmDigest { id: i32, hsm_index: u16 },
+    /// A successful signing operation has no successful signing command in the HSM's log.
+    MissingSignCommand { id: i32 },
+    /// An operation has a different number of signing commands than its signature takes.
+    SignCommandCount { id: i32, expected: usize, found: usize },
+    /// An operation signed with an HSM command that its kind of signature doesn't use.
+    UnexpectedSignCommand { id: i32, command: u8 },
+    /// A signing operation used an HSM key other than the secret's.
+    WrongSigningKey { id: i32, secret_name: String, expected: u16, found: Vec<u16> },
+    /// A signing operation used more than one HSM key, and the secret's key isn't known.
+    MixedSigningKeys { id: i32, secret_name: String, found: Vec<u16> },
+    /// HSM activity which can't be attributed to a secret: signing commands in an entry which
+    /// doesn't name its secret, or an entry recording activity found after an unclean shutdown.
+    Unattributed { id: i32 },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Finding::MissingRoot { first_id } => write!(
+                f,
+                "log starts at entry {} rather than the root, so it can't be checked",
+                first_id
+            ),
+            Finding::IdGap { expected, found } => {
+                write!(f, "entry {}: expected entry {} first", found, expected)
+            }
+            Finding::BadHash { id } => write!(f, "entry {}: hash doesn't match", id),
+            Finding::HsmIndexGap { id, expected, found } => write!(
+                f,
+                "entry {}: HSM log skips from index {} to {}",
+                id, expected, found
+            ),
+            Finding::BadHsmDigest { id, hsm_index } => write!(
+                f,
+                "entry {}: HSM log {} has a bad digest",
+                id, hsm_index
+            ),
+            Finding::MissingSignCommand { id } => write!(
+                f,
+                "entry {}: successful signing operation with no signing command in the HSM log",
+                id
+            ),
+            Finding::SignCommandCount { id, expected, found } => write!(
+                f,
+                "entry {}: expected {} signing commands in the HSM log, but found {}",
+                id, expected, found
+            ),
+            Finding::UnexpectedSignCommand { id, command } => write!(
+                f,
+                "entry {}: signed with HSM command {:#04x}, which its signature doesn't use",
+                id, command
+            ),
+            Finding::WrongSigningKey { id, ref secret_name, expected, ref found } => write!(
+                f,
+                "entry {}: secret {} should sign with HSM key {}, but the HSM signed with {:?}",
+                id, secret_name, expected, found
+            ),
+            Finding::MixedSigningKeys { id, ref secret_name, ref found } => write!(
+                f,
+                "entry {}: secret {} signed with more than one HSM key: {:?}",
+                id, secret_name, found
+            ),
+            Finding::Unattributed { id } => {
+                write!(f, "entry {}: HSM activity can't be attributed to a secret", id)
+            }
+        }
+    }
+}
+
+/// The result of checking an audit log.
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub entries: usize,
+    pub hsm_logs: usize,
+    /// The HSM log index that the digest chain is anchored at, which can't itself be checked.
+    pub hsm_anchor: Option<u16>,
+    pub findings: Vec<Finding>,
+}
+
+fn hsm_digest(log: &HsmLogEntry, previous: &HsmLogEntry) -> Result<Vec<u8>, Error> {
+    let bytes = log.to_bytes()?;
+
+    let mut hasher = Sha256::default();
+    hasher.input(&bytes[..bytes.len() - log.hash.len()]);
+    hasher.input(&previous.hash);
+    Ok(hasher.result()[..HSM_DIGEST_LENGTH].to_vec())
+}
+
+fn secret_name(entry: &LogEntry) -> Option<&str> {
+    let secret_name = if entry.has_signature() {
+        entry.get_signature().get_secret_name()
+    } else if entry.has_ident() {
+        entry.get_ident().get_secretKeyName()
+    } else {
+        ""
+    };
+
+    if secret_name.is_empty() {
+        None
+    } else {
+        Some(secret_name)
+    }
+}
+
+/// The HSM signing commands a successful operation takes: which commands it may use, and how
+/// many. Signatures take a single PKCS#1 v1.5 signing command unless noted otherwise.
+fn signing_commands(entry: &LogEntry) -> (&'static [u8], usize) {
+    match entry.operation_type {
+        LogEntry_OperationType::SIGN => {
+            match entry.get_signature().get_signature_type() {
+                // The signature over the file, and the global signature over it and the trusted
+                // comment.
+                "MINISIGN" => (SIGN_EDDSA, 2),
+                "JWS" => (SIGN_ANY, 1),
+                // The detached Release.gpg and the cleartext InRelease.
+                "RELEASE" => (SIGN_RSA, 2),
+                _ => (SIGN_RSA, 1),
+            }
+        }
+        LogEntry_OperationType::SSH_CERTIFY
+        | LogEntry_OperationType::ISSUE_CERTIFICATE
+        | LogEntry_OperationType::GENERATE_CRL
+        | LogEntry_OperationType::TIMESTAMP
+        | LogEntry_OperationType::CHECKPOINT => (SIGN_RSA, 1),
+        _ => (&[], 0),
+    }
+}
+
+/// Check `entries`, which must be in order, for every gap or inconsistency: breaks in the hash
+/// chain and in the HSM's digest chain, missing HSM log entries, HSM activity which can't be
+/// attributed, and operations whose signing commands in the HSM's log don't match what they
+/// signed. `keys` maps secret names to their HSM key IDs; secrets which aren't in it are
+/// expected to always use whichever key they're first seen using.
+pub fn audit_logs(entries: &[LogEntry], keys: &HashMap<String, u16>) -> Report {
+    let mut report = Report {
+        entries: entries.len(),
+        hsm_logs: 0,
+        hsm_anchor: None,
+        findings: Vec::new(),
+    };
+    let mut keys = keys.clone();
+    let mut previous_hsm_log: Option<HsmLogEntry> = None;
+    // Successful signing commands, as (command, key) pairs, not yet matched to an operation.
+    let mut unmatched = Vec::new();
+
+    match entries.first() {
+        Some(entry) if entry.id != 1 => {
+            report.findings.push(Finding::MissingRoot { first_id: entry.id });
+        }
+        _ => {}
+    }
+
+    for (index, entry) in entries.iter().enumerate() {
+        if index > 0 {
+            let parent = &entries[index - 1];
+            if entry.id != parent.id + 1 {
+                report.findings.push(Finding::IdGap { expected: parent.id + 1, found: entry.id });
+            }
+            match FeroLogEntry::from(entry).hash(&parent.hash) {
+                Ok(ref hash) if *hash == entry.hash => {}
+                _ => report.findings.push(Finding::BadHash { id: entry.id }),
+            }
+        }
+
+        for hsm_log in entry.get_hsm_logs().iter().map(HsmLogEntry::from) {
+            report.hsm_logs += 1;
+
+            match previous_hsm_log {
+                Some(ref previous) if hsm_log.hsm_index != previous.hsm_index.wrapping_add(1) => {
+                    report.findings.push(Finding::HsmIndexGap {
+                        id: entry.id,
+                        expected: previous.hsm_index.wrapping_add(1),
+                        found: hsm_log.hsm_index,
+                    });
+                }
+                Some(ref previous) => match hsm_digest(&hsm_log, previous) {
+                    Ok(ref digest) if *digest == hsm_log.hash => {}
+                    _ => report.findings.push(Finding::BadHsmDigest {
+                        id: entry.id,
+                        hsm_index: hsm_log.hsm_index,
+                    }),
+                },
+                None => report.hsm_anchor = Some(hsm_log.hsm_index),
+            }
+
+            if (hsm_log.command == HSM_COMMAND_SIGN_PKCS1
+                || hsm_log.command == HSM_COMMAND_SIGN_EDDSA)
+                && hsm_log.result == hsm_log.command | HSM_RESPONSE_SUCCESS
+            {
+                unmatched.push((hsm_log.command, hsm_log.target_key));
+            }
+
+            previous_hsm_log = Some(hsm_log);
+        }
+
+        match entry.operation_type {
+            // Revocations are logged ahead of the operation which made them, and committed with
+            // it. The first entry of a commit covers all of its HSM log entries, so the
+            // operation's signing commands are carried over to it.
+            LogEntry_OperationType::REVOKE => continue,
+            LogEntry_OperationType::UNATTRIBUTED => {
+                report.findings.push(Finding::Unattributed { id: entry.id });
+                unmatched.clear();
+                continue;
+            }
+            _ => {}
+        }
+        let signed = mem::replace(&mut unmatched, Vec::new());
+
+        let (commands, count) = signing_commands(entry);
+        let success = entry.result == LogEntry_OperationResult::SUCCESS;
+        if success && count > 0 && signed.is_empty() {
+            report.findings.push(Finding::MissingSignCommand { id: entry.id });
+        } else if (success && signed.len() != count) || signed.len() > count {
+            // A failed operation may have stopped short of signing, but can't have signed more.
+            report.findings.push(Finding::SignCommandCount {
+                id: entry.id,
+                expected: count,
+                found: signed.len(),
+            });
+        }
+        if count == 0 || signed.is_empty() {
+            continue;
+        }
+        for &(command, _) in &signed {
+            if !commands.contains(&command) {
+                report.findings.push(Finding::UnexpectedSignCommand { id: entry.id, command });
+            }
+        }
+
+        let mut signing_keys = signed.iter().map(|&(_, key)| key).collect::<Vec<_>>();
+        signing_keys.sort();
+        signing_keys.dedup();
+
+        let secret_name = match secret_name(entry) {
+            Some(secret_name) => secret_name,
+            None => {
+                report.findings.push(Finding::Unattributed { id: entry.id });
+                continue;
+            }
+        };
+        match keys.get(secret_name).cloned() {
+            Some(expected) => {
+                if signing_keys.iter().any(|&key| key != expected) {
+                    report.findings.push(Finding::WrongSigningKey {
+                        id: entry.id,
+                        secret_name: String::from(secret_name),
+                        expected,
+                        found: signing_keys,
+                    });
+                }
+            }
+            None if signing_keys.len() == 1 => {
+                keys.insert(String::from(secret_name), signing_keys[0]);
+            }
+            None => report.findings.push(Finding::MixedSigningKeys {
+                id: entry.id,
+                secret_name: String::from(secret_name),
+                found: signing_keys,
+            }),
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, ByteOrder};
+
+    use fero::{HsmLog, RevocationRecord, SignatureRecord};
+
+    use super::*;
+
+    const AUTHENTICATE_SESSION: u8 = 0x04;
+    const RSA_KEY: u16 = 0x0100;
+    const ED25519_KEY: u16 = 0x0200;
+
+    /// Builds a log, chaining fero's hashes and the HSM's digests as the server and HSM would.
+    struct Log {
+        entries: Vec<LogEntry>,
+        last_hsm_log: HsmLog,
+    }
+
+    impl Log {
+        /// A log whose root entry covers the HSM's log from `hsm_index`.
+        fn new(hsm_index: u16) -> Log {
+            let mut anchor = HsmLog::new();
+            anchor.set_id(u32::from(hsm_index));
+            anchor.set_command(u32::from(AUTHENTICATE_SESSION));
+            anchor.set_result(u32::from(AUTHENTICATE_SESSION | HSM_RESPONSE_SUCCESS));
+            anchor.set_hash(vec![0xaa; HSM_DIGEST_LENGTH]);
+
+            let mut root = LogEntry::new();
+            root.set_id(1);
+            root.set_operation_type(LogEntry_OperationType::ADD_SECRET);
+            root.mut_hsm_logs().push(anchor.clone());
+            root.set_hash(vec![0; 32]);
+
+            Log { entries: vec![root], last_hsm_log: anchor }
+        }
+
+        /// The HSM's next log entry, for `command` on `target_key`. The digest is the first 16
+        /// bytes of SHA-256 over the entry's fields and the previous entry's digest.
+        fn hsm_log(&mut self, command: u8, target_key: u16) -> HsmLog {
+            let index = self.last_hsm_log.get_id() as u16 + 1;
+            let result = command | HSM_RESPONSE_SUCCESS;
+
+            let mut fields = [0; 16];
+            BigEndian::write_u16(&mut fields[0..2], index);
+            fields[2] = command;
+            BigEndian::write_u16(&mut fields[3..5], 51);
+            BigEndian::write_u16(&mut fields[5..7], 2);
+            BigEndian::write_u16(&mut fields[7..9], target_key);
+            fields[11] = result;
+            BigEndian::write_u32(&mut fields[12..16], u32::from(index) * 1000);
+            let mut hasher = Sha256::default();
+            hasher.input(&fields);
+            hasher.input(self.last_hsm_log.get_hash());
+
+            let mut log = HsmLog::new();
+            log.set_id(u32::from(index));
+            log.set_command(u32::from(command));
+            log.set_data_length(51);
+            log.set_session_key(2);
+            log.set_target_key(u32::from(target_key));
+            log.set_result(u32::from(result));
+            log.set_systick(u32::from(index) * 1000);
+            log.set_hash(hasher.result()[..HSM_DIGEST_LENGTH].to_vec());
+
+            self.last_hsm_log = log.clone();
+            log
+        }
+
+        /// Append an entry covering `hsm_logs`, returning it for any changes before `seal`.
+        fn push(
+            &mut self,
+            operation_type: LogEntry_OperationType,
+            result: LogEntry_OperationResult,
+            hsm_logs: Vec<HsmLog>,
+        ) -> &mut LogEntry {
+            let mut entry = LogEntry::new();
+            entry.set_id(self.entries.len() as i32 + 1);
+            entry.set_operation_type(operation_type);
+            entry.set_result(result);
+            for hsm_log in hsm_logs {
+                entry.mut_hsm_logs().push(hsm_log);
+            }
+
+            self.entries.push(entry);
+            self.entries.last_mut().unwrap()
+        }
+
+        /// A successful operation signed by `secret_name` with `signature_type`, whose signing
+        /// commands are `commands`, as (command, key) pairs.
+        fn sign(
+            &mut self,
+            operation_type: LogEntry_OperationType,
+            secret_name: &str,
+            signature_type: &str,
+            commands: &[(u8, u16)],
+        ) {
+            let hsm_logs = commands
+                .iter()
+                .map(|&(command, key)| self.hsm_log(command, key))
+                .collect();
+
+            let mut signature = SignatureRecord::new();
+            signature.set_secret_name(String::from(secret_name));
+            signature.set_signature_type(String::from(signature_type));
+            self.push(operation_type, LogEntry_OperationResult::SUCCESS, hsm_logs)
+                .set_signature(signature);
+        }
+
+        /// Chain every entry's hash to its parent's, after any changes made to them.
+        fn seal(mut self) -> Vec<LogEntry> {
+            for index in 1..self.entries.len() {
+                let hash = FeroLogEntry::from(&self.entries[index])
+                    .hash(&self.entries[index - 1].hash)
+                    .unwrap();
+                self.entries[index].set_hash(hash);
+            }
+            self.entries
+        }
+    }
+
+    fn rsa(key: u16) -> (u8, u16) {
+        (HSM_COMMAND_SIGN_PKCS1, key)
+    }
+
+    fn eddsa(key: u16) -> (u8, u16) {
+        (HSM_COMMAND_SIGN_EDDSA, key)
+    }
+
+    fn audit(entries: &[LogEntry]) -> Vec<Finding> {
+        audit_logs(entries, &HashMap::new()).findings
+    }
+
+    /// A log with every kind of signing operation, each signed as the server would.
+    fn signed_log() -> Log {
+        let mut log = Log::new(10);
+        for signature_type in &["PGP", "CMS", "AUTHENTICODE", "KERNEL_MODULE", "TUF", "COSIGN"] {
+            log.sign(LogEntry_OperationType::SIGN, "release", signature_type, &[rsa(RSA_KEY)]);
+        }
+        log.sign(LogEntry_OperationType::SIGN, "release", "RPM", &[rsa(RSA_KEY)]);
+        log.sign(LogEntry_OperationType::SIGN, "release", "RELEASE", &[rsa(RSA_KEY); 2]);
+        log.sign(LogEntry_OperationType::SIGN, "release", "JWS", &[rsa(RSA_KEY)]);
+        log.sign(LogEntry_OperationType::SIGN, "edge", "JWS", &[eddsa(ED25519_KEY)]);
+        log.sign(LogEntry_OperationType::SIGN, "edge", "MINISIGN", &[eddsa(ED25519_KEY); 2]);
+        log.sign(LogEntry_OperationType::TIMESTAMP, "tsa", "TIMESTAMP", &[rsa(0x0300)]);
+        log.sign(LogEntry_OperationType::CHECKPOINT, "audit", "CHECKPOINT", &[rsa(0x0400)]);
+        log
+    }
+
+    #[test]
+    fn consistent_log() {
+        let entries = signed_log().seal();
+        let report = audit_logs(&entries, &HashMap::new());
+        assert_eq!(report.findings, vec![]);
+        assert_eq!(report.entries, entries.len());
+        assert_eq!(report.hsm_logs, 16);
+        assert_eq!(report.hsm_anchor, Some(10));
+
+        let keys = vec![(String::from("release"), RSA_KEY), (String::from("edge"), ED25519_KEY)];
+        assert_eq!(audit_logs(&entries, &keys.into_iter().collect()).findings, vec![]);
+    }
+
+    #[test]
+    fn tampered_hsm_digest() {
+        let mut log = signed_log();
+        log.sign(LogEntry_OperationType::SIGN, "release", "PGP", &[rsa(RSA_KEY)]);
+        log.entries.last_mut().unwrap().mut_hsm_logs()[0].set_systick(1);
+        let entries = log.seal();
+        let id = entries.len() as i32;
+
+        assert_eq!(audit(&entries), vec![Finding::BadHsmDigest { id, hsm_index: 26 }]);
+
+        // Tampering with the fero entry's contents breaks its hash as well.
+        let mut entries = entries;
+        entries[3].mut_signature().set_secret_name(String::from("other"));
+        assert_eq!(
+            audit(&entries),
+            vec![Finding::BadHash { id: 4 }, Finding::BadHsmDigest { id, hsm_index: 26 }]
+        );
+    }
+
+    #[test]
+    fn gaps() {
+        let mut log = signed_log();
+        // An HSM log entry which was never stored.
+        log.hsm_log(HSM_COMMAND_SIGN_PKCS1, RSA_KEY);
+        log.sign(LogEntry_OperationType::SIGN, "release", "PGP", &[rsa(RSA_KEY)]);
+        let entries = log.seal();
+        let id = entries.len() as i32;
+        assert_eq!(
+            audit(&entries),
+            vec![Finding::HsmIndexGap { id, expected: 26, found: 27 }]
+        );
+
+        // A missing fero entry.
+        let mut entries = signed_log().seal();
+        entries.remove(5);
+        let findings = audit(&entries);
+        assert_eq!(findings[0], Finding::IdGap { expected: 6, found: 7 });
+        assert_eq!(findings[1], Finding::BadHash { id: 7 });
+        assert_eq!(findings[2], Finding::HsmIndexGap { id: 7, expected: 15, found: 16 });
+
+        let entries = signed_log().seal();
+        assert_eq!(audit(&entries[1..]), vec![Finding::MissingRoot { first_id: 2 }]);
+    }
+
+    #[test]
+    fn wrong_keys() {
+        let mut log = signed_log();
+        log.sign(LogEntry_OperationType::SIGN, "release", "PGP", &[rsa(RSA_KEY + 1)]);
+        let entries = log.seal();
+        let id = entries.len() as i32;
+        let wrong_key = Finding::WrongSigningKey {
+            id,
+            secret_name: String::from("release"),
+            expected: RSA_KEY,
+            found: vec![RSA_KEY + 1],
+        };
+        // Against the key the secret was first seen using, and against a known key.
+        assert_eq!(audit(&entries), vec![wrong_key.clone()]);
+        let keys = vec![(String::from("release"), RSA_KEY)].into_iter().collect();
+        assert_eq!(audit_logs(&entries, &keys).findings, vec![wrong_key]);
+
+        // Every signing command of an entry must use the secret's key.
+        let mut log = signed_log();
+        let commands = [rsa(RSA_KEY), rsa(RSA_KEY + 1)];
+        log.sign(LogEntry_OperationType::SIGN, "release", "RELEASE", &commands);
+        log.sign(LogEntry_OperationType::SIGN, "new", "RELEASE", &commands);
+        let entries = log.seal();
+        let id = entries.len() as i32;
+        assert_eq!(
+            audit(&entries),
+            vec![
+                Finding::WrongSigningKey {
+                    id: id - 1,
+                    secret_name: String::from("release"),
+                    expected: RSA_KEY,
+                    found: vec![RSA_KEY, RSA_KEY + 1],
+                },
+                Finding::MixedSigningKeys {
+                    id,
+                    secret_name: String::from("new"),
+                    found: vec![RSA_KEY, RSA_KEY + 1],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn signing_commands() {
+        let mut log = Log::new(10);
+        log.sign(LogEntry_OperationType::SIGN, "release", "PGP", &[]);
+        log.sign(LogEntry_OperationType::CHECKPOINT, "audit", "CHECKPOINT", &[]);
+        log.sign(LogEntry_OperationType::SIGN, "edge", "MINISIGN", &[eddsa(ED25519_KEY)]);
+        log.sign(LogEntry_OperationType::SIGN, "release", "RPM", &[rsa(RSA_KEY); 2]);
+        log.sign(LogEntry_OperationType::SIGN, "edge", "TUF", &[eddsa(ED25519_KEY)]);
+        // A failed operation can stop short of signing, but can't sign more.
+        let hsm_log = log.hsm_log(HSM_COMMAND_SIGN_PKCS1, RSA_KEY);
+        log.push(LogEntry_OperationType::SIGN, LogEntry_OperationResult::FAILURE, vec![]);
+        log.push(LogEntry_OperationType::THRESHOLD, LogEntry_OperationResult::FAILURE, vec![
+            hsm_log,
+        ]);
+        let entries = log.seal();
+
+        assert_eq!(
+            audit(&entries),
+            vec![
+                Finding::MissingSignCommand { id: 2 },
+                Finding::MissingSignCommand { id: 3 },
+                Finding::SignCommandCount { id: 4, expected: 2, found: 1 },
+                Finding::SignCommandCount { id: 5, expected: 1, found: 2 },
+                Finding::UnexpectedSignCommand { id: 6, command: HSM_COMMAND_SIGN_EDDSA },
+                Finding::SignCommandCount { id: 8, expected: 0, found: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn revocations() {
+        // The entries for the certificates a CRL revokes come first, and the first covers the
+        // CRL's signing command.
+        let revocation = |serial| {
+            let mut revocation = RevocationRecord::new();
+            revocation.set_serial(vec![serial]);
+            revocation
+        };
+        let mut log = Log::new(10);
+        let hsm_log = log.hsm_log(HSM_COMMAND_SIGN_PKCS1, RSA_KEY);
+        log.push(LogEntry_OperationType::REVOKE, LogEntry_OperationResult::SUCCESS, vec![hsm_log])
+            .set_revocation(revocation(1));
+        log.push(LogEntry_OperationType::REVOKE, LogEntry_OperationResult::SUCCESS, vec![])
+            .set_revocation(revocation(2));
+        log.push(LogEntry_OperationType::GENERATE_CRL, LogEntry_OperationResult::SUCCESS, vec![])
+            .mut_ident()
+            .set_secretKeyName(String::from("ca"));
+        let entries = log.seal();
+        assert_eq!(audit(&entries), vec![]);
+
+        let mut keys = HashMap::new();
+        keys.insert(String::from("ca"), RSA_KEY + 1);
+        assert_eq!(audit_logs(&entries, &keys).findings.len(), 1);
+    }
+
+    #[test]
+    fn unattributed() {
+        let mut log = Log::new(10);
+        let hsm_log = log.hsm_log(HSM_COMMAND_SIGN_PKCS1, RSA_KEY);
+        log.push(LogEntry_OperationType::UNATTRIBUTED, LogEntry_OperationResult::FAILURE, vec![
+            hsm_log,
+        ]);
+        // A timestamp from before timestamps recorded which secret signed them.
+        let hsm_log = log.hsm_log(HSM_COMMAND_SIGN_PKCS1, RSA_KEY);
+        log.push(LogEntry_OperationType::TIMESTAMP, LogEntry_OperationResult::SUCCESS, vec![
+            hsm_log,
+        ]);
+        let entries = log.seal();
+
+        assert_eq!(
+            audit(&entries),
+            vec![Finding::Unattributed { id: 2 }, Finding::Unattributed { id: 3 }]
+        );
+    }
+}
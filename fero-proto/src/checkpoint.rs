@@ -0,0 +1,175 @@
+use std::ops::Deref;
+
+use byteorder::{BigEndian, WriteBytesExt};
+use chrono::NaiveDateTime;
+use failure::Error;
+use protobuf::well_known_types::Timestamp;
+use sha2::{Digest, Sha256};
+
+use fero;
+use rsa;
+
+const CHECKPOINT_CONTEXT: &[u8] = b"fero audit log checkpoint\n";
+
+/// A signed statement of the audit log's head, which lets anyone who has seen it detect a later
+/// rewrite of the log.
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    pub log_id: i32,
+    pub log_hash: Vec<u8>,
+    pub hsm_index: u16,
+    pub hsm_hash: Vec<u8>,
+    pub timestamp: NaiveDateTime,
+    pub signature: Vec<u8>,
+    /// The root of the log's Merkle tree of size `log_id`, which older checkpoints lack.
+    pub tree_root: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// The bytes which the audit secret signs. Variable-length fields are prefixed with their
+    /// length, so that the encoding is unambiguous.
+    pub fn signed_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = CHECKPOINT_CONTEXT.to_vec();
+
+        out.write_i32::<BigEndian>(self.log_id)?;
+        out.write_u32::<BigEndian>(self.log_hash.len() as u32)?;
+        out.extend_from_slice(&self.log_hash);
+        out.write_u16::<BigEndian>(self.hsm_index)?;
+        out.write_u32::<BigEndian>(self.hsm_hash.len() as u32)?;
+        out.extend_from_slice(&self.hsm_hash);
+        out.write_i64::<BigEndian>(self.timestamp.timestamp())?;
+        if !self.tree_root.is_empty() {
+            out.write_u32::<BigEndian>(self.tree_root.len() as u32)?;
+            out.extend_from_slice(&self.tree_root);
+        }
+
+        Ok(out)
+    }
+
+    /// The SHA-256 digest of `signed_bytes`, for the HSM to sign.
+    pub fn digest(&self) -> Result<Vec<u8>, Error> {
+        let mut hasher = Sha256::default();
+        hasher.input(&self.signed_bytes()?);
+        Ok(hasher.result().to_vec())
+    }
+
+    /// Check the checkpoint's signature against the audit secret's DER-encoded RSAPublicKey.
+    pub fn verify(&self, public_key: &[u8]) -> Result<(), Error> {
+        rsa::verify(public_key, rsa::SHA256_DIGEST_INFO_PREFIX, &self.digest()?, &self.signature)
+            .map_err(|e| {
+                format_err!("Checkpoint for log entry {} has a bad signature: {}", self.log_id, e)
+            })
+    }
+}
+
+impl<T> From<T> for Checkpoint
+where
+    T: Deref<Target = fero::Checkpoint>,
+{
+    fn from(checkpoint: T) -> Checkpoint {
+        Checkpoint {
+            log_id: checkpoint.log_id,
+            log_hash: checkpoint.log_hash.clone(),
+            hsm_index: checkpoint.hsm_index as u16,
+            hsm_hash: checkpoint.hsm_hash.clone(),
+            timestamp: NaiveDateTime::from_timestamp(
+                checkpoint.get_timestamp().get_seconds(),
+                checkpoint.get_timestamp().get_nanos() as u32,
+            ),
+            signature: checkpoint.signature.clone(),
+            tree_root: checkpoint.tree_root.clone(),
+        }
+    }
+}
+
+impl From<Checkpoint> for fero::Checkpoint {
+    fn from(checkpoint: Checkpoint) -> fero::Checkpoint {
+        let mut proto_checkpoint = fero::Checkpoint::new();
+
+        let mut timestamp = Timestamp::new();
+        timestamp.set_seconds(checkpoint.timestamp.timestamp());
+        timestamp.set_nanos(checkpoint.timestamp.timestamp_subsec_nanos() as i32);
+
+        proto_checkpoint.set_log_id(checkpoint.log_id);
+        proto_checkpoint.set_log_hash(checkpoint.log_hash);
+        proto_checkpoint.set_hsm_index(u32::from(checkpoint.hsm_index));
+        proto_checkpoint.set_hsm_hash(checkpoint.hsm_hash);
+        proto_checkpoint.set_timestamp(timestamp);
+        proto_checkpoint.set_signature(checkpoint.signature);
+        proto_checkpoint.set_tree_root(checkpoint.tree_root);
+
+        proto_checkpoint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The key in testdata/rsa, and its signature over the checkpoint below, made by
+    // `openssl dgst -sha256 -sign`.
+    const PUBLIC_KEY: &[u8] = include_bytes!("../testdata/rsa/public.der");
+    const SIGNATURE: &[u8] = include_bytes!("../testdata/checkpoint/checkpoint.sig");
+
+    fn checkpoint() -> Checkpoint {
+        Checkpoint {
+            log_id: 7,
+            log_hash: vec![0x11; 32],
+            hsm_index: 300,
+            hsm_hash: vec![0x22; 16],
+            timestamp: NaiveDateTime::from_timestamp(1_537_000_000, 0),
+            signature: SIGNATURE.to_vec(),
+            tree_root: vec![0x33; 32],
+        }
+    }
+
+    #[test]
+    fn signed_bytes() {
+        let mut expected = b"fero audit log checkpoint\n".to_vec();
+        expected.extend_from_slice(&[0, 0, 0, 7, 0, 0, 0, 32]);
+        expected.extend_from_slice(&[0x11; 32]);
+        expected.extend_from_slice(&[0x01, 0x2c, 0, 0, 0, 16]);
+        expected.extend_from_slice(&[0x22; 16]);
+        expected.extend_from_slice(&[0, 0, 0, 0, 0x5b, 0x9c, 0xc2, 0x40]);
+        let without_root = expected.clone();
+        expected.extend_from_slice(&[0, 0, 0, 32]);
+        expected.extend_from_slice(&[0x33; 32]);
+
+        assert_eq!(checkpoint().signed_bytes().unwrap(), expected);
+
+        // Checkpoints from before the log was a Merkle tree don't sign a root at all.
+        let legacy = Checkpoint { tree_root: Vec::new(), ..checkpoint() };
+        assert_eq!(legacy.signed_bytes().unwrap(), without_root);
+    }
+
+    #[test]
+    fn signatures() {
+        checkpoint().verify(PUBLIC_KEY).unwrap();
+
+        // Every signed field is covered.
+        assert!(Checkpoint { log_id: 8, ..checkpoint() }.verify(PUBLIC_KEY).is_err());
+        assert!(Checkpoint { hsm_index: 301, ..checkpoint() }.verify(PUBLIC_KEY).is_err());
+        assert!(Checkpoint { tree_root: vec![0x34; 32], ..checkpoint() }
+            .verify(PUBLIC_KEY)
+            .is_err());
+        let later = NaiveDateTime::from_timestamp(1_537_000_001, 0);
+        assert!(Checkpoint { timestamp: later, ..checkpoint() }.verify(PUBLIC_KEY).is_err());
+
+        let mut signature = SIGNATURE.to_vec();
+        signature[100] ^= 1;
+        assert!(Checkpoint { signature, ..checkpoint() }.verify(PUBLIC_KEY).is_err());
+        assert!(Checkpoint { signature: Vec::new(), ..checkpoint() }.verify(PUBLIC_KEY).is_err());
+    }
+
+    #[test]
+    fn protobuf_round_trip() {
+        let proto = fero::Checkpoint::from(checkpoint());
+        assert_eq!(proto.get_hsm_index(), 300);
+        assert_eq!(proto.get_timestamp().get_seconds(), 1_537_000_000);
+
+        let decoded = Checkpoint::from(&proto);
+        assert_eq!(decoded.signed_bytes().unwrap(), checkpoint().signed_bytes().unwrap());
+        assert_eq!(decoded.signature, SIGNATURE);
+        decoded.verify(PUBLIC_KEY).unwrap();
+    }
+}
@@ -0,0 +1,351 @@
+use std::io::{Read, Write};
+
+use chrono::NaiveDateTime;
+use failure::Error;
+use protobuf::{self, CodedInputStream, Message, well_known_types::Timestamp};
+
+use checkpoint::Checkpoint;
+use fero::{self, LogEntry, LogExportHeader, LogExportRecord};
+use log::FeroLogEntry;
+use merkle;
+
+// Audit logs exported for archiving outside of fero. An export is self-describing: a header
+// records which part of the log it covers and the hashes it should chain to, so it can be checked
+// without access to the server.
+
+pub const FORMAT: &str = "fero-log-export/1";
+
+#[derive(Clone, Debug)]
+pub struct LogExport {
+    pub header: LogExportHeader,
+    pub entries: Vec<LogEntry>,
+}
+
+impl LogExport {
+    /// Export `entries`, which must be consecutive. `parent_hash` is the hash of the entry before
+    /// the first, and `tree_root` the root of the log's Merkle tree up to the last.
+    pub fn new(
+        entries: Vec<LogEntry>,
+        parent_hash: Vec<u8>,
+        tree_root: Vec<u8>,
+        checkpoint: Option<fero::Checkpoint>,
+        exported: NaiveDateTime,
+    ) -> LogExport {
+        let mut header = LogExportHeader::new();
+
+        let mut timestamp = Timestamp::new();
+        timestamp.set_seconds(exported.timestamp());
+        timestamp.set_nanos(exported.timestamp_subsec_nanos() as i32);
+
+        header.set_format(String::from(FORMAT));
+        header.set_exported(timestamp);
+        header.set_first_id(entries.first().map(|entry| entry.id).unwrap_or(0));
+        header.set_last_id(entries.last().map(|entry| entry.id).unwrap_or(0));
+        header.set_entry_count(entries.len() as u64);
+        header.set_parent_hash(parent_hash);
+        header.set_head_hash(entries.last().map(|entry| entry.hash.clone()).unwrap_or_default());
+        header.set_tree_root(tree_root);
+        if let Some(checkpoint) = checkpoint {
+            header.set_checkpoint(checkpoint);
+        }
+
+        LogExport { header, entries }
+    }
+
+    /// The header's record, followed by each entry's.
+    pub fn into_records(self) -> Vec<LogExportRecord> {
+        let mut header = LogExportRecord::new();
+        header.set_header(self.header);
+
+        let mut records = vec![header];
+        for entry in self.entries {
+            let mut record = LogExportRecord::new();
+            record.set_entry(entry);
+            records.push(record);
+        }
+
+        records
+    }
+
+    pub fn from_records<I>(records: I) -> Result<LogExport, Error>
+    where
+        I: IntoIterator<Item = LogExportRecord>,
+    {
+        let mut records = records.into_iter();
+
+        let header = match records.next() {
+            Some(mut record) => {
+                if !record.has_header() {
+                    bail!("Log export doesn't start with a header");
+                }
+                record.take_header()
+            }
+            None => bail!("Log export is empty"),
+        };
+        if header.get_format() != FORMAT {
+            bail!("Unsupported log export format {:?}", header.get_format());
+        }
+
+        let entries = records
+            .map(|mut record| {
+                if !record.has_entry() {
+                    bail!("Log export has a header after the first record");
+                }
+                Ok(record.take_entry())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(LogExport { header, entries })
+    }
+
+    pub fn write_to(self, writer: &mut Write) -> Result<(), Error> {
+        for record in self.into_records() {
+            record.write_length_delimited_to_writer(writer)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut Read) -> Result<LogExport, Error> {
+        let mut input = CodedInputStream::new(reader);
+
+        // Each record is read in full before it's parsed, since a message cut short at a field
+        // boundary would otherwise parse as one missing the rest of its fields.
+        let mut records = Vec::new();
+        while !input.eof()? {
+            let length = input.read_raw_varint32()?;
+            let record = input.read_raw_bytes(length)?;
+            records.push(protobuf::parse_from_bytes::<LogExportRecord>(&record)?);
+        }
+
+        LogExport::from_records(records)
+    }
+
+    /// Check that the entries are complete and chain to the hashes in the header. The checkpoint
+    /// is only checked against the entries; its signature needs the audit secret's public key.
+    pub fn verify(&self) -> Result<(), Error> {
+        let header = &self.header;
+
+        if header.get_entry_count() != self.entries.len() as u64 {
+            bail!(
+                "Log export should have {} entries, but has {}",
+                header.get_entry_count(),
+                self.entries.len()
+            );
+        }
+        let (first, last) = match (self.entries.first(), self.entries.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ if header.get_first_id() != 0 || header.get_last_id() != 0 => {
+                bail!("Log export has no entries, but should cover some")
+            }
+            _ => return Ok(()),
+        };
+        if first.id != header.get_first_id() || last.id != header.get_last_id() {
+            bail!(
+                "Log export should cover entries {} to {}, but covers {} to {}",
+                header.get_first_id(),
+                header.get_last_id(),
+                first.id,
+                last.id
+            );
+        }
+
+        // The root entry has no parent to chain to.
+        let mut parent_hash = header.get_parent_hash();
+        for (index, entry) in self.entries.iter().enumerate() {
+            if entry.id != first.id + index as i32 {
+                bail!("Log export is missing entry {}", first.id + index as i32);
+            }
+            if entry.id != 1 && FeroLogEntry::from(entry).hash(parent_hash)? != entry.hash {
+                bail!("Log entry {} doesn't chain to its parent", entry.id);
+            }
+            parent_hash = &entry.hash[..];
+        }
+        if last.hash != header.get_head_hash() {
+            bail!("Log export's last entry doesn't match its head hash");
+        }
+
+        // The Merkle tree can only be recomputed from the start of the log.
+        let leaves = self.entries
+            .iter()
+            .map(|entry| merkle::leaf_hash(&entry.hash))
+            .collect::<Vec<_>>();
+        if first.id == 1 && merkle::root(&leaves) != header.get_tree_root() {
+            bail!("Log export's entries don't match its tree root");
+        }
+
+        if header.has_checkpoint() {
+            let checkpoint = Checkpoint::from(header.get_checkpoint());
+            if checkpoint.log_id > last.id {
+                bail!("Log export's checkpoint is of a later entry than its last");
+            }
+            if checkpoint.log_id >= first.id {
+                let entry = &self.entries[(checkpoint.log_id - first.id) as usize];
+                if entry.hash != checkpoint.log_hash {
+                    bail!("Log entry {} doesn't match its checkpoint", checkpoint.log_id);
+                }
+                if first.id == 1
+                    && !checkpoint.tree_root.is_empty()
+                    && merkle::root(&leaves[..checkpoint.log_id as usize]) != checkpoint.tree_root
+                {
+                    bail!("Log export's entries don't match its checkpoint's tree root");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: i32 = 8;
+
+    /// A log of `SIZE` chained entries.
+    fn entries() -> Vec<LogEntry> {
+        let mut entries: Vec<LogEntry> = Vec::new();
+        for id in 1..SIZE + 1 {
+            let mut entry = LogEntry::new();
+            entry.set_id(id);
+            entry.set_operation_type(fero::LogEntry_OperationType::SIGN);
+            entry.mut_timestamp().set_seconds(1_537_000_000 + i64::from(id));
+
+            let hash = match entries.last() {
+                Some(parent) => FeroLogEntry::from(&entry).hash(&parent.hash).unwrap(),
+                None => vec![0xee; 32],
+            };
+            entry.set_hash(hash);
+            entries.push(entry);
+        }
+        entries
+    }
+
+    fn leaves(entries: &[LogEntry]) -> Vec<Vec<u8>> {
+        entries.iter().map(|entry| merkle::leaf_hash(&entry.hash)).collect()
+    }
+
+    fn checkpoint(entries: &[LogEntry], log_id: i32) -> fero::Checkpoint {
+        let mut checkpoint = fero::Checkpoint::new();
+        checkpoint.set_log_id(log_id);
+        checkpoint.set_log_hash(entries[log_id as usize - 1].hash.clone());
+        checkpoint.set_tree_root(merkle::root(&leaves(&entries[..log_id as usize])));
+        checkpoint
+    }
+
+    /// An export of the whole log, with a checkpoint of entry 6.
+    fn export() -> LogExport {
+        let entries = entries();
+        LogExport::new(
+            entries.clone(),
+            Vec::new(),
+            merkle::root(&leaves(&entries)),
+            Some(checkpoint(&entries, 6)),
+            NaiveDateTime::from_timestamp(1_537_000_100, 0),
+        )
+    }
+
+    fn encode(export: LogExport) -> Vec<u8> {
+        let mut out = Vec::new();
+        export.write_to(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let export = export();
+        assert_eq!(export.header.get_first_id(), 1);
+        assert_eq!(export.header.get_last_id(), SIZE);
+        assert_eq!(export.header.get_entry_count(), SIZE as u64);
+        assert_eq!(export.header.get_head_hash(), &entries()[SIZE as usize - 1].hash[..]);
+        export.verify().unwrap();
+
+        let decoded = LogExport::read_from(&mut &encode(export.clone())[..]).unwrap();
+        assert_eq!(decoded.header, export.header);
+        assert_eq!(decoded.entries, export.entries);
+        decoded.verify().unwrap();
+
+        // An export can start partway through the log, chaining to the entry before it.
+        let entries = entries();
+        let partial = LogExport::new(
+            entries[3..].to_vec(),
+            entries[2].hash.clone(),
+            merkle::root(&leaves(&entries)),
+            Some(checkpoint(&entries, 2)),
+            NaiveDateTime::from_timestamp(1_537_000_100, 0),
+        );
+        let decoded = LogExport::read_from(&mut &encode(partial)[..]).unwrap();
+        assert_eq!(decoded.header.get_first_id(), 4);
+        decoded.verify().unwrap();
+
+        let empty = LogExport::new(
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            None,
+            NaiveDateTime::from_timestamp(1_537_000_100, 0),
+        );
+        let decoded = LogExport::read_from(&mut &encode(empty)[..]).unwrap();
+        assert_eq!(decoded.header.get_entry_count(), 0);
+        decoded.verify().unwrap();
+    }
+
+    #[test]
+    fn truncated() {
+        let encoded = encode(export());
+
+        // Whether it's cut within a record or between them, no prefix of an export is accepted.
+        for length in 0..encoded.len() {
+            let valid = LogExport::read_from(&mut &encoded[..length])
+                .and_then(|export| export.verify())
+                .is_ok();
+            assert!(!valid, "export truncated to {} bytes was accepted", length);
+        }
+
+        let mut records = export().into_records();
+        records.remove(0);
+        assert!(LogExport::from_records(records).is_err());
+        let mut records = export().into_records();
+        let header = records[0].clone();
+        records.push(header);
+        assert!(LogExport::from_records(records).is_err());
+    }
+
+    #[test]
+    fn tampered() {
+        let rejected = |tamper: &Fn(&mut LogExport)| {
+            let mut export = export();
+            tamper(&mut export);
+            LogExport::read_from(&mut &encode(export)[..])
+                .and_then(|export| export.verify())
+                .is_err()
+        };
+
+        assert!(rejected(&|export| export.entries[4].set_result(
+            fero::LogEntry_OperationResult::FAILURE
+        )));
+        assert!(rejected(&|export| export.entries[1].mut_timestamp().set_seconds(0)));
+        assert!(rejected(&|export| export.entries[7].mut_hash()[0] ^= 1));
+        assert!(rejected(&|export| {
+            export.entries.remove(3);
+        }));
+        assert!(rejected(&|export| export.entries.swap(2, 3)));
+        assert!(rejected(&|export| {
+            export.entries.pop();
+        }));
+        assert!(rejected(&|export| {
+            export.entries.remove(0);
+            export.header.set_first_id(2);
+            export.header.set_entry_count(SIZE as u64 - 1);
+        }));
+
+        assert!(rejected(&|export| export.header.set_format(String::from("fero-log-export/2"))));
+        assert!(rejected(&|export| export.header.set_last_id(SIZE + 1)));
+        assert!(rejected(&|export| export.header.mut_head_hash()[0] ^= 1));
+        assert!(rejected(&|export| export.header.mut_tree_root()[0] ^= 1));
+        assert!(rejected(&|export| export.header.mut_checkpoint().mut_log_hash()[0] ^= 1));
+        assert!(rejected(&|export| export.header.mut_checkpoint().mut_tree_root()[0] ^= 1));
+        assert!(rejected(&|export| export.header.mut_checkpoint().set_log_id(SIZE + 1)));
+    }
+}
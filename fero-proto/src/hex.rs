@@ -0,0 +1,15 @@
+/// Lowercase hexadecimal, as used for digests in logs, approval payloads and command output.
+pub fn encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        assert_eq!(encode(&[]), "");
+        assert_eq!(encode(&[0x00, 0x0f, 0xa0, 0xff]), "000fa0ff");
+    }
+}
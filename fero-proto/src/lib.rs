@@ -1,3 +1,4 @@
+extern crate base64;
 extern crate byteorder;
 extern crate chrono;
 extern crate diesel;
@@ -7,10 +8,19 @@ extern crate diesel_derive_enum;
 extern crate failure;
 extern crate futures;
 extern crate grpcio;
+extern crate num;
 extern crate protobuf;
 extern crate sha2;
 
+pub mod armor;
+pub mod audit;
+pub mod checkpoint;
+pub mod export;
+pub mod hex;
 pub mod log;
+pub mod merkle;
+pub mod rpm;
+pub mod rsa;
 mod types;
 
 pub use types::*;
@@ -17,6 +17,18 @@ pub enum OperationType {
     Weight,
     AddSecret,
     AddUser,
+    SshCertify,
+    IssueCertificate,
+    Revoke,
+    GenerateCrl,
+    Timestamp,
+    /// HSM activity which no operation accounts for, found after an unclean shutdown.
+    Unattributed,
+    /// Signing a checkpoint of the log, which covers the entries before this one.
+    Checkpoint,
+    /// A change, from the server's command line, to how a secret may be used, such as its
+    /// certificate, policies or designation as the timestamp or audit secret.
+    Configure,
 }
 
 impl From<fero::LogEntry_OperationType> for OperationType {
@@ -27,6 +39,14 @@ impl From<fero::LogEntry_OperationType> for OperationType {
             fero::LogEntry_OperationType::WEIGHT => OperationType::Weight,
             fero::LogEntry_OperationType::ADD_SECRET => OperationType::AddSecret,
             fero::LogEntry_OperationType::ADD_USER => OperationType::AddUser,
+            fero::LogEntry_OperationType::SSH_CERTIFY => OperationType::SshCertify,
+            fero::LogEntry_OperationType::ISSUE_CERTIFICATE => OperationType::IssueCertificate,
+            fero::LogEntry_OperationType::REVOKE => OperationType::Revoke,
+            fero::LogEntry_OperationType::GENERATE_CRL => OperationType::GenerateCrl,
+            fero::LogEntry_OperationType::TIMESTAMP => OperationType::Timestamp,
+            fero::LogEntry_OperationType::UNATTRIBUTED => OperationType::Unattributed,
+            fero::LogEntry_OperationType::CHECKPOINT => OperationType::Checkpoint,
+            fero::LogEntry_OperationType::CONFIGURE => OperationType::Configure,
         }
     }
 }
@@ -143,6 +163,120 @@ impl HsmLogEntry {
     }
 }
 
+/// What a signing operation signed: the secret and kind of signature used, the SHA-256 digest
+/// of the artifact as submitted, and the SHA-256 digest of the signature produced, which is empty
+/// if signing failed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignatureRecord {
+    pub secret_name: String,
+    pub signature_type: String,
+    pub artifact_digest: Vec<u8>,
+    pub signature_hash: Vec<u8>,
+}
+
+impl SignatureRecord {
+    pub fn new(
+        secret_name: &str,
+        signature_type: &str,
+        artifact: &[u8],
+        signature: Option<&[u8]>,
+    ) -> SignatureRecord {
+        let digest = |data: &[u8]| {
+            let mut hasher = Sha256::default();
+            hasher.input(data);
+            hasher.result().to_vec()
+        };
+
+        SignatureRecord {
+            secret_name: String::from(secret_name),
+            signature_type: String::from(signature_type),
+            artifact_digest: digest(artifact),
+            signature_hash: signature.map(digest).unwrap_or_default(),
+        }
+    }
+
+    /// Each field is prefixed with its length, so that the encoding is unambiguous.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+
+        for field in &[
+            self.secret_name.as_bytes(),
+            self.signature_type.as_bytes(),
+            &self.artifact_digest[..],
+            &self.signature_hash[..],
+        ] {
+            out.write_u32::<BigEndian>(field.len() as u32)?;
+            out.extend_from_slice(field);
+        }
+
+        Ok(out)
+    }
+}
+
+impl<'a> From<&'a fero::SignatureRecord> for SignatureRecord {
+    fn from(record: &fero::SignatureRecord) -> SignatureRecord {
+        SignatureRecord {
+            secret_name: record.secret_name.clone(),
+            signature_type: record.signature_type.clone(),
+            artifact_digest: record.artifact_digest.clone(),
+            signature_hash: record.signature_hash.clone(),
+        }
+    }
+}
+
+impl From<SignatureRecord> for fero::SignatureRecord {
+    fn from(record: SignatureRecord) -> fero::SignatureRecord {
+        let mut proto_record = fero::SignatureRecord::new();
+
+        proto_record.set_secret_name(record.secret_name);
+        proto_record.set_signature_type(record.signature_type);
+        proto_record.set_artifact_digest(record.artifact_digest);
+        proto_record.set_signature_hash(record.signature_hash);
+
+        proto_record
+    }
+}
+
+/// What a revocation revoked: the serial number of the certificate, without leading zeroes, and
+/// the CRLReason code it was revoked with. The issuing secret is in the entry's identification.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RevocationRecord {
+    pub serial: Vec<u8>,
+    pub reason: u32,
+}
+
+impl RevocationRecord {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+
+        out.write_u32::<BigEndian>(self.serial.len() as u32)?;
+        out.extend_from_slice(&self.serial);
+        out.write_u32::<BigEndian>(self.reason)?;
+
+        Ok(out)
+    }
+}
+
+impl<'a> From<&'a fero::RevocationRecord> for RevocationRecord {
+    fn from(record: &fero::RevocationRecord) -> RevocationRecord {
+        RevocationRecord {
+            serial: record.serial.clone(),
+            reason: record.reason,
+        }
+    }
+}
+
+impl From<RevocationRecord> for fero::RevocationRecord {
+    fn from(record: RevocationRecord) -> fero::RevocationRecord {
+        let mut proto_record = fero::RevocationRecord::new();
+
+        proto_record.set_serial(record.serial);
+        proto_record.set_reason(record.reason);
+
+        proto_record
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct FeroLogEntry {
     pub request_type: OperationType,
@@ -150,6 +284,8 @@ pub struct FeroLogEntry {
     pub result: OperationResult,
     pub hsm_logs: Vec<HsmLogEntry>,
     pub identification: Option<Identification>,
+    pub signature: Option<SignatureRecord>,
+    pub revocation: Option<RevocationRecord>,
     pub hash: Vec<u8>,
 }
 
@@ -173,6 +309,14 @@ impl FeroLogEntry {
             hasher.input(&ident.write_to_bytes().unwrap());
         }
 
+        // Entries from before signatures were recorded have none, and hash as they always did.
+        if let Some(ref signature) = self.signature {
+            hasher.input(&signature.to_bytes()?);
+        }
+        if let Some(ref revocation) = self.revocation {
+            hasher.input(&revocation.to_bytes()?);
+        }
+
         hasher.input(parent_log_hash);
 
         let hash_result: &[u8] = &hasher.result();
@@ -212,6 +356,16 @@ where
             ),
             result: entry.result.into(),
             identification: ident.cloned(),
+            signature: if entry.has_signature() {
+                Some(SignatureRecord::from(entry.get_signature()))
+            } else {
+                None
+            },
+            revocation: if entry.has_revocation() {
+                Some(RevocationRecord::from(entry.get_revocation()))
+            } else {
+                None
+            },
             hsm_logs: entry.hsm_logs.iter().map(HsmLogEntry::from).collect(),
             hash: entry.hash.clone(),
         }
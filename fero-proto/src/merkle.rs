@@ -0,0 +1,445 @@
+use failure::Error;
+use sha2::{Digest, Sha256};
+
+// Merkle trees over the audit log, as described in RFC 6962 section 2.1. The leaves are the log
+// entries' hashes, in order, so a tree of size `n` covers the log up to the entry with ID `n`.
+
+pub fn leaf_hash(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::default();
+    hasher.input(&[0x00]);
+    hasher.input(data);
+    hasher.result().to_vec()
+}
+
+fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::default();
+    hasher.input(&[0x01]);
+    hasher.input(left);
+    hasher.input(right);
+    hasher.result().to_vec()
+}
+
+/// The largest power of two smaller than `n`, which must be at least 2.
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+/// The root hash of the tree whose leaves have the hashes `leaves`.
+pub fn root(leaves: &[Vec<u8>]) -> Vec<u8> {
+    match leaves.len() {
+        0 => Sha256::default().result().to_vec(),
+        1 => leaves[0].clone(),
+        n => {
+            let k = split(n);
+            node_hash(&root(&leaves[..k]), &root(&leaves[k..]))
+        }
+    }
+}
+
+/// The audit path for leaf `index` of the subtree covering leaves `start..end`, from the leaf up.
+/// `subtree_root` gives the root of any subtree.
+fn inclusion_path<R>(subtree_root: &R, start: usize, end: usize, index: usize) -> Vec<Vec<u8>>
+where
+    R: Fn(usize, usize) -> Vec<u8>,
+{
+    if end - start == 1 {
+        return Vec::new();
+    }
+
+    let k = start + split(end - start);
+    let (mut proof, sibling) = if index < k {
+        (inclusion_path(subtree_root, start, k, index), subtree_root(k, end))
+    } else {
+        (inclusion_path(subtree_root, k, end, index), subtree_root(start, k))
+    };
+    proof.push(sibling);
+    proof
+}
+
+/// RFC 6962's SUBPROOF, for the tree of size `first` within the subtree covering leaves
+/// `start..end`.
+fn consistency_path<R>(
+    subtree_root: &R,
+    first: usize,
+    start: usize,
+    end: usize,
+    complete: bool,
+) -> Vec<Vec<u8>>
+where
+    R: Fn(usize, usize) -> Vec<u8>,
+{
+    if first == end {
+        return if complete { Vec::new() } else { vec![subtree_root(start, end)] };
+    }
+
+    let k = start + split(end - start);
+    let (mut proof, sibling) = if first <= k {
+        (consistency_path(subtree_root, first, start, k, complete), subtree_root(k, end))
+    } else {
+        (consistency_path(subtree_root, first, k, end, false), subtree_root(start, k))
+    };
+    proof.push(sibling);
+    proof
+}
+
+/// A Merkle tree which keeps the root of every complete subtree, so that the root of, and proofs
+/// for, any size of the tree take a logarithmic number of hashes rather than hashing every leaf.
+#[derive(Clone, Debug, Default)]
+pub struct Tree {
+    /// `levels[h][i]` is the root of the subtree covering leaves `i * 2^h` to `(i + 1) * 2^h`.
+    levels: Vec<Vec<Vec<u8>>>,
+}
+
+impl Tree {
+    pub fn new() -> Tree {
+        Tree::default()
+    }
+
+    /// The number of leaves in the tree.
+    pub fn len(&self) -> usize {
+        self.levels.first().map(|leaves| leaves.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append the leaf hash `leaf`.
+    pub fn push(&mut self, leaf: Vec<u8>) {
+        let mut hash = leaf;
+        for level in 0.. {
+            if self.levels.len() == level {
+                self.levels.push(Vec::new());
+            }
+            self.levels[level].push(hash);
+
+            let nodes = &self.levels[level];
+            if nodes.len() % 2 == 1 {
+                break;
+            }
+            hash = node_hash(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+        }
+    }
+
+    /// The root of the subtree covering leaves `start..end`. Subtrees are only ever split at
+    /// powers of two, so the complete subtrees they're built from are always in `levels`.
+    fn subtree_root(&self, start: usize, end: usize) -> Vec<u8> {
+        let size = end - start;
+        if size.is_power_of_two() && start % size == 0 {
+            return self.levels[size.trailing_zeros() as usize][start / size].clone();
+        }
+
+        let k = start + split(size);
+        node_hash(&self.subtree_root(start, k), &self.subtree_root(k, end))
+    }
+
+    fn check_size(&self, size: usize) -> Result<(), Error> {
+        if size > self.len() {
+            bail!("Tree of size {} is larger than the log", size);
+        }
+        Ok(())
+    }
+
+    /// The root of the tree of size `size`.
+    pub fn root(&self, size: usize) -> Result<Vec<u8>, Error> {
+        self.check_size(size)?;
+        if size == 0 {
+            return Ok(Sha256::default().result().to_vec());
+        }
+
+        Ok(self.subtree_root(0, size))
+    }
+
+    /// The audit path for leaf `index` in the tree of size `size`, from the leaf up.
+    pub fn inclusion_proof(&self, index: usize, size: usize) -> Result<Vec<Vec<u8>>, Error> {
+        self.check_size(size)?;
+        if index >= size {
+            bail!("Leaf {} is not in a tree of size {}", index, size);
+        }
+
+        let subtree_root = |start, end| self.subtree_root(start, end);
+        Ok(inclusion_path(&subtree_root, 0, size, index))
+    }
+
+    /// A proof that the tree of size `first` is a prefix of the tree of size `second`.
+    pub fn consistency_proof(&self, first: usize, second: usize) -> Result<Vec<Vec<u8>>, Error> {
+        self.check_size(second)?;
+        if first > second {
+            bail!("Tree of size {} can't be a prefix of one of size {}", first, second);
+        }
+        if first == 0 {
+            return Ok(Vec::new());
+        }
+
+        let subtree_root = |start, end| self.subtree_root(start, end);
+        Ok(consistency_path(&subtree_root, first, 0, second, true))
+    }
+}
+
+/// Check that `leaf` (a leaf hash) is at `index` in the tree of size `size` with root `root`,
+/// following RFC 9162 section 2.1.3.2.
+pub fn verify_inclusion(
+    leaf: &[u8],
+    index: u64,
+    size: u64,
+    proof: &[Vec<u8>],
+    root: &[u8],
+) -> Result<(), Error> {
+    if index >= size {
+        bail!("Leaf {} is not in a tree of size {}", index, size);
+    }
+
+    let mut f = index;
+    let mut s = size - 1;
+    let mut hash = leaf.to_vec();
+    for sibling in proof {
+        if s == 0 {
+            bail!("Inclusion proof is too long");
+        }
+        if f & 1 == 1 || f == s {
+            hash = node_hash(sibling, &hash);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            hash = node_hash(&hash, sibling);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+
+    if s != 0 || hash != root {
+        bail!("Inclusion proof doesn't match the tree's root");
+    }
+
+    Ok(())
+}
+
+/// Check that the tree of size `first` with root `first_root` is a prefix of the tree of size
+/// `second` with root `second_root`, following RFC 9162 section 2.1.4.2.
+pub fn verify_consistency(
+    first: u64,
+    second: u64,
+    first_root: &[u8],
+    second_root: &[u8],
+    proof: &[Vec<u8>],
+) -> Result<(), Error> {
+    if first > second {
+        bail!("Tree of size {} can't be a prefix of one of size {}", first, second);
+    }
+    if first == second || first == 0 {
+        if !proof.is_empty() || (first == second && first_root != second_root) {
+            bail!("Consistency proof doesn't match the trees' roots");
+        }
+        return Ok(());
+    }
+
+    let mut proof = proof.to_vec();
+    if first.is_power_of_two() {
+        proof.insert(0, first_root.to_vec());
+    }
+
+    let mut f = first - 1;
+    let mut s = second - 1;
+    while f & 1 == 1 {
+        f >>= 1;
+        s >>= 1;
+    }
+
+    let mut hashes = proof.iter();
+    let (mut first_hash, mut second_hash) = match hashes.next() {
+        Some(hash) => (hash.clone(), hash.clone()),
+        None => bail!("Consistency proof is empty"),
+    };
+    for hash in hashes {
+        if s == 0 {
+            bail!("Consistency proof is too long");
+        }
+        if f & 1 == 1 || f == s {
+            first_hash = node_hash(hash, &first_hash);
+            second_hash = node_hash(hash, &second_hash);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            second_hash = node_hash(&second_hash, hash);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+
+    if s != 0 || first_hash != first_root || second_hash != second_root {
+        bail!("Consistency proof doesn't match the trees' roots");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use hex;
+
+    use super::*;
+
+    const SIZE: usize = 16;
+
+    /// One more leaf than the tree has, for a tree which differs from every size of it.
+    fn leaves() -> Vec<Vec<u8>> {
+        (0..SIZE as u8 + 1).map(|i| leaf_hash(&[i])).collect()
+    }
+
+    fn tree() -> Tree {
+        let mut tree = Tree::new();
+        for leaf in &leaves()[..SIZE] {
+            tree.push(leaf.clone());
+        }
+        tree
+    }
+
+    #[test]
+    fn roots() {
+        // The test vectors from Certificate Transparency's reference implementation.
+        let data: &[&[u8]] = &[
+            b"",
+            b"\x00",
+            b"\x10",
+            b"\x20\x21",
+            b"\x30\x31",
+            b"\x40\x41\x42\x43",
+            b"\x50\x51\x52\x53\x54\x55\x56\x57",
+            b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f",
+        ];
+        let roots = [
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+            "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+            "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+            "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+        ];
+
+        let leaves: Vec<_> = data.iter().map(|data| leaf_hash(data)).collect();
+        let mut tree = Tree::new();
+        assert_eq!(
+            hex::encode(&tree.root(0).unwrap()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        for (size, expected) in (1..).zip(roots.iter()) {
+            tree.push(leaves[size - 1].clone());
+            assert_eq!(hex::encode(&root(&leaves[..size])), *expected);
+            assert_eq!(hex::encode(&tree.root(size).unwrap()), *expected);
+        }
+
+        // Earlier sizes are still available once the tree has grown.
+        assert_eq!(hex::encode(&tree.root(5).unwrap()), roots[4]);
+        assert!(tree.root(9).is_err());
+    }
+
+    #[test]
+    fn inclusion_proofs() {
+        let leaves = leaves();
+        let tree = tree();
+
+        for size in 1..SIZE + 1 {
+            let tree_root = root(&leaves[..size]);
+            assert_eq!(tree.root(size).unwrap(), tree_root);
+
+            for index in 0..size {
+                let proof = tree.inclusion_proof(index, size).unwrap();
+                let (i, n) = (index as u64, size as u64);
+                verify_inclusion(&leaves[index], i, n, &proof, &tree_root).unwrap();
+
+                // The proof is for this leaf, at this index, in this tree only.
+                let other = (index + 1) % size;
+                if other != index {
+                    assert!(verify_inclusion(&leaves[other], i, n, &proof, &tree_root).is_err());
+                    let other = other as u64;
+                    assert!(
+                        verify_inclusion(&leaves[index], other, n, &proof, &tree_root).is_err()
+                    );
+                }
+                let bigger_root = root(&leaves[..size + 1]);
+                assert!(verify_inclusion(&leaves[index], i, n, &proof, &bigger_root).is_err());
+
+                let mut long_proof = proof.clone();
+                long_proof.push(tree_root.clone());
+                assert!(verify_inclusion(&leaves[index], i, n, &long_proof, &tree_root).is_err());
+                if let Some((_, short_proof)) = proof.split_last() {
+                    assert!(verify_inclusion(&leaves[index], i, n, short_proof, &tree_root)
+                        .is_err());
+                }
+                for tampered in 0..proof.len() {
+                    let mut bad_proof = proof.clone();
+                    bad_proof[tampered][0] ^= 1;
+                    assert!(verify_inclusion(&leaves[index], i, n, &bad_proof, &tree_root)
+                        .is_err());
+                }
+            }
+
+            assert!(tree.inclusion_proof(size, size).is_err());
+            let n = size as u64;
+            assert!(verify_inclusion(&leaves[0], n, n, &[], &tree_root).is_err());
+        }
+
+        assert!(tree.inclusion_proof(0, SIZE + 1).is_err());
+    }
+
+    #[test]
+    fn consistency_proofs() {
+        let leaves = leaves();
+        let tree = tree();
+
+        for second in 0..SIZE + 1 {
+            let second_root = root(&leaves[..second]);
+
+            for first in 0..second + 1 {
+                let first_root = root(&leaves[..first]);
+                let proof = tree.consistency_proof(first, second).unwrap();
+                let (m, n) = (first as u64, second as u64);
+                verify_consistency(m, n, &first_root, &second_root, &proof).unwrap();
+
+                if first == 0 {
+                    continue;
+                }
+
+                // Neither tree can differ from the ones the proof was made for.
+                let other_first = root(&leaves[1..first + 1]);
+                assert!(verify_consistency(m, n, &other_first, &second_root, &proof).is_err());
+                if first < second {
+                    let other_second = root(&leaves[1..second + 1]);
+                    assert!(verify_consistency(m, n, &first_root, &other_second, &proof).is_err());
+                    let other_size = root(&leaves[..first - 1]);
+                    assert!(
+                        verify_consistency(m - 1, n, &other_size, &second_root, &proof).is_err()
+                    );
+                }
+
+                let mut long_proof = proof.clone();
+                long_proof.push(second_root.clone());
+                assert!(verify_consistency(m, n, &first_root, &second_root, &long_proof).is_err());
+                if let Some((_, short_proof)) = proof.split_last() {
+                    assert!(verify_consistency(m, n, &first_root, &second_root, short_proof)
+                        .is_err());
+                }
+                for tampered in 0..proof.len() {
+                    let mut bad_proof = proof.clone();
+                    bad_proof[tampered][0] ^= 1;
+                    assert!(verify_consistency(m, n, &first_root, &second_root, &bad_proof)
+                        .is_err());
+                }
+            }
+        }
+
+        assert!(tree.consistency_proof(3, 2).is_err());
+        assert!(tree.consistency_proof(1, SIZE + 1).is_err());
+        assert!(verify_consistency(3, 2, &root(&leaves[..3]), &root(&leaves[..2]), &[]).is_err());
+    }
+}
@@ -0,0 +1,405 @@
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use failure::Error;
+use sha2::{Digest, Sha256};
+
+use hex;
+
+// See the "Package File Format" section of the RPM documentation for the format of everything in
+// this module.
+const LEAD_MAGIC: &[u8] = &[0xed, 0xab, 0xee, 0xdb];
+const LEAD_LENGTH: usize = 96;
+const HEADER_MAGIC: &[u8] = &[0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0];
+const ENTRY_LENGTH: usize = 16;
+
+const TYPE_CHAR: u32 = 1;
+const TYPE_INT8: u32 = 2;
+const TYPE_INT16: u32 = 3;
+const TYPE_INT32: u32 = 4;
+const TYPE_INT64: u32 = 5;
+const TYPE_STRING: u32 = 6;
+const TYPE_BIN: u32 = 7;
+const TYPE_STRING_ARRAY: u32 = 8;
+const TYPE_I18NSTRING: u32 = 9;
+
+const TAG_HEADER_SIGNATURES: u32 = 62;
+const TAG_HEADER_IMMUTABLE: u32 = 63;
+const TAG_NAME: u32 = 1000;
+const TAG_VERSION: u32 = 1001;
+const TAG_RELEASE: u32 = 1002;
+const TAG_EPOCH: u32 = 1003;
+const TAG_ARCH: u32 = 1022;
+const TAG_SOURCE_RPM: u32 = 1044;
+
+/// The signature tag holding an OpenPGP signature over the header alone.
+pub const SIGTAG_RSA: u32 = 268;
+
+#[derive(Clone)]
+struct Entry {
+    tag: u32,
+    kind: u32,
+    count: u32,
+    data: Vec<u8>,
+}
+
+fn alignment(kind: u32) -> usize {
+    match kind {
+        TYPE_INT16 => 2,
+        TYPE_INT32 => 4,
+        TYPE_INT64 => 8,
+        _ => 1,
+    }
+}
+
+/// The length of the data of an entry of type `kind` with `count` elements, which starts at the
+/// beginning of `data`.
+fn data_length(kind: u32, count: u32, data: &[u8]) -> Result<usize, Error> {
+    let count = count as usize;
+    let length = match kind {
+        TYPE_CHAR | TYPE_INT8 | TYPE_BIN => count,
+        TYPE_INT16 => 2 * count,
+        TYPE_INT32 => 4 * count,
+        TYPE_INT64 => 8 * count,
+        TYPE_STRING | TYPE_STRING_ARRAY | TYPE_I18NSTRING => {
+            let mut length = 0;
+            for _ in 0..count {
+                match data[length..].iter().position(|&b| b == 0) {
+                    Some(end) => length += end + 1,
+                    None => bail!("Unterminated string in RPM header"),
+                }
+            }
+            length
+        }
+        _ => bail!("Unknown RPM header entry type {}", kind),
+    };
+
+    if length > data.len() {
+        bail!("RPM header entry runs past the end of the header");
+    }
+    Ok(length)
+}
+
+/// A parsed RPM header structure, as used for both the signature header and the main header.
+pub struct Header {
+    entries: Vec<Entry>,
+}
+
+impl Header {
+    /// Parse the header at the start of `data`, returning it along with its length.
+    fn parse(data: &[u8]) -> Result<(Header, usize), Error> {
+        if data.len() < 16 || &data[..8] != HEADER_MAGIC {
+            bail!("Bad RPM header magic");
+        }
+        let index_length = BigEndian::read_u32(&data[8..12]) as usize;
+        let store_length = BigEndian::read_u32(&data[12..16]) as usize;
+
+        let store_start = 16 + index_length * ENTRY_LENGTH;
+        let length = store_start + store_length;
+        if length > data.len() {
+            bail!("RPM header is truncated");
+        }
+        let store = &data[store_start..length];
+
+        let mut entries = Vec::new();
+        for entry in data[16..store_start].chunks(ENTRY_LENGTH) {
+            let tag = BigEndian::read_u32(&entry[0..4]);
+            let kind = BigEndian::read_u32(&entry[4..8]);
+            let offset = BigEndian::read_u32(&entry[8..12]) as usize;
+            let count = BigEndian::read_u32(&entry[12..16]);
+
+            if offset > store.len() {
+                bail!("RPM header entry starts past the end of the header");
+            }
+            // Strings are stored alone, and reading them relies on there being exactly one.
+            if kind == TYPE_STRING && count != 1 {
+                bail!("RPM header string entry has {} elements", count);
+            }
+            let entry_length = data_length(kind, count, &store[offset..])?;
+            entries.push(Entry {
+                tag,
+                kind,
+                count,
+                data: store[offset..offset + entry_length].to_vec(),
+            });
+        }
+
+        Ok((Header { entries }, length))
+    }
+
+    fn get(&self, tag: u32) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.tag == tag)
+    }
+
+    fn string(&self, tag: u32) -> Result<Option<String>, Error> {
+        match self.get(tag) {
+            Some(entry) if entry.kind == TYPE_STRING => {
+                let value = &entry.data[..entry.data.len() - 1];
+                Ok(Some(String::from_utf8(value.to_vec())?))
+            }
+            Some(_) => bail!("RPM header tag {} is not a string", tag),
+            None => Ok(None),
+        }
+    }
+
+    /// Set `tag` to the binary `value`, replacing any existing value.
+    fn set_binary(&mut self, tag: u32, value: &[u8]) {
+        self.entries.retain(|entry| entry.tag != tag);
+        self.entries.push(Entry {
+            tag,
+            kind: TYPE_BIN,
+            count: value.len() as u32,
+            data: value.to_vec(),
+        });
+    }
+
+    /// Serialize the header with all of its entries in a single region, tagged `region_tag`.
+    fn to_bytes(&self, region_tag: u32) -> Vec<u8> {
+        let mut entries = self.entries
+            .iter()
+            .filter(|entry| {
+                entry.tag != TAG_HEADER_SIGNATURES && entry.tag != TAG_HEADER_IMMUTABLE
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.tag);
+
+        let index_length = entries.len() + 1;
+        let mut index = Vec::new();
+        let mut store = Vec::new();
+        for entry in entries {
+            while store.len() % alignment(entry.kind) != 0 {
+                store.push(0);
+            }
+            write_entry(&mut index, entry.tag, entry.kind, store.len() as u32, entry.count);
+            store.extend_from_slice(&entry.data);
+        }
+
+        // The region's trailer is another entry, whose negative offset gives the size of the
+        // region's index.
+        let trailer_offset = store.len() as u32;
+        let region_index_length = -((index_length * ENTRY_LENGTH) as i32);
+        write_entry(&mut store, region_tag, TYPE_BIN, region_index_length as u32, 16);
+
+        let mut out = HEADER_MAGIC.to_vec();
+        out.write_u32::<BigEndian>(index_length as u32).unwrap();
+        out.write_u32::<BigEndian>(store.len() as u32).unwrap();
+        write_entry(&mut out, region_tag, TYPE_BIN, trailer_offset, 16);
+        out.extend_from_slice(&index);
+        out.extend_from_slice(&store);
+
+        out
+    }
+
+    /// The name-epoch:version-release.arch of the package whose main header this is.
+    pub fn nevra(&self) -> Result<String, Error> {
+        let name = self.string(TAG_NAME)?.ok_or(format_err!("RPM header has no name"))?;
+        let version = self.string(TAG_VERSION)?.ok_or(format_err!("RPM header has no version"))?;
+        let release = self.string(TAG_RELEASE)?.ok_or(format_err!("RPM header has no release"))?;
+        let epoch = match self.get(TAG_EPOCH) {
+            Some(entry) if entry.kind == TYPE_INT32 && entry.count == 1 => {
+                BigEndian::read_u32(&entry.data)
+            }
+            Some(_) => bail!("Malformed RPM epoch"),
+            None => 0,
+        };
+        // Only binary packages record the source package they were built from.
+        let arch = if self.get(TAG_SOURCE_RPM).is_some() {
+            self.string(TAG_ARCH)?.ok_or(format_err!("RPM header has no arch"))?
+        } else {
+            String::from("src")
+        };
+
+        Ok(format!("{}-{}:{}-{}.{}", name, epoch, version, release, arch))
+    }
+}
+
+fn write_entry(out: &mut Vec<u8>, tag: u32, kind: u32, offset: u32, count: u32) {
+    for value in &[tag, kind, offset, count] {
+        out.write_u32::<BigEndian>(*value).unwrap();
+    }
+}
+
+/// An RPM package, split into its parts.
+pub struct Package<'a> {
+    lead: &'a [u8],
+    signature: Header,
+    header: &'a [u8],
+    payload: &'a [u8],
+}
+
+impl<'a> Package<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Package<'a>, Error> {
+        if !is_package(data) || data.len() < LEAD_LENGTH {
+            bail!("Not an RPM package");
+        }
+
+        let (signature, signature_length) = Header::parse(&data[LEAD_LENGTH..])?;
+        // The signature header is padded out to a multiple of eight bytes.
+        let header_start = LEAD_LENGTH + (signature_length + 7) / 8 * 8;
+        if header_start > data.len() {
+            bail!("RPM package is truncated");
+        }
+        let (_, header_length) = Header::parse(&data[header_start..])?;
+        let header_end = header_start + header_length;
+
+        Ok(Package {
+            lead: &data[..LEAD_LENGTH],
+            signature,
+            header: &data[header_start..header_end],
+            payload: &data[header_end..],
+        })
+    }
+
+    /// The main header blob, which is what header signatures cover.
+    pub fn header(&self) -> &'a [u8] {
+        self.header
+    }
+
+    /// Rebuild the package with the signature tag `tag` set to `value`.
+    pub fn with_signature(&self, tag: u32, value: &[u8]) -> Vec<u8> {
+        let mut signature = Header {
+            entries: self.signature.entries.clone(),
+        };
+        signature.set_binary(tag, value);
+
+        let mut out = self.lead.to_vec();
+        out.extend_from_slice(&signature.to_bytes(TAG_HEADER_SIGNATURES));
+        while out.len() % 8 != 0 {
+            out.push(0);
+        }
+        out.extend_from_slice(self.header);
+        out.extend_from_slice(self.payload);
+
+        out
+    }
+}
+
+pub fn is_package(data: &[u8]) -> bool {
+    data.starts_with(LEAD_MAGIC)
+}
+
+/// Given either a whole RPM package or just its main header blob, return the header blob.
+pub fn header_blob(data: &[u8]) -> Result<&[u8], Error> {
+    if is_package(data) {
+        Ok(Package::parse(data)?.header())
+    } else {
+        let (_, length) = Header::parse(data)?;
+        if length != data.len() {
+            bail!("Trailing data after RPM header");
+        }
+        Ok(data)
+    }
+}
+
+/// What users sign to approve signing an RPM header: the package's NEVRA and the SHA-256 of its
+/// header, on one line.
+pub fn approval_payload(header: &[u8]) -> Result<Vec<u8>, Error> {
+    let (parsed, _) = Header::parse(header)?;
+
+    let mut hasher = Sha256::default();
+    hasher.input(header);
+    let digest = hex::encode(&hasher.result());
+
+    Ok(format!("{} {}\n", parsed.nevra()?, digest).into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A binary package with a signature header holding a size and SHA-256 digest, built to the
+    // file format independently of this module, and its main header. The signed package is the
+    // same with an RSA signature of "SIG!" added.
+    const PACKAGE: &[u8] = include_bytes!("../testdata/rpm/package.rpm");
+    const HEADER: &[u8] = include_bytes!("../testdata/rpm/header");
+    const SIGNED: &[u8] = include_bytes!("../testdata/rpm/signed.rpm");
+
+    /// A header of `(tag, kind, count, data)` entries, stored in order without any region.
+    fn raw_header(entries: &[(u32, u32, u32, &[u8])]) -> Vec<u8> {
+        let mut index = Vec::new();
+        let mut store = Vec::new();
+        for &(tag, kind, count, data) in entries {
+            write_entry(&mut index, tag, kind, store.len() as u32, count);
+            store.extend_from_slice(data);
+        }
+
+        let mut out = HEADER_MAGIC.to_vec();
+        out.write_u32::<BigEndian>(entries.len() as u32).unwrap();
+        out.write_u32::<BigEndian>(store.len() as u32).unwrap();
+        out.extend_from_slice(&index);
+        out.extend_from_slice(&store);
+        out
+    }
+
+    #[test]
+    fn header_blobs() {
+        assert_eq!(Package::parse(PACKAGE).unwrap().header(), HEADER);
+        assert_eq!(header_blob(PACKAGE).unwrap(), HEADER);
+        assert_eq!(header_blob(HEADER).unwrap(), HEADER);
+
+        let mut trailing = HEADER.to_vec();
+        trailing.push(0);
+        assert!(header_blob(&trailing).is_err());
+        assert!(header_blob(&HEADER[..HEADER.len() - 1]).is_err());
+        assert!(header_blob(&PACKAGE[..200]).is_err());
+        assert!(header_blob(&PACKAGE[..50]).is_err());
+    }
+
+    #[test]
+    fn approval_payloads() {
+        assert_eq!(
+            approval_payload(HEADER).unwrap(),
+            &b"fero-2:1.0-1.x86_64 \
+               f616b1296962f4a26b7210c20f169063298ed18d487c0ee144ce9120a66f256e\n"[..]
+        );
+    }
+
+    #[test]
+    fn source_packages() {
+        let header = raw_header(&[
+            (TAG_NAME, TYPE_STRING, 1, b"fero\0"),
+            (TAG_VERSION, TYPE_STRING, 1, b"1.0\0"),
+            (TAG_RELEASE, TYPE_STRING, 1, b"1\0"),
+            (TAG_ARCH, TYPE_STRING, 1, b"x86_64\0"),
+        ]);
+        assert!(approval_payload(&header).unwrap().starts_with(b"fero-0:1.0-1.src "));
+    }
+
+    #[test]
+    fn signatures() {
+        let package = Package::parse(PACKAGE).unwrap();
+        assert_eq!(package.with_signature(SIGTAG_RSA, b"SIG!"), SIGNED);
+
+        // Re-signing replaces the old signature rather than adding another.
+        let signed = Package::parse(SIGNED).unwrap();
+        assert_eq!(signed.with_signature(SIGTAG_RSA, b"SIG!"), SIGNED);
+        assert_eq!(signed.header(), HEADER);
+    }
+
+    #[test]
+    fn malformed_headers() {
+        let nevra = |entries: &[(u32, u32, u32, &[u8])]| {
+            Header::parse(&raw_header(entries)).and_then(|(header, _)| header.nevra())
+        };
+
+        // Strings must have exactly one element, or reading them would run off either end.
+        assert!(nevra(&[(TAG_NAME, TYPE_STRING, 0, b"")]).is_err());
+        assert!(nevra(&[(TAG_NAME, TYPE_STRING, 2, b"a\0b\0")]).is_err());
+        assert!(nevra(&[(TAG_NAME, TYPE_STRING, 1, b"fero")]).is_err());
+        assert!(nevra(&[(TAG_NAME, TYPE_STRING_ARRAY, 1, b"fero\0")]).is_err());
+        assert!(nevra(&[(TAG_NAME, 42, 1, b"fero\0")]).is_err());
+        assert!(nevra(&[(TAG_EPOCH, TYPE_INT32, 2, b"\0\0\0\x01")]).is_err());
+        assert!(nevra(&[
+            (TAG_NAME, TYPE_STRING, 1, b"fero\0"),
+            (TAG_VERSION, TYPE_STRING, 1, b"1.0\0"),
+            (TAG_RELEASE, TYPE_STRING, 1, b"1\0"),
+            (TAG_EPOCH, TYPE_INT16, 1, b"\0\x01"),
+        ]).is_err());
+
+        let mut past_end = raw_header(&[(TAG_NAME, TYPE_STRING, 1, b"fero\0")]);
+        past_end[27] = 6;
+        assert!(Header::parse(&past_end).is_err());
+
+        let mut bad_magic = HEADER.to_vec();
+        bad_magic[0] ^= 1;
+        assert!(Header::parse(&bad_magic).is_err());
+    }
+}
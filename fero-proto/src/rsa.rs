@@ -0,0 +1,148 @@
+use failure::Error;
+use num::BigUint;
+
+// Just enough of PKCS #1 (RFC 8017) to check signatures made by the HSM and by clients, since
+// everything else in fero leaves the cryptography to the HSM.
+
+/// The DER encoding of the SHA-256 AlgorithmIdentifier and the start of the digest's OCTET
+/// STRING, which precede the digest in a PKCS #1 v1.5 DigestInfo.
+pub const SHA256_DIGEST_INFO_PREFIX: &[u8] = &[
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01,
+    0x05, 0x00, 0x04, 0x20,
+];
+pub const SHA384_DIGEST_INFO_PREFIX: &[u8] = &[
+    0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02,
+    0x05, 0x00, 0x04, 0x30,
+];
+pub const SHA512_DIGEST_INFO_PREFIX: &[u8] = &[
+    0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03,
+    0x05, 0x00, 0x04, 0x40,
+];
+
+/// Split a DER element into its tag, its contents and whatever follows it.
+fn parse_der(data: &[u8]) -> Result<(u8, &[u8], &[u8]), Error> {
+    if data.len() < 2 {
+        bail!("Truncated DER element");
+    }
+
+    let (length, header) = match data[1] {
+        length @ 0..=0x7f => (length as usize, 2),
+        0x81..=0x84 => {
+            let count = (data[1] & 0x7f) as usize;
+            if data.len() < 2 + count {
+                bail!("Truncated DER element");
+            }
+            let length = data[2..2 + count]
+                .iter()
+                .fold(0, |length, byte| (length << 8) | *byte as usize);
+            (length, 2 + count)
+        }
+        _ => bail!("Unsupported DER length"),
+    };
+
+    if data.len() - header < length {
+        bail!("Truncated DER element");
+    }
+
+    Ok((data[0], &data[header..header + length], &data[header + length..]))
+}
+
+/// Parse an RSAPublicKey (RFC 8017 appendix A.1.1) into its modulus and exponent.
+pub fn parse_public_key(der: &[u8]) -> Result<(BigUint, BigUint), Error> {
+    let (tag, contents, rest) = parse_der(der)?;
+    if tag != 0x30 || !rest.is_empty() {
+        bail!("Malformed RSA public key");
+    }
+
+    let (n_tag, n, rest) = parse_der(contents)?;
+    let (e_tag, e, rest) = parse_der(rest)?;
+    if n_tag != 0x02 || e_tag != 0x02 || !rest.is_empty() {
+        bail!("Malformed RSA public key");
+    }
+
+    Ok((BigUint::from_bytes_be(n), BigUint::from_bytes_be(e)))
+}
+
+/// Check an RSASSA-PKCS1-v1_5 `signature` over `digest` by the DER-encoded RSAPublicKey
+/// `public_key`. `digest_info_prefix` identifies the hash, as with `SHA256_DIGEST_INFO_PREFIX`.
+pub fn verify(
+    public_key: &[u8],
+    digest_info_prefix: &[u8],
+    digest: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    let (n, e) = parse_public_key(public_key)?;
+    let length = (n.bits() + 7) / 8;
+
+    let signature_value = BigUint::from_bytes_be(signature);
+    if signature.len() != length || signature_value >= n {
+        bail!("Malformed RSA signature");
+    }
+    let encoded = signature_value.modpow(&e, &n).to_bytes_be();
+
+    // EMSA-PKCS1-v1_5 (RFC 8017 section 9.2), without the leading zero byte which
+    // `to_bytes_be` drops.
+    let digest_info_length = digest_info_prefix.len() + digest.len();
+    if length < digest_info_length + 11 {
+        bail!("RSA key is too small");
+    }
+    let mut expected = vec![0x01];
+    expected.resize(length - digest_info_length - 2, 0xff);
+    expected.push(0x00);
+    expected.extend_from_slice(digest_info_prefix);
+    expected.extend_from_slice(digest);
+
+    if encoded != expected {
+        bail!("Bad RSA signature");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+
+    // A 2048-bit key, and its signature made by `openssl dgst -sha256 -sign`.
+    const PUBLIC_KEY: &[u8] = include_bytes!("../testdata/rsa/public.der");
+    const PAYLOAD: &[u8] = include_bytes!("../testdata/rsa/payload");
+    const SIGNATURE: &[u8] = include_bytes!("../testdata/rsa/payload.sig");
+
+    fn sha256(data: &[u8]) -> Vec<u8> {
+        let mut hasher = Sha256::default();
+        hasher.input(data);
+        hasher.result().to_vec()
+    }
+
+    #[test]
+    fn public_key() {
+        let (n, e) = parse_public_key(PUBLIC_KEY).unwrap();
+        assert_eq!(n.bits(), 2048);
+        assert_eq!(e, BigUint::from(65_537u32));
+
+        assert!(parse_public_key(&PUBLIC_KEY[..PUBLIC_KEY.len() - 1]).is_err());
+        let mut trailing = PUBLIC_KEY.to_vec();
+        trailing.push(0);
+        assert!(parse_public_key(&trailing).is_err());
+    }
+
+    #[test]
+    fn good_signature() {
+        verify(PUBLIC_KEY, SHA256_DIGEST_INFO_PREFIX, &sha256(PAYLOAD), SIGNATURE).unwrap();
+    }
+
+    #[test]
+    fn bad_signatures() {
+        let digest = sha256(PAYLOAD);
+        assert!(verify(PUBLIC_KEY, SHA256_DIGEST_INFO_PREFIX, &sha256(b"other"), SIGNATURE)
+            .is_err());
+        assert!(verify(PUBLIC_KEY, SHA512_DIGEST_INFO_PREFIX, &digest, SIGNATURE).is_err());
+
+        let mut signature = SIGNATURE.to_vec();
+        signature[100] ^= 1;
+        assert!(verify(PUBLIC_KEY, SHA256_DIGEST_INFO_PREFIX, &digest, &signature).is_err());
+        assert!(verify(PUBLIC_KEY, SHA256_DIGEST_INFO_PREFIX, &digest, &SIGNATURE[1..]).is_err());
+    }
+}
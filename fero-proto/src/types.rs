@@ -156,6 +156,14 @@ impl Display for fero::LogEntry_OperationType {
             fero::LogEntry_OperationType::WEIGHT => write!(f, "Set User Weight"),
             fero::LogEntry_OperationType::ADD_SECRET => write!(f, "Import Secret (local)"),
             fero::LogEntry_OperationType::ADD_USER => write!(f, "Import User (local)"),
+            fero::LogEntry_OperationType::SSH_CERTIFY => write!(f, "Sign SSH Certificate"),
+            fero::LogEntry_OperationType::ISSUE_CERTIFICATE => write!(f, "Issue X.509 Certificate"),
+            fero::LogEntry_OperationType::REVOKE => write!(f, "Revoke X.509 Certificate"),
+            fero::LogEntry_OperationType::GENERATE_CRL => write!(f, "Generate CRL"),
+            fero::LogEntry_OperationType::TIMESTAMP => write!(f, "Timestamp"),
+            fero::LogEntry_OperationType::UNATTRIBUTED => write!(f, "Unattributed HSM Activity"),
+            fero::LogEntry_OperationType::CHECKPOINT => write!(f, "Audit Log Checkpoint"),
+            fero::LogEntry_OperationType::CONFIGURE => write!(f, "Configure Secret"),
         }
     }
 }
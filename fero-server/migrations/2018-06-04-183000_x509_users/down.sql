@@ -0,0 +1,13 @@
+PRAGMA foreign_keys = false;
+CREATE TABLE tmp_users (
+	id INTEGER PRIMARY KEY NOT NULL,
+	key_id UNSIGNED BIG INT NOT NULL UNIQUE,
+	key_data BLOB NOT NULL DEFAULT X'00'
+);
+INSERT INTO tmp_users
+    SELECT id, key_id, key_data
+    FROM users;
+DROP TABLE users;
+ALTER TABLE tmp_users RENAME TO users;
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys = true;
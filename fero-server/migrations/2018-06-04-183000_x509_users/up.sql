@@ -0,0 +1,2 @@
+ALTER TABLE users
+    ADD COLUMN key_type TEXT CHECK(key_type in ('pgp', 'x509')) NOT NULL DEFAULT 'pgp';
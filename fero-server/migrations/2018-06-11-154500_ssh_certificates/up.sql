@@ -0,0 +1,22 @@
+ALTER TABLE secrets
+    ADD COLUMN public_key BLOB;
+
+PRAGMA foreign_keys = false;
+CREATE TABLE tmp_fero_logs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    request_type TEXT CHECK(request_type in ('sign', 'threshold', 'weight', 'add_secret', 'add_user', 'ssh_certify')) NOT NULL,
+    timestamp DATETIME NOT NULL,
+    result TEXT CHECK(result in ('success', 'failure')) NOT NULL,
+    hsm_index_start INTEGER NOT NULL,
+    hsm_index_end INTEGER NOT NULL,
+    identification BLOB,
+    hash BLOB NOT NULL,
+
+    FOREIGN KEY(hsm_index_start) REFERENCES hsm_logs(hsm_index),
+    FOREIGN KEY(hsm_index_end) REFERENCES hsm_logs(hsm_index)
+);
+INSERT INTO tmp_fero_logs SELECT * FROM fero_logs;
+DROP TABLE fero_logs;
+ALTER TABLE tmp_fero_logs RENAME TO fero_logs;
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys = true;
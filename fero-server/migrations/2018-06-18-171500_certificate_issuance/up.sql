@@ -0,0 +1,35 @@
+ALTER TABLE secrets
+    ADD COLUMN certificate BLOB;
+
+CREATE TABLE certificate_profiles (
+    id INTEGER PRIMARY KEY NOT NULL,
+    secret_id INTEGER NOT NULL UNIQUE,
+    validity_days INTEGER NOT NULL,
+    key_usage INTEGER NOT NULL DEFAULT 0,
+    extended_key_usage TEXT NOT NULL DEFAULT "",
+    dns_suffixes TEXT NOT NULL DEFAULT "",
+    is_ca BOOLEAN NOT NULL DEFAULT 0,
+    path_length INTEGER,
+
+    FOREIGN KEY(secret_id) REFERENCES secrets(id)
+);
+
+PRAGMA foreign_keys = false;
+CREATE TABLE tmp_fero_logs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    request_type TEXT CHECK(request_type in ('sign', 'threshold', 'weight', 'add_secret', 'add_user', 'ssh_certify', 'issue_certificate')) NOT NULL,
+    timestamp DATETIME NOT NULL,
+    result TEXT CHECK(result in ('success', 'failure')) NOT NULL,
+    hsm_index_start INTEGER NOT NULL,
+    hsm_index_end INTEGER NOT NULL,
+    identification BLOB,
+    hash BLOB NOT NULL,
+
+    FOREIGN KEY(hsm_index_start) REFERENCES hsm_logs(hsm_index),
+    FOREIGN KEY(hsm_index_end) REFERENCES hsm_logs(hsm_index)
+);
+INSERT INTO tmp_fero_logs SELECT * FROM fero_logs;
+DROP TABLE fero_logs;
+ALTER TABLE tmp_fero_logs RENAME TO fero_logs;
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys = true;
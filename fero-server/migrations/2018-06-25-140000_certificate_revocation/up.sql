@@ -0,0 +1,33 @@
+ALTER TABLE secrets
+    ADD COLUMN crl_number BIG INT NOT NULL DEFAULT 0;
+
+CREATE TABLE revoked_certificates (
+    id INTEGER PRIMARY KEY NOT NULL,
+    secret_id INTEGER NOT NULL,
+    serial BLOB NOT NULL,
+    revocation_date DATETIME NOT NULL,
+    reason INTEGER NOT NULL DEFAULT 0,
+
+    UNIQUE(secret_id, serial),
+    FOREIGN KEY(secret_id) REFERENCES secrets(id)
+);
+
+PRAGMA foreign_keys = false;
+CREATE TABLE tmp_fero_logs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    request_type TEXT CHECK(request_type in ('sign', 'threshold', 'weight', 'add_secret', 'add_user', 'ssh_certify', 'issue_certificate', 'revoke', 'generate_crl')) NOT NULL,
+    timestamp DATETIME NOT NULL,
+    result TEXT CHECK(result in ('success', 'failure')) NOT NULL,
+    hsm_index_start INTEGER NOT NULL,
+    hsm_index_end INTEGER NOT NULL,
+    identification BLOB,
+    hash BLOB NOT NULL,
+
+    FOREIGN KEY(hsm_index_start) REFERENCES hsm_logs(hsm_index),
+    FOREIGN KEY(hsm_index_end) REFERENCES hsm_logs(hsm_index)
+);
+INSERT INTO tmp_fero_logs SELECT * FROM fero_logs;
+DROP TABLE fero_logs;
+ALTER TABLE tmp_fero_logs RENAME TO fero_logs;
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys = true;
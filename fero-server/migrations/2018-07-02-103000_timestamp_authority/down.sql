@@ -0,0 +1,39 @@
+PRAGMA foreign_keys = false;
+CREATE TABLE tmp_fero_logs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    request_type TEXT CHECK(request_type in ('sign', 'threshold', 'weight', 'add_secret', 'add_user', 'ssh_certify', 'issue_certificate', 'revoke', 'generate_crl')) NOT NULL,
+    timestamp DATETIME NOT NULL,
+    result TEXT CHECK(result in ('success', 'failure')) NOT NULL,
+    hsm_index_start INTEGER NOT NULL,
+    hsm_index_end INTEGER NOT NULL,
+    identification BLOB,
+    hash BLOB NOT NULL,
+
+    FOREIGN KEY(hsm_index_start) REFERENCES hsm_logs(hsm_index),
+    FOREIGN KEY(hsm_index_end) REFERENCES hsm_logs(hsm_index)
+);
+INSERT INTO tmp_fero_logs
+    SELECT * FROM fero_logs
+    WHERE request_type != 'timestamp';
+DROP TABLE fero_logs;
+ALTER TABLE tmp_fero_logs RENAME TO fero_logs;
+
+DROP TABLE timestamps;
+
+CREATE TABLE tmp_secrets (
+	id INTEGER PRIMARY KEY NOT NULL,
+	key_id UNSIGNED BIG INT UNIQUE,
+	threshold INTEGER NOT NULL,
+    hsm_id INTEGER NOT NULL DEFAULT 0,
+    name TEXT NOT NULL DEFAULT "" UNIQUE,
+    public_key BLOB,
+    certificate BLOB,
+    crl_number BIG INT NOT NULL DEFAULT 0
+);
+INSERT INTO tmp_secrets
+    SELECT id, key_id, threshold, hsm_id, name, public_key, certificate, crl_number
+    FROM secrets;
+DROP TABLE secrets;
+ALTER TABLE tmp_secrets RENAME TO secrets;
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys = true;
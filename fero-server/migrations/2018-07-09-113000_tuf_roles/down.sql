@@ -0,0 +1 @@
+DROP TABLE tuf_roles;
@@ -0,0 +1,10 @@
+CREATE TABLE tuf_roles (
+    id INTEGER PRIMARY KEY NOT NULL,
+    secret_id INTEGER NOT NULL,
+    role TEXT NOT NULL,
+    max_expiry_days INTEGER NOT NULL,
+    version BIG INT NOT NULL DEFAULT 0,
+
+    FOREIGN KEY(secret_id) REFERENCES secrets(id),
+    UNIQUE(secret_id, role)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE release_policies (
+    id INTEGER PRIMARY KEY NOT NULL,
+    secret_id INTEGER NOT NULL UNIQUE,
+    max_validity_days INTEGER NOT NULL,
+    max_date_age_hours INTEGER NOT NULL,
+
+    FOREIGN KEY(secret_id) REFERENCES secrets(id)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE secrets
+    ADD COLUMN algorithm TEXT CHECK(algorithm in ('rsa', 'ed25519')) NOT NULL DEFAULT 'rsa';
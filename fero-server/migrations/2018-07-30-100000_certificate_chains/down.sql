@@ -0,0 +1,21 @@
+PRAGMA foreign_keys = false;
+CREATE TABLE tmp_secrets (
+	id INTEGER PRIMARY KEY NOT NULL,
+	key_id UNSIGNED BIG INT UNIQUE,
+	threshold INTEGER NOT NULL,
+    hsm_id INTEGER NOT NULL DEFAULT 0,
+    name TEXT NOT NULL DEFAULT "" UNIQUE,
+    public_key BLOB,
+    certificate BLOB,
+    crl_number BIG INT NOT NULL DEFAULT 0,
+    tsa_policy TEXT,
+    algorithm TEXT CHECK(algorithm in ('rsa', 'ed25519')) NOT NULL DEFAULT 'rsa'
+);
+INSERT INTO tmp_secrets
+    SELECT id, key_id, threshold, hsm_id, name, public_key, certificate, crl_number, tsa_policy,
+        algorithm
+    FROM secrets;
+DROP TABLE secrets;
+ALTER TABLE tmp_secrets RENAME TO secrets;
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys = true;
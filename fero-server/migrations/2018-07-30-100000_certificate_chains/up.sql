@@ -0,0 +1,2 @@
+ALTER TABLE secrets
+    ADD COLUMN certificate_chain BLOB;
@@ -0,0 +1,8 @@
+ALTER TABLE fero_logs
+    ADD COLUMN secret_name TEXT;
+ALTER TABLE fero_logs
+    ADD COLUMN signature_type TEXT;
+ALTER TABLE fero_logs
+    ADD COLUMN artifact_digest BLOB;
+ALTER TABLE fero_logs
+    ADD COLUMN signature_hash BLOB;
@@ -0,0 +1,14 @@
+ALTER TABLE secrets
+    ADD COLUMN audit BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE TABLE checkpoints (
+    id INTEGER PRIMARY KEY NOT NULL,
+    fero_log_id INTEGER NOT NULL,
+    log_hash BLOB NOT NULL,
+    hsm_index INTEGER NOT NULL,
+    hsm_hash BLOB NOT NULL,
+    timestamp DATETIME NOT NULL,
+    signature BLOB NOT NULL,
+
+    FOREIGN KEY(fero_log_id) REFERENCES fero_logs(id)
+);
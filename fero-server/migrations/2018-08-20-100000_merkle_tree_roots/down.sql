@@ -0,0 +1,19 @@
+PRAGMA foreign_keys = false;
+CREATE TABLE tmp_checkpoints (
+    id INTEGER PRIMARY KEY NOT NULL,
+    fero_log_id INTEGER NOT NULL,
+    log_hash BLOB NOT NULL,
+    hsm_index INTEGER NOT NULL,
+    hsm_hash BLOB NOT NULL,
+    timestamp DATETIME NOT NULL,
+    signature BLOB NOT NULL,
+
+    FOREIGN KEY(fero_log_id) REFERENCES fero_logs(id)
+);
+INSERT INTO tmp_checkpoints
+    SELECT id, fero_log_id, log_hash, hsm_index, hsm_hash, timestamp, signature
+    FROM checkpoints;
+DROP TABLE checkpoints;
+ALTER TABLE tmp_checkpoints RENAME TO checkpoints;
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys = true;
@@ -0,0 +1,5 @@
+CREATE TABLE log_intents (
+    id INTEGER PRIMARY KEY NOT NULL,
+    request_type TEXT CHECK(request_type in ('sign', 'threshold', 'weight', 'add_secret', 'add_user', 'ssh_certify', 'issue_certificate', 'revoke', 'generate_crl', 'timestamp')) NOT NULL,
+    timestamp DATETIME NOT NULL
+);
@@ -0,0 +1,4 @@
+ALTER TABLE fero_logs
+    ADD COLUMN revoked_serial BLOB;
+ALTER TABLE fero_logs
+    ADD COLUMN revocation_reason INTEGER;
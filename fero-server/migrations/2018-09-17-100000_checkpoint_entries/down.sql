@@ -0,0 +1,37 @@
+PRAGMA foreign_keys = false;
+CREATE TABLE tmp_fero_logs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    request_type TEXT CHECK(request_type in ('sign', 'threshold', 'weight', 'add_secret', 'add_user', 'ssh_certify', 'issue_certificate', 'revoke', 'generate_crl', 'timestamp', 'unattributed')) NOT NULL,
+    timestamp DATETIME NOT NULL,
+    result TEXT CHECK(result in ('success', 'failure')) NOT NULL,
+    hsm_index_start INTEGER NOT NULL,
+    hsm_index_end INTEGER NOT NULL,
+    identification BLOB,
+    hash BLOB NOT NULL,
+    secret_name TEXT,
+    signature_type TEXT,
+    artifact_digest BLOB,
+    signature_hash BLOB,
+    revoked_serial BLOB,
+    revocation_reason INTEGER,
+
+    FOREIGN KEY(hsm_index_start) REFERENCES hsm_logs(hsm_index),
+    FOREIGN KEY(hsm_index_end) REFERENCES hsm_logs(hsm_index)
+);
+INSERT INTO tmp_fero_logs
+    SELECT * FROM fero_logs
+    WHERE request_type != 'checkpoint';
+DROP TABLE fero_logs;
+ALTER TABLE tmp_fero_logs RENAME TO fero_logs;
+CREATE TABLE tmp_log_intents (
+    id INTEGER PRIMARY KEY NOT NULL,
+    request_type TEXT CHECK(request_type in ('sign', 'threshold', 'weight', 'add_secret', 'add_user', 'ssh_certify', 'issue_certificate', 'revoke', 'generate_crl', 'timestamp')) NOT NULL,
+    timestamp DATETIME NOT NULL
+);
+INSERT INTO tmp_log_intents
+    SELECT * FROM log_intents
+    WHERE request_type != 'checkpoint';
+DROP TABLE log_intents;
+ALTER TABLE tmp_log_intents RENAME TO log_intents;
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys = true;
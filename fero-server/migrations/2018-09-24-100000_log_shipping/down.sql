@@ -0,0 +1 @@
+DROP TABLE shipped_logs;
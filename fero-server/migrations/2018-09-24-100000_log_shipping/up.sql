@@ -0,0 +1,4 @@
+CREATE TABLE shipped_logs (
+    sink TEXT PRIMARY KEY NOT NULL,
+    fero_log_id INTEGER NOT NULL
+);
@@ -0,0 +1,321 @@
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use failure::Error;
+use sha2::{Digest, Sha256};
+use yasna::{self, models::ObjectIdentifier};
+
+use cms::SignedData;
+use hsm::Hsm;
+use x509::{self, Certificate, DerElement};
+
+// See Microsoft's "Windows Authenticode Portable Executable Signature Format" and the PE Format
+// documentation for the structures in this module.
+
+const OID_SPC_INDIRECT_DATA: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 2, 1, 4];
+const OID_SPC_STATEMENT_TYPE: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 2, 1, 11];
+const OID_SPC_SP_OPUS_INFO: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 2, 1, 12];
+const OID_SPC_PE_IMAGE_DATA: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 2, 1, 15];
+const OID_SPC_INDIVIDUAL_SP_KEY_PURPOSE: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 2, 1, 21];
+
+const PE_MAGIC: &[u8] = b"PE\0\0";
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+const COFF_HEADER_LENGTH: usize = 20;
+const SECTION_HEADER_LENGTH: usize = 40;
+const CERTIFICATE_TABLE_INDEX: usize = 4;
+
+const WIN_CERT_REVISION_2_0: u16 = 0x0200;
+const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 0x0002;
+
+/// SpcPeImageData with no flags and the obsolete file link that Windows expects: an SpcLink
+/// holding the SpcString "<<<Obsolete>>>" in UTF-16.
+const SPC_PE_IMAGE_DATA: &[u8] = &[
+    0x30, 0x25, 0x03, 0x01, 0x00, 0xa0, 0x20, 0xa2, 0x1e, 0x80, 0x1c, 0x00, 0x3c, 0x00,
+    0x3c, 0x00, 0x3c, 0x00, 0x4f, 0x00, 0x62, 0x00, 0x73, 0x00, 0x6f, 0x00, 0x6c, 0x00,
+    0x65, 0x00, 0x74, 0x00, 0x65, 0x00, 0x3e, 0x00, 0x3e, 0x00, 0x3e,
+];
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, Error> {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => Ok(LittleEndian::read_u16(bytes)),
+        None => bail!("PE image is truncated"),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    match data.get(offset..offset + 4) {
+        Some(bytes) => Ok(LittleEndian::read_u32(bytes)),
+        None => bail!("PE image is truncated"),
+    }
+}
+
+/// The locations in a PE image which the Authenticode hash needs.
+struct PeImage {
+    data: Vec<u8>,
+    checksum_offset: usize,
+    certificate_entry_offset: usize,
+    size_of_headers: usize,
+    /// The file offsets and lengths of the sections' raw data, in file order.
+    sections: Vec<(usize, usize)>,
+}
+
+impl PeImage {
+    /// Parse an unsigned PE image. The image is padded to a multiple of eight bytes, since
+    /// that's where the certificate table will go, and the padding is covered by the hash.
+    fn parse(data: &[u8]) -> Result<PeImage, Error> {
+        if !data.starts_with(b"MZ") {
+            bail!("Payload is not a PE image");
+        }
+        let pe_offset = read_u32(data, 0x3c)? as usize;
+        if data.get(pe_offset..pe_offset + 4) != Some(PE_MAGIC) {
+            bail!("Payload is not a PE image");
+        }
+
+        let coff_offset = pe_offset + 4;
+        let section_count = read_u16(data, coff_offset + 2)? as usize;
+        let optional_header_length = read_u16(data, coff_offset + 16)? as usize;
+
+        let optional_offset = coff_offset + COFF_HEADER_LENGTH;
+        let (directory_count_offset, directories_offset) = match read_u16(data, optional_offset)? {
+            PE32_MAGIC => (optional_offset + 92, optional_offset + 96),
+            PE32_PLUS_MAGIC => (optional_offset + 108, optional_offset + 112),
+            magic => bail!("Unknown PE optional header magic {:#x}", magic),
+        };
+        let size_of_headers = read_u32(data, optional_offset + 60)? as usize;
+        let checksum_offset = optional_offset + 64;
+
+        if read_u32(data, directory_count_offset)? as usize <= CERTIFICATE_TABLE_INDEX {
+            bail!("PE image has no certificate table directory entry");
+        }
+        let certificate_entry_offset = directories_offset + 8 * CERTIFICATE_TABLE_INDEX;
+        if certificate_entry_offset + 8 > optional_offset + optional_header_length {
+            bail!("PE image has no certificate table directory entry");
+        }
+        if read_u32(data, certificate_entry_offset + 4)? != 0 {
+            bail!("PE image is already signed");
+        }
+
+        let sections_offset = optional_offset + optional_header_length;
+        let mut sections = Vec::new();
+        for index in 0..section_count {
+            let header = sections_offset + index * SECTION_HEADER_LENGTH;
+            let length = read_u32(data, header + 16)? as usize;
+            let offset = read_u32(data, header + 20)? as usize;
+            if length == 0 {
+                continue;
+            }
+            if offset + length > data.len() {
+                bail!("PE section {} runs past the end of the image", index);
+            }
+            sections.push((offset, length));
+        }
+        sections.sort();
+
+        if size_of_headers < certificate_entry_offset + 8 || size_of_headers > data.len() {
+            bail!("Malformed PE SizeOfHeaders");
+        }
+
+        let mut data = data.to_vec();
+        while data.len() % 8 != 0 {
+            data.push(0);
+        }
+
+        Ok(PeImage {
+            data,
+            checksum_offset,
+            certificate_entry_offset,
+            size_of_headers,
+            sections,
+        })
+    }
+
+    /// The Authenticode SHA-256 hash, which skips the checksum and certificate table entry, and
+    /// covers the sections in file order followed by any data after them.
+    fn digest(&self) -> Vec<u8> {
+        let mut hasher = Sha256::default();
+        hasher.input(&self.data[..self.checksum_offset]);
+        hasher.input(&self.data[self.checksum_offset + 4..self.certificate_entry_offset]);
+        hasher.input(&self.data[self.certificate_entry_offset + 8..self.size_of_headers]);
+
+        let mut hashed_end = self.size_of_headers;
+        for &(offset, length) in &self.sections {
+            hasher.input(&self.data[offset..offset + length]);
+            hashed_end = hashed_end.max(offset + length);
+        }
+        if hashed_end < self.data.len() {
+            hasher.input(&self.data[hashed_end..]);
+        }
+
+        hasher.result().to_vec()
+    }
+
+    /// Append a certificate table holding `signature`, and update the header to match.
+    fn embed_signature(mut self, signature: &[u8]) -> Vec<u8> {
+        let table_offset = self.data.len();
+
+        let mut table = Vec::new();
+        table.write_u32::<LittleEndian>(8 + signature.len() as u32).unwrap();
+        table.write_u16::<LittleEndian>(WIN_CERT_REVISION_2_0).unwrap();
+        table.write_u16::<LittleEndian>(WIN_CERT_TYPE_PKCS_SIGNED_DATA).unwrap();
+        table.extend_from_slice(signature);
+        while table.len() % 8 != 0 {
+            table.push(0);
+        }
+
+        let entry = self.certificate_entry_offset;
+        LittleEndian::write_u32(&mut self.data[entry..entry + 4], table_offset as u32);
+        LittleEndian::write_u32(&mut self.data[entry + 4..entry + 8], table.len() as u32);
+        self.data.extend_from_slice(&table);
+
+        let checksum = checksum(&self.data, self.checksum_offset);
+        let offset = self.checksum_offset;
+        LittleEndian::write_u32(&mut self.data[offset..offset + 4], checksum);
+
+        self.data
+    }
+}
+
+/// The PE checksum: a 16-bit one's complement sum of the image, with the checksum itself read
+/// as zero, plus the image's length. The checksum needn't be word aligned, so it can't simply be
+/// skipped.
+fn checksum(data: &[u8], checksum_offset: usize) -> u32 {
+    let byte = |index: usize| {
+        if index >= checksum_offset && index < checksum_offset + 4 {
+            0
+        } else {
+            data.get(index).map_or(0, |&byte| u32::from(byte))
+        }
+    };
+
+    let mut sum = 0u32;
+    for index in 0..(data.len() + 1) / 2 {
+        sum += byte(2 * index) | (byte(2 * index + 1) << 8);
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    sum + data.len() as u32
+}
+
+/// An SpcIndirectDataContent describing a PE image with the Authenticode hash `digest`.
+fn indirect_data(digest: &[u8]) -> Vec<u8> {
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_sequence(|writer| {
+                writer.next().write_oid(&ObjectIdentifier::from_slice(OID_SPC_PE_IMAGE_DATA));
+                x509::write_der(writer.next(), SPC_PE_IMAGE_DATA);
+            });
+            writer.next().write_sequence(|writer| {
+                x509::write_algorithm(writer.next(), x509::OID_SHA256);
+                writer.next().write_bytes(digest);
+            });
+        });
+    })
+}
+
+/// Sign the PE image `data` with `key`, whose certificate is `certificate`, returning the image
+/// with the signature appended. `chain` holds any intermediate certificates to include.
+pub fn sign(
+    hsm: &Hsm,
+    key: u16,
+    certificate: &Certificate,
+    chain: &[Vec<u8>],
+    data: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let image = PeImage::parse(data)?;
+    let content = indirect_data(&image.digest());
+
+    // Unlike CMS, Authenticode's messageDigest covers only the contents octets of the
+    // SpcIndirectDataContent, not its tag and length.
+    let mut hasher = Sha256::default();
+    hasher.input(DerElement::parse_all(&content)?.contents);
+    let content_digest: &[u8] = &hasher.result();
+
+    let statement_type = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer
+                .next()
+                .write_oid(&ObjectIdentifier::from_slice(OID_SPC_INDIVIDUAL_SP_KEY_PURPOSE));
+        });
+    });
+    let opus_info = yasna::construct_der(|writer| writer.write_sequence(|_| {}));
+
+    let mut signed_data = SignedData::new(certificate, OID_SPC_INDIRECT_DATA, content_digest)
+        .encapsulate_pkcs7(&content)
+        .attribute(OID_SPC_STATEMENT_TYPE, &statement_type)
+        .attribute(OID_SPC_SP_OPUS_INFO, &opus_info)
+        .certificate(&certificate.der);
+    for intermediate in chain {
+        signed_data = signed_data.certificate(intermediate);
+    }
+    let signature = signed_data.sign(hsm, key)?;
+
+    Ok(image.embed_signature(&signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A PE32+ EFI application built by objcopy, which also computed its checksum, and the same
+    // image with its PE header moved along a byte, so that the checksum is at an odd offset. The
+    // signed image is the first one signed with the testdata/cms signer's key. Its signature
+    // verifies, and goblin's Authenticode hash of it matches the unsigned image's.
+    const IMAGE: &[u8] = include_bytes!("../testdata/authenticode/image.efi");
+    const SHIFTED: &[u8] = include_bytes!("../testdata/authenticode/shifted.efi");
+    const SIGNED: &[u8] = include_bytes!("../testdata/authenticode/signed.efi");
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn digests() {
+        // As computed by goblin.
+        assert_eq!(
+            hex(&PeImage::parse(IMAGE).unwrap().digest()),
+            "539aafde6ee1eaca2b5dc4157b9398b69529508901b98eebc963b03f4e0b16cc"
+        );
+        assert_eq!(
+            hex(&PeImage::parse(SHIFTED).unwrap().digest()),
+            "a6725511f48a598f401a9d83faaf6393d3c07dbec0fa27b0231fa7218269d271"
+        );
+    }
+
+    #[test]
+    fn checksums() {
+        for image in &[IMAGE, SHIFTED] {
+            let offset = PeImage::parse(image).unwrap().checksum_offset;
+            assert_eq!(checksum(image, offset), read_u32(image, offset).unwrap());
+
+            // The old checksum mustn't contribute to the new one.
+            let mut changed = image.to_vec();
+            changed[offset..offset + 4].copy_from_slice(&[0xff; 4]);
+            assert_eq!(checksum(&changed, offset), read_u32(image, offset).unwrap());
+        }
+        assert_eq!(PeImage::parse(SHIFTED).unwrap().checksum_offset % 2, 1);
+    }
+
+    #[test]
+    fn embedding() {
+        let table_offset = read_u32(SIGNED, 0x80 + 24 + 112 + 32).unwrap() as usize;
+        let length = read_u32(SIGNED, table_offset).unwrap() as usize;
+        let signature = &SIGNED[table_offset + 8..table_offset + length];
+
+        assert_eq!(PeImage::parse(IMAGE).unwrap().embed_signature(signature), SIGNED);
+    }
+
+    #[test]
+    fn malformed_images() {
+        assert!(PeImage::parse(SIGNED).is_err());
+        assert!(PeImage::parse(b"MZ").is_err());
+        assert!(PeImage::parse(&IMAGE[..0x100]).is_err());
+        assert!(PeImage::parse(&IMAGE[..2048]).is_err());
+
+        let mut not_pe = IMAGE.to_vec();
+        not_pe[0x80] = b'N';
+        assert!(PeImage::parse(&not_pe).is_err());
+
+        let mut unknown_magic = IMAGE.to_vec();
+        unknown_magic[0x80 + 24] = 0;
+        assert!(PeImage::parse(&unknown_magic).is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use chrono::prelude::*;
+use failure::Error;
+use sha2::{Digest, Sha256};
+use yasna::{self, DERWriter, Tag};
+use yasna::models::ObjectIdentifier;
+
+use hsm::Hsm;
+use x509::{self, Certificate};
+
+pub const OID_DATA: &[u64] = &[1, 2, 840, 113549, 1, 7, 1];
+const OID_SIGNED_DATA: &[u64] = &[1, 2, 840, 113549, 1, 7, 2];
+const OID_CONTENT_TYPE: &[u64] = &[1, 2, 840, 113549, 1, 9, 3];
+const OID_MESSAGE_DIGEST: &[u64] = &[1, 2, 840, 113549, 1, 9, 4];
+const OID_SIGNING_TIME: &[u64] = &[1, 2, 840, 113549, 1, 9, 5];
+const OID_SIGNING_CERTIFICATE_V2: &[u64] = &[1, 2, 840, 113549, 1, 9, 16, 2, 47];
+
+fn write_attribute<F>(writer: DERWriter, oid: &[u64], value: F)
+where
+    F: FnOnce(DERWriter),
+{
+    writer.write_sequence(|writer| {
+        writer.next().write_oid(&ObjectIdentifier::from_slice(oid));
+        writer.next().write_set(|writer| value(writer.next()));
+    });
+}
+
+/// Write a SET OF from already-encoded elements. DER requires the elements of a SET OF to be
+/// sorted by their encodings, which yasna leaves up to us.
+fn write_sorted_set(writer: DERWriter, mut elements: Vec<Vec<u8>>) {
+    elements.sort();
+    writer.write_set(|writer| {
+        for element in &elements {
+            x509::write_der(writer.next(), element);
+        }
+    });
+}
+
+/// How the content is carried inside a SignedData.
+enum Content<'a> {
+    Octets(&'a [u8]),
+    /// PKCS #7 (RFC 2315) carries any content type's DER encoding directly.
+    Pkcs7(&'a [u8]),
+}
+
+/// A CMS SignedData structure (RFC 5652) with a single RSA signer, signing with SHA-256.
+pub struct SignedData<'a> {
+    signer: &'a Certificate,
+    content_type: &'a [u64],
+    content: Option<Content<'a>>,
+    digest: Vec<u8>,
+    attributes: Vec<Vec<u8>>,
+    signed_attributes: bool,
+    certificates: Vec<Vec<u8>>,
+}
+
+impl<'a> SignedData<'a> {
+    /// Sign content of type `content_type` whose SHA-256 digest is `digest`. Unless
+    /// `encapsulate` is used, the signature is detached.
+    pub fn new(signer: &'a Certificate, content_type: &'a [u64], digest: &[u8]) -> SignedData<'a> {
+        SignedData {
+            signer,
+            content_type,
+            content: None,
+            digest: digest.to_vec(),
+            attributes: Vec::new(),
+            signed_attributes: true,
+            certificates: Vec::new(),
+        }
+    }
+
+    /// Sign the content's digest directly, without any signed attributes, as the Linux kernel
+    /// expects. Any attributes added are ignored.
+    pub fn without_signed_attributes(mut self) -> SignedData<'a> {
+        self.signed_attributes = false;
+        self
+    }
+
+    /// Carry `content` inside the SignedData.
+    pub fn encapsulate(mut self, content: &'a [u8]) -> SignedData<'a> {
+        self.content = Some(Content::Octets(content));
+        self
+    }
+
+    /// Carry the DER-encoded `content` inside the SignedData as PKCS #7 does, rather than in an
+    /// OCTET STRING, producing a version 1 PKCS #7 SignedData. Authenticode requires this.
+    pub fn encapsulate_pkcs7(mut self, content: &'a [u8]) -> SignedData<'a> {
+        self.content = Some(Content::Pkcs7(content));
+        self
+    }
+
+    /// Add a signed attribute with a single, already-encoded value.
+    pub fn attribute(mut self, oid: &[u64], value: &[u8]) -> SignedData<'a> {
+        self.attributes.push(yasna::construct_der(|writer| {
+            write_attribute(writer, oid, |writer| x509::write_der(writer, value))
+        }));
+        self
+    }
+
+    /// Add a signingCertificateV2 attribute identifying the signer's certificate, as required by
+    /// some profiles (notably RFC 3161 and RFC 5816) to bind the signature to the certificate.
+    pub fn signing_certificate(self) -> SignedData<'a> {
+        let mut hasher = Sha256::default();
+        hasher.input(&self.signer.der);
+        let cert_hash: &[u8] = &hasher.result();
+
+        // SigningCertificateV2 ::= SEQUENCE { certs SEQUENCE OF ESSCertIDv2 }, where the hash
+        // algorithm of ESSCertIDv2 defaults to SHA-256.
+        let value = yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_sequence(|writer| {
+                    writer.next().write_sequence(|writer| {
+                        writer.next().write_bytes(cert_hash);
+                    });
+                });
+            });
+        });
+
+        self.attribute(OID_SIGNING_CERTIFICATE_V2, &value)
+    }
+
+    /// Add a signingTime attribute.
+    pub fn signing_time(self, time: &DateTime<Utc>) -> SignedData<'a> {
+        let value = yasna::construct_der(|writer| x509::write_time(writer, time));
+
+        self.attribute(OID_SIGNING_TIME, &value)
+    }
+
+    /// Include the DER-encoded certificate `certificate` in the SignedData.
+    pub fn certificate(mut self, certificate: &[u8]) -> SignedData<'a> {
+        self.certificates.push(certificate.to_vec());
+        self
+    }
+
+    fn signed_attributes(&self) -> Vec<Vec<u8>> {
+        let mut attributes = self.attributes.clone();
+        attributes.push(yasna::construct_der(|writer| {
+            write_attribute(writer, OID_CONTENT_TYPE, |writer| {
+                writer.write_oid(&ObjectIdentifier::from_slice(self.content_type))
+            })
+        }));
+        attributes.push(yasna::construct_der(|writer| {
+            write_attribute(writer, OID_MESSAGE_DIGEST, |writer| {
+                writer.write_bytes(&self.digest)
+            })
+        }));
+
+        attributes
+    }
+
+    /// Sign with `key` in the HSM, returning the DER-encoded ContentInfo.
+    pub fn sign(&self, hsm: &Hsm, key: u16) -> Result<Vec<u8>, Error> {
+        let signature = hsm.create_rsa_signature(&self.signature_digest(), key)?;
+
+        Ok(self.encode(&signature))
+    }
+
+    /// The SHA-256 digest which the signer signs.
+    pub fn signature_digest(&self) -> Vec<u8> {
+        if !self.signed_attributes {
+            return self.digest.clone();
+        }
+
+        // The signature covers the DER encoding of the attributes with a SET tag, even though
+        // they're included in the SignerInfo with an implicit [0] tag.
+        let signed_attributes = yasna::construct_der(|writer| {
+            write_sorted_set(writer, self.signed_attributes());
+        });
+        let mut hasher = Sha256::default();
+        hasher.input(&signed_attributes);
+        hasher.result().to_vec()
+    }
+
+    /// Encode the ContentInfo carrying the signer's `signature`.
+    pub fn encode(&self, signature: &[u8]) -> Vec<u8> {
+        let attributes = self.signed_attributes();
+
+        // The version depends on whether the content is plain data; see RFC 5652 section 5.1.
+        let version = match self.content {
+            Some(Content::Pkcs7(_)) => 1,
+            _ if self.content_type == OID_DATA => 1,
+            _ => 3,
+        };
+
+        yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_oid(&ObjectIdentifier::from_slice(OID_SIGNED_DATA));
+                writer.next().write_tagged(Tag::context(0), |writer| {
+                    writer.write_sequence(|writer| {
+                        writer.next().write_u8(version);
+                        writer.next().write_set(|writer| {
+                            x509::write_algorithm(writer.next(), x509::OID_SHA256);
+                        });
+                        writer.next().write_sequence(|writer| {
+                            writer.next().write_oid(
+                                &ObjectIdentifier::from_slice(self.content_type),
+                            );
+                            match self.content {
+                                Some(Content::Octets(content)) => {
+                                    writer.next().write_tagged(Tag::context(0), |writer| {
+                                        writer.write_bytes(content);
+                                    });
+                                }
+                                Some(Content::Pkcs7(content)) => {
+                                    writer.next().write_tagged(Tag::context(0), |writer| {
+                                        x509::write_der(writer, content);
+                                    });
+                                }
+                                None => {}
+                            }
+                        });
+                        if !self.certificates.is_empty() {
+                            writer.next().write_tagged_implicit(Tag::context(0), |writer| {
+                                write_sorted_set(writer, self.certificates.clone());
+                            });
+                        }
+                        writer.next().write_set(|writer| {
+                            writer.next().write_sequence(|writer| {
+                                writer.next().write_u8(1);
+                                writer.next().write_sequence(|writer| {
+                                    x509::write_der(writer.next(), &self.signer.issuer);
+                                    x509::write_der(writer.next(), &self.signer.serial);
+                                });
+                                x509::write_algorithm(writer.next(), x509::OID_SHA256);
+                                if self.signed_attributes {
+                                    writer.next().write_tagged_implicit(Tag::context(0), |writer| {
+                                        write_sorted_set(writer, attributes);
+                                    });
+                                }
+                                x509::write_algorithm(writer.next(), x509::OID_RSA_ENCRYPTION);
+                                writer.next().write_bytes(signature);
+                            });
+                        });
+                    });
+                });
+            });
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fero_proto::rsa;
+    use x509::DerElement;
+
+    use super::*;
+
+    // A self-signed certificate, the signatures its key made with `openssl pkeyutl` over each
+    // SignedData's digest, and the resulting SignedData, which `openssl cms -verify` accepts.
+    const SIGNER: &[u8] = include_bytes!("../testdata/cms/signer.der");
+    const ATTRIBUTES_SIGNATURE: &[u8] = include_bytes!("../testdata/cms/attributes.sig");
+    const ATTRIBUTES_SIGNED_DATA: &[u8] = include_bytes!("../testdata/cms/attributes.p7s");
+    const PLAIN_SIGNATURE: &[u8] = include_bytes!("../testdata/cms/plain.sig");
+    const PLAIN_SIGNED_DATA: &[u8] = include_bytes!("../testdata/cms/plain.p7s");
+
+    fn sha256(data: &[u8]) -> Vec<u8> {
+        let mut hasher = Sha256::default();
+        hasher.input(data);
+        hasher.result().to_vec()
+    }
+
+    /// The signer's RSAPublicKey, from its SubjectPublicKeyInfo.
+    fn public_key(certificate: &Certificate) -> Vec<u8> {
+        let spki = DerElement::parse_all(&certificate.subject_public_key_info).unwrap();
+        spki.children().unwrap()[1].contents[1..].to_vec()
+    }
+
+    /// The fields of the SignedData's only SignerInfo.
+    fn signer_info(signed_data: &[u8]) -> Vec<Vec<u8>> {
+        let content_info = DerElement::parse_all(signed_data).unwrap().children().unwrap();
+        let signed_data = content_info[1].children().unwrap()[0].children().unwrap();
+        let signer_infos = signed_data.last().unwrap().children().unwrap();
+        assert_eq!(signer_infos.len(), 1);
+        signer_infos[0]
+            .children()
+            .unwrap()
+            .iter()
+            .map(|field| field.raw.to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn signed_attributes() {
+        let certificate = Certificate::from_der(SIGNER).unwrap();
+        let digest = sha256(b"hello");
+        let signed_data = SignedData::new(&certificate, OID_DATA, &digest)
+            .signing_certificate()
+            .certificate(SIGNER);
+
+        rsa::verify(
+            &public_key(&certificate),
+            rsa::SHA256_DIGEST_INFO_PREFIX,
+            &signed_data.signature_digest(),
+            ATTRIBUTES_SIGNATURE,
+        ).unwrap();
+        assert_eq!(signed_data.encode(ATTRIBUTES_SIGNATURE), ATTRIBUTES_SIGNED_DATA);
+
+        // The attributes are in DER order, and the signature covers them with a SET tag.
+        let fields = signer_info(ATTRIBUTES_SIGNED_DATA);
+        let attributes = DerElement::parse_all(&fields[3]).unwrap();
+        assert_eq!(attributes.tag, 0xa0);
+        let encodings: Vec<&[u8]> =
+            attributes.children().unwrap().iter().map(|attribute| attribute.raw).collect();
+        let mut sorted = encodings.clone();
+        sorted.sort();
+        assert_eq!(encodings, sorted);
+        let mut set = fields[3].clone();
+        set[0] = 0x31;
+        assert_eq!(signed_data.signature_digest(), sha256(&set));
+    }
+
+    #[test]
+    fn without_signed_attributes() {
+        let certificate = Certificate::from_der(SIGNER).unwrap();
+        let digest = sha256(b"hello");
+        let signed_data = SignedData::new(&certificate, OID_DATA, &digest)
+            .without_signed_attributes()
+            .encapsulate(b"hello");
+
+        assert_eq!(signed_data.signature_digest(), digest);
+        assert_eq!(signed_data.encode(PLAIN_SIGNATURE), PLAIN_SIGNED_DATA);
+        // version, sid, digestAlgorithm, signatureAlgorithm, signature
+        assert_eq!(signer_info(PLAIN_SIGNED_DATA).len(), 5);
+    }
+
+    #[test]
+    fn versions() {
+        let certificate = Certificate::from_der(SIGNER).unwrap();
+        let digest = sha256(b"hello");
+        let version = |signed_data: SignedData| {
+            let encoded = signed_data.encode(&[]);
+            let content_info = DerElement::parse_all(&encoded).unwrap().children().unwrap();
+            let signed_data = content_info[1].children().unwrap()[0].children().unwrap();
+            signed_data[0].raw.to_vec()
+        };
+
+        let tst_info = &[1, 2, 840, 113549, 1, 9, 16, 1, 4];
+        assert_eq!(version(SignedData::new(&certificate, OID_DATA, &digest)), [2, 1, 1]);
+        assert_eq!(version(SignedData::new(&certificate, tst_info, &digest)), [2, 1, 3]);
+        assert_eq!(
+            version(SignedData::new(&certificate, tst_info, &digest).encapsulate_pkcs7(&[5, 0])),
+            [2, 1, 1]
+        );
+    }
+}
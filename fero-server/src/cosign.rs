@@ -0,0 +1,84 @@
+use base64;
+use failure::Error;
+use serde_json;
+use sha2::{Digest, Sha256};
+
+use fero_proto::fero::ContainerImage;
+use hsm::Hsm;
+use x509;
+
+// Container image signatures sign a "simple signing" payload, as described in the containers
+// image library's signature format and used by cosign. Bundles follow the Sigstore protobuf
+// specs, serialized as JSON.
+
+const BUNDLE_MEDIA_TYPE: &str = "application/vnd.dev.sigstore.bundle.v0.3+json";
+
+/// Build the simple signing payload claiming that `image.docker_reference` refers to the
+/// manifest with digest `image.manifest_digest`.
+pub fn simple_signing_payload(image: &ContainerImage) -> Result<Vec<u8>, Error> {
+    let reference = image.get_docker_reference();
+    let digest = image.get_manifest_digest();
+
+    if reference.is_empty() || reference.contains('@') {
+        bail!("The docker reference must name a repository, not a digest");
+    }
+    let hex = match digest.find(':') {
+        Some(index) if &digest[..index] == "sha256" => &digest[index + 1..],
+        _ => bail!("The manifest digest must be a sha256 digest"),
+    };
+    let is_hex = hex.bytes().all(|b| match b {
+        b'0'..=b'9' | b'a'..=b'f' => true,
+        _ => false,
+    });
+    if hex.len() != 64 || !is_hex {
+        bail!("Malformed manifest digest: {}", digest);
+    }
+
+    Ok(serde_json::to_vec(&json!({
+        "critical": {
+            "identity": { "docker-reference": reference },
+            "image": { "docker-manifest-digest": digest },
+            "type": "cosign container image signature",
+        },
+        "optional": null,
+    }))?)
+}
+
+/// Sign `payload` with `key` in the HSM, whose PKCS#1 public key is `public_key`. Returns the
+/// base64 signature, which is what `cosign verify-blob --signature` expects, along with a
+/// Sigstore bundle containing it.
+pub fn sign(
+    hsm: &Hsm,
+    key: u16,
+    public_key: &[u8],
+    payload: &[u8],
+) -> Result<(String, String), Error> {
+    let mut hasher = Sha256::default();
+    hasher.input(payload);
+    let digest: &[u8] = &hasher.result();
+
+    let signature = base64::encode(&hsm.create_rsa_signature(digest, key)?);
+
+    // There's no certificate or transparency log entry, so the verifier has to be given the key
+    // out of band. The hint lets it pick the right one.
+    let mut hasher = Sha256::default();
+    hasher.input(&x509::rsa_subject_public_key_info(public_key));
+    let hint = base64::encode(&hasher.result());
+
+    let bundle = serde_json::to_string(&json!({
+        "mediaType": BUNDLE_MEDIA_TYPE,
+        "verificationMaterial": {
+            "publicKey": { "hint": hint },
+            "tlogEntries": [],
+        },
+        "messageSignature": {
+            "messageDigest": {
+                "algorithm": "SHA2_256",
+                "digest": base64::encode(digest),
+            },
+            "signature": signature,
+        },
+    }))?;
+
+    Ok((signature, bundle))
+}
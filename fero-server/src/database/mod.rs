@@ -1,28 +1,222 @@
 pub(crate) mod models;
 mod schema;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
 use std::os::unix::ffi::OsStrExt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
 
+use chrono::NaiveDateTime;
 use diesel::{self, Connection};
+use diesel::connection::TransactionManager;
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
 use failure::Error;
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use gpgme::{Context, Protocol, SignatureSummary};
 use tempfile::TempDir;
 
 use fero_proto::fero::Identification;
+use fero_proto::log::{OperationResult, OperationType};
+use fero_proto::merkle::{self, Tree};
 use self::models::*;
 use super::local::LocalIdentification;
+use x509::DerElement;
+
+/// CMS signatures are DER (a SEQUENCE, so always starting with 0x30) or PEM, whereas binary
+/// OpenPGP packets always have the high bit of their first byte set.
+fn is_cms_signature(signature: &[u8]) -> bool {
+    signature.first() == Some(&0x30)
+        || signature.starts_with(b"-----BEGIN PKCS7-----")
+        || signature.starts_with(b"-----BEGIN CMS-----")
+}
+
+fn verify_pgp_signature(
+    gpg: &mut Context,
+    signature: &[u8],
+    payload: &[u8],
+) -> Result<Vec<i64>, Error> {
+    let mut ids = Vec::new();
+    let verification = gpg.verify_detached(signature, payload)?;
+
+    for signature in verification.signatures() {
+        // Valid signatures here actually should always have empty summaries, since the user keys
+        // we import above are not signed by a key trusted by our brand-new keyring, so all user
+        // keys have unknown validity. This check is primarily here to catch signatures over the
+        // wrong payload (SignatureSummary::RED), but this is the most technically-correct check
+        // (barring adding some extra plumbing above to generate a trusted key and sign each user
+        // key we import for each signing operation).
+        if !signature.summary().is_empty() &&
+            signature.summary() != SignatureSummary::GREEN &&
+            signature.summary() != SignatureSummary::VALID
+        {
+            continue;
+        }
+
+        // It seems gpgme is not filling in the .key() field here, so we retrieve it from gpgme
+        // via the fingerprint of the signature.
+        let fingerprint = signature
+            .fingerprint()
+            .map_err(|_| format_err!("Failed to get signature fingerprint"))?;
+        if let Ok(signing_key) = gpg.find_key(fingerprint) {
+            let signing_key_id = signing_key.id().map_err(|_| {
+                format_err!("Failed to get signing key's ID from fingerprint")
+            })?;
+            ids.push(u64::from_str_radix(signing_key_id, 16)? as i64);
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Import an enrolled user's certificate, recording its full fingerprint against the user's key
+/// ID in `enrolled`.
+fn import_certificate(
+    gpgsm: &mut Context,
+    certificate: &[u8],
+    key_id: i64,
+    enrolled: &mut HashMap<String, i64>,
+) -> Result<(), Error> {
+    let result = gpgsm.import(certificate)?;
+    for import in result.imports() {
+        let fingerprint = import
+            .fingerprint()
+            .map_err(|_| format_err!("Failed to get certificate fingerprint"))?;
+        enrolled.insert(fingerprint.to_uppercase(), key_id);
+    }
+
+    Ok(())
+}
+
+/// The key IDs of the enrolled users whose certificates made valid signatures. `enrolled` maps
+/// each enrolled certificate's fingerprint to its user's key ID.
+fn verify_cms_signature(
+    gpgsm: &mut Context,
+    signature: &[u8],
+    payload: &[u8],
+    require_chain: bool,
+    enrolled: &HashMap<String, i64>,
+) -> Result<Vec<i64>, Error> {
+    let mut ids = Vec::new();
+    let verification = gpgsm.verify_detached(signature, payload)?;
+
+    for signature in verification.signatures() {
+        // Without any trust anchors, every user certificate has an unverifiable chain, so only
+        // reject signatures which are actually bad. With trust anchors, gpgsm only reports a
+        // signature as VALID once the signing certificate chains up to one of them.
+        if signature.summary().contains(SignatureSummary::RED) {
+            continue;
+        }
+        if require_chain && !signature.summary().contains(SignatureSummary::VALID) {
+            continue;
+        }
+
+        // gpgsm imports any certificates embedded in the signature, so the signing certificate
+        // has to be matched on its full fingerprint, rather than on its key ID (only the low 64
+        // bits of the fingerprint), which someone else's certificate could be made to share.
+        let fingerprint = signature
+            .fingerprint()
+            .map_err(|_| format_err!("Failed to get signature fingerprint"))?;
+        if let Some(&key_id) = enrolled.get(&fingerprint.to_uppercase()) {
+            ids.push(key_id);
+        }
+    }
+
+    Ok(ids)
+}
+
+/// How often followers of the log are woken regardless of commits, to pick up entries committed
+/// by other processes, such as `fero-server` commands, and to retry whatever failed.
+const LOG_POLL_INTERVAL_SECS: u64 = 5;
+
+/// Followers of the log, which are woken whenever entries are committed, and periodically by a
+/// poller while there are any.
+#[derive(Default)]
+struct LogFollowers {
+    followers: Vec<UnboundedSender<()>>,
+    polling: bool,
+}
+
+impl LogFollowers {
+    /// Wake every follower. Followers which have gone away are dropped as they're found.
+    fn wake(&mut self) {
+        self.followers.retain(|follower| follower.unbounded_send(()).is_ok());
+    }
+}
+
+/// Which log entries to retrieve. Empty filters match every entry.
+#[derive(Clone, Debug, Default)]
+pub struct LogFilter {
+    pub max_index: Option<i32>,
+    pub request_types: Vec<OperationType>,
+    pub results: Vec<OperationResult>,
+    pub secret_name: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
 
 #[derive(Clone)]
 pub struct Configuration {
     connection_string: String,
+    trust_anchors: Vec<Vec<u8>>,
+    log_followers: Arc<Mutex<LogFollowers>>,
+    operations: Arc<Mutex<()>>,
+    merkle_tree: Arc<Mutex<Tree>>,
 }
 
 impl Configuration {
     pub fn new(connection_string: &str) -> Configuration {
-        Configuration { connection_string: connection_string.to_string() }
+        Configuration {
+            connection_string: connection_string.to_string(),
+            trust_anchors: Vec::new(),
+            log_followers: Arc::new(Mutex::new(LogFollowers::default())),
+            operations: Arc::new(Mutex::new(())),
+            merkle_tree: Arc::new(Mutex::new(Tree::new())),
+        }
+    }
+
+    /// Wait until no other operation is running. An operation's HSM log entries are attributed
+    /// to it by position, so only one may run at a time.
+    pub fn lock_operations(&self) -> Result<MutexGuard<()>, Error> {
+        self.operations
+            .lock()
+            .map_err(|_| format_err!("An operation panicked before it was logged"))
+    }
+
+    /// Require approvers' X.509 certificates to chain up to one of `trust_anchors` (DER or PEM
+    /// encoded certificates). Without any trust anchors, only the CMS signatures themselves are
+    /// checked against the enrolled certificates.
+    pub fn with_trust_anchors(mut self, trust_anchors: Vec<Vec<u8>>) -> Configuration {
+        self.trust_anchors = trust_anchors;
+        self
+    }
+
+    fn cms_context(&self, homedir: &TempDir) -> Result<Context, Error> {
+        let mut gpgsm = Context::from_protocol(Protocol::Cms)?;
+        gpgsm.set_engine_home_dir(homedir.path().as_os_str().as_bytes())?;
+
+        // The server isn't expected to have network access, so never try to fetch CRLs or
+        // certificates. Only the certificates imported here and those embedded in a signature
+        // are ever in the keybox.
+        File::create(homedir.path().join("gpgsm.conf"))?
+            .write_all(b"disable-crl-checks\ndisable-dirmngr\n")?;
+
+        // gpg-agent decides which root certificates are trusted based on its trustlist.
+        let mut trustlist = File::create(homedir.path().join("trustlist.txt"))?;
+        for anchor in &self.trust_anchors {
+            let result = gpgsm.import(anchor)?;
+            for import in result.imports() {
+                let fingerprint = import
+                    .fingerprint()
+                    .map_err(|_| format_err!("Failed to get trust anchor fingerprint"))?;
+                writeln!(trustlist, "{} S relax", fingerprint)?;
+            }
+        }
+
+        Ok(gpgsm)
     }
 
     pub fn authenticate(
@@ -44,50 +238,45 @@ impl Configuration {
             .filter(schema::user_secret_weights::columns::secret_id.eq(secret.id))
             .load::<UserKey>(&conn)?;
 
-        let mut gpg = Context::from_protocol(Protocol::OpenPgp)?;
-        let gpg_homedir = TempDir::new()?;
-        gpg.set_engine_home_dir(gpg_homedir.path().as_os_str().as_bytes())?;
+        let mut pgp = Context::from_protocol(Protocol::OpenPgp)?;
+        let pgp_homedir = TempDir::new()?;
+        pgp.set_engine_home_dir(pgp_homedir.path().as_os_str().as_bytes())?;
+        File::create(pgp_homedir.path().join("gpg.conf"))?.write_all(b"no-auto-key-retrieve\n")?;
 
+        let cms_homedir = TempDir::new()?;
+        let mut cms = self.cms_context(&cms_homedir)?;
+
+        let mut enrolled = HashMap::new();
         for user in applicable_users {
-            gpg.import(user.key_data)?;
+            match user.key_type {
+                UserKeyType::Pgp => {
+                    pgp.import(user.key_data)?;
+                }
+                UserKeyType::X509 => {
+                    import_certificate(&mut cms, &user.key_data, user.key_id, &mut enrolled)?;
+                }
+            };
         }
 
         let mut ids = HashSet::new();
         for signature in &ident.signatures {
-            let verification = gpg.verify_detached(signature, payload)?;
-
-            for signature in verification.signatures() {
-                // Valid signatures here actually should always have empty summaries, since the
-                // user keys we import above are not signed by a key trusted by our brand-new
-                // keyring, so all user keys have unknown validity. This check is primarily here to
-                // catch signatures over the wrong payload (SignatureSummary::RED), but this is the
-                // most technically-correct check (barring adding some extra plumbing above to
-                // generate a trusted key and sign each user key we import for each signing
-                // operation).
-                if !signature.summary().is_empty() &&
-                    signature.summary() != SignatureSummary::GREEN &&
-                    signature.summary() != SignatureSummary::VALID
-                {
-                    continue;
-                }
-
-                // It seems gpgme is not filling in the .key() field here, so we retrieve it from
-                // gpgme via the fingerprint of the signature.
-                let fingerprint = signature
-                    .fingerprint()
-                    .map_err(|_| format_err!("Failed to get signature fingerprint"))?;
-                if let Ok(signing_key) = gpg.find_key(fingerprint) {
-                    let signing_key_id = signing_key.id().map_err(|_| {
-                        format_err!("Failed to get signing key's ID from fingerprint")
-                    })?;
-                    ids.insert(u64::from_str_radix(signing_key_id, 16)? as i64);
-                }
+            if is_cms_signature(signature) {
+                ids.extend(verify_cms_signature(
+                    &mut cms,
+                    signature,
+                    payload,
+                    !self.trust_anchors.is_empty(),
+                    &enrolled,
+                )?);
+            } else {
+                ids.extend(verify_pgp_signature(&mut pgp, signature, payload)?);
             }
         }
 
         let mut weight = 0;
+        let mut approvers = Vec::new();
         for id in ids {
-            weight += schema::user_secret_weights::table
+            let user_weight = schema::user_secret_weights::table
                 .select(schema::user_secret_weights::columns::weight)
                 .inner_join(schema::users::table)
                 .filter(schema::user_secret_weights::columns::secret_id.eq(secret.id))
@@ -95,19 +284,63 @@ impl Configuration {
                 .load(&conn)?
                 .pop()
                 .unwrap_or(0);
+            if user_weight > 0 {
+                approvers.push(id as u64);
+            }
+            weight += user_weight;
         }
+        approvers.sort();
 
         if weight >= secret.threshold {
             Ok((AuthenticatedConnection {
                 connection: conn,
                 secret_key: secret.key_id.map(|id| id as u64),
                 secret_name: String::from(ident.get_secretKeyName()),
+                approvers,
             }, payload.to_vec()))
         } else {
             bail!("Signatures do not meet threshold");
         }
     }
 
+    /// Connect as the secret designated as the timestamp authority. Since a TSA has to answer
+    /// requests without any human involvement, designating a secret is what authorizes its use.
+    pub fn timestamp_authority(&self) -> Result<AuthenticatedConnection, Error> {
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        let secret = schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::tsa_policy.is_not_null())
+            .load::<SecretKey>(&conn)?
+            .pop()
+            .ok_or(format_err!("No secret is designated as the timestamp authority"))?;
+
+        Ok(AuthenticatedConnection {
+            secret_key: secret.key_id.map(|id| id as u64),
+            secret_name: secret.name,
+            approvers: Vec::new(),
+            connection: conn,
+        })
+    }
+
+    /// Connect as the secret designated to sign audit log checkpoints. Like the timestamp
+    /// authority, it signs without any human involvement.
+    pub fn audit_authority(&self) -> Result<AuthenticatedConnection, Error> {
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        let secret = schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::audit.eq(true))
+            .load::<SecretKey>(&conn)?
+            .pop()
+            .ok_or(format_err!("No secret is designated to sign audit log checkpoints"))?;
+
+        Ok(AuthenticatedConnection {
+            secret_key: secret.key_id.map(|id| id as u64),
+            secret_name: secret.name,
+            approvers: Vec::new(),
+            connection: conn,
+        })
+    }
+
     pub(crate) fn local_authenticate(
         &self,
         local_ident: LocalIdentification,
@@ -115,38 +348,110 @@ impl Configuration {
         Ok(AuthenticatedConnection {
             secret_key: local_ident.secret_key,
             secret_name: local_ident.name,
+            approvers: Vec::new(),
             connection: SqliteConnection::establish(&self.connection_string)?,
         })
     }
 
-    pub fn insert_user_key(&self, key_id: u64, key_data: &[u8]) -> Result<(), Error> {
+    pub fn insert_user_key(
+        &self,
+        key_id: u64,
+        key_type: UserKeyType,
+        key_data: &[u8],
+    ) -> Result<(), Error> {
         let conn = SqliteConnection::establish(&self.connection_string)?;
 
         diesel::insert_into(schema::users::dsl::users)
-            .values(&NewUserKey { key_id: key_id as i64, key_data })
+            .values(&NewUserKey { key_id: key_id as i64, key_data, key_type })
             .execute(&conn)
             .map(|_| ())
             .map_err(|e| e.into())
     }
 
-    pub fn insert_secret_key(&self, hsm_id: i32, key_id: Option<i64>, name: &str, threshold: i32) -> Result<(), Error> {
+    pub fn insert_secret_key(
+        &self,
+        hsm_id: i32,
+        key_id: Option<i64>,
+        public_key: &[u8],
+        algorithm: SecretAlgorithm,
+        name: &str,
+        threshold: i32,
+    ) -> Result<(), Error> {
         let conn = SqliteConnection::establish(&self.connection_string)?;
 
         diesel::insert_into(schema::secrets::dsl::secrets)
-            .values(&NewSecret { key_id: key_id, hsm_id, name: String::from(name), threshold })
+            .values(&NewSecret {
+                key_id: key_id,
+                hsm_id,
+                name: String::from(name),
+                threshold,
+                public_key: Some(public_key.to_vec()),
+                certificate: None,
+                algorithm,
+            })
             .execute(&conn)
             .map(|_| ())
             .map_err(|e| e.into())
     }
 
-    pub fn fero_logs_since(&self, idx: i32) -> Result<Vec<FeroLog>, Error> {
+    /// Up to `limit` log entries after `idx` which match `filter`, each with its HSM log
+    /// entries, from a single joined query.
+    pub fn fero_logs_page(
+        &self,
+        idx: i32,
+        limit: i64,
+        filter: &LogFilter,
+    ) -> Result<Vec<(FeroLog, Vec<HsmLog>)>, Error> {
+        use self::schema::{fero_logs, hsm_logs};
+
         let conn = SqliteConnection::establish(&self.connection_string)?;
 
-        schema::fero_logs::dsl::fero_logs
-            .order(schema::fero_logs::columns::id.asc())
-            .filter(schema::fero_logs::columns::id.gt(idx))
-            .load::<FeroLog>(&conn)
-            .map_err(|e| e.into())
+        let mut page = fero_logs::table
+            .select(fero_logs::id)
+            .filter(fero_logs::id.gt(idx))
+            .into_boxed();
+        if let Some(max_index) = filter.max_index {
+            page = page.filter(fero_logs::id.le(max_index));
+        }
+        if !filter.request_types.is_empty() {
+            page = page.filter(fero_logs::request_type.eq_any(filter.request_types.clone()));
+        }
+        if !filter.results.is_empty() {
+            page = page.filter(fero_logs::result.eq_any(filter.results.clone()));
+        }
+        if let Some(ref secret_name) = filter.secret_name {
+            page = page.filter(fero_logs::secret_name.eq(secret_name.clone()));
+        }
+        if let Some(since) = filter.since {
+            page = page.filter(fero_logs::timestamp.ge(since));
+        }
+        if let Some(until) = filter.until {
+            page = page.filter(fero_logs::timestamp.lt(until));
+        }
+        let page = page.order(fero_logs::id.asc()).limit(limit);
+
+        let rows = fero_logs::table
+            .left_join(
+                hsm_logs::table.on(hsm_logs::hsm_index
+                    .gt(fero_logs::hsm_index_start)
+                    .and(hsm_logs::hsm_index.le(fero_logs::hsm_index_end))),
+            )
+            .filter(fero_logs::id.eq_any(page))
+            .order((fero_logs::id.asc(), hsm_logs::hsm_index.asc()))
+            .load::<(FeroLog, Option<HsmLog>)>(&conn)?;
+
+        let mut entries: Vec<(FeroLog, Vec<HsmLog>)> = Vec::new();
+        for (fero_log, hsm_log) in rows {
+            let is_new_entry = entries.last().map(|&(ref last, _)| last.id) != Some(fero_log.id);
+            if is_new_entry {
+                entries.push((fero_log, Vec::new()));
+            }
+            if let (Some(hsm_log), Some(entry)) = (hsm_log, entries.last_mut()) {
+                entry.1.push(hsm_log);
+            }
+        }
+
+        Ok(entries)
     }
 
     pub fn associated_hsm_logs(&self, fero_log: &FeroLog) -> Result<Vec<HsmLog>, Error> {
@@ -178,6 +483,101 @@ impl Configuration {
             .pop())
     }
 
+    pub fn fero_log_entry(&self, id: i32) -> Result<Option<FeroLog>, Error> {
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        Ok(schema::fero_logs::dsl::fero_logs
+            .filter(schema::fero_logs::columns::id.eq(id))
+            .load::<FeroLog>(&conn)?
+            .pop())
+    }
+
+    /// The log's Merkle tree, whose leaves are the hashes of every log entry, in order. The tree
+    /// is kept between calls, and only the entries logged since are read.
+    pub fn merkle_tree(&self) -> Result<MutexGuard<Tree>, Error> {
+        let mut tree = self.merkle_tree
+            .lock()
+            .map_err(|_| format_err!("The log's Merkle tree is poisoned"))?;
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        let entries = schema::fero_logs::dsl::fero_logs
+            .select((schema::fero_logs::columns::id, schema::fero_logs::columns::hash))
+            .filter(schema::fero_logs::columns::id.gt(tree.len() as i32))
+            .order(schema::fero_logs::columns::id.asc())
+            .load::<(i32, Vec<u8>)>(&conn)?;
+        for (id, hash) in entries {
+            // A tree of size `n` covers the entries with IDs 1 to `n`.
+            if id as usize != tree.len() + 1 {
+                bail!("Log entry {} follows entry {}", id, tree.len());
+            }
+            tree.push(merkle::leaf_hash(&hash));
+        }
+
+        Ok(tree)
+    }
+
+    pub fn hsm_log_entry(&self, hsm_index: i32) -> Result<Option<HsmLog>, Error> {
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        Ok(schema::hsm_logs::dsl::hsm_logs
+            .filter(schema::hsm_logs::columns::hsm_index.eq(hsm_index))
+            .load::<HsmLog>(&conn)?
+            .pop())
+    }
+
+    pub fn checkpoints_since(&self, idx: i32) -> Result<Vec<Checkpoint>, Error> {
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        schema::checkpoints::dsl::checkpoints
+            .order(schema::checkpoints::columns::id.asc())
+            .filter(schema::checkpoints::columns::fero_log_id.gt(idx))
+            .load::<Checkpoint>(&conn)
+            .map_err(|e| e.into())
+    }
+
+    pub fn last_checkpoint(&self) -> Result<Option<Checkpoint>, Error> {
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        Ok(schema::checkpoints::dsl::checkpoints
+            .order(schema::checkpoints::columns::id.asc())
+            .load::<Checkpoint>(&conn)?
+            .pop())
+    }
+
+    /// The newest checkpoint of log entry `idx` or an earlier one.
+    pub fn last_checkpoint_covering(&self, idx: i32) -> Result<Option<Checkpoint>, Error> {
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        Ok(schema::checkpoints::dsl::checkpoints
+            .order(schema::checkpoints::columns::id.asc())
+            .filter(schema::checkpoints::columns::fero_log_id.le(idx))
+            .load::<Checkpoint>(&conn)?
+            .pop())
+    }
+
+    /// The ID of the last log entry shipped to `sink`, if any have been.
+    pub fn last_shipped_log_entry(&self, sink: &str) -> Result<Option<i32>, Error> {
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        schema::shipped_logs::dsl::shipped_logs
+            .select(schema::shipped_logs::columns::fero_log_id)
+            .filter(schema::shipped_logs::columns::sink.eq(sink))
+            .first::<i32>(&conn)
+            .optional()
+            .map_err(|e| e.into())
+    }
+
+    /// Record that every log entry up to `fero_log_id` has been shipped to `sink`.
+    pub fn set_last_shipped_log_entry(&self, sink: &str, fero_log_id: i32) -> Result<(), Error> {
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        diesel::replace_into(schema::shipped_logs::dsl::shipped_logs)
+            .values(&ShippedLog { sink, fero_log_id })
+            .execute(&conn)
+            .map(|_| ())
+            .map_err(|e| e.into())
+    }
+
     fn last_id(&self, conn: &SqliteConnection) -> Result<Option<i32>, Error> {
         no_arg_sql_function!(last_insert_rowid, diesel::sql_types::Integer);
         Ok(diesel::select(last_insert_rowid)
@@ -185,42 +585,203 @@ impl Configuration {
            .pop())
     }
 
-    pub fn insert_hsm_logs(&self, hsm_logs: Vec<NewHsmLog>) -> Result<Option<i32>, Error> {
-        let conn = SqliteConnection::establish(&self.connection_string)?;
+    /// Commit log entries in one transaction along with the HSM log entries they cover, the
+    /// removal of the intents they fulfil, and any changes held back on `changes`. `build` creates
+    /// the entries, in order, from the current head and the HSM log entries since the head's.
+    /// Returns the index of the last HSM log entry stored.
+    pub fn commit_log_entries<F>(
+        &self,
+        changes: Option<AuthenticatedConnection>,
+        intents: Vec<i32>,
+        hsm_logs: Vec<NewHsmLog>,
+        build: F,
+    ) -> Result<i32, Error>
+    where
+        F: FnOnce(&FeroLog, &[HsmLog]) -> Result<Vec<NewFeroLog>, Error>,
+    {
+        // Dropping the connection without committing rolls everything back.
+        let conn = match changes {
+            Some(changes) => changes.connection,
+            None => {
+                let conn = SqliteConnection::establish(&self.connection_string)?;
+                conn.transaction_manager().begin_transaction(&conn)?;
+                conn
+            }
+        };
 
-        conn.transaction(|| {
-            for log in hsm_logs {
-                diesel::insert_into(schema::hsm_logs::dsl::hsm_logs)
-                    .values(&log)
+        let head = || {
+            schema::fero_logs::dsl::fero_logs
+                .order(schema::fero_logs::columns::id.desc())
+                .first::<FeroLog>(&conn)
+                .optional()
+        };
+        let parent = match head()? {
+            Some(parent) => parent,
+            None => {
+                diesel::insert_into(schema::fero_logs::dsl::fero_logs)
+                    .values(&NewFeroLog::default())
                     .execute(&conn)?;
+                head()?.ok_or_else(|| format_err!("Couldn't retrieve root log entry"))?
             }
+        };
+
+        // Another operation may have committed some of the same HSM log entries first.
+        let last_hsm_index = schema::hsm_logs::dsl::hsm_logs
+            .select(diesel::dsl::max(schema::hsm_logs::columns::hsm_index))
+            .first::<Option<i32>>(&conn)?
+            .unwrap_or(0);
+        for log in hsm_logs.iter().filter(|log| log.hsm_index > last_hsm_index) {
+            diesel::insert_into(schema::hsm_logs::dsl::hsm_logs)
+                .values(log)
+                .execute(&conn)?;
+        }
+
+        // Any HSM log entries stored without a log entry of their own, by a server which didn't
+        // commit both at once, are covered too.
+        let covered = schema::hsm_logs::dsl::hsm_logs
+            .filter(schema::hsm_logs::columns::hsm_index.gt(parent.hsm_index_end))
+            .order(schema::hsm_logs::columns::hsm_index.asc())
+            .load::<HsmLog>(&conn)?;
+
+        for entry in build(&parent, &covered)? {
+            diesel::insert_into(schema::fero_logs::dsl::fero_logs)
+                .values(&entry)
+                .execute(&conn)?;
+        }
+        diesel::delete(
+            schema::log_intents::dsl::log_intents
+                .filter(schema::log_intents::columns::id.eq_any(intents)),
+        ).execute(&conn)?;
+
+        conn.transaction_manager().commit_transaction(&conn)?;
+
+        if let Ok(mut followers) = self.log_followers.lock() {
+            followers.wake();
+        }
 
-            self.last_id(&conn)
+        Ok(covered
+            .last()
+            .map(|hsm_log| hsm_log.hsm_index)
+            .unwrap_or(parent.hsm_index_end))
+    }
+
+    /// Record that an operation is about to run, returning the intent's ID.
+    pub fn insert_log_intent(&self, intent: NewLogIntent) -> Result<i32, Error> {
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        conn.transaction(|| {
+            diesel::insert_into(schema::log_intents::dsl::log_intents)
+                .values(&intent)
+                .execute(&conn)?;
+
+            self.last_id(&conn)?
+                .ok_or_else(|| format_err!("Couldn't retrieve log intent"))
         })
     }
 
-    pub fn insert_fero_log(&self, fero_log: NewFeroLog) -> Result<(), Error> {
+    pub fn delete_log_intent(&self, id: i32) -> Result<(), Error> {
         let conn = SqliteConnection::establish(&self.connection_string)?;
 
-        diesel::insert_into(schema::fero_logs::dsl::fero_logs)
-            .values(&fero_log)
+        diesel::delete(schema::log_intents::dsl::log_intents.find(id))
             .execute(&conn)
             .map(|_| ())
             .map_err(|e| e.into())
     }
+
+    /// Intents of operations which never finished being logged.
+    pub fn log_intents(&self) -> Result<Vec<LogIntent>, Error> {
+        let conn = SqliteConnection::establish(&self.connection_string)?;
+
+        schema::log_intents::dsl::log_intents
+            .order(schema::log_intents::columns::id.asc())
+            .load::<LogIntent>(&conn)
+            .map_err(|e| e.into())
+    }
+
+    /// Be woken each time a log entry is inserted through this configuration or a clone of it,
+    /// and every few seconds besides, since entries may be inserted by other processes. A wakeup
+    /// doesn't mean that anything has been logged.
+    pub fn follow_logs(&self) -> UnboundedReceiver<()> {
+        let (follower, wakeups) = mpsc::unbounded();
+        if let Ok(mut followers) = self.log_followers.lock() {
+            followers.followers.push(follower);
+            if !followers.polling {
+                followers.polling = true;
+                self.poll_logs();
+            }
+        }
+
+        wakeups
+    }
+
+    /// Wake the log's followers periodically, for as long as there are any.
+    fn poll_logs(&self) {
+        let followers = self.log_followers.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(LOG_POLL_INTERVAL_SECS));
+
+            let mut followers = match followers.lock() {
+                Ok(followers) => followers,
+                Err(_) => return,
+            };
+            followers.wake();
+            if followers.followers.is_empty() {
+                followers.polling = false;
+                return;
+            }
+        });
+    }
 }
 
 pub struct AuthenticatedConnection {
     secret_key: Option<u64>,
     secret_name: String,
+    approvers: Vec<u64>,
     connection: SqliteConnection,
 }
 
 impl AuthenticatedConnection {
+    /// Hold back changes made through this connection, so that they're only committed along with
+    /// the log entry of the operation making them, by `Configuration::commit_log_entries`. They're
+    /// rolled back if the connection is dropped first.
+    pub fn hold_changes(&self) -> Result<(), Error> {
+        Ok(self.connection
+            .transaction_manager()
+            .begin_transaction(&self.connection)?)
+    }
+
     pub(crate) fn get_pgp_key_id(&self) -> Option<u64> {
         self.secret_key
     }
 
+    pub fn get_name(&self) -> &str {
+        &self.secret_name
+    }
+
+    /// Store a checkpoint signed by this connection's secret, which must be the audit secret.
+    pub fn insert_checkpoint(&self, checkpoint: NewCheckpoint) -> Result<(), Error> {
+        diesel::insert_into(schema::checkpoints::dsl::checkpoints)
+            .values(&checkpoint)
+            .execute(&self.connection)
+            .map(|_| ())
+            .map_err(|e| e.into())
+    }
+
+    /// The key IDs of the users whose signatures counted towards the threshold, in ascending
+    /// order. This is empty for connections which weren't authenticated by user signatures.
+    pub fn get_approvers(&self) -> &[u64] {
+        &self.approvers
+    }
+
+    pub fn get_algorithm(&self) -> Result<SecretAlgorithm, Error> {
+        schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<SecretKey>(&self.connection)?
+            .pop()
+            .map(|key| key.algorithm)
+            .ok_or(format_err!("Secret key deleted while in use?"))
+    }
+
     pub fn get_hsm_key_id(&self) -> Result<u16, Error> {
         schema::secrets::dsl::secrets
             .filter(schema::secrets::columns::name.eq(&self.secret_name))
@@ -230,6 +791,309 @@ impl AuthenticatedConnection {
             .ok_or(format_err!("Secret key deleted while in use?"))
     }
 
+    pub fn get_public_key(&self) -> Result<Vec<u8>, Error> {
+        schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<SecretKey>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("Secret key deleted while in use?"))?
+            .public_key
+            .ok_or(format_err!(
+                "No public key recorded for secret {}; re-import it to use it for this operation",
+                self.secret_name
+            ))
+    }
+
+    pub fn get_certificate(&self) -> Result<Vec<u8>, Error> {
+        schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<SecretKey>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("Secret key deleted while in use?"))?
+            .certificate
+            .ok_or(format_err!("No certificate recorded for secret {}", self.secret_name))
+    }
+
+    /// The intermediate certificates recorded for the secret, in DER form.
+    pub fn get_certificate_chain(&self) -> Result<Vec<Vec<u8>>, Error> {
+        let chain = schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<SecretKey>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("Secret key deleted while in use?"))?
+            .certificate_chain
+            .unwrap_or_default();
+
+        let mut certificates = Vec::new();
+        let mut rest = &chain[..];
+        while !rest.is_empty() {
+            let (certificate, next) = DerElement::parse(rest)?;
+            certificates.push(certificate.raw.to_vec());
+            rest = next;
+        }
+
+        Ok(certificates)
+    }
+
+    pub fn set_certificate(&self, certificate: &[u8], chain: &[Vec<u8>]) -> Result<(), Error> {
+        diesel::update(
+            schema::secrets::dsl::secrets
+                .filter(schema::secrets::columns::name.eq(&self.secret_name)))
+            .set((
+                schema::secrets::dsl::certificate.eq(certificate),
+                schema::secrets::dsl::certificate_chain.eq(chain.concat()),
+            ))
+            .execute(&self.connection)
+            .map(|_| ())
+            .map_err(|e| e.into())
+    }
+
+    pub fn get_certificate_profile(&self) -> Result<CertificateProfile, Error> {
+        schema::certificate_profiles::table
+            .select(schema::certificate_profiles::all_columns)
+            .inner_join(schema::secrets::table)
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<CertificateProfile>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("No certificate profile for secret {}", self.secret_name))
+    }
+
+    pub fn upsert_certificate_profile(
+        &self,
+        mut profile: NewCertificateProfile,
+    ) -> Result<(), Error> {
+        let secret = schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<SecretKey>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("No secret key found ({})", self.secret_name))?;
+        profile.secret_id = secret.id;
+
+        self.connection.transaction(|| {
+            diesel::delete(
+                schema::certificate_profiles::dsl::certificate_profiles
+                    .filter(schema::certificate_profiles::dsl::secret_id.eq(secret.id)),
+            ).execute(&self.connection)?;
+
+            diesel::insert_into(schema::certificate_profiles::dsl::certificate_profiles)
+                .values(&profile)
+                .execute(&self.connection)
+                .map(|_| ())
+                .map_err(|e| e.into())
+        })
+    }
+
+    pub fn get_release_policy(&self) -> Result<ReleasePolicy, Error> {
+        schema::release_policies::table
+            .select(schema::release_policies::all_columns)
+            .inner_join(schema::secrets::table)
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<ReleasePolicy>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("No Release file policy for secret {}", self.secret_name))
+    }
+
+    pub fn upsert_release_policy(&self, mut policy: NewReleasePolicy) -> Result<(), Error> {
+        let secret = schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<SecretKey>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("No secret key found ({})", self.secret_name))?;
+        policy.secret_id = secret.id;
+
+        self.connection.transaction(|| {
+            diesel::delete(
+                schema::release_policies::dsl::release_policies
+                    .filter(schema::release_policies::dsl::secret_id.eq(secret.id)),
+            ).execute(&self.connection)?;
+
+            diesel::insert_into(schema::release_policies::dsl::release_policies)
+                .values(&policy)
+                .execute(&self.connection)
+                .map(|_| ())
+                .map_err(|e| e.into())
+        })
+    }
+
+    pub fn get_revoked_certificates(&self) -> Result<Vec<RevokedCertificate>, Error> {
+        schema::revoked_certificates::table
+            .select((
+                schema::revoked_certificates::columns::secret_id,
+                schema::revoked_certificates::columns::serial,
+                schema::revoked_certificates::columns::revocation_date,
+                schema::revoked_certificates::columns::reason,
+            ))
+            .inner_join(schema::secrets::table)
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .order(schema::revoked_certificates::columns::id.asc())
+            .load::<RevokedCertificate>(&self.connection)
+            .map_err(|e| e.into())
+    }
+
+    pub fn get_crl_number(&self) -> Result<i64, Error> {
+        schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<SecretKey>(&self.connection)?
+            .pop()
+            .map(|key| key.crl_number)
+            .ok_or(format_err!("Secret key deleted while in use?"))
+    }
+
+    /// Record that CRL number `crl_number` was issued, adding `revocations` to those it lists.
+    pub fn record_crl(
+        &self,
+        crl_number: i64,
+        revocations: &[RevokedCertificate],
+    ) -> Result<(), Error> {
+        let secret = schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<SecretKey>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("No secret key found ({})", self.secret_name))?;
+
+        self.connection.transaction(|| {
+            diesel::update(
+                schema::secrets::dsl::secrets.filter(schema::secrets::columns::id.eq(secret.id)),
+            ).set(schema::secrets::dsl::crl_number.eq(crl_number))
+                .execute(&self.connection)?;
+
+            for revocation in revocations {
+                diesel::insert_into(schema::revoked_certificates::dsl::revoked_certificates)
+                    .values(&RevokedCertificate {
+                        secret_id: secret.id,
+                        ..revocation.clone()
+                    })
+                    .execute(&self.connection)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn get_tuf_role(&self, role: &str) -> Result<TufRole, Error> {
+        schema::tuf_roles::table
+            .select(schema::tuf_roles::all_columns)
+            .inner_join(schema::secrets::table)
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .filter(schema::tuf_roles::columns::role.eq(role))
+            .load::<TufRole>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("Secret {} may not sign TUF {} metadata", self.secret_name, role))
+    }
+
+    /// Allow this secret to sign metadata for the TUF role `role`, expiring at most
+    /// `max_expiry_days` in the future. The highest version signed so far is kept.
+    pub fn upsert_tuf_role(&self, role: &str, max_expiry_days: i32) -> Result<(), Error> {
+        let secret = schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<SecretKey>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("No secret key found ({})", self.secret_name))?;
+
+        let updated = diesel::update(
+            schema::tuf_roles::dsl::tuf_roles
+                .filter(schema::tuf_roles::dsl::secret_id.eq(secret.id))
+                .filter(schema::tuf_roles::dsl::role.eq(role)),
+        ).set(schema::tuf_roles::dsl::max_expiry_days.eq(max_expiry_days))
+            .execute(&self.connection)?;
+
+        if updated == 0 {
+            diesel::insert_into(schema::tuf_roles::dsl::tuf_roles)
+                .values(&NewTufRole {
+                    secret_id: secret.id,
+                    role,
+                    max_expiry_days,
+                })
+                .execute(&self.connection)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_tuf_version(&self, role: &TufRole, version: i64) -> Result<(), Error> {
+        diesel::update(
+            schema::tuf_roles::dsl::tuf_roles.filter(schema::tuf_roles::dsl::id.eq(role.id)),
+        ).set(schema::tuf_roles::dsl::version.eq(version))
+            .execute(&self.connection)
+            .map(|_| ())
+            .map_err(|e| e.into())
+    }
+
+    pub fn get_tsa_policy(&self) -> Result<String, Error> {
+        schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<SecretKey>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("Secret key deleted while in use?"))?
+            .tsa_policy
+            .ok_or(format_err!("Secret {} is not a timestamp authority", self.secret_name))
+    }
+
+    /// Designate this secret as the timestamp authority, with the TSA policy OID `policy`. Only
+    /// one secret can be the timestamp authority at a time.
+    pub fn set_tsa_policy(&self, policy: &str) -> Result<(), Error> {
+        self.connection.transaction(|| {
+            diesel::update(schema::secrets::dsl::secrets)
+                .set(schema::secrets::dsl::tsa_policy.eq(None::<String>))
+                .execute(&self.connection)?;
+
+            diesel::update(
+                schema::secrets::dsl::secrets
+                    .filter(schema::secrets::columns::name.eq(&self.secret_name)))
+                .set(schema::secrets::dsl::tsa_policy.eq(policy))
+                .execute(&self.connection)
+                .map(|_| ())
+                .map_err(|e| e.into())
+        })
+    }
+
+    /// Designate this secret to sign audit log checkpoints. Only one secret can do so at a time.
+    pub fn set_audit(&self) -> Result<(), Error> {
+        self.connection.transaction(|| {
+            diesel::update(schema::secrets::dsl::secrets)
+                .set(schema::secrets::dsl::audit.eq(false))
+                .execute(&self.connection)?;
+
+            diesel::update(
+                schema::secrets::dsl::secrets
+                    .filter(schema::secrets::columns::name.eq(&self.secret_name)))
+                .set(schema::secrets::dsl::audit.eq(true))
+                .execute(&self.connection)
+                .map(|_| ())
+                .map_err(|e| e.into())
+        })
+    }
+
+    /// Record a timestamp for `message_imprint`, returning its serial number.
+    pub fn insert_timestamp(
+        &self,
+        message_imprint: &[u8],
+        gen_time: NaiveDateTime,
+    ) -> Result<i64, Error> {
+        let secret = schema::secrets::dsl::secrets
+            .filter(schema::secrets::columns::name.eq(&self.secret_name))
+            .load::<SecretKey>(&self.connection)?
+            .pop()
+            .ok_or(format_err!("No secret key found ({})", self.secret_name))?;
+
+        self.connection.transaction(|| {
+            diesel::insert_into(schema::timestamps::dsl::timestamps)
+                .values(&NewTimestamp {
+                    secret_id: secret.id,
+                    message_imprint,
+                    gen_time,
+                })
+                .execute(&self.connection)?;
+
+            schema::timestamps::dsl::timestamps
+                .select(schema::timestamps::columns::id)
+                .order(schema::timestamps::columns::id.desc())
+                .first::<i32>(&self.connection)
+                .map(i64::from)
+                .map_err(|e| e.into())
+        })
+    }
+
     pub fn get_user_key(&self, key_id: u64) -> Result<Option<UserKey>, Error> {
         Ok(schema::users::dsl::users
             .filter(schema::users::columns::key_id.eq(key_id as i64))
@@ -282,3 +1146,164 @@ impl AuthenticatedConnection {
             .map_err(|e| e.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use logging::{self, LoggedOperation};
+    use test::migrated_database;
+
+    const PAYLOAD: &[u8] = include_bytes!("../../testdata/cms/payload");
+    const ALICE_CERTIFICATE: &[u8] = include_bytes!("../../testdata/cms/alice.der");
+    const ALICE_SIGNATURE: &[u8] = include_bytes!("../../testdata/cms/alice.p7s");
+    // Mallory's signature embeds Mallory's certificate, whose fingerprint is
+    // 01F5B62E1DA97E7AECC7F5DA3A776598A1D74EC0.
+    const MALLORY_SIGNATURE: &[u8] = include_bytes!("../../testdata/cms/mallory.p7s");
+    const MALLORY_KEY_ID: i64 = 0x3A77_6598_A1D7_4EC0;
+
+    /// Verify `signature` with only Alice's certificate enrolled, as `key_id`.
+    fn verify(signature: &[u8], key_id: i64) -> Vec<i64> {
+        let homedir = TempDir::new().unwrap();
+        let mut gpgsm = Configuration::new("").cms_context(&homedir).unwrap();
+
+        let mut enrolled = HashMap::new();
+        import_certificate(&mut gpgsm, ALICE_CERTIFICATE, key_id, &mut enrolled).unwrap();
+
+        verify_cms_signature(&mut gpgsm, signature, PAYLOAD, false, &enrolled).unwrap()
+    }
+
+    #[test]
+    fn cms_signature_from_enrolled_certificate() {
+        assert_eq!(verify(ALICE_SIGNATURE, 1), vec![1]);
+    }
+
+    #[test]
+    fn cms_signature_from_other_certificate() {
+        assert!(verify(MALLORY_SIGNATURE, 1).is_empty());
+        // Sharing the enrolled user's key ID isn't enough either.
+        assert!(verify(MALLORY_SIGNATURE, MALLORY_KEY_ID).is_empty());
+    }
+
+    #[test]
+    fn cms_signature_over_other_payload() {
+        let homedir = TempDir::new().unwrap();
+        let mut gpgsm = Configuration::new("").cms_context(&homedir).unwrap();
+
+        let mut enrolled = HashMap::new();
+        import_certificate(&mut gpgsm, ALICE_CERTIFICATE, 1, &mut enrolled).unwrap();
+
+        let ids = verify_cms_signature(&mut gpgsm, ALICE_SIGNATURE, b"other", false, &enrolled);
+        assert!(ids.map(|ids| ids.is_empty()).unwrap_or(true));
+    }
+
+    /// A connection for the secret `name`, as if it had been authenticated.
+    fn connection(database: &Configuration, name: &str) -> AuthenticatedConnection {
+        AuthenticatedConnection {
+            secret_key: None,
+            secret_name: String::from(name),
+            approvers: Vec::new(),
+            connection: SqliteConnection::establish(&database.connection_string).unwrap(),
+        }
+    }
+
+    fn hsm_log(hsm_index: i32) -> NewHsmLog {
+        NewHsmLog {
+            hsm_index,
+            command: 0x56,
+            data_length: 32,
+            session_key: 1,
+            target_key: 2,
+            second_key: 0xffff,
+            result: 0x80 | 0x56,
+            systick: hsm_index,
+            hash: vec![hsm_index as u8; 16],
+        }
+    }
+
+    fn set_tuf_version(database: &Configuration, version: i64, hsm_logs: Vec<NewHsmLog>) {
+        let changes = connection(database, "tuf");
+        changes.hold_changes().unwrap();
+        let role = changes.get_tuf_role("root").unwrap();
+        changes.set_tuf_version(&role, version).unwrap();
+
+        let _ = database.commit_log_entries(Some(changes), Vec::new(), hsm_logs, |_, _| {
+            Ok(vec![NewFeroLog::default()])
+        });
+    }
+
+    #[test]
+    fn held_changes_commit_with_log_entries() {
+        let directory = TempDir::new().unwrap();
+        let database = migrated_database(&directory).unwrap();
+        database.insert_secret_key(1, None, &[0x30], SecretAlgorithm::Rsa, "tuf", 1).unwrap();
+        connection(&database, "tuf").upsert_tuf_role("root", 365).unwrap();
+
+        // The second HSM log entry has the same index as the first, so storing it fails, and
+        // with it the whole commit.
+        set_tuf_version(&database, 2, vec![hsm_log(1), hsm_log(1)]);
+        let role = connection(&database, "tuf").get_tuf_role("root").unwrap();
+        assert_eq!(role.version, 0);
+        assert!(database.last_fero_log_entry().unwrap().is_none());
+        assert!(database.last_hsm_log_entry().unwrap().is_none());
+
+        set_tuf_version(&database, 2, vec![hsm_log(1)]);
+        let role = connection(&database, "tuf").get_tuf_role("root").unwrap();
+        assert_eq!(role.version, 2);
+        // The root entry, and the operation's.
+        assert_eq!(database.last_fero_log_entry().unwrap().map(|log| log.id), Some(2));
+        assert_eq!(database.last_hsm_log_entry().unwrap().map(|log| log.hsm_index), Some(1));
+    }
+
+    fn operation(request_type: OperationType, secret_name: &str) -> LoggedOperation {
+        let mut identification = Identification::new();
+        identification.set_secretKeyName(String::from(secret_name));
+
+        LoggedOperation {
+            request_type,
+            result: OperationResult::Success,
+            identification: Some(identification),
+            signature: None,
+            revocation: None,
+            timestamp: NaiveDateTime::from_timestamp(0, 0),
+        }
+    }
+
+    #[test]
+    fn secret_name_filter() {
+        let directory = TempDir::new().unwrap();
+        let database = migrated_database(&directory).unwrap();
+
+        // None of these operations sign anything, so the secret comes from the identification.
+        let operations = vec![
+            operation(OperationType::Threshold, "release"),
+            operation(OperationType::IssueCertificate, "release"),
+            operation(OperationType::Weight, "other"),
+        ];
+        database
+            .commit_log_entries(None, Vec::new(), Vec::new(), |parent, _| {
+                let mut parent_hash = parent.hash.clone();
+                let mut entries = Vec::new();
+                for operation in operations {
+                    let entry = logging::create_fero_log(
+                        operation,
+                        &[],
+                        &parent_hash,
+                        parent.hsm_index_end,
+                    )?;
+                    parent_hash = entry.hash.clone();
+                    entries.push(entry);
+                }
+                Ok(entries)
+            })
+            .unwrap();
+
+        let filter = LogFilter {
+            secret_name: Some(String::from("release")),
+            ..LogFilter::default()
+        };
+        let page = database.fero_logs_page(0, 10, &filter).unwrap();
+        let ids = page.iter().map(|&(ref log, _)| log.id).collect::<Vec<_>>();
+        // Entry 1 is the root.
+        assert_eq!(ids, vec![2, 3]);
+    }
+}
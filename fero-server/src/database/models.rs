@@ -3,7 +3,9 @@ use chrono::naive::NaiveDateTime;
 use rand::{self, Rng};
 use sha2::{Sha256, Digest};
 
-use database::schema::{fero_logs, hsm_logs, secrets, users, user_secret_weights};
+use database::schema::{certificate_profiles, checkpoints, fero_logs, hsm_logs, log_intents,
+                       release_policies, revoked_certificates, secrets, shipped_logs, timestamps,
+                       tuf_roles, users, user_secret_weights};
 use fero_proto::log;
 
 #[derive(Queryable)]
@@ -13,6 +15,76 @@ pub struct SecretKey {
     pub threshold: i32,
     pub hsm_id: i32,
     pub name: String,
+    pub public_key: Option<Vec<u8>>,
+    pub certificate: Option<Vec<u8>>,
+    pub crl_number: i64,
+    pub tsa_policy: Option<String>,
+    pub algorithm: SecretAlgorithm,
+    /// Intermediate certificates between `certificate` and a root, as concatenated DER.
+    pub certificate_chain: Option<Vec<u8>>,
+    /// Whether this secret signs audit log checkpoints.
+    pub audit: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, DbEnum)]
+pub enum SecretAlgorithm {
+    Rsa,
+    Ed25519,
+}
+
+/// Constraints applied to every certificate issued by a secret. `key_usage` is a bitmask of the
+/// KeyUsage named bits, `extended_key_usage` a comma-separated list of dotted OIDs, and
+/// `dns_suffixes` a comma-separated list of domains under which subjectAltNames may fall, along
+/// with the commonNames of certificates which may authenticate TLS servers.
+#[derive(Queryable)]
+pub struct CertificateProfile {
+    pub id: i32,
+    pub secret_id: i32,
+    pub validity_days: i32,
+    pub key_usage: i32,
+    pub extended_key_usage: String,
+    pub dns_suffixes: String,
+    pub is_ca: bool,
+    pub path_length: Option<i32>,
+}
+
+/// Constraints on the Release files a secret signs: the `Date` must be within
+/// `max_date_age_hours` of the time of signing, and `Valid-Until` no more than
+/// `max_validity_days` after the `Date`.
+#[derive(Queryable)]
+pub struct ReleasePolicy {
+    pub id: i32,
+    pub secret_id: i32,
+    pub max_validity_days: i32,
+    pub max_date_age_hours: i32,
+}
+
+/// `serial` is the big-endian serial number, without any leading zeroes, and `reason` is an RFC
+/// 5280 CRLReason code.
+#[derive(Clone, Insertable, Queryable)]
+#[table_name = "revoked_certificates"]
+pub struct RevokedCertificate {
+    pub secret_id: i32,
+    pub serial: Vec<u8>,
+    pub revocation_date: NaiveDateTime,
+    pub reason: i32,
+}
+
+/// A TUF role which a secret may sign metadata for. `version` is the highest metadata version
+/// signed so far, and `max_expiry_days` bounds how far in the future metadata may expire.
+#[derive(Queryable)]
+pub struct TufRole {
+    pub id: i32,
+    pub secret_id: i32,
+    pub role: String,
+    pub max_expiry_days: i32,
+    pub version: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, DbEnum)]
+pub enum UserKeyType {
+    Pgp,
+    X509,
 }
 
 #[derive(Queryable)]
@@ -20,6 +92,7 @@ pub struct UserKey {
     pub id: i32,
     pub key_id: i64,
     pub key_data: Vec<u8>,
+    pub key_type: UserKeyType,
 }
 
 #[derive(Queryable)]
@@ -40,6 +113,12 @@ pub struct FeroLog {
     pub hsm_index_end: i32,
     pub identification: Option<Vec<u8>>,
     pub hash: Vec<u8>,
+    pub secret_name: Option<String>,
+    pub signature_type: Option<String>,
+    pub artifact_digest: Option<Vec<u8>>,
+    pub signature_hash: Option<Vec<u8>>,
+    pub revoked_serial: Option<Vec<u8>>,
+    pub revocation_reason: Option<i32>,
 }
 
 #[derive(Queryable)]
@@ -56,6 +135,18 @@ pub struct HsmLog {
     pub hash: Vec<u8>,
 }
 
+#[derive(Queryable)]
+pub struct Checkpoint {
+    pub id: i32,
+    pub fero_log_id: i32,
+    pub log_hash: Vec<u8>,
+    pub hsm_index: i32,
+    pub hsm_hash: Vec<u8>,
+    pub timestamp: NaiveDateTime,
+    pub signature: Vec<u8>,
+    pub tree_root: Option<Vec<u8>>,
+}
+
 #[derive(Insertable)]
 #[table_name = "secrets"]
 pub struct NewSecret {
@@ -63,6 +154,45 @@ pub struct NewSecret {
     pub threshold: i32,
     pub hsm_id: i32,
     pub name: String,
+    pub public_key: Option<Vec<u8>>,
+    pub certificate: Option<Vec<u8>>,
+    pub algorithm: SecretAlgorithm,
+}
+
+#[derive(Insertable)]
+#[table_name = "certificate_profiles"]
+pub struct NewCertificateProfile {
+    pub secret_id: i32,
+    pub validity_days: i32,
+    pub key_usage: i32,
+    pub extended_key_usage: String,
+    pub dns_suffixes: String,
+    pub is_ca: bool,
+    pub path_length: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "release_policies"]
+pub struct NewReleasePolicy {
+    pub secret_id: i32,
+    pub max_validity_days: i32,
+    pub max_date_age_hours: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "timestamps"]
+pub struct NewTimestamp<'a> {
+    pub secret_id: i32,
+    pub message_imprint: &'a [u8],
+    pub gen_time: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "tuf_roles"]
+pub struct NewTufRole<'a> {
+    pub secret_id: i32,
+    pub role: &'a str,
+    pub max_expiry_days: i32,
 }
 
 #[derive(Insertable)]
@@ -70,6 +200,7 @@ pub struct NewSecret {
 pub struct NewUserKey<'a> {
     pub key_id: i64,
     pub key_data: &'a [u8],
+    pub key_type: UserKeyType,
 }
 
 #[derive(Insertable)]
@@ -80,6 +211,20 @@ pub struct NewWeight {
     pub weight: i32,
 }
 
+#[derive(Queryable)]
+pub struct LogIntent {
+    pub id: i32,
+    pub request_type: log::OperationType,
+    pub timestamp: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "log_intents"]
+pub struct NewLogIntent {
+    pub request_type: log::OperationType,
+    pub timestamp: NaiveDateTime,
+}
+
 #[derive(Insertable)]
 #[table_name = "fero_logs"]
 pub struct NewFeroLog {
@@ -90,6 +235,12 @@ pub struct NewFeroLog {
     pub hsm_index_end: i32,
     pub identification: Option<Vec<u8>>,
     pub hash: Vec<u8>,
+    pub secret_name: Option<String>,
+    pub signature_type: Option<String>,
+    pub artifact_digest: Option<Vec<u8>>,
+    pub signature_hash: Option<Vec<u8>>,
+    pub revoked_serial: Option<Vec<u8>>,
+    pub revocation_reason: Option<i32>,
 }
 
 impl Default for NewFeroLog {
@@ -113,6 +264,12 @@ impl Default for NewFeroLog {
             hsm_index_end: 0,
             identification: None,
             hash: Vec::from(hash),
+            secret_name: None,
+            signature_type: None,
+            artifact_digest: None,
+            signature_hash: None,
+            revoked_serial: None,
+            revocation_reason: None,
         }
     }
 }
@@ -130,3 +287,23 @@ pub struct NewHsmLog {
     pub systick: i32,
     pub hash: Vec<u8>,
 }
+
+#[derive(Insertable)]
+#[table_name = "checkpoints"]
+pub struct NewCheckpoint {
+    pub fero_log_id: i32,
+    pub log_hash: Vec<u8>,
+    pub hsm_index: i32,
+    pub hsm_hash: Vec<u8>,
+    pub timestamp: NaiveDateTime,
+    pub signature: Vec<u8>,
+    pub tree_root: Option<Vec<u8>>,
+}
+
+/// The last log entry shipped to a sink, which is named as by `LogSink::name`.
+#[derive(Insertable)]
+#[table_name = "shipped_logs"]
+pub struct ShippedLog<'a> {
+    pub sink: &'a str,
+    pub fero_log_id: i32,
+}
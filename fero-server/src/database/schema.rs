@@ -1,10 +1,71 @@
 table! {
+    use diesel::sql_types::{BigInt, Binary, Bool, Integer, Nullable, Text};
+    use database::models::SecretAlgorithmMapping;
+
     secrets (id) {
         id -> Integer,
         key_id -> Nullable<BigInt>,
         threshold -> Integer,
         hsm_id -> Integer,
         name -> Text,
+        public_key -> Nullable<Binary>,
+        certificate -> Nullable<Binary>,
+        crl_number -> BigInt,
+        tsa_policy -> Nullable<Text>,
+        algorithm -> SecretAlgorithmMapping,
+        certificate_chain -> Nullable<Binary>,
+        audit -> Bool,
+    }
+}
+
+table! {
+    timestamps (id) {
+        id -> Integer,
+        secret_id -> Integer,
+        message_imprint -> Binary,
+        gen_time -> Timestamp,
+    }
+}
+
+table! {
+    certificate_profiles (id) {
+        id -> Integer,
+        secret_id -> Integer,
+        validity_days -> Integer,
+        key_usage -> Integer,
+        extended_key_usage -> Text,
+        dns_suffixes -> Text,
+        is_ca -> Bool,
+        path_length -> Nullable<Integer>,
+    }
+}
+
+table! {
+    release_policies (id) {
+        id -> Integer,
+        secret_id -> Integer,
+        max_validity_days -> Integer,
+        max_date_age_hours -> Integer,
+    }
+}
+
+table! {
+    revoked_certificates (id) {
+        id -> Integer,
+        secret_id -> Integer,
+        serial -> Binary,
+        revocation_date -> Timestamp,
+        reason -> Integer,
+    }
+}
+
+table! {
+    tuf_roles (id) {
+        id -> Integer,
+        secret_id -> Integer,
+        role -> Text,
+        max_expiry_days -> Integer,
+        version -> BigInt,
     }
 }
 
@@ -18,18 +79,27 @@ table! {
 }
 
 table! {
+    use diesel::sql_types::{BigInt, Binary, Integer};
+    use database::models::UserKeyTypeMapping;
+
     users (id) {
         id -> Integer,
         key_id -> BigInt,
         key_data -> Binary,
+        key_type -> UserKeyTypeMapping,
     }
 }
 
+joinable!(certificate_profiles -> secrets (secret_id));
+joinable!(release_policies -> secrets (secret_id));
+joinable!(revoked_certificates -> secrets (secret_id));
+joinable!(timestamps -> secrets (secret_id));
+joinable!(tuf_roles -> secrets (secret_id));
 joinable!(user_secret_weights -> secrets (secret_id));
 joinable!(user_secret_weights -> users (user_id));
 
 table! {
-    use diesel::sql_types::{Binary, Integer, Nullable, Timestamp};
+    use diesel::sql_types::{Binary, Integer, Nullable, Text, Timestamp};
     use fero_proto::log::{OperationResultMapping, OperationTypeMapping};
 
     fero_logs (id) {
@@ -41,6 +111,36 @@ table! {
         hsm_index_end -> Integer,
         identification -> Nullable<Binary>,
         hash -> Binary,
+        secret_name -> Nullable<Text>,
+        signature_type -> Nullable<Text>,
+        artifact_digest -> Nullable<Binary>,
+        signature_hash -> Nullable<Binary>,
+        revoked_serial -> Nullable<Binary>,
+        revocation_reason -> Nullable<Integer>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Integer, Timestamp};
+    use fero_proto::log::OperationTypeMapping;
+
+    log_intents (id) {
+        id -> Integer,
+        request_type -> OperationTypeMapping,
+        timestamp -> Timestamp,
+    }
+}
+
+table! {
+    checkpoints (id) {
+        id -> Integer,
+        fero_log_id -> Integer,
+        log_hash -> Binary,
+        hsm_index -> Integer,
+        hsm_hash -> Binary,
+        timestamp -> Timestamp,
+        signature -> Binary,
+        tree_root -> Nullable<Binary>,
     }
 }
 
@@ -59,8 +159,22 @@ table! {
     }
 }
 
+table! {
+    shipped_logs (sink) {
+        sink -> Text,
+        fero_log_id -> Integer,
+    }
+}
+
 allow_tables_to_appear_in_same_query!(
+    certificate_profiles,
+    fero_logs,
+    hsm_logs,
+    release_policies,
+    revoked_certificates,
     secrets,
+    timestamps,
+    tuf_roles,
     user_secret_weights,
     users,
 );
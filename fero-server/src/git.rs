@@ -0,0 +1,105 @@
+use failure::Error;
+
+// See the "Signed Commits" and "Signed Tags" sections of git's signature-format documentation.
+
+const SIGNATURE_BEGIN: &[u8] = b"-----BEGIN PGP SIGNATURE-----";
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+/// The raw commit object, as signed, is everything other than the `gpgsig` header. Return the
+/// commit with that header, holding the armored signature `signature`, added to the end of its
+/// headers.
+pub fn embed_commit_signature(commit: &[u8], signature: &str) -> Result<Vec<u8>, Error> {
+    if !commit.starts_with(b"tree ") {
+        bail!("Payload is not a git commit object");
+    }
+    if commit.starts_with(b"gpgsig ") || contains(commit, b"\ngpgsig ") {
+        bail!("Git commit is already signed");
+    }
+
+    // The headers end at the first blank line, or at the end of the object if it has no message.
+    let headers_end = commit
+        .windows(2)
+        .position(|window| window == b"\n\n")
+        .map(|index| index + 1)
+        .unwrap_or_else(|| commit.len());
+    if commit[..headers_end].last() != Some(&b'\n') {
+        bail!("Malformed git commit object");
+    }
+
+    // Continuation lines of a header start with a space.
+    let mut header = String::from("gpgsig");
+    for line in signature.trim_right_matches('\n').split('\n') {
+        header.push(' ');
+        header.push_str(line);
+        header.push('\n');
+    }
+
+    let mut out = commit[..headers_end].to_vec();
+    out.extend_from_slice(header.as_bytes());
+    out.extend_from_slice(&commit[headers_end..]);
+
+    Ok(out)
+}
+
+/// Tag signatures are simply appended to the signed tag object.
+pub fn embed_tag_signature(tag: &[u8], signature: &str) -> Result<Vec<u8>, Error> {
+    if !tag.starts_with(b"object ") {
+        bail!("Payload is not a git tag object");
+    }
+    if contains(tag, SIGNATURE_BEGIN) {
+        bail!("Git tag is already signed");
+    }
+    if tag.last() != Some(&b'\n') {
+        bail!("Git tag objects must end with a newline to be signed");
+    }
+
+    let mut out = tag.to_vec();
+    out.extend_from_slice(signature.as_bytes());
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Objects from `git commit -S` and `git tag -s`, with the payloads git gave gpg.program and
+    // the signature it returned.
+    const COMMIT: &[u8] = include_bytes!("../testdata/git/commit");
+    const SIGNED_COMMIT: &[u8] = include_bytes!("../testdata/git/commit.signed");
+    const TAG: &[u8] = include_bytes!("../testdata/git/tag");
+    const SIGNED_TAG: &[u8] = include_bytes!("../testdata/git/tag.signed");
+    const SIGNATURE: &str = include_str!("../testdata/git/signature.asc");
+
+    #[test]
+    fn commits_match_git() {
+        assert_eq!(embed_commit_signature(COMMIT, SIGNATURE).unwrap(), SIGNED_COMMIT);
+
+        assert!(embed_commit_signature(SIGNED_COMMIT, SIGNATURE).is_err());
+        assert!(embed_commit_signature(TAG, SIGNATURE).is_err());
+        assert!(embed_commit_signature(b"tree 4b825dc642cb6eb9a060e54bf8d69288", SIGNATURE)
+            .is_err());
+    }
+
+    #[test]
+    fn commits_without_messages() {
+        let commit = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n";
+        assert_eq!(
+            embed_commit_signature(commit, "-----BEGIN PGP SIGNATURE-----\n").unwrap(),
+            &b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+               gpgsig -----BEGIN PGP SIGNATURE-----\n"[..]
+        );
+    }
+
+    #[test]
+    fn tags_match_git() {
+        assert_eq!(embed_tag_signature(TAG, SIGNATURE).unwrap(), SIGNED_TAG);
+
+        assert!(embed_tag_signature(SIGNED_TAG, SIGNATURE).is_err());
+        assert!(embed_tag_signature(COMMIT, SIGNATURE).is_err());
+        assert!(embed_tag_signature(&TAG[..TAG.len() - 1], SIGNATURE).is_err());
+    }
+}
@@ -39,10 +39,11 @@ impl Hsm {
         &self,
         payload: T,
         signing_key: u16,
+        signature_type: SignatureType,
         hash_algorithm: HashAlgorithm,
     ) -> Result<SignaturePacket, Error> {
         let mut sig_packet = SignaturePacket::new(
-            SignatureType::BinaryDocument,
+            signature_type,
             PublicKeyAlgorithm::Rsa,
             hash_algorithm,
         )?;
@@ -81,15 +82,7 @@ impl Hsm {
             b => bail!("Unknown RSA key size: {}", b),
         };
 
-        let objects = self.session
-            .list_objects()
-            .object_type(ObjectType::Asymmetric)
-            .execute()?;
-        let object_id = match (1..).find(|id| objects.iter().find(|obj| obj.id == *id).is_none()) {
-            Some(id) => id,
-            None => bail!("Couldn't find a suitable free object ID"),
-        };
-
+        let object_id = self.free_object_id()?;
         let this_authkey = self.session
             .get_object_info(self.authkey, ObjectType::AuthKey)?;
 
@@ -106,6 +99,50 @@ impl Hsm {
         Ok(object_id)
     }
 
+    /// Import an Ed25519 key from its 32-byte seed, returning its object ID and public key.
+    pub fn put_ed25519_key(&self, seed: &[u8]) -> Result<(u16, Vec<u8>), Error> {
+        if seed.len() != 32 {
+            bail!("Ed25519 private keys must be 32 bytes long");
+        }
+
+        let object_id = self.free_object_id()?;
+        let this_authkey = self.session
+            .get_object_info(self.authkey, ObjectType::AuthKey)?;
+
+        self.session.put_key_ed(
+            object_id,
+            "",
+            &this_authkey.domains,
+            &[Capability::AsymmetricSignEddsa],
+            Algorithm::Ed25519,
+            seed,
+        )?;
+        let public_key = self.session.get_pubkey(object_id)?;
+
+        Ok((object_id, public_key))
+    }
+
+    /// Ed25519 signs the message itself rather than a hash of it, so `payload` must be small
+    /// enough to fit in a single HSM command.
+    pub fn create_ed25519_signature<T: AsRef<[u8]>>(
+        &self,
+        payload: T,
+        signing_key: u16,
+    ) -> Result<Vec<u8>, Error> {
+        self.session.sign_eddsa(signing_key, payload.as_ref())
+    }
+
+    fn free_object_id(&self) -> Result<u16, Error> {
+        let objects = self.session
+            .list_objects()
+            .object_type(ObjectType::Asymmetric)
+            .execute()?;
+        match (1..).find(|id| objects.iter().find(|obj| obj.id == *id).is_none()) {
+            Some(id) => Ok(id),
+            None => bail!("Couldn't find a suitable free object ID"),
+        }
+    }
+
     pub fn logs(&self) -> Result<Vec<LogEntry>, Error> {
         Ok(self.session.get_logs()?
            .log_entries()
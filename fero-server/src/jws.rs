@@ -0,0 +1,163 @@
+use base64;
+use failure::Error;
+use sha2::{Digest, Sha256};
+
+use database::models::SecretAlgorithm;
+use fero_proto::fero::JwsRequest_Serialization;
+use fero_proto::rsa;
+use hsm::Hsm;
+
+// See RFC 7515 for JWS, RFC 7638 for JWK thumbprints and RFC 8037 for EdDSA.
+
+fn base64url<T: AsRef<[u8]>>(data: T) -> String {
+    base64::encode_config(data.as_ref(), base64::URL_SAFE_NO_PAD)
+}
+
+/// The JWK thumbprint of a secret's public key (a PKCS#1 RSAPublicKey, or the 32 bytes of an
+/// Ed25519 key), used as the `kid` of signatures made with it so that verifiers can pick the right
+/// key out of a JWK set.
+pub fn key_id(algorithm: SecretAlgorithm, public_key: &[u8]) -> Result<String, Error> {
+    // The thumbprint covers the required members only, in lexicographic order and without
+    // whitespace. None of them need escaping.
+    let jwk = match algorithm {
+        SecretAlgorithm::Rsa => {
+            let (n, e) = rsa::parse_public_key(public_key)?;
+            format!(
+                r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#,
+                base64url(e.to_bytes_be()),
+                base64url(n.to_bytes_be()),
+            )
+        }
+        SecretAlgorithm::Ed25519 => {
+            if public_key.len() != 32 {
+                bail!("Secret does not have an Ed25519 public key");
+            }
+            format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, base64url(public_key))
+        }
+    };
+
+    let mut hasher = Sha256::default();
+    hasher.input(jwk.as_bytes());
+    Ok(base64url(hasher.result()))
+}
+
+/// The encoded protected header for signatures by a secret. RSA secrets sign with RS256 and
+/// Ed25519 secrets with EdDSA.
+fn protected_header(algorithm: SecretAlgorithm, public_key: &[u8]) -> Result<String, Error> {
+    let alg = match algorithm {
+        SecretAlgorithm::Rsa => "RS256",
+        SecretAlgorithm::Ed25519 => "EdDSA",
+    };
+
+    Ok(base64url(format!(
+        r#"{{"alg":"{}","kid":"{}"}}"#,
+        alg,
+        key_id(algorithm, public_key)?
+    )))
+}
+
+fn serialize(
+    protected: &str,
+    payload: &str,
+    signature: &str,
+    serialization: JwsRequest_Serialization,
+) -> String {
+    match serialization {
+        JwsRequest_Serialization::COMPACT => format!("{}.{}.{}", protected, payload, signature),
+        JwsRequest_Serialization::JSON => format!(
+            r#"{{"payload":"{}","protected":"{}","signature":"{}"}}"#,
+            payload, protected, signature,
+        ),
+    }
+}
+
+/// Sign `payload` as a JWS with `key` in the HSM, whose public key is `public_key`.
+pub fn sign(
+    hsm: &Hsm,
+    key: u16,
+    algorithm: SecretAlgorithm,
+    public_key: &[u8],
+    payload: &[u8],
+    serialization: JwsRequest_Serialization,
+) -> Result<String, Error> {
+    let protected = protected_header(algorithm, public_key)?;
+    let payload = base64url(payload);
+    let signing_input = format!("{}.{}", protected, payload);
+
+    let signature = match algorithm {
+        SecretAlgorithm::Rsa => {
+            let mut hasher = Sha256::default();
+            hasher.input(signing_input.as_bytes());
+            hsm.create_rsa_signature(hasher.result(), key)?
+        }
+        SecretAlgorithm::Ed25519 => {
+            let signature = hsm.create_ed25519_signature(signing_input.as_bytes(), key)?;
+            if signature.len() != 64 {
+                bail!("HSM returned a malformed Ed25519 signature");
+            }
+            signature
+        }
+    };
+
+    Ok(serialize(&protected, &payload, &base64url(signature), serialization))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The RSA key from RFC 7638 section 3.1, as a PKCS#1 RSAPublicKey.
+    const RFC_7638_KEY: &[u8] = include_bytes!("../testdata/jws/rfc7638.der");
+
+    // The Ed25519 key from RFC 8037 appendix A.2.
+    const RFC_8037_KEY: &[u8] = &[
+        0xd7, 0x5a, 0x98, 0x01, 0x82, 0xb1, 0x0a, 0xb7, 0xd5, 0x4b, 0xfe, 0xd3, 0xc9, 0x64, 0x07,
+        0x3a, 0x0e, 0xe1, 0x72, 0xf3, 0xda, 0xa6, 0x23, 0x25, 0xaf, 0x02, 0x1a, 0x68, 0xf7, 0x07,
+        0x51, 0x1a,
+    ];
+
+    #[test]
+    fn thumbprints() {
+        assert_eq!(
+            key_id(SecretAlgorithm::Rsa, RFC_7638_KEY).unwrap(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+        assert_eq!(
+            key_id(SecretAlgorithm::Ed25519, RFC_8037_KEY).unwrap(),
+            "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k"
+        );
+    }
+
+    #[test]
+    fn mismatched_keys() {
+        assert!(key_id(SecretAlgorithm::Rsa, RFC_8037_KEY).is_err());
+        assert!(key_id(SecretAlgorithm::Ed25519, RFC_7638_KEY).is_err());
+        assert!(key_id(SecretAlgorithm::Ed25519, &RFC_8037_KEY[1..]).is_err());
+    }
+
+    #[test]
+    fn headers() {
+        let decode = |header: String| {
+            let json = base64::decode_config(&header, base64::URL_SAFE_NO_PAD).unwrap();
+            String::from_utf8(json).unwrap()
+        };
+
+        assert_eq!(
+            decode(protected_header(SecretAlgorithm::Rsa, RFC_7638_KEY).unwrap()),
+            r#"{"alg":"RS256","kid":"NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"}"#
+        );
+        assert_eq!(
+            decode(protected_header(SecretAlgorithm::Ed25519, RFC_8037_KEY).unwrap()),
+            r#"{"alg":"EdDSA","kid":"kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k"}"#
+        );
+    }
+
+    #[test]
+    fn serializations() {
+        assert_eq!(serialize("a", "b", "c", JwsRequest_Serialization::COMPACT), "a.b.c");
+        assert_eq!(
+            serialize("a", "b", "c", JwsRequest_Serialization::JSON),
+            r#"{"payload":"b","protected":"a","signature":"c"}"#
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use failure::Error;
+use sha2::{Digest, Sha256};
+
+use cms::{self, SignedData};
+use hsm::Hsm;
+use x509::Certificate;
+
+// See the kernel's module-signing documentation and scripts/sign-file.c for this format.
+
+const MODULE_SIGNATURE_MAGIC: &[u8] = b"~Module signature appended~\n";
+const PKEY_ID_PKCS7: u8 = 2;
+
+/// The SignedData for a detached PKCS#7 signature over `module`, as `sign-file -d` writes it: no
+/// signed attributes and no certificates, with the signer identified by the issuer and serial
+/// number of the key's certificate, which the kernel already holds.
+fn signed_data<'a>(certificate: &'a Certificate, module: &[u8]) -> Result<SignedData<'a>, Error> {
+    if module.ends_with(MODULE_SIGNATURE_MAGIC) {
+        bail!("Kernel module is already signed");
+    }
+
+    let mut hasher = Sha256::default();
+    hasher.input(module);
+    let digest: &[u8] = &hasher.result();
+
+    Ok(SignedData::new(certificate, cms::OID_DATA, digest).without_signed_attributes())
+}
+
+/// A detached PKCS#7 signature over `module` by `key`, whose certificate is `certificate`.
+pub fn signature(
+    hsm: &Hsm,
+    key: u16,
+    certificate: &Certificate,
+    module: &[u8],
+) -> Result<Vec<u8>, Error> {
+    signed_data(certificate, module)?.sign(hsm, key)
+}
+
+/// Append `signature` to `module`, followed by the trailer describing it.
+pub fn embed_signature(module: &[u8], signature: &[u8]) -> Vec<u8> {
+    let mut out = module.to_vec();
+    out.extend_from_slice(signature);
+
+    // struct module_signature: the algorithm, hash, signer and key ID fields are unused for
+    // PKCS#7 signatures, then three bytes of padding and the signature's length.
+    out.extend_from_slice(&[0, 0, PKEY_ID_PKCS7, 0, 0, 0, 0, 0]);
+    out.write_u32::<BigEndian>(signature.len() as u32).unwrap();
+    out.extend_from_slice(MODULE_SIGNATURE_MAGIC);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An object file standing in for a module, the signature that its key in testdata/cms made
+    // with `openssl pkeyutl` over its digest, and the resulting SignedData, which `openssl cms
+    // -verify` accepts. openssl's `cms -sign -noattr -nocerts`, as used by sign-file, makes the
+    // same signature, but omits the NULL digest algorithm parameters. The signed module has the
+    // SignedData and trailer appended, laid out following the kernel's struct module_signature.
+    const SIGNER: &[u8] = include_bytes!("../testdata/cms/signer.der");
+    const MODULE: &[u8] = include_bytes!("../testdata/kmod/module.ko");
+    const RSA_SIGNATURE: &[u8] = include_bytes!("../testdata/kmod/module.sig");
+    const SIGNATURE: &[u8] = include_bytes!("../testdata/kmod/module.p7s");
+    const SIGNED_MODULE: &[u8] = include_bytes!("../testdata/kmod/module.signed.ko");
+
+    #[test]
+    fn signatures() {
+        let certificate = Certificate::from_der(SIGNER).unwrap();
+        let signed_data = signed_data(&certificate, MODULE).unwrap();
+
+        let mut hasher = Sha256::default();
+        hasher.input(MODULE);
+        assert_eq!(signed_data.signature_digest(), hasher.result().to_vec());
+        assert_eq!(signed_data.encode(RSA_SIGNATURE), SIGNATURE);
+    }
+
+    #[test]
+    fn embedding() {
+        assert_eq!(embed_signature(MODULE, SIGNATURE), SIGNED_MODULE);
+
+        let certificate = Certificate::from_der(SIGNER).unwrap();
+        assert!(signed_data(&certificate, SIGNED_MODULE).is_err());
+    }
+}
@@ -1,7 +1,8 @@
 use std::fs::File;
 use std::io::Read;
 use std::mem::drop;
-use std::path::Path;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::str;
 use std::thread;
 use std::time::{Duration, Instant};
@@ -11,27 +12,46 @@ use diesel::{sqlite::SqliteConnection, Connection};
 use diesel_migrations::run_pending_migrations;
 use failure::Error;
 use gag::Gag;
+use gpgme::{Context, Protocol};
 use libyubihsm::{Capability, ObjectType, ReturnCode, Yubihsm};
 use num::BigUint;
 use pem;
 use pretty_good::{Key, KeyMaterial, Packet};
 use secstr::SecStr;
-use yasna;
+use tempfile::TempDir;
+use yasna::{self, models::ObjectIdentifier};
 
-use database;
+use database::{self, AuthenticatedConnection};
+use database::models::{NewCertificateProfile, NewReleasePolicy, SecretAlgorithm, UserKeyType};
+use fero_proto::export::LogExport;
+use fero_proto::fero::Identification;
 use fero_proto::log::*;
 use hsm::Hsm;
 use logging;
+use minisign;
+use tsa;
+use tuf;
+use x509::{self, Certificate, DerElement};
 
 const DEFAULT_HSM_AUTHKEY_ID: u16 = 1;
 const DEFAULT_HSM_PASSWORD: &'static str = "password";
 
+const OID_ED25519: &[u64] = &[1, 3, 101, 112];
+
 pub(crate) struct LocalIdentification {
     pub(crate) secret_key: Option<u64>,
     pub(crate) name: String,
     _priv: (),
 }
 
+/// Names the secret a local operation acts on in its log entry, so that it's found when the log
+/// is filtered by secret.
+fn logged_identification(secret_key_name: &str) -> Identification {
+    let mut identification = Identification::new();
+    identification.set_secretKeyName(String::from(secret_key_name));
+    identification
+}
+
 pub(crate) fn find_secret_subkey(packets_bytes: &[u8], subkey_id: &BigUint) -> Result<Key, Error> {
     let packets = Packet::all_from_bytes(&packets_bytes)?;
     let mut subkeys = packets.into_iter().filter_map(|packet| match packet {
@@ -75,14 +95,84 @@ pub(crate) fn find_keyid(packets_bytes: &[u8]) -> Result<u64, Error> {
     key_id.ok_or(format_err!("No key ID found"))?
 }
 
+pub(crate) fn find_x509_keyid(cert_bytes: &[u8]) -> Result<u64, Error> {
+    // Derive the key ID the same way `Configuration::authenticate` will when it sees a CMS
+    // signature from this certificate, by asking gpgsm.
+    let mut gpgsm = Context::from_protocol(Protocol::Cms)?;
+    let gpgsm_homedir = TempDir::new()?;
+    gpgsm.set_engine_home_dir(gpgsm_homedir.path().as_os_str().as_bytes())?;
+
+    let result = gpgsm.import(cert_bytes)?;
+    let fingerprint = result
+        .imports()
+        .filter_map(|import| import.fingerprint().ok())
+        .next()
+        .ok_or(format_err!("No X.509 certificate found"))?;
+    let cert = gpgsm.find_key(fingerprint)?;
+    let key_id = cert.id().map_err(|_| format_err!("Failed to get certificate's key ID"))?;
+
+    Ok(u64::from_str_radix(key_id, 16)?)
+}
+
+/// Encode an RSA public key as a PKCS#1 RSAPublicKey, which is how secrets' public keys are
+/// recorded in the database.
+pub(crate) fn rsa_public_key_der(n: &BigUint, e: &BigUint) -> Vec<u8> {
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_biguint(n);
+            writer.next().write_biguint(e);
+        });
+    })
+}
+
 pub(crate) fn store_key(
     database: &database::Configuration,
     hsm_id: u16,
     key_id: Option<u64>,
+    public_key: &[u8],
+    algorithm: SecretAlgorithm,
     name: &str,
     threshold: i32,
 ) -> Result<(), Error> {
-    database.insert_secret_key(i32::from(hsm_id), key_id.map(|id| id as i64), name, threshold)
+    database.insert_secret_key(
+        i32::from(hsm_id),
+        key_id.map(|id| id as i64),
+        public_key,
+        algorithm,
+        name,
+        threshold,
+    )
+}
+
+/// Change how a secret may be used through `authed_database`. Since no users approve these
+/// changes, each is logged, and only committed along with its log entry.
+fn configure_secret<F>(
+    hsm: &Hsm,
+    database: &database::Configuration,
+    authed_database: AuthenticatedConnection,
+    change: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&AuthenticatedConnection) -> Result<(), Error>,
+{
+    let identification = Some(logged_identification(authed_database.get_name()));
+    let intent =
+        logging::begin_operation(database, OperationType::Configure, Utc::now().naive_utc())?;
+    let interior_result = authed_database
+        .hold_changes()
+        .and_then(|()| change(&authed_database));
+
+    let (intent, logged_result) = match interior_result {
+        Ok(()) => (intent.with_changes(authed_database), OperationResult::Success),
+        Err(_) => {
+            // Roll back whatever was held, so that the failure can be logged.
+            drop(authed_database);
+            (intent, OperationResult::Failure)
+        }
+    };
+    logging::log_operation(hsm, database, intent, logged_result, identification)?;
+
+    interior_result
 }
 
 pub(crate) fn import_pgp_secret(
@@ -111,117 +201,136 @@ pub(crate) fn import_pgp_secret(
         KeyMaterial::Elgamal(_, _) => bail!("Elgamal keys aren't supported."),
     };
 
+    let public_key = rsa_public_key_der(&pubkey_material.n, &pubkey_material.e);
+    let intent =
+        logging::begin_operation(&db_conf, OperationType::AddSecret, Utc::now().naive_utc())?;
     let interior_result = hsm
         .put_rsa_key(&pubkey_material.n, &privkey_material.p, &privkey_material.q)
-        .and_then(|hsm_id| store_key(&db_conf, hsm_id, Some(subkey.id()?), name, threshold));
-
-    match interior_result {
-        Ok(_) => logging::log_operation(
-            hsm,
-            &db_conf,
-            OperationType::AddSecret,
-            OperationResult::Success,
-            None,
-            Utc::now().naive_utc(),
-        ),
-        Err(_) => logging::log_operation(
-            hsm,
-            &db_conf,
-            OperationType::AddSecret,
-            OperationResult::Failure,
-            None,
-            Utc::now().naive_utc(),
-        ),
-    }.unwrap_or_else(|e| panic!("Failed to log operation: {}", e));
+        .and_then(|hsm_id| {
+            let key_id = Some(subkey.id()?);
+            store_key(&db_conf, hsm_id, key_id, &public_key, SecretAlgorithm::Rsa, name, threshold)
+        });
+
+    let logged_result = match interior_result {
+        Ok(_) => OperationResult::Success,
+        Err(_) => OperationResult::Failure,
+    };
+    let identification = Some(logged_identification(name));
+    logging::log_operation(hsm, &db_conf, intent, logged_result, identification)?;
 
     interior_result
 }
 
-pub(crate) fn import_pem_secret(
-    hsm: &Hsm,
-    filename: &Path,
-    database: &str,
-    name: &str,
-    threshold: i32,
-) -> Result<(), Error> {
-    let mut pem_bytes = Vec::new();
-    File::open(filename)?.read_to_end(&mut pem_bytes)?;
-    let pem = match pem::parse(&pem_bytes) {
-        Ok(pem) => pem,
-        Err(e) => bail!("Error parsing PEM: {}", e),
-    };
-
-    if pem.tag != "RSA PRIVATE KEY" {
-        bail!("Only PEMs containing RSA PRIVATE KEYs are supported");
-    }
-
-    let (n, p, q) = yasna::parse_der(&pem.contents, |reader| {
+fn parse_rsa_private_key(der: &[u8]) -> Result<(BigUint, BigUint, BigUint, BigUint), Error> {
+    Ok(yasna::parse_der(der, |reader| {
         reader.read_sequence(|reader| {
             // We don't care about most of these fields, but we have to read them all to keep
             // `read_sequence` happy.
             let _version = reader.next().read_u8()?;
             let n = reader.next().read_biguint()?;
-            let _e = reader.next().read_biguint()?;
+            let e = reader.next().read_biguint()?;
             let _d = reader.next().read_biguint()?;
             let p = reader.next().read_biguint()?;
             let q = reader.next().read_biguint()?;
             let _exp1 = reader.next().read_biguint()?;
             let _exp2 = reader.next().read_biguint()?;
             let _invq_modp = reader.next().read_biguint()?;
-            Ok((n, p, q))
+            Ok((n, e, p, q))
+        })
+    })?)
+}
+
+/// Extract the seed from an Ed25519 PKCS#8 private key, as described in RFC 8410. The optional
+/// attributes and public key fields aren't supported, since OpenSSL doesn't write them.
+fn parse_ed25519_private_key(der: &[u8]) -> Result<Vec<u8>, Error> {
+    let (algorithm, private_key) = yasna::parse_der(der, |reader| {
+        reader.read_sequence(|reader| {
+            let _version = reader.next().read_u8()?;
+            let algorithm = reader.next().read_sequence(|reader| reader.next().read_oid())?;
+            let private_key = reader.next().read_bytes()?;
+            Ok((algorithm, private_key))
         })
     })?;
 
+    if algorithm != ObjectIdentifier::from_slice(OID_ED25519) {
+        bail!("Only RSA and Ed25519 private keys are supported");
+    }
+
+    // The key is wrapped in a second OCTET STRING.
+    Ok(yasna::parse_der(&private_key, |reader| reader.read_bytes())?)
+}
+
+pub(crate) fn import_pem_secret(
+    hsm: &Hsm,
+    filename: &Path,
+    database: &str,
+    name: &str,
+    threshold: i32,
+) -> Result<(), Error> {
+    let mut pem_bytes = Vec::new();
+    File::open(filename)?.read_to_end(&mut pem_bytes)?;
+    let pem = match pem::parse(&pem_bytes) {
+        Ok(pem) => pem,
+        Err(e) => bail!("Error parsing PEM: {}", e),
+    };
+
     let db_conf = database::Configuration::new(database);
 
-    let interior_result = hsm
-        .put_rsa_key(&n, &p, &q)
-        .and_then(|hsm_id| store_key(&db_conf, hsm_id, None, name, threshold));
-
-    match interior_result {
-        Ok(_) => logging::log_operation(
-            hsm,
-            &db_conf,
-            OperationType::AddSecret,
-            OperationResult::Success,
-            None,
-            Utc::now().naive_utc(),
-        ),
-        Err(_) => logging::log_operation(
-            hsm,
-            &db_conf,
-            OperationType::AddSecret,
-            OperationResult::Failure,
-            None,
-            Utc::now().naive_utc(),
-        ),
-    }.unwrap_or_else(|e| panic!("Failed to log operation: {}", e));
+    // The intent is only recorded once the key has parsed, since nothing can have happened to
+    // the HSM before then.
+    let begin = || {
+        logging::begin_operation(&db_conf, OperationType::AddSecret, Utc::now().naive_utc())
+    };
+    let (intent, interior_result) = match pem.tag.as_str() {
+        "RSA PRIVATE KEY" => {
+            let (n, e, p, q) = parse_rsa_private_key(&pem.contents)?;
+            let public_key = rsa_public_key_der(&n, &e);
+            let intent = begin()?;
+            let result = hsm.put_rsa_key(&n, &p, &q).and_then(|hsm_id| {
+                let algorithm = SecretAlgorithm::Rsa;
+                store_key(&db_conf, hsm_id, None, &public_key, algorithm, name, threshold)
+            });
+            (intent, result)
+        }
+        "PRIVATE KEY" => {
+            let seed = parse_ed25519_private_key(&pem.contents)?;
+            let intent = begin()?;
+            let result = hsm.put_ed25519_key(&seed).and_then(|(hsm_id, public_key)| {
+                let algorithm = SecretAlgorithm::Ed25519;
+                store_key(&db_conf, hsm_id, None, &public_key, algorithm, name, threshold)
+            });
+            (intent, result)
+        }
+        _ => bail!("Only PEMs containing RSA PRIVATE KEYs or Ed25519 PRIVATE KEYs are supported"),
+    };
+
+    let logged_result = match interior_result {
+        Ok(_) => OperationResult::Success,
+        Err(_) => OperationResult::Failure,
+    };
+    let identification = Some(logged_identification(name));
+    logging::log_operation(hsm, &db_conf, intent, logged_result, identification)?;
 
     interior_result
 }
 
-pub(crate) fn store_user(hsm: &Hsm, database_url: &str, key_id: u64, key: &[u8]) -> Result<(), Error> {
+pub(crate) fn store_user(
+    hsm: &Hsm,
+    database_url: &str,
+    key_id: u64,
+    key_type: UserKeyType,
+    key: &[u8],
+) -> Result<(), Error> {
     let database = database::Configuration::new(database_url);
-    let interior_result = database.insert_user_key(key_id, key);
-
-    match interior_result {
-        Ok(_) => logging::log_operation(
-            hsm,
-            &database,
-            OperationType::AddUser,
-            OperationResult::Success,
-            None,
-            Utc::now().naive_utc(),
-        ),
-        Err(_) => logging::log_operation(
-            hsm,
-            &database,
-            OperationType::AddUser,
-            OperationResult::Failure,
-            None,
-            Utc::now().naive_utc(),
-        ),
-    }.unwrap_or_else(|e| panic!("Failed to log operation: {}", e));
+    let intent =
+        logging::begin_operation(&database, OperationType::AddUser, Utc::now().naive_utc())?;
+    let interior_result = database.insert_user_key(key_id, key_type, key);
+
+    let logged_result = match interior_result {
+        Ok(_) => OperationResult::Success,
+        Err(_) => OperationResult::Failure,
+    };
+    logging::log_operation(hsm, &database, intent, logged_result, None)?;
 
     interior_result
 }
@@ -246,6 +355,232 @@ pub(crate) fn set_user_weight(
     authed_database.upsert_user_key_weight(user_key_obj, weight)
 }
 
+pub(crate) fn set_secret_certificate(
+    hsm: &Hsm,
+    database_url: &str,
+    secret_key_name: &str,
+    filename: &Path,
+    chain_filenames: &[PathBuf],
+) -> Result<(), Error> {
+    let mut cert_bytes = Vec::new();
+    File::open(filename)?.read_to_end(&mut cert_bytes)?;
+    let certificate = Certificate::from_der(&x509::decode_pem_or_der(&cert_bytes)?)?;
+
+    // Each certificate in the chain must have issued the one before it.
+    let mut chain = Vec::new();
+    let mut issuer = certificate.issuer.clone();
+    for chain_filename in chain_filenames {
+        let mut chain_bytes = Vec::new();
+        File::open(chain_filename)?.read_to_end(&mut chain_bytes)?;
+        let chain_certificate = Certificate::from_der(&x509::decode_pem_or_der(&chain_bytes)?)?;
+        if chain_certificate.subject != issuer {
+            bail!("{} did not issue the certificate before it", chain_filename.display());
+        }
+        issuer = chain_certificate.issuer.clone();
+        chain.push(chain_certificate.der);
+    }
+
+    let database = database::Configuration::new(database_url);
+    let authed_database = database.local_authenticate(LocalIdentification {
+        secret_key: None,
+        name: String::from(secret_key_name),
+        _priv: (),
+    })?;
+
+    // The subjectPublicKey of an RSA certificate is just the PKCS#1 public key we already store,
+    // wrapped in a BIT STRING.
+    let public_key = authed_database.get_public_key()?;
+    let cert_public_key = DerElement::parse_all(&certificate.subject_public_key_info)?
+        .children()?
+        .into_iter()
+        .nth(1)
+        .ok_or(format_err!("Malformed subjectPublicKeyInfo"))?;
+    if cert_public_key.contents.get(1..) != Some(&public_key[..]) {
+        bail!("Certificate does not match secret {}", secret_key_name);
+    }
+
+    configure_secret(hsm, &database, authed_database, |authed_database| {
+        authed_database.set_certificate(&certificate.der, &chain)
+    })
+}
+
+pub(crate) fn set_tsa_secret(
+    hsm: &Hsm,
+    database_url: &str,
+    secret_key_name: &str,
+    policy: &str,
+) -> Result<(), Error> {
+    x509::parse_oid(policy)?;
+
+    let database = database::Configuration::new(database_url);
+    let authed_database = database.local_authenticate(LocalIdentification {
+        secret_key: None,
+        name: String::from(secret_key_name),
+        _priv: (),
+    })?;
+
+    // Timestamp tokens identify their signer by its certificate, which must be marked for use by
+    // a TSA.
+    tsa::check_certificate(&Certificate::from_der(&authed_database.get_certificate()?)?)?;
+
+    // Designating the secret is what authorizes timestamps, so it's logged like an operation.
+    configure_secret(hsm, &database, authed_database, |authed_database| {
+        authed_database.set_tsa_policy(policy)
+    })
+}
+
+pub(crate) fn set_audit_secret(
+    hsm: &Hsm,
+    database_url: &str,
+    secret_key_name: &str,
+) -> Result<(), Error> {
+    let database = database::Configuration::new(database_url);
+    let authed_database = database.local_authenticate(LocalIdentification {
+        secret_key: None,
+        name: String::from(secret_key_name),
+        _priv: (),
+    })?;
+
+    // Checkpoints are verified by clients with the secret's recorded RSA public key.
+    if authed_database.get_algorithm()? != SecretAlgorithm::Rsa {
+        bail!("Audit log checkpoints must be signed by an RSA secret");
+    }
+    authed_database.get_public_key()?;
+
+    configure_secret(hsm, &database, authed_database, |authed_database| {
+        authed_database.set_audit()
+    })
+}
+
+pub(crate) fn set_release_policy(
+    hsm: &Hsm,
+    database_url: &str,
+    secret_key_name: &str,
+    max_validity_days: i32,
+    max_date_age_hours: i32,
+) -> Result<(), Error> {
+    if max_validity_days <= 0 || max_date_age_hours <= 0 {
+        bail!("Release file policy limits must be positive");
+    }
+
+    let database = database::Configuration::new(database_url);
+    let authed_database = database.local_authenticate(LocalIdentification {
+        secret_key: None,
+        name: String::from(secret_key_name),
+        _priv: (),
+    })?;
+
+    configure_secret(hsm, &database, authed_database, |authed_database| {
+        authed_database.upsert_release_policy(NewReleasePolicy {
+            secret_id: 0,
+            max_validity_days,
+            max_date_age_hours,
+        })
+    })
+}
+
+pub(crate) fn set_tuf_role(
+    hsm: &Hsm,
+    database_url: &str,
+    secret_key_name: &str,
+    role: &str,
+    max_expiry_days: i32,
+) -> Result<(), Error> {
+    if !tuf::ROLES.contains(&role) {
+        bail!("Unknown TUF role: {}", role);
+    }
+    if max_expiry_days <= 0 {
+        bail!("The maximum expiry must be at least a day");
+    }
+
+    let database = database::Configuration::new(database_url);
+    let authed_database = database.local_authenticate(LocalIdentification {
+        secret_key: None,
+        name: String::from(secret_key_name),
+        _priv: (),
+    })?;
+
+    configure_secret(hsm, &database, authed_database, |authed_database| {
+        authed_database.upsert_tuf_role(role, max_expiry_days)
+    })
+}
+
+/// The minisign public key file for an Ed25519 secret.
+pub(crate) fn minisign_public_key(
+    database_url: &str,
+    secret_key_name: &str,
+) -> Result<String, Error> {
+    let database = database::Configuration::new(database_url);
+    let authed_database = database.local_authenticate(LocalIdentification {
+        secret_key: None,
+        name: String::from(secret_key_name),
+        _priv: (),
+    })?;
+
+    if authed_database.get_algorithm()? != SecretAlgorithm::Ed25519 {
+        bail!("Secret {} is not an Ed25519 secret", secret_key_name);
+    }
+
+    minisign::public_key(&authed_database.get_public_key()?)
+}
+
+/// Export the log entries after `since`, checking the export before it's written anywhere.
+pub(crate) fn export_logs(database_url: &str, since: i32) -> Result<LogExport, Error> {
+    let database = database::Configuration::new(database_url);
+
+    let export = logging::export_logs(&database, since)?;
+    export.verify()?;
+
+    Ok(export)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+pub(crate) fn set_certificate_profile(
+    hsm: &Hsm,
+    database_url: &str,
+    secret_key_name: &str,
+    validity_days: i32,
+    key_usages: &[String],
+    extended_key_usages: &[String],
+    dns_suffixes: &[String],
+    is_ca: bool,
+    path_length: Option<i32>,
+) -> Result<(), Error> {
+    if path_length.map_or(false, |path_length| path_length < 0) {
+        bail!("Path length can't be negative");
+    }
+
+    let mut key_usage = 0;
+    for usage in key_usages {
+        key_usage |= x509::parse_key_usage(usage)?;
+    }
+
+    let extended_key_usage = extended_key_usages
+        .iter()
+        .map(|usage| x509::parse_extended_key_usage(usage))
+        .collect::<Result<Vec<_>, _>>()?
+        .join(",");
+
+    let database = database::Configuration::new(database_url);
+    let authed_database = database.local_authenticate(LocalIdentification {
+        secret_key: None,
+        name: String::from(secret_key_name),
+        _priv: (),
+    })?;
+
+    configure_secret(hsm, &database, authed_database, |authed_database| {
+        authed_database.upsert_certificate_profile(NewCertificateProfile {
+            secret_id: 0,
+            validity_days,
+            key_usage: i32::from(key_usage),
+            extended_key_usage,
+            dns_suffixes: dns_suffixes.join(","),
+            is_ca,
+            path_length,
+        })
+    })
+}
+
 pub(crate) fn provision(
     database_url: &str,
     hsm_connector_url: &str,
@@ -1,16 +1,20 @@
 use std::ops::Deref;
+use std::sync::MutexGuard;
 
 use chrono::prelude::*;
 use failure::Error;
 use libyubihsm;
-use protobuf::Message;
+use protobuf::{Message, repeated::RepeatedField, well_known_types::Timestamp};
 
-use database::Configuration;
-use database::models::{FeroLog, NewFeroLog, NewHsmLog};
-use fero_proto::fero::Identification;
+use database::{AuthenticatedConnection, Configuration, LogFilter};
+use database::models::{self, FeroLog, NewCheckpoint, NewFeroLog, NewHsmLog, NewLogIntent,
+                       SecretAlgorithm};
+use fero_proto::checkpoint::Checkpoint;
+use fero_proto::export::LogExport;
+use fero_proto::fero::{self, HsmLog, Identification, LogEntry, LogEntry_OperationResult,
+                       LogEntry_OperationType};
 use fero_proto::log::*;
 use hsm::Hsm;
-use logging;
 
 impl<T> From<T> for NewHsmLog
 where
@@ -31,43 +35,97 @@ where
     }
 }
 
+impl<'a> From<&'a models::Checkpoint> for Checkpoint {
+    fn from(checkpoint: &models::Checkpoint) -> Checkpoint {
+        Checkpoint {
+            log_id: checkpoint.fero_log_id,
+            log_hash: checkpoint.log_hash.clone(),
+            hsm_index: checkpoint.hsm_index as u16,
+            hsm_hash: checkpoint.hsm_hash.clone(),
+            timestamp: checkpoint.timestamp,
+            signature: checkpoint.signature.clone(),
+            tree_root: checkpoint.tree_root.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// What a log entry records about its operation, besides the HSM log entries it covers.
+pub struct LoggedOperation {
+    pub request_type: OperationType,
+    pub result: OperationResult,
+    pub identification: Option<Identification>,
+    pub signature: Option<SignatureRecord>,
+    pub revocation: Option<RevocationRecord>,
+    pub timestamp: NaiveDateTime,
+}
+
+impl LoggedOperation {
+    fn new(
+        request_type: OperationType,
+        result: OperationResult,
+        identification: Option<Identification>,
+        timestamp: NaiveDateTime,
+    ) -> LoggedOperation {
+        LoggedOperation {
+            request_type,
+            result,
+            identification,
+            signature: None,
+            revocation: None,
+            timestamp,
+        }
+    }
+}
+
+/// Create the log entry for `operation` following the one whose hash is `parent_hash`, covering
+/// the HSM log entries `hsm_logs` from after `hsm_index_start`.
 pub fn create_fero_log(
-    request_type: OperationType,
-    result: OperationResult,
-    hsm_logs: &[libyubihsm::LogEntry],
+    operation: LoggedOperation,
+    hsm_logs: &[models::HsmLog],
+    parent_hash: &[u8],
     hsm_index_start: i32,
-    hsm_index_end: i32,
-    parent_entry: &FeroLog,
-    identification: Option<Identification>,
-    timestamp: NaiveDateTime,
 ) -> Result<NewFeroLog, Error> {
-    // `HsmLogEntry` is defined in fero-proto, which can't link against libyubihsm (so can't depend
-    // on libyubihsm-rs), and `LogEntry` is defined in libyubihsm, so we can't use a `From` here.
+    let hsm_index_end = hsm_logs
+        .last()
+        .map(|log| log.hsm_index)
+        .unwrap_or(hsm_index_start);
+
     let hsm_logs = hsm_logs
         .iter()
         .map(|log| HsmLogEntry {
-            hsm_index: log.index,
-            command: u8::from(log.command),
-            data_length: log.data_length,
-            session_key: log.session_key,
-            target_key: log.target_key,
-            second_key: log.second_key,
-            result: u8::from(log.result),
-            systick: log.systick,
-            hash: Vec::from(log.digest()),
+            hsm_index: log.hsm_index as u16,
+            command: log.command as u8,
+            data_length: log.data_length as u16,
+            session_key: log.session_key as u16,
+            target_key: log.target_key as u16,
+            second_key: log.second_key as u16,
+            result: log.result as u8,
+            systick: log.systick as u32,
+            hash: log.hash.clone(),
         })
         .collect::<Vec<_>>();
 
     let mut new_fero_log = FeroLogEntry {
-        request_type,
-        timestamp,
-        result,
+        request_type: operation.request_type,
+        timestamp: operation.timestamp,
+        result: operation.result,
         hsm_logs,
-        identification,
+        identification: operation.identification,
+        signature: operation.signature,
+        revocation: operation.revocation,
         hash: Vec::new(),
     };
 
-    new_fero_log.hash = new_fero_log.hash(&parent_entry.hash)?;
+    new_fero_log.hash = new_fero_log.hash(parent_hash)?;
+
+    // Every operation on a secret is found by its name, whether or not it signed anything.
+    let secret_name = match (&new_fero_log.signature, &new_fero_log.identification) {
+        (&Some(ref signature), _) => Some(signature.secret_name.clone()),
+        (&None, &Some(ref ident)) if !ident.get_secretKeyName().is_empty() => {
+            Some(String::from(ident.get_secretKeyName()))
+        }
+        (&None, _) => None,
+    };
 
     Ok(NewFeroLog {
         request_type: new_fero_log.request_type,
@@ -79,58 +137,565 @@ pub fn create_fero_log(
             .identification
             .map(|i| i.write_to_bytes().unwrap()),
         hash: new_fero_log.hash,
+        secret_name,
+        signature_type: new_fero_log.signature.as_ref().map(|s| s.signature_type.clone()),
+        artifact_digest: new_fero_log.signature.as_ref().map(|s| s.artifact_digest.clone()),
+        signature_hash: new_fero_log.signature.map(|s| s.signature_hash),
+        revoked_serial: new_fero_log.revocation.as_ref().map(|r| r.serial.clone()),
+        revocation_reason: new_fero_log.revocation.map(|r| r.reason as i32),
     })
 }
 
-pub fn log_operation(
-    hsm: &Hsm,
+/// Create consecutive log entries for `operations` following `parent`. The first covers all of
+/// `hsm_logs`, and the rest none.
+fn create_fero_logs(
+    operations: Vec<LoggedOperation>,
+    hsm_logs: &[models::HsmLog],
+    parent: &FeroLog,
+) -> Result<Vec<NewFeroLog>, Error> {
+    let mut entries: Vec<NewFeroLog> = Vec::new();
+    for operation in operations {
+        let entry = match entries.last() {
+            Some(previous) => {
+                create_fero_log(operation, &[], &previous.hash, previous.hsm_index_end)?
+            }
+            None => create_fero_log(operation, hsm_logs, &parent.hash, parent.hsm_index_end)?,
+        };
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+/// A record, made before an operation runs, that it's about to. An operation is refused unless
+/// its intent can be recorded, and the intent is only removed once the operation is logged, so
+/// one left behind shows that an operation may have run without being logged. No other operation
+/// can begin until it's logged.
+#[must_use]
+pub struct Intent<'a> {
+    id: i32,
+    request_type: OperationType,
+    timestamp: NaiveDateTime,
+    changes: Option<AuthenticatedConnection>,
+    _operation: MutexGuard<'a, ()>,
+}
+
+impl<'a> Intent<'a> {
+    /// Commit the changes held back on `changes` along with the operation's log entry.
+    pub fn with_changes(mut self, changes: AuthenticatedConnection) -> Intent<'a> {
+        self.changes = Some(changes);
+        self
+    }
+
+    /// Withdraw the intent of an operation which turns out to have nothing to do, before it's
+    /// done anything.
+    fn abandon(self, database: &Configuration) -> Result<(), Error> {
+        database.delete_log_intent(self.id)
+    }
+}
+
+pub fn begin_operation(
     database: &Configuration,
     request_type: OperationType,
-    result: OperationResult,
-    identification: Option<Identification>,
     timestamp: NaiveDateTime,
-) -> Result<(), Error> {
-    let last_hsm_index = database.last_hsm_log_entry()?;
+) -> Result<Intent, Error> {
+    let operation = database.lock_operations()?;
+    let id = database.insert_log_intent(NewLogIntent {
+        request_type,
+        timestamp,
+    })?;
 
+    Ok(Intent {
+        id,
+        request_type,
+        timestamp,
+        changes: None,
+        _operation: operation,
+    })
+}
+
+/// The HSM's log entries after `last_hsm_index`, the last one stored.
+fn hsm_logs_since(hsm: &Hsm, last_hsm_index: Option<i32>) -> Result<Vec<NewHsmLog>, Error> {
     let hsm_logs = match last_hsm_index {
-        Some(ref last_hsm_index) => hsm.logs_since(last_hsm_index.hsm_index as u16)?,
+        Some(last_hsm_index) => hsm.logs_since(last_hsm_index as u16)?,
         // TODO(csssuf): fail loudly here if we can't get HSM log entry 0, since we'll never be
         // able to verify the full log chain in that case
         None => hsm.logs()?,
     };
-    let last_hsm_index = last_hsm_index.map(|hsm_log| hsm_log.hsm_index).unwrap_or(0);
-
-    let new_hsm_index =
-        database.insert_hsm_logs(hsm_logs.iter().map(NewHsmLog::from).collect::<Vec<_>>())?;
-
-    hsm.set_log_index(new_hsm_index.unwrap_or(last_hsm_index) as u16)?;
-
-    let parent_log = database.last_fero_log_entry()?;
-    let parent_log = parent_log.unwrap_or_else(|| {
-        // There's no concise way to bubble an Err back up through an unwrap_or_else call, and
-        // this error is sufficiently fatal to panic anyway, so .expect() here for initial log
-        // entry handling.
-        database
-            .insert_fero_log(NewFeroLog::default())
-            .expect("Couldn't insert root log entry");
-        database
-            .last_fero_log_entry()
-            .expect("Couldn't retrieve root log entry")
-            .expect("Couldn't retrieve root log entry")
-    });
 
-    let new_fero_log = logging::create_fero_log(
-        request_type,
+    Ok(hsm_logs.iter().map(NewHsmLog::from).collect())
+}
+
+/// Reconcile the log with the HSM's after an unclean shutdown. HSM log entries which no log entry
+/// covers, whether stored or only on the HSM, are recorded in an unattributed entry, which also
+/// fulfils any intents left behind. The HSM is then told which of its log entries are stored.
+pub fn recover(hsm: &Hsm, database: &Configuration) -> Result<(), Error> {
+    let intents = database.log_intents()?;
+    for intent in &intents {
+        warn!(
+            "A {:?} operation begun at {} was never logged",
+            intent.request_type, intent.timestamp
+        );
+    }
+
+    let last_hsm_index = database.last_hsm_log_entry()?.map(|hsm_log| hsm_log.hsm_index);
+    let head_hsm_index = database
+        .last_fero_log_entry()?
+        .map(|head| head.hsm_index_end)
+        .unwrap_or(0);
+
+    let hsm_logs = hsm_logs_since(hsm, last_hsm_index)?;
+    if let (Some(last_hsm_index), Some(first)) = (last_hsm_index, hsm_logs.first()) {
+        if first.hsm_index > last_hsm_index + 1 {
+            warn!(
+                "HSM log entries {} to {} were lost before they could be stored",
+                last_hsm_index + 1,
+                first.hsm_index - 1
+            );
+        }
+    }
+
+    if hsm_logs.is_empty() && last_hsm_index.unwrap_or(0) <= head_hsm_index {
+        // Nothing ran on the HSM, and any changes held back for the intents' log entries were
+        // rolled back, so their operations had no effect.
+        for intent in intents {
+            database.delete_log_intent(intent.id)?;
+        }
+        if let Some(last_hsm_index) = last_hsm_index {
+            hsm.set_log_index(last_hsm_index as u16)?;
+        }
+        return Ok(());
+    }
+
+    // Operations, checkpoints included, record their intent first, so without any intents this
+    // is only activity such as opening sessions, which isn't alarming.
+    let result = if intents.is_empty() {
+        info!("Recording HSM activity since the last log entry as unattributed");
+        OperationResult::Success
+    } else {
+        warn!("Recording HSM activity of interrupted operations as unattributed");
+        OperationResult::Failure
+    };
+
+    let operation = LoggedOperation::new(
+        OperationType::Unattributed,
+        result,
+        None,
+        Utc::now().naive_utc(),
+    );
+    let hsm_index = database.commit_log_entries(
+        None,
+        intents.iter().map(|intent| intent.id).collect(),
+        hsm_logs,
+        |parent, hsm_logs| create_fero_logs(vec![operation], hsm_logs, parent),
+    )?;
+
+    hsm.set_log_index(hsm_index as u16)
+}
+
+pub fn log_operation(
+    hsm: &Hsm,
+    database: &Configuration,
+    intent: Intent,
+    result: OperationResult,
+    identification: Option<Identification>,
+) -> Result<(), Error> {
+    log_operation_with_signature(hsm, database, intent, result, identification, None)
+}
+
+/// Log a signing operation along with what it signed, so that the log can prove which artifact
+/// each signature corresponds to.
+pub fn log_operation_with_signature(
+    hsm: &Hsm,
+    database: &Configuration,
+    intent: Intent,
+    result: OperationResult,
+    identification: Option<Identification>,
+    signature: Option<SignatureRecord>,
+) -> Result<(), Error> {
+    let mut operation =
+        LoggedOperation::new(intent.request_type, result, identification, intent.timestamp);
+    operation.signature = signature;
+
+    log_operations(hsm, database, intent, vec![operation])
+}
+
+/// Log an operation which revoked certificates. Each revocation gets a `Revoke` entry of its
+/// own, ahead of the operation's entry, and all of them are committed at once.
+pub fn log_operation_with_revocations(
+    hsm: &Hsm,
+    database: &Configuration,
+    intent: Intent,
+    result: OperationResult,
+    identification: Option<Identification>,
+    revocations: Vec<RevocationRecord>,
+) -> Result<(), Error> {
+    let mut operations = revocations
+        .into_iter()
+        .map(|revocation| {
+            let mut operation = LoggedOperation::new(
+                OperationType::Revoke,
+                OperationResult::Success,
+                identification.clone(),
+                intent.timestamp,
+            );
+            operation.revocation = Some(revocation);
+            operation
+        })
+        .collect::<Vec<_>>();
+    operations.push(LoggedOperation::new(
+        intent.request_type,
         result,
-        &hsm_logs,
-        last_hsm_index,
-        new_hsm_index.unwrap_or(last_hsm_index),
-        &parent_log,
         identification,
-        timestamp,
+        intent.timestamp,
+    ));
+
+    log_operations(hsm, database, intent, operations)
+}
+
+fn log_operations(
+    hsm: &Hsm,
+    database: &Configuration,
+    intent: Intent,
+    operations: Vec<LoggedOperation>,
+) -> Result<(), Error> {
+    let last_hsm_index = database.last_hsm_log_entry()?.map(|hsm_log| hsm_log.hsm_index);
+    let hsm_logs = hsm_logs_since(hsm, last_hsm_index)?;
+
+    let hsm_index = database.commit_log_entries(
+        intent.changes,
+        vec![intent.id],
+        hsm_logs,
+        |parent, hsm_logs| create_fero_logs(operations, hsm_logs, parent),
     )?;
 
-    database.insert_fero_log(new_fero_log)?;
+    // Only acknowledged once stored, so that a crash in between leaves the HSM's log entries
+    // for recovery.
+    hsm.set_log_index(hsm_index as u16)
+}
+
+/// Sign a checkpoint of the log's head with the audit secret, unless nothing but a checkpoint
+/// has been logged since the last one. Signing is logged like any other operation, so that the
+/// HSM's signing command is attributed to it, and the checkpoint is only stored along with that
+/// log entry.
+pub fn create_checkpoint(hsm: &Hsm, database: &Configuration) -> Result<Option<Checkpoint>, Error> {
+    let auditor = database.audit_authority()?;
+    if auditor.get_algorithm()? != SecretAlgorithm::Rsa {
+        bail!("Audit log checkpoints must be signed by an RSA secret");
+    }
+    let key_id = auditor.get_hsm_key_id()?;
+
+    // The head is only read once no other operation can log anything, so that it's still the
+    // head when the checkpoint of it is signed.
+    let intent = begin_operation(database, OperationType::Checkpoint, Utc::now().naive_utc())?;
+    let mut checkpoint = match unsigned_checkpoint(database, intent.timestamp) {
+        Ok(Some(checkpoint)) => checkpoint,
+        Ok(None) => {
+            intent.abandon(database)?;
+            return Ok(None);
+        }
+        Err(e) => {
+            intent.abandon(database)?;
+            return Err(e);
+        }
+    };
+
+    let signature = hsm.create_rsa_signature(checkpoint.digest()?, key_id);
+    let record = SignatureRecord::new(
+        auditor.get_name(),
+        "CHECKPOINT",
+        &checkpoint.signed_bytes()?,
+        signature.as_ref().ok().map(|signature| signature.as_slice()),
+    );
+
+    let result = signature.and_then(|signature| {
+        checkpoint.signature = signature;
+        auditor.hold_changes()?;
+        auditor.insert_checkpoint(NewCheckpoint {
+            fero_log_id: checkpoint.log_id,
+            log_hash: checkpoint.log_hash.clone(),
+            hsm_index: i32::from(checkpoint.hsm_index),
+            hsm_hash: checkpoint.hsm_hash.clone(),
+            timestamp: checkpoint.timestamp,
+            signature: checkpoint.signature.clone(),
+            tree_root: Some(checkpoint.tree_root.clone()),
+        })
+    });
+
+    let (intent, logged_result) = match result {
+        Ok(()) => (intent.with_changes(auditor), OperationResult::Success),
+        Err(_) => {
+            // Roll back anything held on the auditor's connection, whose transaction would
+            // otherwise keep the failure from being logged on another.
+            drop(auditor);
+            (intent, OperationResult::Failure)
+        }
+    };
+    log_operation_with_signature(hsm, database, intent, logged_result, None, Some(record))?;
+
+    result.map(|()| Some(checkpoint))
+}
+
+/// A checkpoint of the log's head, yet to be signed, unless nothing but a checkpoint has been
+/// logged since the last one.
+fn unsigned_checkpoint(
+    database: &Configuration,
+    timestamp: NaiveDateTime,
+) -> Result<Option<Checkpoint>, Error> {
+    let head = match database.last_fero_log_entry()? {
+        Some(head) => head,
+        None => return Ok(None),
+    };
+    // A checkpoint's own log entry is covered by the next one, once anything else is logged.
+    if let (OperationType::Checkpoint, OperationResult::Success) =
+        (head.request_type, head.result)
+    {
+        return Ok(None);
+    }
+    if let Some(last_checkpoint) = database.last_checkpoint()? {
+        if last_checkpoint.fero_log_id == head.id {
+            return Ok(None);
+        }
+    }
+
+    // The HSM log position as of the head, rather than the newest HSM log entry, which may
+    // belong to an operation that hasn't been logged yet.
+    let hsm_hash = database
+        .hsm_log_entry(head.hsm_index_end)?
+        .map(|hsm_log| hsm_log.hash)
+        .unwrap_or_default();
+
+    Ok(Some(Checkpoint {
+        log_id: head.id,
+        log_hash: head.hash,
+        hsm_index: head.hsm_index_end as u16,
+        hsm_hash,
+        timestamp,
+        signature: Vec::new(),
+        tree_root: database.merkle_tree()?.root(head.id as usize)?,
+    }))
+}
 
-    Ok(())
+/// The entry's full form, with its HSM log entries, as served to clients.
+pub fn log_entry(database: &Configuration, fero_db_log: &FeroLog) -> Result<LogEntry, Error> {
+    build_log_entry(fero_db_log, &database.associated_hsm_logs(fero_db_log)?)
+}
+
+/// Up to `limit` log entries after `idx` which match `filter`.
+pub fn log_page(
+    database: &Configuration,
+    idx: i32,
+    limit: i64,
+    filter: &LogFilter,
+) -> Result<Vec<LogEntry>, Error> {
+    database
+        .fero_logs_page(idx, limit, filter)?
+        .iter()
+        .map(|&(ref fero_db_log, ref hsm_db_logs)| build_log_entry(fero_db_log, hsm_db_logs))
+        .collect()
+}
+
+fn build_log_entry(
+    fero_db_log: &FeroLog,
+    hsm_db_logs: &[models::HsmLog],
+) -> Result<LogEntry, Error> {
+    let hsm_logs = hsm_db_logs
+        .iter()
+        .map(|hsm_db_log| {
+            let mut hsm_log = HsmLog::new();
+
+            hsm_log.set_id(hsm_db_log.hsm_index as u32);
+            hsm_log.set_command(hsm_db_log.command as u32);
+            hsm_log.set_data_length(hsm_db_log.data_length as u32);
+            hsm_log.set_session_key(hsm_db_log.session_key as u32);
+            hsm_log.set_target_key(hsm_db_log.target_key as u32);
+            hsm_log.set_second_key(hsm_db_log.second_key as u32);
+            hsm_log.set_result(hsm_db_log.result as u32);
+            hsm_log.set_systick(hsm_db_log.systick as u32);
+            hsm_log.set_hash(hsm_db_log.hash.clone());
+
+            hsm_log
+        })
+        .collect::<Vec<_>>();
+
+    let mut entry = LogEntry::new();
+
+    entry.set_id(fero_db_log.id);
+    entry.set_operation_type(match fero_db_log.request_type {
+        OperationType::Sign => LogEntry_OperationType::SIGN,
+        OperationType::Threshold => LogEntry_OperationType::THRESHOLD,
+        OperationType::Weight => LogEntry_OperationType::WEIGHT,
+        OperationType::AddSecret => LogEntry_OperationType::ADD_SECRET,
+        OperationType::AddUser => LogEntry_OperationType::ADD_USER,
+        OperationType::SshCertify => LogEntry_OperationType::SSH_CERTIFY,
+        OperationType::IssueCertificate => LogEntry_OperationType::ISSUE_CERTIFICATE,
+        OperationType::Revoke => LogEntry_OperationType::REVOKE,
+        OperationType::GenerateCrl => LogEntry_OperationType::GENERATE_CRL,
+        OperationType::Timestamp => LogEntry_OperationType::TIMESTAMP,
+        OperationType::Unattributed => LogEntry_OperationType::UNATTRIBUTED,
+        OperationType::Checkpoint => LogEntry_OperationType::CHECKPOINT,
+        OperationType::Configure => LogEntry_OperationType::CONFIGURE,
+    });
+    let mut timestamp = Timestamp::new();
+    timestamp.set_seconds(fero_db_log.timestamp.timestamp());
+    timestamp.set_nanos(fero_db_log.timestamp.timestamp_subsec_nanos() as i32);
+    entry.set_timestamp(timestamp);
+    entry.set_result(match fero_db_log.result {
+        OperationResult::Success => LogEntry_OperationResult::SUCCESS,
+        OperationResult::Failure => LogEntry_OperationResult::FAILURE,
+    });
+    if let Some(ref ident_bytes) = fero_db_log.identification {
+        let mut ident = Identification::new();
+        ident.merge_from_bytes(ident_bytes)?;
+        entry.set_ident(ident);
+    }
+    if let (
+        Some(ref secret_name),
+        Some(ref signature_type),
+        Some(ref artifact_digest),
+        Some(ref signature_hash),
+    ) = (
+        &fero_db_log.secret_name,
+        &fero_db_log.signature_type,
+        &fero_db_log.artifact_digest,
+        &fero_db_log.signature_hash,
+    ) {
+        entry.set_signature(
+            SignatureRecord {
+                secret_name: secret_name.clone(),
+                signature_type: signature_type.clone(),
+                artifact_digest: artifact_digest.clone(),
+                signature_hash: signature_hash.clone(),
+            }.into(),
+        );
+    }
+    if let (Some(ref serial), Some(reason)) =
+        (&fero_db_log.revoked_serial, fero_db_log.revocation_reason)
+    {
+        entry.set_revocation(
+            RevocationRecord {
+                serial: serial.clone(),
+                reason: reason as u32,
+            }.into(),
+        );
+    }
+    entry.set_hsm_logs(RepeatedField::from_vec(hsm_logs));
+    entry.set_hash(fero_db_log.hash.clone());
+
+    Ok(entry)
+}
+
+/// Export the log entries after `min_index`, along with what's needed to check them offline.
+pub fn export_logs(database: &Configuration, min_index: i32) -> Result<LogExport, Error> {
+    let entries = log_page(database, min_index, i64::max_value(), &LogFilter::default())?;
+
+    let (parent_hash, tree_root, checkpoint) = match (entries.first(), entries.last()) {
+        (Some(first), Some(last)) => {
+            let parent_hash = database
+                .fero_log_entry(first.id - 1)?
+                .map(|parent| parent.hash)
+                .unwrap_or_default();
+            let tree_root = database.merkle_tree()?.root(last.id as usize)?;
+            let checkpoint = database
+                .last_checkpoint_covering(last.id)?
+                .map(|checkpoint| fero::Checkpoint::from(Checkpoint::from(&checkpoint)));
+            (parent_hash, tree_root, checkpoint)
+        }
+        _ => (Vec::new(), Vec::new(), None),
+    };
+
+    Ok(LogExport::new(
+        entries,
+        parent_hash,
+        tree_root,
+        checkpoint,
+        Utc::now().naive_utc(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parent() -> FeroLog {
+        FeroLog {
+            id: 1,
+            request_type: OperationType::Sign,
+            timestamp: NaiveDateTime::from_timestamp(0, 0),
+            result: OperationResult::Success,
+            hsm_index_start: 0,
+            hsm_index_end: 3,
+            identification: None,
+            hash: vec![0xaa; 32],
+            secret_name: None,
+            signature_type: None,
+            artifact_digest: None,
+            signature_hash: None,
+            revoked_serial: None,
+            revocation_reason: None,
+        }
+    }
+
+    fn hsm_log(hsm_index: i32) -> models::HsmLog {
+        models::HsmLog {
+            id: hsm_index,
+            hsm_index,
+            command: 0x56,
+            data_length: 32,
+            session_key: 1,
+            target_key: 2,
+            second_key: 0xffff,
+            result: 0xd6,
+            systick: 1000,
+            hash: vec![hsm_index as u8; 16],
+        }
+    }
+
+    fn revocation(serial: u8) -> LoggedOperation {
+        let mut operation = LoggedOperation::new(
+            OperationType::Revoke,
+            OperationResult::Success,
+            None,
+            NaiveDateTime::from_timestamp(1_500_000_000, 0),
+        );
+        operation.revocation = Some(RevocationRecord {
+            serial: vec![serial],
+            reason: 1,
+        });
+        operation
+    }
+
+    #[test]
+    fn consecutive_entries_chain() {
+        let hsm_logs = vec![hsm_log(4), hsm_log(5)];
+        let crl = LoggedOperation::new(
+            OperationType::GenerateCrl,
+            OperationResult::Success,
+            None,
+            NaiveDateTime::from_timestamp(1_500_000_000, 0),
+        );
+        let entries =
+            create_fero_logs(vec![revocation(1), revocation(2), crl], &hsm_logs, &parent())
+                .unwrap();
+        assert_eq!(entries.len(), 3);
+
+        // Only the first entry covers the HSM's log entries.
+        assert_eq!((entries[0].hsm_index_start, entries[0].hsm_index_end), (3, 5));
+        assert_eq!((entries[1].hsm_index_start, entries[1].hsm_index_end), (5, 5));
+        assert_eq!((entries[2].hsm_index_start, entries[2].hsm_index_end), (5, 5));
+
+        assert_eq!(entries[0].revoked_serial, Some(vec![1]));
+        assert_eq!(entries[0].revocation_reason, Some(1));
+        assert_eq!(entries[2].revoked_serial, None);
+
+        let expected = create_fero_log(revocation(2), &[], &entries[0].hash, 5).unwrap();
+        assert_eq!(entries[1].hash, expected.hash);
+    }
+
+    #[test]
+    fn revocations_are_hashed() {
+        let first = create_fero_log(revocation(1), &[], &[], 0).unwrap();
+        let second = create_fero_log(revocation(2), &[], &[], 0).unwrap();
+        assert_ne!(first.hash, second.hash);
+    }
 }
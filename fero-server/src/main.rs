@@ -1,7 +1,10 @@
+extern crate base64;
 extern crate byteorder;
 extern crate chrono;
 #[macro_use]
 extern crate diesel;
+#[macro_use]
+extern crate diesel_derive_enum;
 extern crate diesel_migrations;
 #[macro_use]
 extern crate failure;
@@ -20,6 +23,8 @@ extern crate pretty_good;
 extern crate protobuf;
 extern crate rpassword;
 extern crate secstr;
+#[macro_use]
+extern crate serde_json;
 extern crate rand;
 extern crate sha2;
 #[macro_use]
@@ -27,21 +32,35 @@ extern crate structopt;
 extern crate tempfile;
 extern crate yasna;
 
+mod authenticode;
+mod cms;
+mod cosign;
 mod database;
+mod git;
 mod hsm;
+mod jws;
+mod kmod;
 mod local;
 mod logging;
+mod minisign;
+mod release;
 mod service;
+mod sinks;
+mod ssh;
 #[cfg(test)]
 mod test;
+mod tsa;
+mod tuf;
+mod x509;
 
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::num::ParseIntError;
 use std::path::PathBuf;
 use std::str;
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 use failure::Error;
 use futures::sync::oneshot;
@@ -51,6 +70,7 @@ use num::{bigint::ParseBigIntError, BigUint, Num};
 use secstr::SecStr;
 use structopt::StructOpt;
 
+use database::models::UserKeyType;
 use fero_proto::fero_grpc::create_fero;
 
 #[derive(StructOpt)]
@@ -86,6 +106,30 @@ enum FeroServerCommand {
     #[structopt(name = "set-user-weight")]
     /// Set a user's weight for a particular secret.
     SetUserWeight(SetUserWeightCommand),
+    #[structopt(name = "set-secret-certificate")]
+    /// Record the X.509 CA certificate for a secret, so it can issue certificates.
+    SetSecretCertificate(SetSecretCertificateCommand),
+    #[structopt(name = "set-certificate-profile")]
+    /// Set the constraints applied to certificates issued by a secret.
+    SetCertificateProfile(SetCertificateProfileCommand),
+    #[structopt(name = "set-tsa-secret")]
+    /// Designate the secret which signs RFC 3161 timestamps.
+    SetTsaSecret(SetTsaSecretCommand),
+    #[structopt(name = "set-release-policy")]
+    /// Set the constraints on Debian Release files signed by a secret.
+    SetReleasePolicy(SetReleasePolicyCommand),
+    #[structopt(name = "set-tuf-role")]
+    /// Allow a secret to sign metadata for a TUF role.
+    SetTufRole(SetTufRoleCommand),
+    #[structopt(name = "export-minisign-key")]
+    /// Export the minisign public key of an Ed25519 secret.
+    ExportMinisignKey(ExportMinisignKeyCommand),
+    #[structopt(name = "set-audit-secret")]
+    /// Designate the secret which signs audit log checkpoints.
+    SetAuditSecret(SetAuditSecretCommand),
+    #[structopt(name = "export-logs")]
+    /// Export the audit log to a file which can be checked offline.
+    ExportLogs(ExportLogsCommand),
     #[structopt(name = "provision")]
     /// Perform first-time initialization to set up a fero server.
     Provision(ProvisionCommand),
@@ -105,6 +149,18 @@ struct ServeCommand {
     #[structopt(short = "w", long = "password")]
     /// Password for the HSM AuthKey.
     hsm_password: Option<String>,
+    #[structopt(long = "x509-trust-anchor", parse(from_os_str))]
+    /// CA certificate(s) which X.509 users' certificates must chain up to.
+    trust_anchors: Vec<PathBuf>,
+    #[structopt(long = "checkpoint-interval", default_value = "60")]
+    /// Minutes between signed checkpoints of the audit log, or 0 to disable them.
+    checkpoint_interval: u64,
+    #[structopt(long = "json-log", parse(from_os_str))]
+    /// A file to append each new audit log entry to, as a line of JSON.
+    json_log: Option<PathBuf>,
+    #[structopt(long = "syslog-socket", parse(from_os_str))]
+    /// The local syslog socket, such as `/dev/log`, to send each new audit log entry to.
+    syslog_socket: Option<PathBuf>,
 }
 
 #[derive(StructOpt)]
@@ -151,8 +207,11 @@ struct AddPemSecretCommand {
 #[derive(StructOpt)]
 struct AddUserCommand {
     #[structopt(short = "f", long = "file", parse(from_os_str))]
-    /// File containing the user's GPG public key to add.
+    /// File containing the user's GPG public key (or X.509 certificate) to add.
     file: PathBuf,
+    #[structopt(long = "x509")]
+    /// The file contains an X.509 certificate rather than a GPG public key.
+    x509: bool,
     #[structopt(short = "k", long = "authkey")]
     /// YubiHSM2 AuthKey to use.
     hsm_authkey: u16,
@@ -174,6 +233,143 @@ struct SetUserWeightCommand {
     weight: i32,
 }
 
+#[derive(StructOpt)]
+struct SetSecretCertificateCommand {
+    #[structopt(short = "n", long = "name")]
+    /// Name of the secret.
+    secret: String,
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    /// File containing the secret's certificate, in PEM or DER form.
+    file: PathBuf,
+    #[structopt(short = "c", long = "chain", parse(from_os_str))]
+    /// Files containing intermediate certificates, starting with the secret certificate's issuer.
+    chain: Vec<PathBuf>,
+    #[structopt(short = "k", long = "authkey")]
+    /// YubiHSM2 AuthKey to use.
+    hsm_authkey: u16,
+    #[structopt(short = "w", long = "password")]
+    /// Password for the HSM AuthKey.
+    hsm_password: Option<String>,
+}
+
+#[derive(StructOpt)]
+struct SetCertificateProfileCommand {
+    #[structopt(short = "n", long = "name")]
+    /// Name of the secret.
+    secret: String,
+    #[structopt(long = "validity-days", default_value = "365")]
+    /// How long issued certificates are valid for.
+    validity_days: i32,
+    #[structopt(long = "key-usage")]
+    /// Key usages to include, such as `digital-signature` or `key-cert-sign`.
+    key_usages: Vec<String>,
+    #[structopt(long = "extended-key-usage")]
+    /// Extended key usages to include, such as `server-auth`, or a dotted OID.
+    extended_key_usages: Vec<String>,
+    #[structopt(long = "dns-suffix")]
+    /// Domain under which requested DNS names must fall. Without any, DNS names are rejected.
+    dns_suffixes: Vec<String>,
+    #[structopt(long = "ca")]
+    /// Issue CA certificates.
+    is_ca: bool,
+    #[structopt(long = "path-length")]
+    /// Maximum number of intermediate CAs below issued CA certificates.
+    path_length: Option<i32>,
+    #[structopt(short = "k", long = "authkey")]
+    /// YubiHSM2 AuthKey to use.
+    hsm_authkey: u16,
+    #[structopt(short = "w", long = "password")]
+    /// Password for the HSM AuthKey.
+    hsm_password: Option<String>,
+}
+
+#[derive(StructOpt)]
+struct SetTsaSecretCommand {
+    #[structopt(short = "n", long = "name")]
+    /// Name of the secret, which must already have a certificate.
+    secret: String,
+    #[structopt(long = "policy")]
+    /// The TSA policy OID to issue timestamps under.
+    policy: String,
+    #[structopt(short = "k", long = "authkey")]
+    /// YubiHSM2 AuthKey to use.
+    hsm_authkey: u16,
+    #[structopt(short = "w", long = "password")]
+    /// Password for the HSM AuthKey.
+    hsm_password: Option<String>,
+}
+
+#[derive(StructOpt)]
+struct SetReleasePolicyCommand {
+    #[structopt(short = "n", long = "name")]
+    /// Name of the secret.
+    secret: String,
+    #[structopt(long = "max-validity-days")]
+    /// How long after its Date a Release file may be valid until.
+    max_validity_days: i32,
+    #[structopt(long = "max-date-age-hours", default_value = "24")]
+    /// How far a Release file's Date may be from the time it's signed.
+    max_date_age_hours: i32,
+    #[structopt(short = "k", long = "authkey")]
+    /// YubiHSM2 AuthKey to use.
+    hsm_authkey: u16,
+    #[structopt(short = "w", long = "password")]
+    /// Password for the HSM AuthKey.
+    hsm_password: Option<String>,
+}
+
+#[derive(StructOpt)]
+struct SetTufRoleCommand {
+    #[structopt(short = "n", long = "name")]
+    /// Name of the secret.
+    secret: String,
+    #[structopt(short = "r", long = "role")]
+    /// The TUF role: root, targets, snapshot or timestamp.
+    role: String,
+    #[structopt(long = "max-expiry-days")]
+    /// How far in the future the role's metadata may expire when it's signed.
+    max_expiry_days: i32,
+    #[structopt(short = "k", long = "authkey")]
+    /// YubiHSM2 AuthKey to use.
+    hsm_authkey: u16,
+    #[structopt(short = "w", long = "password")]
+    /// Password for the HSM AuthKey.
+    hsm_password: Option<String>,
+}
+
+#[derive(StructOpt)]
+struct ExportMinisignKeyCommand {
+    #[structopt(short = "n", long = "name")]
+    /// Name of the secret.
+    secret: String,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to write the public key to, rather than standard output.
+    output: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct SetAuditSecretCommand {
+    #[structopt(short = "n", long = "name")]
+    /// Name of the secret, which must be an RSA secret.
+    secret: String,
+    #[structopt(short = "k", long = "authkey")]
+    /// YubiHSM2 AuthKey to use.
+    hsm_authkey: u16,
+    #[structopt(short = "w", long = "password")]
+    /// Password for the HSM AuthKey.
+    hsm_password: Option<String>,
+}
+
+#[derive(StructOpt)]
+struct ExportLogsCommand {
+    #[structopt(short = "s", long = "since", default_value = "0")]
+    /// Only export logs created since this log index.
+    since: i32,
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    /// The file to write the export to, rather than standard output.
+    output: Option<PathBuf>,
+}
+
 #[derive(StructOpt)]
 struct ProvisionCommand {
     #[structopt(short = "y", long = "yes")]
@@ -197,24 +393,41 @@ fn parse_biguint(s: &str) -> Result<BigUint, ParseBigIntError> {
     }
 }
 
-fn create_server(
-    address: &str,
-    port: u16,
-    database: &str,
-    hsm_connector: &str,
-    hsm_authkey: u16,
-    hsm_password: &str,
-) -> Result<Server, Error> {
+/// Open an HSM session with `authkey`, prompting for its password unless it's given.
+fn open_hsm(
+    connector_url: &str,
+    authkey: u16,
+    password: Option<String>,
+) -> Result<hsm::Hsm, Error> {
+    let password = match password {
+        Some(password) => SecStr::from(password),
+        None => SecStr::from(rpassword::prompt_password_stdout("Password for HSM AuthKey: ")?),
+    };
+
+    hsm::Hsm::new(connector_url, authkey, str::from_utf8(password.unsecure())?)
+}
+
+fn create_server(address: &str, port: u16, service: service::FeroService) -> Result<Server, Error> {
     ServerBuilder::new(Arc::new(Environment::new(1)))
-        .register_service(create_fero(service::FeroService::new(
-            database::Configuration::new(database),
-            hsm::Hsm::new(hsm_connector, hsm_authkey, hsm_password)?,
-        )))
+        .register_service(create_fero(service))
         .bind(address, port)
         .build()
         .map_err(|e| e.into())
 }
 
+/// Sign a checkpoint of the audit log every `interval`, for as long as the server runs. The
+/// checkpointer has its own HSM session, since a session mustn't be used from two threads at once.
+fn spawn_checkpointer(hsm: hsm::Hsm, database: database::Configuration, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        match logging::create_checkpoint(&hsm, &database) {
+            Ok(Some(checkpoint)) => info!("Signed checkpoint of log entry {}", checkpoint.log_id),
+            Ok(None) => {}
+            Err(e) => error!("Failed to sign audit log checkpoint: {}", e),
+        }
+    });
+}
+
 pub fn main() -> Result<(), Error> {
     let opts = Opt::from_args();
 
@@ -229,14 +442,50 @@ pub fn main() -> Result<(), Error> {
                 ),
             };
 
-            let mut server = create_server(
-                &serve_opts.address,
-                serve_opts.port,
-                &opts.database,
+            let mut trust_anchors = Vec::new();
+            for path in &serve_opts.trust_anchors {
+                let mut anchor = Vec::new();
+                File::open(path)?.read_to_end(&mut anchor)?;
+                trust_anchors.push(anchor);
+            }
+
+            let database =
+                database::Configuration::new(&opts.database).with_trust_anchors(trust_anchors);
+
+            let mut log_sinks: Vec<Box<sinks::LogSink>> = Vec::new();
+            if let Some(ref path) = serve_opts.json_log {
+                log_sinks.push(Box::new(sinks::JsonSink::new(path)?));
+            }
+            if let Some(ref path) = serve_opts.syslog_socket {
+                log_sinks.push(Box::new(sinks::SyslogSink::new(path)?));
+            }
+            if !log_sinks.is_empty() {
+                sinks::spawn_log_shipper(database.clone(), log_sinks)?;
+            }
+
+            let hsm = hsm::Hsm::new(
                 &opts.hsm_connector_url,
                 serve_opts.hsm_authkey,
                 str::from_utf8(hsm_password.unsecure())?,
             )?;
+            logging::recover(&hsm, &database)?;
+
+            if serve_opts.checkpoint_interval > 0 {
+                let checkpoint_hsm = hsm::Hsm::new(
+                    &opts.hsm_connector_url,
+                    serve_opts.hsm_authkey,
+                    str::from_utf8(hsm_password.unsecure())?,
+                )?;
+                spawn_checkpointer(
+                    checkpoint_hsm,
+                    database.clone(),
+                    Duration::from_secs(serve_opts.checkpoint_interval * 60),
+                );
+            }
+
+            let service = service::FeroService::new(database, hsm);
+
+            let mut server = create_server(&serve_opts.address, serve_opts.port, service)?;
 
             server.start();
             let (tx, rx) = oneshot::channel();
@@ -309,9 +558,13 @@ pub fn main() -> Result<(), Error> {
 
             let mut key_bytes = Vec::new();
             File::open(&user_opts.file)?.read_to_end(&mut key_bytes)?;
-            let key_id = local::find_keyid(&key_bytes)?;
+            let (key_id, key_type) = if user_opts.x509 {
+                (local::find_x509_keyid(&key_bytes)?, UserKeyType::X509)
+            } else {
+                (local::find_keyid(&key_bytes)?, UserKeyType::Pgp)
+            };
 
-            local::store_user(&hsm, &opts.database, key_id, &key_bytes)?;
+            local::store_user(&hsm, &opts.database, key_id, key_type, &key_bytes)?;
         }
         FeroServerCommand::SetUserWeight(weight_opts) => {
             local::set_user_weight(
@@ -321,6 +574,87 @@ pub fn main() -> Result<(), Error> {
                 weight_opts.weight,
             )?;
         }
+        FeroServerCommand::SetSecretCertificate(cert_opts) => {
+            let hsm =
+                open_hsm(&opts.hsm_connector_url, cert_opts.hsm_authkey, cert_opts.hsm_password)?;
+            local::set_secret_certificate(
+                &hsm,
+                &opts.database,
+                &cert_opts.secret,
+                &cert_opts.file,
+                &cert_opts.chain,
+            )?;
+        }
+        FeroServerCommand::SetCertificateProfile(profile_opts) => {
+            let hsm = open_hsm(
+                &opts.hsm_connector_url,
+                profile_opts.hsm_authkey,
+                profile_opts.hsm_password,
+            )?;
+            local::set_certificate_profile(
+                &hsm,
+                &opts.database,
+                &profile_opts.secret,
+                profile_opts.validity_days,
+                &profile_opts.key_usages,
+                &profile_opts.extended_key_usages,
+                &profile_opts.dns_suffixes,
+                profile_opts.is_ca,
+                profile_opts.path_length,
+            )?;
+        }
+        FeroServerCommand::SetTsaSecret(tsa_opts) => {
+            let hsm =
+                open_hsm(&opts.hsm_connector_url, tsa_opts.hsm_authkey, tsa_opts.hsm_password)?;
+            local::set_tsa_secret(&hsm, &opts.database, &tsa_opts.secret, &tsa_opts.policy)?;
+        }
+        FeroServerCommand::SetReleasePolicy(release_opts) => {
+            let hsm = open_hsm(
+                &opts.hsm_connector_url,
+                release_opts.hsm_authkey,
+                release_opts.hsm_password,
+            )?;
+            local::set_release_policy(
+                &hsm,
+                &opts.database,
+                &release_opts.secret,
+                release_opts.max_validity_days,
+                release_opts.max_date_age_hours,
+            )?;
+        }
+        FeroServerCommand::SetTufRole(tuf_opts) => {
+            let hsm =
+                open_hsm(&opts.hsm_connector_url, tuf_opts.hsm_authkey, tuf_opts.hsm_password)?;
+            local::set_tuf_role(
+                &hsm,
+                &opts.database,
+                &tuf_opts.secret,
+                &tuf_opts.role,
+                tuf_opts.max_expiry_days,
+            )?;
+        }
+        FeroServerCommand::ExportMinisignKey(export_opts) => {
+            let public_key = local::minisign_public_key(&opts.database, &export_opts.secret)?;
+            match export_opts.output {
+                Some(output) => File::create(output)?.write_all(public_key.as_bytes())?,
+                None => print!("{}", public_key),
+            }
+        }
+        FeroServerCommand::SetAuditSecret(audit_opts) => {
+            let hsm = open_hsm(
+                &opts.hsm_connector_url,
+                audit_opts.hsm_authkey,
+                audit_opts.hsm_password,
+            )?;
+            local::set_audit_secret(&hsm, &opts.database, &audit_opts.secret)?;
+        }
+        FeroServerCommand::ExportLogs(export_opts) => {
+            let export = local::export_logs(&opts.database, export_opts.since)?;
+            match export_opts.output {
+                Some(output) => export.write_to(&mut File::create(output)?)?,
+                None => export.write_to(&mut io::stdout())?,
+            }
+        }
         FeroServerCommand::Provision(provision_opts) => {
             if !provision_opts.confirm {
                 error!("Provisioning the HSM is destructive! Pass the `-y` option to fero-server to confirm you want to do this.");
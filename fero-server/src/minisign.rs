@@ -0,0 +1,283 @@
+use base64;
+use byteorder::{ByteOrder, LittleEndian};
+use failure::Error;
+use sha2::{Digest, Sha256};
+
+use hsm::Hsm;
+
+// See the minisign documentation for the signature and public key formats. Signatures use the
+// prehashed "ED" algorithm, so only a BLAKE2b-512 hash of the payload has to be sent to the HSM.
+
+const PUBLIC_KEY_ALGORITHM: &[u8] = b"Ed";
+const SIGNATURE_ALGORITHM: &[u8] = b"ED";
+
+// BLAKE2b-512 with no key, as described in RFC 7693.
+const BLAKE2B_IV: [u64; 8] = [
+    0x6a09_e667_f3bc_c908,
+    0xbb67_ae85_84ca_a73b,
+    0x3c6e_f372_fe94_f82b,
+    0xa54f_f53a_5f1d_36f1,
+    0x510e_527f_ade6_82d1,
+    0x9b05_688c_2b3e_6c1f,
+    0x1f83_d9ab_fb41_bd6b,
+    0x5be0_cd19_137e_2179,
+];
+
+const BLAKE2B_SIGMA: [[usize; 16]; 12] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+];
+
+#[cfg_attr(feature = "cargo-clippy", allow(many_single_char_names))]
+fn blake2b_mix(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+}
+
+fn blake2b_compress(h: &mut [u64; 8], block: &[u8], counter: u128, last: bool) {
+    let mut m = [0u64; 16];
+    LittleEndian::read_u64_into(block, &mut m);
+
+    let mut v = [0u64; 16];
+    v[..8].copy_from_slice(h);
+    v[8..].copy_from_slice(&BLAKE2B_IV);
+    v[12] ^= counter as u64;
+    v[13] ^= (counter >> 64) as u64;
+    if last {
+        v[14] = !v[14];
+    }
+
+    for sigma in &BLAKE2B_SIGMA {
+        blake2b_mix(&mut v, 0, 4, 8, 12, m[sigma[0]], m[sigma[1]]);
+        blake2b_mix(&mut v, 1, 5, 9, 13, m[sigma[2]], m[sigma[3]]);
+        blake2b_mix(&mut v, 2, 6, 10, 14, m[sigma[4]], m[sigma[5]]);
+        blake2b_mix(&mut v, 3, 7, 11, 15, m[sigma[6]], m[sigma[7]]);
+        blake2b_mix(&mut v, 0, 5, 10, 15, m[sigma[8]], m[sigma[9]]);
+        blake2b_mix(&mut v, 1, 6, 11, 12, m[sigma[10]], m[sigma[11]]);
+        blake2b_mix(&mut v, 2, 7, 8, 13, m[sigma[12]], m[sigma[13]]);
+        blake2b_mix(&mut v, 3, 4, 9, 14, m[sigma[14]], m[sigma[15]]);
+    }
+
+    for i in 0..8 {
+        h[i] ^= v[i] ^ v[i + 8];
+    }
+}
+
+fn blake2b_512(data: &[u8]) -> Vec<u8> {
+    let mut h = BLAKE2B_IV;
+    // Parameter block: a 64-byte digest, no key, fanout and depth of 1.
+    h[0] ^= 0x0101_0040;
+
+    let mut counter = 0u128;
+    let mut blocks = data.chunks(128).peekable();
+    if blocks.peek().is_none() {
+        blake2b_compress(&mut h, &[0; 128], 0, true);
+    }
+    while let Some(block) = blocks.next() {
+        counter += block.len() as u128;
+        let mut padded = [0; 128];
+        padded[..block.len()].copy_from_slice(block);
+        blake2b_compress(&mut h, &padded, counter, blocks.peek().is_none());
+    }
+
+    let mut out = vec![0; 64];
+    LittleEndian::write_u64_into(&h, &mut out);
+    out
+}
+
+/// minisign key IDs are arbitrary 8-byte values; ours are taken from the SHA-256 of the public
+/// key, so that they never need to be stored.
+fn key_id(public_key: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::default();
+    hasher.input(public_key);
+    hasher.result()[..8].to_vec()
+}
+
+/// minisign displays key IDs as a little-endian integer.
+fn key_id_hex(key_id: &[u8]) -> String {
+    key_id.iter().rev().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// The contents of a minisign public key file for the Ed25519 public key `public_key`.
+pub fn public_key(public_key: &[u8]) -> Result<String, Error> {
+    if public_key.len() != 32 {
+        bail!("Secret does not have an Ed25519 public key");
+    }
+
+    let key_id = key_id(public_key);
+    let mut blob = PUBLIC_KEY_ALGORITHM.to_vec();
+    blob.extend_from_slice(&key_id);
+    blob.extend_from_slice(public_key);
+
+    Ok(format!(
+        "untrusted comment: minisign public key {}\n{}\n",
+        key_id_hex(&key_id),
+        base64::encode(&blob)
+    ))
+}
+
+/// The trusted comment of signatures, which records when signing was requested and the key IDs
+/// of the users who approved it.
+pub fn trusted_comment(timestamp: i64, approvers: &[u64]) -> String {
+    let approvers = approvers
+        .iter()
+        .map(|id| format!("{:016X}", id))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!("timestamp:{}\tapprovers:{}", timestamp, approvers)
+}
+
+/// The contents of a `.minisig` file, given `signature` over the payload's hash and
+/// `global_signature` over `signature` and `trusted_comment`.
+fn encode(
+    public_key: &[u8],
+    signature: &[u8],
+    trusted_comment: &str,
+    global_signature: &[u8],
+) -> String {
+    let mut blob = SIGNATURE_ALGORITHM.to_vec();
+    blob.extend_from_slice(&key_id(public_key));
+    blob.extend_from_slice(signature);
+
+    format!(
+        "untrusted comment: signature from fero secret key\n{}\ntrusted comment: {}\n{}\n",
+        base64::encode(&blob),
+        trusted_comment,
+        base64::encode(global_signature)
+    )
+}
+
+/// Create a `.minisig` file for `payload`. The signature covers the payload's hash, and the
+/// global signature covers that signature and `trusted_comment`.
+pub fn sign(
+    hsm: &Hsm,
+    key: u16,
+    public_key: &[u8],
+    payload: &[u8],
+    trusted_comment: &str,
+) -> Result<String, Error> {
+    if public_key.len() != 32 {
+        bail!("Secret does not have an Ed25519 public key");
+    }
+    if trusted_comment.contains('\n') || trusted_comment.contains('\r') {
+        bail!("Trusted comments must be a single line");
+    }
+
+    let signature = hsm.create_ed25519_signature(blake2b_512(payload), key)?;
+    let mut global = signature.clone();
+    global.extend_from_slice(trusted_comment.as_bytes());
+    let global_signature = hsm.create_ed25519_signature(global, key)?;
+    if signature.len() != 64 || global_signature.len() != 64 {
+        bail!("HSM returned a malformed Ed25519 signature");
+    }
+
+    Ok(encode(public_key, &signature, trusted_comment, &global_signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The Ed25519 key from RFC 8032 section 7.1, test 1, and a signature of the file
+    // testdata/minisign/payload made with its secret key, which `minisign -V` accepts.
+    const PUBLIC_KEY: &[u8] = &[
+        0xd7, 0x5a, 0x98, 0x01, 0x82, 0xb1, 0x0a, 0xb7, 0xd5, 0x4b, 0xfe, 0xd3, 0xc9, 0x64, 0x07,
+        0x3a, 0x0e, 0xe1, 0x72, 0xf3, 0xda, 0xa6, 0x23, 0x25, 0xaf, 0x02, 0x1a, 0x68, 0xf7, 0x07,
+        0x51, 0x1a,
+    ];
+    const PUBLIC_KEY_FILE: &str = include_str!("../testdata/minisign/minisign.pub");
+    const SIGNATURE_FILE: &str = include_str!("../testdata/minisign/payload.minisig");
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn blake2b() {
+        // From RFC 7693 appendix A.
+        assert_eq!(
+            hex(&blake2b_512(b"abc")),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+        assert_eq!(
+            hex(&blake2b_512(b"")),
+            "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419\
+             d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce"
+        );
+
+        // Either side of the block size, where the last block must not be compressed twice.
+        let data = (0..256).map(|byte| byte as u8).collect::<Vec<_>>();
+        let digests = [
+            (
+                127,
+                "b6292669ccd38d5f01caae96ba272c76a879a45743afa0725d83b9ebb26665b7\
+                 31f1848c52f11972b6644f554c064fa90780dbbbf3a89d4fc31f67df3e5857ef",
+            ),
+            (
+                128,
+                "2319e3789c47e2daa5fe807f61bec2a1a6537fa03f19ff32e87eecbfd64b7e0e\
+                 8ccff439ac333b040f19b0c4ddd11a61e24ac1fe0f10a039806c5dcc0da3d115",
+            ),
+            (
+                129,
+                "f59711d44a031d5f97a9413c065d1e614c417ede998590325f49bad2fd444d3e\
+                 4418be19aec4e11449ac1a57207898bc57d76a1bcf3566292c20c683a5c4648f",
+            ),
+            (
+                256,
+                "1ecc896f34d3f9cac484c73f75f6a5fb58ee6784be41b35f46067b9c65c63a67\
+                 94d3d744112c653f73dd7deb6666204c5a9bfa5b46081fc10fdbe7884fa5cbf8",
+            ),
+        ];
+        for &(length, digest) in &digests {
+            assert_eq!(hex(&blake2b_512(&data[..length])), digest);
+        }
+    }
+
+    #[test]
+    fn public_key_file() {
+        assert_eq!(public_key(PUBLIC_KEY).unwrap(), PUBLIC_KEY_FILE);
+        assert!(public_key(&PUBLIC_KEY[1..]).is_err());
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            trusted_comment(1_500_000_000, &[0x0123_4567_89ab_cdef, 1]),
+            "timestamp:1500000000\tapprovers:0123456789ABCDEF,0000000000000001"
+        );
+        assert_eq!(trusted_comment(0, &[]), "timestamp:0\tapprovers:");
+    }
+
+    #[test]
+    fn signature_file() {
+        let lines = SIGNATURE_FILE.lines().collect::<Vec<_>>();
+        let blob = base64::decode(lines[1]).unwrap();
+        let trusted_comment = lines[2].trim_left_matches("trusted comment: ");
+        let global_signature = base64::decode(lines[3]).unwrap();
+
+        assert_eq!(trusted_comment, self::trusted_comment(1_500_000_000, &[0x0123_4567_89ab_cdef]));
+        assert_eq!(
+            encode(PUBLIC_KEY, &blob[10..], trusted_comment, &global_signature),
+            SIGNATURE_FILE
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::str::{self, Split};
+
+use chrono::prelude::*;
+use chrono::Duration;
+use failure::Error;
+
+use database::models::ReleasePolicy;
+
+// Release files are a single deb822 paragraph; see the Debian repository format documentation.
+// The cleartext signature framework is described in RFC 4880 section 7.
+
+/// Parse a Release file date, which is in RFC 2822 form but usually with a `UTC` zone, which
+/// RFC 2822 doesn't allow for.
+fn parse_date(date: &str) -> Result<DateTime<Utc>, Error> {
+    let date = match date.trim() {
+        date if date.ends_with(" UTC") => format!("{} +0000", &date[..date.len() - 4]),
+        date => String::from(date),
+    };
+
+    Ok(DateTime::parse_from_rfc2822(&date)?.with_timezone(&Utc))
+}
+
+/// The fields of a Release file which are checked before signing it.
+pub struct Release<'a> {
+    text: &'a str,
+    pub date: DateTime<Utc>,
+    pub valid_until: DateTime<Utc>,
+}
+
+impl<'a> Release<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Release<'a>, Error> {
+        let text = str::from_utf8(data)?;
+        if !text.ends_with('\n') {
+            bail!("Release file must end with a newline");
+        }
+
+        let mut date = None;
+        let mut valid_until = None;
+        for (index, line) in text[..text.len() - 1].split('\n').enumerate() {
+            if line.starts_with(' ') || line.starts_with('\t') {
+                if index == 0 {
+                    bail!("Release file starts with a continuation line");
+                }
+                continue;
+            }
+
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap_or("");
+            let value = match parts.next() {
+                Some(value) if !name.is_empty() && !name.contains(' ') => value,
+                _ => bail!("Malformed Release file line {}: {:?}", index + 1, line),
+            };
+            match name {
+                "Date" => date = Some(parse_date(value)?),
+                "Valid-Until" => valid_until = Some(parse_date(value)?),
+                _ => {}
+            }
+        }
+
+        Ok(Release {
+            text,
+            date: date.ok_or(format_err!("Release file has no Date"))?,
+            valid_until: valid_until.ok_or(format_err!("Release file has no Valid-Until"))?,
+        })
+    }
+
+    /// Check the Release file's dates against the secret's policy: it must have been generated
+    /// recently, and be valid for no longer than the policy allows.
+    pub fn check(&self, policy: &ReleasePolicy, now: DateTime<Utc>) -> Result<(), Error> {
+        let max_date_age = Duration::hours(i64::from(policy.max_date_age_hours));
+        if self.date > now + max_date_age || self.date < now - max_date_age {
+            bail!(
+                "Release file Date must be within {} hours of now",
+                policy.max_date_age_hours
+            );
+        }
+        if self.valid_until <= now {
+            bail!("Release file has already expired");
+        }
+        if self.valid_until > self.date + Duration::days(i64::from(policy.max_validity_days)) {
+            bail!(
+                "Release file may be valid for at most {} days",
+                policy.max_validity_days
+            );
+        }
+
+        Ok(())
+    }
+
+    fn lines(&self) -> Split<'a, char> {
+        self.text[..self.text.len() - 1].split('\n')
+    }
+
+    /// The text covered by a cleartext signature: trailing whitespace is dropped and lines end
+    /// with CRLF, except for the last, whose line ending belongs to the signature.
+    pub fn canonical_text(&self) -> Vec<u8> {
+        self.lines()
+            .map(|line| line.trim_right_matches(|c: char| c == ' ' || c == '\t' || c == '\r'))
+            .collect::<Vec<_>>()
+            .join("\r\n")
+            .into_bytes()
+    }
+
+    /// Build the InRelease file from the armored signature over `canonical_text`.
+    pub fn in_release(&self, signature: &str) -> String {
+        let mut out = String::from("-----BEGIN PGP SIGNED MESSAGE-----\nHash: SHA256\n\n");
+        for line in self.lines() {
+            if line.starts_with('-') {
+                out.push_str("- ");
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push_str(signature);
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A Release file, and the InRelease made from it by `gpg --clearsign`.
+    const RELEASE: &[u8] = include_bytes!("../testdata/release/Release");
+    const IN_RELEASE: &str = include_str!("../testdata/release/InRelease");
+
+    fn policy(max_validity_days: i32, max_date_age_hours: i32) -> ReleasePolicy {
+        ReleasePolicy {
+            id: 1,
+            secret_id: 1,
+            max_validity_days,
+            max_date_age_hours,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let release = Release::parse(RELEASE).unwrap();
+        assert_eq!(release.date, Utc.ymd(2018, 9, 10).and_hms(12, 0, 0));
+        assert_eq!(release.valid_until, Utc.ymd(2018, 9, 17).and_hms(12, 0, 0));
+
+        let release = Release::parse(
+            b"Date: Mon, 10 Sep 2018 14:00:00 +0200\nValid-Until: Tue, 11 Sep 2018 12:00:00 GMT\n",
+        ).unwrap();
+        assert_eq!(release.date, Utc.ymd(2018, 9, 10).and_hms(12, 0, 0));
+        assert_eq!(release.valid_until, Utc.ymd(2018, 9, 11).and_hms(12, 0, 0));
+    }
+
+    #[test]
+    fn malformed() {
+        for release in &[
+            &b"Date: Mon, 10 Sep 2018 12:00:00 UTC\nValid-Until: Mon, 17 Sep 2018 12:00:00 UTC"[..],
+            b"Valid-Until: Mon, 17 Sep 2018 12:00:00 UTC\n",
+            b"Date: Mon, 10 Sep 2018 12:00:00 UTC\n",
+            b"Date: yesterday\nValid-Until: Mon, 17 Sep 2018 12:00:00 UTC\n",
+            b" SHA256:\nDate: Mon, 10 Sep 2018 12:00:00 UTC\n",
+            b"Date: Mon, 10 Sep 2018 12:00:00 UTC\nNo colon\n",
+            b"Date: Mon, 10 Sep 2018 12:00:00 UTC\n\nValid-Until: Mon, 17 Sep 2018 12:00:00 UTC\n",
+            b"Date: Mon, 10 Sep 2018 12:00:00 UTC\nValid-Until: \xff\n",
+        ] {
+            assert!(Release::parse(release).is_err());
+        }
+    }
+
+    #[test]
+    fn policy_checks() {
+        let release = Release::parse(RELEASE).unwrap();
+        let now = Utc.ymd(2018, 9, 10).and_hms(13, 0, 0);
+
+        release.check(&policy(7, 2), now).unwrap();
+        // Valid for too long
+        assert!(release.check(&policy(6, 2), now).is_err());
+        // Too old, or from the future
+        assert!(release.check(&policy(7, 2), now + Duration::hours(2)).is_err());
+        assert!(release.check(&policy(7, 2), now - Duration::hours(4)).is_err());
+        // Expired
+        assert!(release.check(&policy(7, 200), now + Duration::days(7)).is_err());
+    }
+
+    #[test]
+    fn in_release_matches_gpg() {
+        let release = Release::parse(RELEASE).unwrap();
+        let signature = &IN_RELEASE[IN_RELEASE.find("-----BEGIN PGP SIGNATURE-----").unwrap()..];
+        assert_eq!(release.in_release(signature), IN_RELEASE);
+    }
+
+    #[test]
+    fn canonical_text() {
+        let release = Release {
+            text: "A: b \nC: d\t\r\n-E: f\n",
+            date: Utc::now(),
+            valid_until: Utc::now(),
+        };
+        assert_eq!(release.canonical_text(), b"A: b\r\nC: d\r\n-E: f");
+    }
+}
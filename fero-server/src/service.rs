@@ -1,19 +1,45 @@
+use std::cmp;
+use std::fmt::Debug;
 use std::io::Write;
 
 use byteorder::{BigEndian, WriteBytesExt};
 use chrono::prelude::*;
+use chrono::Duration;
 use failure::Error;
-use futures::Future;
-use grpcio::{self, RpcContext, RpcStatus, UnarySink};
-use pretty_good::{HashAlgorithm, Packet};
+use futures::{future, stream, Future, Sink, Stream};
+use grpcio::{self, RpcContext, RpcStatus, ServerStreamingSink, UnarySink, WriteFlags};
+use num::BigUint;
+use pretty_good::{HashAlgorithm, Packet, SignatureType};
 use protobuf::{Message, repeated::RepeatedField, well_known_types::Timestamp};
+use sha2::{Digest, Sha256};
 
-use database::Configuration;
+use authenticode;
+use cms::{self, SignedData};
+use cosign;
+use database::{AuthenticatedConnection, Configuration, LogFilter};
+use database::models::{RevokedCertificate, SecretAlgorithm};
+use fero_proto::armor::armor_signature;
+use fero_proto::checkpoint;
 use fero_proto::fero::*;
 use fero_proto::fero_grpc::*;
 use fero_proto::log::*;
+// Both globs above export these records; these are the ones we build log entries from.
+use fero_proto::log::{RevocationRecord, SignatureRecord};
+use fero_proto::rpm;
+use git;
 use hsm::*;
+use jws;
+use kmod;
 use logging;
+use minisign;
+use release::Release;
+use ssh;
+use tsa::{self, TimestampRequest as TsaRequest};
+use tuf;
+use x509::{self, Certificate, CertificationRequest};
+
+const DEFAULT_LOG_PAGE_SIZE: u32 = 100;
+const MAX_LOG_PAGE_SIZE: u32 = 1000;
 
 #[derive(Clone)]
 pub struct FeroService {
@@ -28,10 +54,27 @@ impl Fero for FeroService {
             req.get_timestamp().get_nanos() as u32,
         );
 
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::Sign,
+            timestamp,
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
+
         let operation_result = self.sign_payload(
             req.get_identification(),
             req.get_payload(),
             req.get_sigType(),
+            &timestamp,
+        );
+
+        let record = SignatureRecord::new(
+            req.get_identification().get_secretKeyName(),
+            &format!("{:?}", req.get_sigType()),
+            req.get_payload(),
+            operation_result.as_ref().ok().map(|signature| signature.as_slice()),
         );
 
         let logged_result = match operation_result {
@@ -39,14 +82,16 @@ impl Fero for FeroService {
             Err(_) => OperationResult::Failure,
         };
 
-        logging::log_operation(
+        if let Err(e) = logging::log_operation_with_signature(
             &self.signer,
             &self.database,
-            OperationType::Sign,
+            intent,
             logged_result,
             Some(req.take_identification()),
-            timestamp,
-        ).unwrap_or_else(|e| panic!("Failed to log an operation: {}", e));
+            Some(record),
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
+        }
 
         match operation_result {
             Ok(signature) => {
@@ -77,24 +122,40 @@ impl Fero for FeroService {
             req.get_timestamp().get_nanos() as u32,
         );
 
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::Threshold,
+            timestamp,
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
+
         let operation_result = self.set_secret_key_threshold(
             req.get_identification(),
             req.get_threshold(),
         );
 
+        // The new threshold is only committed along with its log entry.
+        let (intent, operation_result) = match operation_result {
+            Ok(changes) => (intent.with_changes(changes), Ok(())),
+            Err(e) => (intent, Err(e)),
+        };
+
         let logged_result = match operation_result {
             Ok(_) => OperationResult::Success,
             Err(_) => OperationResult::Failure,
         };
 
-        logging::log_operation(
+        if let Err(e) = logging::log_operation(
             &self.signer,
             &self.database,
-            OperationType::Threshold,
+            intent,
             logged_result,
             Some(req.take_identification()),
-            timestamp,
-        ).unwrap_or_else(|e| panic!("Failed to log an operation: {}", e));
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
+        }
 
         match operation_result {
             Ok(_) => ctx.spawn(sink.success(ThresholdResponse::new()).map_err(move |err| {
@@ -118,25 +179,41 @@ impl Fero for FeroService {
             req.get_timestamp().get_nanos() as u32,
         );
 
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::Weight,
+            timestamp,
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
+
         let operation_result = self.set_user_key_weight(
             req.get_identification(),
             req.get_userKeyId(),
             req.get_weight(),
         );
 
+        // The new weight is only committed along with its log entry.
+        let (intent, operation_result) = match operation_result {
+            Ok(changes) => (intent.with_changes(changes), Ok(())),
+            Err(e) => (intent, Err(e)),
+        };
+
         let logged_result = match operation_result {
             Ok(_) => OperationResult::Success,
             Err(_) => OperationResult::Failure,
         };
 
-        logging::log_operation(
+        if let Err(e) = logging::log_operation(
             &self.signer,
             &self.database,
-            OperationType::Weight,
+            intent,
             logged_result,
             Some(req.take_identification()),
-            timestamp,
-        ).unwrap_or_else(|e| panic!("Failed to log an operation: {}", e));
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
+        }
 
         match operation_result {
             Ok(_) => ctx.spawn(sink.success(WeightResponse::new()).map_err(move |err| {
@@ -149,143 +226,1272 @@ impl Fero for FeroService {
         }
     }
 
-    fn get_logs(&self, ctx: RpcContext, req: LogRequest, sink: UnarySink<LogResponse>) {
-        match self.get_logs(req.get_minIndex()) {
-            Ok(logs) => {
-                let mut response = LogResponse::new();
-                response.set_logs(RepeatedField::from_vec(logs));
+    fn sign_ssh_certificate(
+        &self,
+        ctx: RpcContext,
+        mut req: SshCertificateRequest,
+        sink: UnarySink<SshCertificateResponse>,
+    ) {
+        let timestamp = NaiveDateTime::from_timestamp(
+            req.get_timestamp().get_seconds(),
+            req.get_timestamp().get_nanos() as u32,
+        );
+
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::SshCertify,
+            timestamp,
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
 
+        let operation_result = self.sign_ssh_certificate(
+            req.get_identification(),
+            req.get_params(),
+        );
+
+        let logged_result = match operation_result {
+            Ok(_) => OperationResult::Success,
+            Err(_) => OperationResult::Failure,
+        };
+
+        if let Err(e) = logging::log_operation(
+            &self.signer,
+            &self.database,
+            intent,
+            logged_result,
+            Some(req.take_identification()),
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
+        }
+
+        match operation_result {
+            Ok(certificate) => {
+                let mut response = SshCertificateResponse::new();
+                response.set_certificate(certificate);
                 ctx.spawn(sink.success(response).map_err(move |err| {
                     error!("failed to reply {:?}: {:?}", req, err)
                 }))
             }
-            Err(err) => ctx.spawn(sink.fail(RpcStatus {
-                status: grpcio::RpcStatusCode::Aborted,
-                details: Some(format!("Failed to retrive logs: {}", err)),
-            }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err))),
-        }
-    }
-}
-
-impl FeroService {
-    pub fn new(database: Configuration, signer: Hsm) -> FeroService {
-        FeroService { database, signer }
+            Err(e) => {
+                warn!("Failed to sign SSH certificate: {}", e);
+                ctx.spawn(sink.fail(RpcStatus {
+                    status: grpcio::RpcStatusCode::PermissionDenied,
+                    details: Some(format!("{}", e)),
+                }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)))
+            }
+        };
     }
 
-    fn set_secret_key_threshold(
+    fn issue_certificate(
         &self,
-        ident: &Identification,
-        threshold: i32,
-    ) -> Result<(), Error> {
-        let mut payload = Vec::new();
-        payload.write(ident.get_secretKeyName().as_bytes())?;
-        payload.write_i32::<BigEndian>(threshold)?;
+        ctx: RpcContext,
+        mut req: CertificateRequest,
+        sink: UnarySink<CertificateResponse>,
+    ) {
+        let timestamp = NaiveDateTime::from_timestamp(
+            req.get_timestamp().get_seconds(),
+            req.get_timestamp().get_nanos() as u32,
+        );
 
-        let (conn, _) = self.database.authenticate(ident, &payload)?;
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::IssueCertificate,
+            timestamp,
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
+
+        let operation_result = self.issue_certificate(req.get_identification(), req.get_csr());
+
+        let logged_result = match operation_result {
+            Ok(_) => OperationResult::Success,
+            Err(_) => OperationResult::Failure,
+        };
+
+        if let Err(e) = logging::log_operation(
+            &self.signer,
+            &self.database,
+            intent,
+            logged_result,
+            Some(req.take_identification()),
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
+        }
 
-        conn.set_secret_key_threshold(ident.get_secretKeyId(), threshold)
+        match operation_result {
+            Ok(certificate) => {
+                let mut response = CertificateResponse::new();
+                response.set_certificate(certificate);
+                ctx.spawn(sink.success(response).map_err(move |err| {
+                    error!("failed to reply {:?}: {:?}", req, err)
+                }))
+            }
+            Err(e) => {
+                warn!("Failed to issue certificate: {}", e);
+                ctx.spawn(sink.fail(RpcStatus {
+                    status: grpcio::RpcStatusCode::PermissionDenied,
+                    details: Some(format!("{}", e)),
+                }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)))
+            }
+        };
     }
 
-    fn set_user_key_weight(
-        &self,
-        ident: &Identification,
-        user_key_id: u64,
-        weight: i32,
-    ) -> Result<(), Error> {
-        let mut payload = Vec::new();
-        payload.write(ident.get_secretKeyName().as_bytes())?;
-        payload.write_u64::<BigEndian>(user_key_id)?;
-        payload.write_i32::<BigEndian>(weight)?;
+    fn generate_crl(&self, ctx: RpcContext, mut req: CrlRequest, sink: UnarySink<CrlResponse>) {
+        let timestamp = NaiveDateTime::from_timestamp(
+            req.get_timestamp().get_seconds(),
+            req.get_timestamp().get_nanos() as u32,
+        );
 
-        let (conn, _) = self.database.authenticate(ident, &payload)?;
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::GenerateCrl,
+            timestamp,
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
 
-        if let Some(user) = conn.get_user_key(user_key_id)? {
-            conn.upsert_user_key_weight(user, weight)
-        } else {
-            bail!("No such user")
+        let operation_result = self.generate_crl(req.get_identification(), req.get_params());
+
+        // The new CRL number and revocations are only committed along with the log entries.
+        let (intent, operation_result) = match operation_result {
+            Ok((crl, revocations, changes)) => {
+                (intent.with_changes(changes), Ok((crl, revocations)))
+            }
+            Err(e) => (intent, Err(e)),
+        };
+
+        let logged_result = match operation_result {
+            Ok(_) => OperationResult::Success,
+            Err(_) => OperationResult::Failure,
+        };
+
+        // Each newly-revoked certificate gets its own entry in the audit log, ahead of the entry
+        // for the CRL which first lists it.
+        let revocations = match operation_result {
+            Ok((_, ref revocations)) => revocations
+                .iter()
+                .map(|certificate| RevocationRecord {
+                    serial: certificate.serial.clone(),
+                    reason: certificate.reason as u32,
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        if let Err(e) = logging::log_operation_with_revocations(
+            &self.signer,
+            &self.database,
+            intent,
+            logged_result,
+            Some(req.take_identification()),
+            revocations,
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
         }
+
+        match operation_result {
+            Ok((crl, _)) => {
+                let mut response = CrlResponse::new();
+                response.set_crl(crl);
+                ctx.spawn(sink.success(response).map_err(move |err| {
+                    error!("failed to reply {:?}: {:?}", req, err)
+                }))
+            }
+            Err(e) => {
+                warn!("Failed to generate CRL: {}", e);
+                ctx.spawn(sink.fail(RpcStatus {
+                    status: grpcio::RpcStatusCode::PermissionDenied,
+                    details: Some(format!("{}", e)),
+                }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)))
+            }
+        };
     }
 
-    pub(crate) fn sign_payload(
+    fn timestamp(
         &self,
-        ident: &Identification,
-        payload: &[u8],
-        sig_type: SignRequest_SignatureType,
-    ) -> Result<Vec<u8>, Error>{
-        let (database, data) = self.database.authenticate(ident, payload)?;
+        ctx: RpcContext,
+        req: TimestampRequest,
+        sink: UnarySink<TimestampResponse>,
+    ) {
+        // Unlike other operations, the time comes from the server's clock rather than the
+        // bastion's, since the time is the thing being attested to.
+        let now = Utc::now();
 
-        let hsm_key = database.get_hsm_key_id()?;
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::Timestamp,
+            now.naive_utc(),
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
 
-        let out = match sig_type {
-            SignRequest_SignatureType::PGP => match database.get_pgp_key_id() {
-                Some(pgp_key_id) => self.signer
-                    .create_pgp_signature(data, hsm_key, HashAlgorithm::Sha256)
-                    .and_then(|mut sig| {
-                        sig.set_signer(pgp_key_id);
-                        Packet::Signature(sig).to_bytes()
-                    })
-                    .map(Vec::from)?,
-                None => bail!("Tried to use non-PGP key for PGP signature"),
+        let operation_result = self.timestamp(req.get_request(), now);
+
+        // The timestamp's serial number is only committed along with the log entry. Only
+        // granted requests are signed, by the timestamp authority's secret.
+        let (intent, logged_result, record, operation_result) = match operation_result {
+            Ok((response, Some(changes))) => {
+                let record = SignatureRecord::new(
+                    changes.get_name(),
+                    "TIMESTAMP",
+                    req.get_request(),
+                    Some(&response),
+                );
+                let intent = intent.with_changes(changes);
+                (intent, OperationResult::Success, Some(record), Ok(response))
             }
-            SignRequest_SignatureType::PKCS1V1_5 => self.signer.create_rsa_signature(data, hsm_key)?
+            Ok((response, None)) => (intent, OperationResult::Failure, None, Ok(response)),
+            Err(e) => (intent, OperationResult::Failure, None, Err(e)),
         };
 
-        Ok(out)
+        if let Err(e) = logging::log_operation_with_signature(
+            &self.signer,
+            &self.database,
+            intent,
+            logged_result,
+            None,
+            record,
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
+        }
+
+        match operation_result {
+            Ok(timestamp_response) => {
+                let mut response = TimestampResponse::new();
+                response.set_response(timestamp_response);
+                ctx.spawn(sink.success(response).map_err(move |err| {
+                    error!("failed to reply {:?}: {:?}", req, err)
+                }))
+            }
+            Err(e) => {
+                warn!("Failed to timestamp: {}", e);
+                ctx.spawn(sink.fail(RpcStatus {
+                    status: grpcio::RpcStatusCode::Unavailable,
+                    details: Some(format!("{}", e)),
+                }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)))
+            }
+        };
     }
 
-    fn get_logs(&self, min_index: i32) -> Result<Vec<LogEntry>, Error> {
-        self.database
-            .fero_logs_since(min_index)?
-            .iter()
-            .map(|fero_db_log| -> Result<_, Error> {
-                let hsm_logs = self.database
-                    .associated_hsm_logs(fero_db_log)?
-                    .iter()
-                    .map(|hsm_db_log| {
-                        let mut hsm_log = HsmLog::new();
-
-                        hsm_log.set_id(hsm_db_log.hsm_index as u32);
-                        hsm_log.set_command(hsm_db_log.command as u32);
-                        hsm_log.set_data_length(hsm_db_log.data_length as u32);
-                        hsm_log.set_session_key(hsm_db_log.session_key as u32);
-                        hsm_log.set_target_key(hsm_db_log.target_key as u32);
-                        hsm_log.set_second_key(hsm_db_log.second_key as u32);
-                        hsm_log.set_result(hsm_db_log.result as u32);
-                        hsm_log.set_systick(hsm_db_log.systick as u32);
-                        hsm_log.set_hash(hsm_db_log.hash.clone());
-
-                        hsm_log
-                    })
-                    .collect::<Vec<_>>();
-
-                let mut entry = LogEntry::new();
-
-                entry.set_id(fero_db_log.id);
-                entry.set_operation_type(match fero_db_log.request_type {
-                    OperationType::Sign => LogEntry_OperationType::SIGN,
-                    OperationType::Threshold => LogEntry_OperationType::THRESHOLD,
-                    OperationType::Weight => LogEntry_OperationType::WEIGHT,
-                    OperationType::AddSecret => LogEntry_OperationType::ADD_SECRET,
-                    OperationType::AddUser => LogEntry_OperationType::ADD_USER,
-                });
-                let mut timestamp = Timestamp::new();
-                timestamp.set_seconds(fero_db_log.timestamp.timestamp());
-                timestamp.set_nanos(fero_db_log.timestamp.timestamp_subsec_nanos() as i32);
-                entry.set_timestamp(timestamp);
-                entry.set_result(match fero_db_log.result {
-                    OperationResult::Success => LogEntry_OperationResult::SUCCESS,
-                    OperationResult::Failure => LogEntry_OperationResult::FAILURE,
-                });
-                if let Some(ref ident_bytes) = fero_db_log.identification {
-                    let mut ident = Identification::new();
-                    ident.merge_from_bytes(ident_bytes)?;
-                    entry.set_ident(ident);
-                }
-                entry.set_hsm_logs(RepeatedField::from_vec(hsm_logs));
-                entry.set_hash(fero_db_log.hash.clone());
+    fn sign_jws(&self, ctx: RpcContext, mut req: JwsRequest, sink: UnarySink<JwsResponse>) {
+        let timestamp = NaiveDateTime::from_timestamp(
+            req.get_timestamp().get_seconds(),
+            req.get_timestamp().get_nanos() as u32,
+        );
 
-                Ok(entry)
-            })
-            .collect::<Result<Vec<_>, Error>>()
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::Sign,
+            timestamp,
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
+
+        let operation_result = self.sign_jws(
+            req.get_identification(),
+            req.get_payload(),
+            req.get_serialization(),
+        );
+
+        let record = SignatureRecord::new(
+            req.get_identification().get_secretKeyName(),
+            "JWS",
+            req.get_payload(),
+            operation_result.as_ref().ok().map(|jws| jws.as_bytes()),
+        );
+
+        let logged_result = match operation_result {
+            Ok(_) => OperationResult::Success,
+            Err(_) => OperationResult::Failure,
+        };
+
+        if let Err(e) = logging::log_operation_with_signature(
+            &self.signer,
+            &self.database,
+            intent,
+            logged_result,
+            Some(req.take_identification()),
+            Some(record),
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
+        }
+
+        match operation_result {
+            Ok(jws) => {
+                let mut response = JwsResponse::new();
+                response.set_jws(jws);
+                ctx.spawn(sink.success(response).map_err(move |err| {
+                    error!("failed to reply {:?}: {:?}", req, err)
+                }))
+            }
+            Err(e) => {
+                warn!("Failed to sign JWS: {}", e);
+                ctx.spawn(sink.fail(RpcStatus {
+                    status: grpcio::RpcStatusCode::PermissionDenied,
+                    details: Some(format!("{}", e)),
+                }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)))
+            }
+        };
     }
+
+    fn sign_tuf_metadata(
+        &self,
+        ctx: RpcContext,
+        mut req: TufRequest,
+        sink: UnarySink<TufResponse>,
+    ) {
+        let timestamp = NaiveDateTime::from_timestamp(
+            req.get_timestamp().get_seconds(),
+            req.get_timestamp().get_nanos() as u32,
+        );
+
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::Sign,
+            timestamp,
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
+
+        let operation_result =
+            self.sign_tuf_metadata(req.get_identification(), req.get_metadata());
+
+        // The role's new version is only committed along with the log entry.
+        let (intent, operation_result) = match operation_result {
+            Ok((response, changes)) => (intent.with_changes(changes), Ok(response)),
+            Err(e) => (intent, Err(e)),
+        };
+
+        let record = SignatureRecord::new(
+            req.get_identification().get_secretKeyName(),
+            "TUF",
+            req.get_metadata(),
+            operation_result.as_ref().ok().map(|response| response.get_sig().as_bytes()),
+        );
+
+        let logged_result = match operation_result {
+            Ok(_) => OperationResult::Success,
+            Err(_) => OperationResult::Failure,
+        };
+
+        if let Err(e) = logging::log_operation_with_signature(
+            &self.signer,
+            &self.database,
+            intent,
+            logged_result,
+            Some(req.take_identification()),
+            Some(record),
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
+        }
+
+        match operation_result {
+            Ok(response) => ctx.spawn(sink.success(response).map_err(move |err| {
+                error!("failed to reply {:?}: {:?}", req, err)
+            })),
+            Err(e) => {
+                warn!("Failed to sign TUF metadata: {}", e);
+                ctx.spawn(sink.fail(RpcStatus {
+                    status: grpcio::RpcStatusCode::PermissionDenied,
+                    details: Some(format!("{}", e)),
+                }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)))
+            }
+        };
+    }
+
+    fn sign_cosign(
+        &self,
+        ctx: RpcContext,
+        mut req: CosignRequest,
+        sink: UnarySink<CosignResponse>,
+    ) {
+        let timestamp = NaiveDateTime::from_timestamp(
+            req.get_timestamp().get_seconds(),
+            req.get_timestamp().get_nanos() as u32,
+        );
+
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::Sign,
+            timestamp,
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
+
+        // For images, the artifact is what users approved: the serialized image.
+        let artifact = if req.has_image() {
+            req.get_image()
+                .write_to_bytes()
+                .map_err(|e| format_err!("Failed to serialize image: {}", e))
+        } else {
+            Ok(req.get_blob().to_vec())
+        };
+
+        let (operation_result, record) = match artifact {
+            Ok(artifact) => {
+                let operation_result = self.sign_cosign(&req, &artifact);
+                let record = SignatureRecord::new(
+                    req.get_identification().get_secretKeyName(),
+                    "COSIGN",
+                    &artifact,
+                    operation_result
+                        .as_ref()
+                        .ok()
+                        .map(|response| response.get_signature().as_bytes()),
+                );
+                (operation_result, Some(record))
+            }
+            Err(e) => (Err(e), None),
+        };
+
+        let logged_result = match operation_result {
+            Ok(_) => OperationResult::Success,
+            Err(_) => OperationResult::Failure,
+        };
+
+        if let Err(e) = logging::log_operation_with_signature(
+            &self.signer,
+            &self.database,
+            intent,
+            logged_result,
+            Some(req.take_identification()),
+            record,
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
+        }
+
+        match operation_result {
+            Ok(response) => ctx.spawn(sink.success(response).map_err(move |err| {
+                error!("failed to reply {:?}: {:?}", req, err)
+            })),
+            Err(e) => {
+                warn!("Failed to sign for cosign: {}", e);
+                ctx.spawn(sink.fail(RpcStatus {
+                    status: grpcio::RpcStatusCode::PermissionDenied,
+                    details: Some(format!("{}", e)),
+                }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)))
+            }
+        };
+    }
+
+    fn sign_release(
+        &self,
+        ctx: RpcContext,
+        mut req: ReleaseRequest,
+        sink: UnarySink<ReleaseResponse>,
+    ) {
+        let timestamp = NaiveDateTime::from_timestamp(
+            req.get_timestamp().get_seconds(),
+            req.get_timestamp().get_nanos() as u32,
+        );
+
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::Sign,
+            timestamp,
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
+
+        let operation_result = self.sign_release(req.get_identification(), req.get_release());
+
+        let record = SignatureRecord::new(
+            req.get_identification().get_secretKeyName(),
+            "RELEASE",
+            req.get_release(),
+            operation_result.as_ref().ok().map(|response| response.get_release_gpg().as_bytes()),
+        );
+
+        let logged_result = match operation_result {
+            Ok(_) => OperationResult::Success,
+            Err(_) => OperationResult::Failure,
+        };
+
+        if let Err(e) = logging::log_operation_with_signature(
+            &self.signer,
+            &self.database,
+            intent,
+            logged_result,
+            Some(req.take_identification()),
+            Some(record),
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
+        }
+
+        match operation_result {
+            Ok(response) => ctx.spawn(sink.success(response).map_err(move |err| {
+                error!("failed to reply {:?}: {:?}", req, err)
+            })),
+            Err(e) => {
+                warn!("Failed to sign Release file: {}", e);
+                ctx.spawn(sink.fail(RpcStatus {
+                    status: grpcio::RpcStatusCode::PermissionDenied,
+                    details: Some(format!("{}", e)),
+                }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)))
+            }
+        };
+    }
+
+    fn sign_rpm(&self, ctx: RpcContext, mut req: RpmRequest, sink: UnarySink<RpmResponse>) {
+        let timestamp = NaiveDateTime::from_timestamp(
+            req.get_timestamp().get_seconds(),
+            req.get_timestamp().get_nanos() as u32,
+        );
+
+        let intent = match logging::begin_operation(
+            &self.database,
+            OperationType::Sign,
+            timestamp,
+        ) {
+            Ok(intent) => intent,
+            Err(e) => return fail_logging(&ctx, sink, req, &e),
+        };
+
+        let operation_result = self.sign_rpm(req.get_identification(), req.get_package());
+
+        // Record the header that was signed rather than the whole package, whose payload may be
+        // large and isn't covered by the signature. Its digest is the one users approved.
+        let artifact = rpm::header_blob(req.get_package())
+            .unwrap_or_else(|_| req.get_package())
+            .to_vec();
+        let record = SignatureRecord::new(
+            req.get_identification().get_secretKeyName(),
+            "RPM",
+            &artifact,
+            operation_result.as_ref().ok().map(|response| response.get_signature()),
+        );
+
+        let logged_result = match operation_result {
+            Ok(_) => OperationResult::Success,
+            Err(_) => OperationResult::Failure,
+        };
+
+        if let Err(e) = logging::log_operation_with_signature(
+            &self.signer,
+            &self.database,
+            intent,
+            logged_result,
+            Some(req.take_identification()),
+            Some(record),
+        ) {
+            return fail_logging(&ctx, sink, req, &e);
+        }
+
+        match operation_result {
+            Ok(response) => ctx.spawn(sink.success(response).map_err(move |err| {
+                error!("failed to reply {:?}: {:?}", req, err)
+            })),
+            Err(e) => {
+                warn!("Failed to sign RPM: {}", e);
+                ctx.spawn(sink.fail(RpcStatus {
+                    status: grpcio::RpcStatusCode::PermissionDenied,
+                    details: Some(format!("{}", e)),
+                }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)))
+            }
+        };
+    }
+
+    fn get_logs(&self, ctx: RpcContext, req: LogRequest, sink: UnarySink<LogResponse>) {
+        match self.get_logs(req.get_minIndex()) {
+            Ok(logs) => {
+                let mut response = LogResponse::new();
+                response.set_logs(RepeatedField::from_vec(logs));
+
+                ctx.spawn(sink.success(response).map_err(move |err| {
+                    error!("failed to reply {:?}: {:?}", req, err)
+                }))
+            }
+            Err(err) => ctx.spawn(sink.fail(RpcStatus {
+                status: grpcio::RpcStatusCode::Aborted,
+                details: Some(format!("Failed to retrive logs: {}", err)),
+            }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err))),
+        }
+    }
+
+    fn get_checkpoints(
+        &self,
+        ctx: RpcContext,
+        req: CheckpointRequest,
+        sink: UnarySink<CheckpointResponse>,
+    ) {
+        match self.get_checkpoints(req.get_minIndex()) {
+            Ok(response) => ctx.spawn(sink.success(response).map_err(move |err| {
+                error!("failed to reply {:?}: {:?}", req, err)
+            })),
+            Err(err) => ctx.spawn(sink.fail(RpcStatus {
+                status: grpcio::RpcStatusCode::Aborted,
+                details: Some(format!("Failed to retrieve checkpoints: {}", err)),
+            }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err))),
+        }
+    }
+
+    fn get_inclusion_proof(
+        &self,
+        ctx: RpcContext,
+        req: InclusionProofRequest,
+        sink: UnarySink<InclusionProofResponse>,
+    ) {
+        match self.get_inclusion_proof(req.get_log_id(), req.get_tree_size()) {
+            Ok(response) => ctx.spawn(sink.success(response).map_err(move |err| {
+                error!("failed to reply {:?}: {:?}", req, err)
+            })),
+            Err(err) => ctx.spawn(sink.fail(RpcStatus {
+                status: grpcio::RpcStatusCode::Aborted,
+                details: Some(format!("Failed to prove inclusion: {}", err)),
+            }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err))),
+        }
+    }
+
+    fn get_consistency_proof(
+        &self,
+        ctx: RpcContext,
+        req: ConsistencyProofRequest,
+        sink: UnarySink<ConsistencyProofResponse>,
+    ) {
+        match self.get_consistency_proof(req.get_first(), req.get_second()) {
+            Ok(response) => ctx.spawn(sink.success(response).map_err(move |err| {
+                error!("failed to reply {:?}: {:?}", req, err)
+            })),
+            Err(err) => ctx.spawn(sink.fail(RpcStatus {
+                status: grpcio::RpcStatusCode::Aborted,
+                details: Some(format!("Failed to prove consistency: {}", err)),
+            }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err))),
+        }
+    }
+
+    fn export_logs(
+        &self,
+        ctx: RpcContext,
+        req: LogRequest,
+        sink: ServerStreamingSink<LogExportRecord>,
+    ) {
+        match logging::export_logs(&self.database, req.get_minIndex()) {
+            Ok(export) => {
+                let records = export
+                    .into_records()
+                    .into_iter()
+                    .map(|record| (record, WriteFlags::default()));
+
+                ctx.spawn(
+                    sink.send_all(stream::iter_ok::<_, grpcio::Error>(records))
+                        .map(|_| ())
+                        .map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)),
+                )
+            }
+            Err(err) => ctx.spawn(sink.fail(RpcStatus {
+                status: grpcio::RpcStatusCode::Aborted,
+                details: Some(format!("Failed to export logs: {}", err)),
+            }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err))),
+        }
+    }
+
+    fn stream_logs(
+        &self,
+        ctx: RpcContext,
+        req: LogStreamRequest,
+        sink: ServerStreamingSink<LogResponse>,
+    ) {
+        let database = self.database.clone();
+        let filter = log_filter(&req);
+        let page_size = match req.get_page_size() {
+            0 => DEFAULT_LOG_PAGE_SIZE,
+            page_size => cmp::min(page_size, MAX_LOG_PAGE_SIZE),
+        };
+
+        // Each page is only queried once the previous one has been sent, and a full page is
+        // followed by another until one comes back short.
+        let pages = stream::unfold(Some(req.get_minIndex()), move |after| {
+            let after = match after {
+                Some(after) => after,
+                None => return None,
+            };
+
+            match logging::log_page(&database, after, i64::from(page_size), &filter) {
+                Ok(ref logs) if logs.is_empty() => None,
+                Ok(logs) => {
+                    let next = if logs.len() < page_size as usize {
+                        None
+                    } else {
+                        logs.last().map(|log| log.id)
+                    };
+
+                    let mut response = LogResponse::new();
+                    response.set_logs(RepeatedField::from_vec(logs));
+                    Some(future::ok(((response, WriteFlags::default()), next)))
+                }
+                Err(err) => Some(future::err(grpcio::Error::RpcFailure(RpcStatus {
+                    status: grpcio::RpcStatusCode::Aborted,
+                    details: Some(format!("Failed to retrieve logs: {}", err)),
+                }))),
+            }
+        });
+
+        ctx.spawn(
+            sink.send_all(pages)
+                .map(|_| ())
+                .map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)),
+        )
+    }
+
+    fn follow_logs(
+        &self,
+        ctx: RpcContext,
+        req: LogStreamRequest,
+        sink: ServerStreamingSink<LogResponse>,
+    ) {
+        let database = self.database.clone();
+        let filter = log_filter(&req);
+        let mut after = req.get_minIndex();
+
+        // Start following before reading what's already logged, so that nothing committed in
+        // between is missed. Each wakeup sends whatever has been logged since the last, and a
+        // failure to read the log is retried on the next.
+        let wakeups = self.database.follow_logs().map_err(|()| {
+            grpcio::Error::RpcFailure(RpcStatus {
+                status: grpcio::RpcStatusCode::Aborted,
+                details: Some(String::from("Stopped following logs")),
+            })
+        });
+        let pages = stream::once::<(), grpcio::Error>(Ok(()))
+            .chain(wakeups)
+            .and_then(move |()| {
+                let mut pages = Vec::new();
+                loop {
+                    let page_size = i64::from(MAX_LOG_PAGE_SIZE);
+                    let logs = match logging::log_page(&database, after, page_size, &filter) {
+                        Ok(logs) => logs,
+                        Err(err) => {
+                            error!("Failed to retrieve logs for a follower: {}", err);
+                            return Ok(stream::iter_ok::<_, grpcio::Error>(pages));
+                        }
+                    };
+                    let last_page = logs.len() < MAX_LOG_PAGE_SIZE as usize;
+                    if let Some(log) = logs.last() {
+                        after = log.id;
+                    }
+
+                    if !logs.is_empty() {
+                        let mut response = LogResponse::new();
+                        response.set_logs(RepeatedField::from_vec(logs));
+                        pages.push((response, WriteFlags::default()));
+                    }
+                    if last_page {
+                        return Ok(stream::iter_ok::<_, grpcio::Error>(pages));
+                    }
+                }
+            })
+            .flatten();
+
+        ctx.spawn(
+            sink.send_all(pages)
+                .map(|_| ())
+                .map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)),
+        )
+    }
+}
+
+impl FeroService {
+    pub fn new(database: Configuration, signer: Hsm) -> FeroService {
+        FeroService { database, signer }
+    }
+
+    /// Change the secret's threshold, returning the connection holding the change back until it's
+    /// logged.
+    fn set_secret_key_threshold(
+        &self,
+        ident: &Identification,
+        threshold: i32,
+    ) -> Result<AuthenticatedConnection, Error> {
+        let mut payload = Vec::new();
+        payload.write(ident.get_secretKeyName().as_bytes())?;
+        payload.write_i32::<BigEndian>(threshold)?;
+
+        let (conn, _) = self.database.authenticate(ident, &payload)?;
+
+        conn.hold_changes()?;
+        conn.set_secret_key_threshold(ident.get_secretKeyId(), threshold)?;
+        Ok(conn)
+    }
+
+    /// Change the user's weight, returning the connection holding the change back until it's
+    /// logged.
+    fn set_user_key_weight(
+        &self,
+        ident: &Identification,
+        user_key_id: u64,
+        weight: i32,
+    ) -> Result<AuthenticatedConnection, Error> {
+        let mut payload = Vec::new();
+        payload.write(ident.get_secretKeyName().as_bytes())?;
+        payload.write_u64::<BigEndian>(user_key_id)?;
+        payload.write_i32::<BigEndian>(weight)?;
+
+        let (conn, _) = self.database.authenticate(ident, &payload)?;
+
+        conn.hold_changes()?;
+        if let Some(user) = conn.get_user_key(user_key_id)? {
+            conn.upsert_user_key_weight(user, weight)?;
+            Ok(conn)
+        } else {
+            bail!("No such user")
+        }
+    }
+
+    pub(crate) fn sign_payload(
+        &self,
+        ident: &Identification,
+        payload: &[u8],
+        sig_type: SignRequest_SignatureType,
+        timestamp: &NaiveDateTime,
+    ) -> Result<Vec<u8>, Error>{
+        let (database, data) = self.database.authenticate(ident, payload)?;
+
+        let hsm_key = database.get_hsm_key_id()?;
+
+        let out = match sig_type {
+            SignRequest_SignatureType::PGP => {
+                self.pgp_signature(&database, &data, SignatureType::BinaryDocument)?
+            }
+            SignRequest_SignatureType::PKCS1V1_5 => {
+                self.signer.create_rsa_signature(data, hsm_key)?
+            }
+            SignRequest_SignatureType::CMS => {
+                let certificate = Certificate::from_der(&database.get_certificate()?)?;
+
+                let mut hasher = Sha256::default();
+                hasher.input(&data);
+                let digest: &[u8] = &hasher.result();
+
+                SignedData::new(&certificate, cms::OID_DATA, digest)
+                    .signing_time(&Utc::now())
+                    .certificate(&certificate.der)
+                    .sign(&self.signer, hsm_key)?
+            }
+            SignRequest_SignatureType::GIT_COMMIT => {
+                let packet = self.pgp_signature(&database, &data, SignatureType::BinaryDocument)?;
+                git::embed_commit_signature(&data, &armor_signature(&packet))?
+            }
+            SignRequest_SignatureType::GIT_TAG => {
+                let packet = self.pgp_signature(&database, &data, SignatureType::BinaryDocument)?;
+                git::embed_tag_signature(&data, &armor_signature(&packet))?
+            }
+            SignRequest_SignatureType::AUTHENTICODE => {
+                let certificate = Certificate::from_der(&database.get_certificate()?)?;
+                let chain = database.get_certificate_chain()?;
+
+                authenticode::sign(&self.signer, hsm_key, &certificate, &chain, &data)?
+            }
+            SignRequest_SignatureType::KERNEL_MODULE_SIGNATURE => {
+                let certificate = Certificate::from_der(&database.get_certificate()?)?;
+                kmod::signature(&self.signer, hsm_key, &certificate, &data)?
+            }
+            SignRequest_SignatureType::KERNEL_MODULE => {
+                let certificate = Certificate::from_der(&database.get_certificate()?)?;
+                let signature = kmod::signature(&self.signer, hsm_key, &certificate, &data)?;
+                kmod::embed_signature(&data, &signature)
+            }
+            SignRequest_SignatureType::MINISIGN => {
+                if database.get_algorithm()? != SecretAlgorithm::Ed25519 {
+                    bail!("minisign signatures require an Ed25519 secret");
+                }
+
+                let comment =
+                    minisign::trusted_comment(timestamp.timestamp(), database.get_approvers());
+                minisign::sign(&self.signer, hsm_key, &database.get_public_key()?, &data, &comment)?
+                    .into_bytes()
+            }
+        };
+
+        Ok(out)
+    }
+
+    /// Create an OpenPGP signature packet over `data` with the secret's key.
+    fn pgp_signature(
+        &self,
+        database: &AuthenticatedConnection,
+        data: &[u8],
+        signature_type: SignatureType,
+    ) -> Result<Vec<u8>, Error> {
+        match database.get_pgp_key_id() {
+            Some(pgp_key_id) => self.signer
+                .create_pgp_signature(
+                    data,
+                    database.get_hsm_key_id()?,
+                    signature_type,
+                    HashAlgorithm::Sha256,
+                )
+                .and_then(|mut sig| {
+                    sig.set_signer(pgp_key_id);
+                    Packet::Signature(sig).to_bytes()
+                })
+                .map(Vec::from),
+            None => bail!("Tried to use non-PGP key for PGP signature"),
+        }
+    }
+
+    fn sign_ssh_certificate(
+        &self,
+        ident: &Identification,
+        params: &SshCertificateParams,
+    ) -> Result<String, Error> {
+        let (database, _) = self.database.authenticate(ident, &params.write_to_bytes()?)?;
+
+        ssh::create_certificate(
+            &self.signer,
+            database.get_hsm_key_id()?,
+            &database.get_public_key()?,
+            params,
+        )
+    }
+
+    fn issue_certificate(&self, ident: &Identification, csr: &[u8]) -> Result<Vec<u8>, Error> {
+        let (database, csr) = self.database.authenticate(ident, csr)?;
+
+        let request = CertificationRequest::from_der(&x509::decode_pem_or_der(&csr)?)?;
+        let issuer = Certificate::from_der(&database.get_certificate()?)?;
+
+        x509::issue_certificate(
+            &self.signer,
+            database.get_hsm_key_id()?,
+            &issuer,
+            &database.get_certificate_profile()?,
+            &request,
+            Utc::now(),
+        )
+    }
+
+    /// Generate a new CRL, returning it along with the certificates which it newly revokes and
+    /// the connection holding back the revocations until they're logged.
+    fn generate_crl(
+        &self,
+        ident: &Identification,
+        params: &CrlParams,
+    ) -> Result<(Vec<u8>, Vec<RevokedCertificate>, AuthenticatedConnection), Error> {
+        let (database, _) = self.database.authenticate(ident, &params.write_to_bytes()?)?;
+
+        if params.get_validity_days() == 0 {
+            bail!("CRL validity period is empty");
+        }
+
+        // Read the CRL number and revocations in the same transaction that updates them.
+        database.hold_changes()?;
+
+        let issuer = Certificate::from_der(&database.get_certificate()?)?;
+        let mut revoked = database.get_revoked_certificates()?;
+        let now = Utc::now();
+
+        let mut revocations = Vec::new();
+        for revocation in params.get_revocations() {
+            x509::check_crl_reason(revocation.get_reason())?;
+
+            // Normalize away any leading zeroes, so that each serial has only one representation.
+            let serial = BigUint::from_bytes_be(revocation.get_serial()).to_bytes_be();
+            if revoked.iter().any(|certificate| certificate.serial == serial) {
+                continue;
+            }
+
+            let certificate = RevokedCertificate {
+                secret_id: 0,
+                serial,
+                revocation_date: now.naive_utc(),
+                reason: revocation.get_reason() as i32,
+            };
+            revoked.push(certificate.clone());
+            revocations.push(certificate);
+        }
+
+        let crl_number = database.get_crl_number()? + 1;
+        let crl = x509::generate_crl(
+            &self.signer,
+            database.get_hsm_key_id()?,
+            &issuer,
+            crl_number,
+            &revoked,
+            now,
+            now + Duration::days(i64::from(params.get_validity_days())),
+        )?;
+
+        database.record_crl(crl_number, &revocations)?;
+
+        Ok((crl, revocations, database))
+    }
+
+    /// Respond to a TimeStampReq. Granted requests also return the connection holding the
+    /// timestamp's record, which must be committed along with the log entry.
+    fn timestamp(
+        &self,
+        request: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<(Vec<u8>, Option<AuthenticatedConnection>), Error> {
+        let database = self.database.timestamp_authority()?;
+        let policy = x509::parse_oid(&database.get_tsa_policy()?)?;
+
+        let request = match TsaRequest::from_der(request, &policy) {
+            Ok(request) => request,
+            Err(rejection) => return Ok((tsa::rejection(&rejection), None)),
+        };
+
+        let certificate = Certificate::from_der(&database.get_certificate()?)?;
+        tsa::check_certificate(&certificate)?;
+
+        database.hold_changes()?;
+        let serial = database.insert_timestamp(&request.message_imprint, now.naive_utc())?;
+
+        let response = tsa::create_response(
+            &self.signer,
+            database.get_hsm_key_id()?,
+            &certificate,
+            &policy,
+            &request,
+            serial,
+            now,
+        )?;
+
+        Ok((response, Some(database)))
+    }
+
+    fn sign_jws(
+        &self,
+        ident: &Identification,
+        payload: &[u8],
+        serialization: JwsRequest_Serialization,
+    ) -> Result<String, Error> {
+        let (database, payload) = self.database.authenticate(ident, payload)?;
+
+        jws::sign(
+            &self.signer,
+            database.get_hsm_key_id()?,
+            database.get_algorithm()?,
+            &database.get_public_key()?,
+            &payload,
+            serialization,
+        )
+    }
+
+    /// Sign TUF metadata, returning the signature along with the connection holding back the
+    /// role's new version until it's logged.
+    fn sign_tuf_metadata(
+        &self,
+        ident: &Identification,
+        metadata: &[u8],
+    ) -> Result<(TufResponse, AuthenticatedConnection), Error> {
+        let (database, metadata) = self.database.authenticate(ident, metadata)?;
+
+        let parsed = tuf::Metadata::from_canonical_json(&metadata)?;
+
+        // Read the role's version in the same transaction that updates it.
+        database.hold_changes()?;
+        let role = database.get_tuf_role(&parsed.role)?;
+        parsed.check(&role, Utc::now())?;
+
+        let key = tuf::key(&database.get_public_key()?)?;
+        let signature = tuf::sign(&self.signer, database.get_hsm_key_id()?, &metadata)?;
+        database.set_tuf_version(&role, parsed.version)?;
+
+        let mut response = TufResponse::new();
+        response.set_keyid(tuf::key_id(&key));
+        response.set_sig(signature);
+        response.set_key(key);
+        Ok((response, database))
+    }
+
+    /// Sign a container image or blob for cosign. `artifact` is what users approved: the blob
+    /// itself, or the serialized image.
+    fn sign_cosign(&self, req: &CosignRequest, artifact: &[u8]) -> Result<CosignResponse, Error> {
+        let mut response = CosignResponse::new();
+
+        let (database, payload) = if req.has_image() {
+            let (database, _) = self.database.authenticate(req.get_identification(), artifact)?;
+            let payload = cosign::simple_signing_payload(req.get_image())?;
+            response.set_payload(payload.clone());
+            (database, payload)
+        } else {
+            self.database.authenticate(req.get_identification(), artifact)?
+        };
+
+        let (signature, bundle) = cosign::sign(
+            &self.signer,
+            database.get_hsm_key_id()?,
+            &database.get_public_key()?,
+            &payload,
+        )?;
+
+        response.set_signature(signature);
+        response.set_bundle(bundle);
+        Ok(response)
+    }
+
+    /// Sign a Release file both ways apt accepts: a detached signature for Release.gpg, and a
+    /// cleartext signature for InRelease.
+    fn sign_release(
+        &self,
+        ident: &Identification,
+        release: &[u8],
+    ) -> Result<ReleaseResponse, Error> {
+        let (database, release) = self.database.authenticate(ident, release)?;
+
+        let parsed = Release::parse(&release)?;
+        parsed.check(&database.get_release_policy()?, Utc::now())?;
+
+        let detached =
+            self.pgp_signature(&database, &release, SignatureType::BinaryDocument)?;
+        let cleartext = self.pgp_signature(
+            &database,
+            &parsed.canonical_text(),
+            SignatureType::TextDocument,
+        )?;
+
+        let mut response = ReleaseResponse::new();
+        response.set_release_gpg(armor_signature(&detached));
+        response.set_in_release(parsed.in_release(&armor_signature(&cleartext)));
+        Ok(response)
+    }
+
+    fn sign_rpm(&self, ident: &Identification, package: &[u8]) -> Result<RpmResponse, Error> {
+        let header = rpm::header_blob(package)?;
+        let (database, _) = self.database.authenticate(ident, &rpm::approval_payload(header)?)?;
+
+        let signature = self.pgp_signature(&database, header, SignatureType::BinaryDocument)?;
+
+        let mut response = RpmResponse::new();
+        if rpm::is_package(package) {
+            let signed = rpm::Package::parse(package)?.with_signature(rpm::SIGTAG_RSA, &signature);
+            response.set_package(signed);
+        }
+        response.set_signature(signature);
+        Ok(response)
+    }
+
+    fn get_checkpoints(&self, min_index: i32) -> Result<CheckpointResponse, Error> {
+        let checkpoints = self.database
+            .checkpoints_since(min_index)?
+            .iter()
+            .map(|db_checkpoint| checkpoint::Checkpoint::from(db_checkpoint).into())
+            .collect::<Vec<_>>();
+
+        let mut response = CheckpointResponse::new();
+        response.set_checkpoints(RepeatedField::from_vec(checkpoints));
+        response.set_public_key(self.database.audit_authority()?.get_public_key()?);
+        Ok(response)
+    }
+
+    fn get_logs(&self, min_index: i32) -> Result<Vec<LogEntry>, Error> {
+        logging::log_page(&self.database, min_index, i64::max_value(), &LogFilter::default())
+    }
+
+    /// The size of the tree a proof was requested for, where 0 means the whole log.
+    fn tree_size(&self, requested: u64) -> Result<u64, Error> {
+        if requested != 0 {
+            return Ok(requested);
+        }
+
+        Ok(self.database
+            .last_fero_log_entry()?
+            .map(|head| head.id as u64)
+            .unwrap_or(0))
+    }
+
+    fn get_inclusion_proof(
+        &self,
+        log_id: i32,
+        tree_size: u64,
+    ) -> Result<InclusionProofResponse, Error> {
+        let tree_size = self.tree_size(tree_size)?;
+        if log_id < 1 || log_id as u64 > tree_size {
+            bail!("Log entry {} is not in a tree of size {}", log_id, tree_size);
+        }
+
+        let fero_db_log = self.database
+            .fero_log_entry(log_id)?
+            .ok_or(format_err!("No log entry {}", log_id))?;
+        let tree = self.database.merkle_tree()?;
+
+        let mut response = InclusionProofResponse::new();
+        response.set_entry(logging::log_entry(&self.database, &fero_db_log)?);
+        response.set_tree_size(tree_size);
+        response.set_tree_root(tree.root(tree_size as usize)?);
+        response.set_proof(RepeatedField::from_vec(
+            tree.inclusion_proof(log_id as usize - 1, tree_size as usize)?,
+        ));
+        Ok(response)
+    }
+
+    fn get_consistency_proof(
+        &self,
+        first: u64,
+        second: u64,
+    ) -> Result<ConsistencyProofResponse, Error> {
+        let second = self.tree_size(second)?;
+        if first > second {
+            bail!("Tree of size {} can't be a prefix of one of size {}", first, second);
+        }
+
+        let tree = self.database.merkle_tree()?;
+
+        let mut response = ConsistencyProofResponse::new();
+        response.set_first(first);
+        response.set_second(second);
+        response.set_first_root(tree.root(first as usize)?);
+        response.set_second_root(tree.root(second as usize)?);
+        response.set_proof(RepeatedField::from_vec(
+            tree.consistency_proof(first as usize, second as usize)?,
+        ));
+        Ok(response)
+    }
+}
+
+fn log_filter(req: &LogStreamRequest) -> LogFilter {
+    let timestamp = |timestamp: &Timestamp| {
+        NaiveDateTime::from_timestamp(timestamp.get_seconds(), timestamp.get_nanos() as u32)
+    };
+
+    LogFilter {
+        max_index: if req.get_maxIndex() > 0 {
+            Some(req.get_maxIndex())
+        } else {
+            None
+        },
+        request_types: req.get_operation_types()
+            .iter()
+            .map(|&request_type| request_type.into())
+            .collect(),
+        results: req.get_results().iter().map(|&result| result.into()).collect(),
+        secret_name: if req.get_secret_name().is_empty() {
+            None
+        } else {
+            Some(String::from(req.get_secret_name()))
+        },
+        since: if req.has_since() {
+            Some(timestamp(req.get_since()))
+        } else {
+            None
+        },
+        until: if req.has_until() {
+            Some(timestamp(req.get_until()))
+        } else {
+            None
+        },
+    }
+}
+
+/// Refuse an operation which couldn't be recorded in the audit log, withholding anything it
+/// produced.
+fn fail_logging<T, R>(ctx: &RpcContext, sink: UnarySink<T>, req: R, err: &Error)
+where
+    R: Debug + Send + 'static,
+{
+    error!("Failed to log an operation: {}", err);
+    ctx.spawn(sink.fail(RpcStatus {
+        status: grpcio::RpcStatusCode::Unavailable,
+        details: Some(format!("Audit logging failed: {}", err)),
+    }).map_err(move |err| error!("failed to reply {:?}: {:?}", req, err)))
 }
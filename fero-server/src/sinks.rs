@@ -0,0 +1,444 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+
+use chrono::prelude::*;
+use failure::Error;
+use futures::{stream, Stream};
+use serde_json::{self, Value};
+
+use database::{Configuration, LogFilter};
+use fero_proto::fero::{LogEntry, LogEntry_OperationResult};
+use fero_proto::hex;
+use logging;
+
+// Shipping of committed audit log entries to systems outside fero, such as a SIEM. Each entry is
+// sent with its own hash and its parent's, so that a receiver can tell if any went missing. The
+// last entry shipped to each sink is recorded in the database, and delivery is at least once: an
+// entry is sent again if the server stops between sending it and recording it.
+
+/// RFC 5424's "log audit" facility.
+const SYSLOG_FACILITY_AUDIT: u8 = 13;
+const SYSLOG_SEVERITY_WARNING: u8 = 4;
+const SYSLOG_SEVERITY_INFORMATIONAL: u8 = 6;
+
+pub trait LogSink: Send {
+    /// A name for the sink which stays the same across restarts, under which the last entry
+    /// shipped to it is recorded.
+    fn name(&self) -> String;
+    fn emit(&mut self, entry: &LogEntry, parent_hash: &[u8]) -> Result<(), Error>;
+}
+
+fn timestamp(entry: &LogEntry) -> DateTime<Utc> {
+    let timestamp = NaiveDateTime::from_timestamp(
+        entry.get_timestamp().get_seconds(),
+        entry.get_timestamp().get_nanos() as u32,
+    );
+    DateTime::from_utc(timestamp, Utc)
+}
+
+/// The entry as a JSON object, leaving out the HSM's own log entries other than their range.
+fn to_json(entry: &LogEntry, parent_hash: &[u8]) -> Value {
+    let secret = if entry.has_signature() {
+        Some(entry.get_signature().get_secret_name())
+    } else if entry.has_ident() {
+        Some(entry.get_ident().get_secretKeyName())
+    } else {
+        None
+    };
+    let signature = if entry.has_signature() {
+        let signature = entry.get_signature();
+        json!({
+            "type": signature.get_signature_type(),
+            "artifact_digest": hex::encode(signature.get_artifact_digest()),
+            "signature_hash": hex::encode(signature.get_signature_hash()),
+        })
+    } else {
+        Value::Null
+    };
+    let revocation = if entry.has_revocation() {
+        let revocation = entry.get_revocation();
+        json!({
+            "serial": hex::encode(revocation.get_serial()),
+            "reason": revocation.get_reason(),
+        })
+    } else {
+        Value::Null
+    };
+
+    json!({
+        "id": entry.id,
+        "timestamp": timestamp(entry).to_rfc3339(),
+        "operation": format!("{:?}", entry.operation_type),
+        "result": format!("{:?}", entry.result),
+        "secret": secret,
+        "signature": signature,
+        "revocation": revocation,
+        "hsm_log_first": entry.get_hsm_logs().first().map(|log| log.id),
+        "hsm_log_last": entry.get_hsm_logs().last().map(|log| log.id),
+        "hash": hex::encode(&entry.hash),
+        "parent_hash": hex::encode(parent_hash),
+    })
+}
+
+/// Appends each entry to a file as a line of JSON.
+pub struct JsonSink {
+    output: File,
+    path: PathBuf,
+}
+
+impl JsonSink {
+    pub fn new(path: &Path) -> Result<JsonSink, Error> {
+        Ok(JsonSink {
+            output: OpenOptions::new().create(true).append(true).open(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl LogSink for JsonSink {
+    fn name(&self) -> String {
+        format!("json:{}", self.path.display())
+    }
+
+    fn emit(&mut self, entry: &LogEntry, parent_hash: &[u8]) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(&to_json(entry, parent_hash))?;
+        line.push(b'\n');
+        self.output.write_all(&line)?;
+        Ok(self.output.sync_data()?)
+    }
+}
+
+/// Sends each entry to a local syslog daemon as an RFC 5424 message, whose body is the same JSON
+/// as `JsonSink` writes.
+pub struct SyslogSink {
+    socket: UnixDatagram,
+    path: PathBuf,
+    hostname: String,
+}
+
+impl SyslogSink {
+    pub fn new(path: &Path) -> Result<SyslogSink, Error> {
+        let hostname = fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|hostname| String::from(hostname.trim()))
+            .unwrap_or_default();
+
+        Ok(SyslogSink {
+            socket: UnixDatagram::unbound()?,
+            path: path.to_path_buf(),
+            // RFC 5424 uses "-" for a missing field.
+            hostname: if hostname.is_empty() {
+                String::from("-")
+            } else {
+                hostname
+            },
+        })
+    }
+}
+
+impl LogSink for SyslogSink {
+    fn name(&self) -> String {
+        format!("syslog:{}", self.path.display())
+    }
+
+    fn emit(&mut self, entry: &LogEntry, parent_hash: &[u8]) -> Result<(), Error> {
+        let severity = match entry.result {
+            LogEntry_OperationResult::SUCCESS => SYSLOG_SEVERITY_INFORMATIONAL,
+            LogEntry_OperationResult::FAILURE => SYSLOG_SEVERITY_WARNING,
+        };
+
+        let message = format!(
+            "<{}>1 {} {} fero-server {} {:?} - {}",
+            SYSLOG_FACILITY_AUDIT * 8 + severity,
+            timestamp(entry).format("%Y-%m-%dT%H:%M:%S%.6fZ"),
+            self.hostname,
+            process::id(),
+            entry.operation_type,
+            serde_json::to_string(&to_json(entry, parent_hash))?,
+        );
+        self.socket.send_to(message.as_bytes(), &self.path)?;
+
+        Ok(())
+    }
+}
+
+/// A sink, along with the last log entry shipped to it and that entry's hash.
+struct Shipment {
+    sink: Box<LogSink>,
+    name: String,
+    after: i32,
+    parent_hash: Vec<u8>,
+}
+
+impl Shipment {
+    /// Pick up after the last entry recorded as shipped to `sink`. A sink which hasn't been
+    /// shipped anything yet starts with the entries logged from now on.
+    fn resume(database: &Configuration, sink: Box<LogSink>) -> Result<Shipment, Error> {
+        let name = sink.name();
+        let after = match database.last_shipped_log_entry(&name)? {
+            Some(after) => after,
+            None => {
+                let head = database.last_fero_log_entry()?;
+                let after = head.map(|head| head.id).unwrap_or(0);
+                database.set_last_shipped_log_entry(&name, after)?;
+                after
+            }
+        };
+        let parent_hash = match database.fero_log_entry(after)? {
+            Some(parent) => parent.hash,
+            None if after == 0 => Vec::new(),
+            None => bail!("Log entry {}, last shipped to {}, is missing", after, name),
+        };
+
+        Ok(Shipment {
+            sink,
+            name,
+            after,
+            parent_hash,
+        })
+    }
+
+    /// Emit every entry logged since the last one shipped, in order, recording each as it's
+    /// shipped. The first entry which fails stops the shipment, to be retried by the next one.
+    fn ship(&mut self, database: &Configuration) -> Result<(), Error> {
+        let logs = logging::log_page(
+            database,
+            self.after,
+            i64::max_value(),
+            &LogFilter::default(),
+        )?;
+
+        for log in logs {
+            self.sink.emit(&log, &self.parent_hash)?;
+            database.set_last_shipped_log_entry(&self.name, log.id)?;
+            self.after = log.id;
+            self.parent_hash = log.hash;
+        }
+
+        Ok(())
+    }
+}
+
+/// Emit every log entry to each of `sinks`, in order, from a thread of their own. Each sink picks
+/// up where it left off, and is retried whenever the log's followers are woken until it has
+/// caught up, so a sink which fails to emit an entry misses nothing.
+pub fn spawn_log_shipper(database: Configuration, sinks: Vec<Box<LogSink>>) -> Result<(), Error> {
+    let mut shipments = sinks
+        .into_iter()
+        .map(|sink| Shipment::resume(&database, sink))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let wakeups = database.follow_logs();
+    thread::spawn(move || {
+        // Anything logged while the server wasn't running is shipped straight away.
+        for _ in stream::once::<(), ()>(Ok(())).chain(wakeups).wait() {
+            for shipment in &mut shipments {
+                if let Err(e) = shipment.ship(&database) {
+                    error!("Failed to ship log entries to {}: {}", shipment.name, e);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::str;
+    use std::sync::{Arc, Mutex};
+
+    use protobuf::well_known_types::Timestamp;
+    use tempfile::TempDir;
+
+    use database::models::NewFeroLog;
+    use fero_proto::fero::{LogEntry_OperationType, SignatureRecord};
+    use test::migrated_database;
+    use super::*;
+
+    fn entry(id: i32, result: LogEntry_OperationResult) -> LogEntry {
+        let mut timestamp = Timestamp::new();
+        timestamp.set_seconds(1_537_000_000);
+
+        let mut signature = SignatureRecord::new();
+        signature.set_secret_name(String::from("release"));
+        signature.set_signature_type(String::from("PGP"));
+        signature.set_artifact_digest(vec![0x11; 32]);
+        signature.set_signature_hash(vec![0x22; 32]);
+
+        let mut entry = LogEntry::new();
+        entry.set_id(id);
+        entry.set_operation_type(LogEntry_OperationType::SIGN);
+        entry.set_timestamp(timestamp);
+        entry.set_result(result);
+        entry.set_hash(vec![id as u8; 32]);
+        entry.set_signature(signature);
+        entry
+    }
+
+    #[test]
+    fn json() {
+        let json = to_json(&entry(7, LogEntry_OperationResult::SUCCESS), &[0xaa, 0xbb]);
+
+        assert_eq!(json["id"], 7);
+        assert_eq!(json["timestamp"], "2018-09-15T08:26:40+00:00");
+        assert_eq!(json["operation"], "SIGN");
+        assert_eq!(json["result"], "SUCCESS");
+        assert_eq!(json["secret"], "release");
+        assert_eq!(json["signature"]["type"], "PGP");
+        assert_eq!(json["signature"]["artifact_digest"], hex::encode(&[0x11; 32]));
+        assert_eq!(json["signature"]["signature_hash"], hex::encode(&[0x22; 32]));
+        assert_eq!(json["revocation"], Value::Null);
+        assert_eq!(json["hsm_log_first"], Value::Null);
+        assert_eq!(json["hash"], hex::encode(&[7; 32]));
+        assert_eq!(json["parent_hash"], "aabb");
+    }
+
+    #[test]
+    fn json_sink() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("audit.json");
+        let entries = vec![
+            (entry(2, LogEntry_OperationResult::SUCCESS), vec![1; 32]),
+            (entry(3, LogEntry_OperationResult::FAILURE), vec![2; 32]),
+            (entry(4, LogEntry_OperationResult::SUCCESS), vec![3; 32]),
+        ];
+
+        let mut sink = JsonSink::new(&path).unwrap();
+        assert_eq!(sink.name(), format!("json:{}", path.display()));
+        for &(ref entry, ref parent_hash) in &entries[..2] {
+            sink.emit(entry, parent_hash).unwrap();
+        }
+        // Entries are appended to what's already there.
+        let mut sink = JsonSink::new(&path).unwrap();
+        sink.emit(&entries[2].0, &entries[2].1).unwrap();
+
+        let output = fs::read_to_string(&path).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), entries.len());
+        for (line, &(ref entry, ref parent_hash)) in lines.iter().zip(&entries) {
+            let json = serde_json::from_str::<Value>(line).unwrap();
+            assert_eq!(json, to_json(entry, parent_hash));
+        }
+    }
+
+    #[test]
+    fn syslog_sink() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("log");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        let mut sink = SyslogSink::new(&path).unwrap();
+        assert_eq!(sink.name(), format!("syslog:{}", path.display()));
+
+        // Successes are informational and failures warnings, in the audit facility.
+        for &(result, priority) in &[
+            (LogEntry_OperationResult::SUCCESS, "<110>1"),
+            (LogEntry_OperationResult::FAILURE, "<108>1"),
+        ] {
+            let entry = entry(2, result);
+            sink.emit(&entry, &[1; 32]).unwrap();
+
+            let mut buf = [0; 4096];
+            let length = receiver.recv(&mut buf).unwrap();
+            let message = str::from_utf8(&buf[..length]).unwrap();
+            let fields = message.splitn(8, ' ').collect::<Vec<_>>();
+            assert_eq!(fields.len(), 8);
+            assert_eq!(fields[0], priority);
+            assert_eq!(fields[1], "2018-09-15T08:26:40.000000Z");
+            assert_eq!(fields[3], "fero-server");
+            assert_eq!(fields[4], process::id().to_string());
+            assert_eq!(fields[5], "SIGN");
+            assert_eq!(fields[6], "-");
+            assert_eq!(
+                serde_json::from_str::<Value>(fields[7]).unwrap(),
+                to_json(&entry, &[1; 32])
+            );
+        }
+    }
+
+    /// The ID, hash and parent hash of each entry shipped.
+    type Shipped = Arc<Mutex<Vec<(i32, Vec<u8>, Vec<u8>)>>>;
+
+    /// A sink which records each entry it's sent, and fails to emit each entry in `failures` the
+    /// first time it's sent.
+    struct FlakySink {
+        shipped: Shipped,
+        failures: Arc<Mutex<HashSet<i32>>>,
+    }
+
+    impl LogSink for FlakySink {
+        fn name(&self) -> String {
+            String::from("flaky")
+        }
+
+        fn emit(&mut self, entry: &LogEntry, parent_hash: &[u8]) -> Result<(), Error> {
+            if self.failures.lock().unwrap().remove(&entry.id) {
+                bail!("Failed to emit log entry {}", entry.id);
+            }
+            self.shipped
+                .lock()
+                .unwrap()
+                .push((entry.id, entry.hash.clone(), parent_hash.to_vec()));
+            Ok(())
+        }
+    }
+
+    fn log(database: &Configuration, count: usize) {
+        database
+            .commit_log_entries(None, Vec::new(), Vec::new(), |_, _| {
+                Ok((0..count).map(|_| NewFeroLog::default()).collect())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn shipping() {
+        let directory = TempDir::new().unwrap();
+        let database = migrated_database(&directory).unwrap();
+        // The root entry, and entry 2.
+        log(&database, 1);
+
+        let shipped = Arc::new(Mutex::new(Vec::new()));
+        let failures = Arc::new(Mutex::new(HashSet::new()));
+        let sink = || -> Box<LogSink> {
+            Box::new(FlakySink {
+                shipped: shipped.clone(),
+                failures: failures.clone(),
+            })
+        };
+
+        // A new sink is shipped what's logged from then on.
+        let mut shipment = Shipment::resume(&database, sink()).unwrap();
+        shipment.ship(&database).unwrap();
+        assert!(shipped.lock().unwrap().is_empty());
+        assert_eq!(database.last_shipped_log_entry("flaky").unwrap(), Some(2));
+
+        // A failure stops the shipment at that entry, until the next.
+        log(&database, 4);
+        failures.lock().unwrap().extend(vec![4, 6]);
+        assert!(shipment.ship(&database).is_err());
+        assert_eq!(database.last_shipped_log_entry("flaky").unwrap(), Some(3));
+        assert!(shipment.ship(&database).is_err());
+        assert_eq!(database.last_shipped_log_entry("flaky").unwrap(), Some(5));
+
+        // After a restart, the sink picks up where it left off.
+        let mut shipment = Shipment::resume(&database, sink()).unwrap();
+        shipment.ship(&database).unwrap();
+        assert_eq!(database.last_shipped_log_entry("flaky").unwrap(), Some(6));
+
+        // Every entry was shipped once, in order, with its parent's hash.
+        let shipped = shipped.lock().unwrap();
+        let ids = shipped.iter().map(|&(id, _, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 4, 5, 6]);
+        let mut parent_hash = database.fero_log_entry(2).unwrap().unwrap().hash;
+        for &(_, ref hash, ref parent) in shipped.iter() {
+            assert_eq!(parent, &parent_hash);
+            parent_hash = hash.clone();
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use base64;
+use byteorder::{BigEndian, WriteBytesExt};
+use failure::Error;
+use num::BigUint;
+use rand::{self, Rng};
+use sha2::{Digest, Sha512};
+
+use fero_proto::fero::{SshCertificateOption, SshCertificateParams,
+                       SshCertificateParams_CertificateType};
+use fero_proto::rsa;
+use hsm::Hsm;
+
+// See PROTOCOL.certkeys in the OpenSSH source for the format of everything in this module.
+const SSH_CERT_TYPE_USER: u32 = 1;
+const SSH_CERT_TYPE_HOST: u32 = 2;
+
+fn write_string<T: AsRef<[u8]>>(out: &mut Vec<u8>, data: T) -> Result<(), Error> {
+    let data = data.as_ref();
+    out.write_u32::<BigEndian>(data.len() as u32)?;
+    out.extend_from_slice(data);
+
+    Ok(())
+}
+
+fn write_mpint(out: &mut Vec<u8>, value: &BigUint) -> Result<(), Error> {
+    let mut bytes = value.to_bytes_be();
+    if bytes == [0] {
+        bytes.clear();
+    } else if bytes[0] & 0x80 != 0 {
+        // mpints are two's complement, so positive numbers with the high bit set need padding.
+        bytes.insert(0, 0);
+    }
+
+    write_string(out, bytes)
+}
+
+fn write_options(out: &mut Vec<u8>, options: &[SshCertificateOption]) -> Result<(), Error> {
+    let mut options = options.iter().collect::<Vec<_>>();
+    options.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+
+    let mut packed = Vec::new();
+    for option in options {
+        write_string(&mut packed, option.get_name())?;
+        if option.get_value().is_empty() {
+            write_string(&mut packed, &[])?;
+        } else {
+            let mut data = Vec::new();
+            write_string(&mut data, option.get_value())?;
+            write_string(&mut packed, data)?;
+        }
+    }
+
+    write_string(out, packed)
+}
+
+/// Split an `authorized_keys`-style public key line into its key type and binary blob.
+fn parse_public_key(public_key: &str) -> Result<(String, Vec<u8>), Error> {
+    let mut fields = public_key.split_whitespace();
+    let key_type = fields
+        .next()
+        .ok_or(format_err!("Empty SSH public key"))?;
+    let blob = base64::decode(fields.next().ok_or(format_err!("Malformed SSH public key"))?)?;
+
+    // The blob starts with its own copy of the key type, which must agree with the line's.
+    if blob.len() < 4 + key_type.len() || &blob[4..4 + key_type.len()] != key_type.as_bytes() {
+        bail!("SSH public key type doesn't match its key data");
+    }
+    if key_type.contains("-cert-") {
+        bail!("Can't certify an SSH certificate");
+    }
+
+    Ok((String::from(key_type), blob))
+}
+
+/// Encode a PKCS#1 RSAPublicKey as an SSH `ssh-rsa` public key blob.
+fn rsa_signature_key(public_key: &[u8]) -> Result<Vec<u8>, Error> {
+    let (n, e) = rsa::parse_public_key(public_key)?;
+
+    let mut out = Vec::new();
+    write_string(&mut out, "ssh-rsa")?;
+    write_mpint(&mut out, &e)?;
+    write_mpint(&mut out, &n)?;
+
+    Ok(out)
+}
+
+/// The OpenSSH certificate for the subject key in `params`, up to but not including its
+/// signature, along with its certificate type.
+fn certificate_body(
+    ca_public_key: &[u8],
+    params: &SshCertificateParams,
+    nonce: &[u8],
+) -> Result<(String, Vec<u8>), Error> {
+    let (key_type, blob) = parse_public_key(params.get_public_key())?;
+    let cert_type = format!("{}-cert-v01@openssh.com", key_type);
+
+    let mut cert = Vec::new();
+    write_string(&mut cert, &cert_type)?;
+    write_string(&mut cert, nonce)?;
+    // Everything after the key type in the public key blob is the key material itself.
+    cert.extend_from_slice(&blob[4 + key_type.len()..]);
+    cert.write_u64::<BigEndian>(params.get_serial())?;
+    cert.write_u32::<BigEndian>(match params.get_cert_type() {
+        SshCertificateParams_CertificateType::USER => SSH_CERT_TYPE_USER,
+        SshCertificateParams_CertificateType::HOST => SSH_CERT_TYPE_HOST,
+    })?;
+    write_string(&mut cert, params.get_key_id())?;
+    let mut principals = Vec::new();
+    for principal in params.get_principals() {
+        write_string(&mut principals, principal)?;
+    }
+    write_string(&mut cert, principals)?;
+    cert.write_u64::<BigEndian>(params.get_valid_after())?;
+    cert.write_u64::<BigEndian>(params.get_valid_before())?;
+    write_options(&mut cert, params.get_critical_options())?;
+    write_options(&mut cert, params.get_extensions())?;
+    // Reserved
+    write_string(&mut cert, &[])?;
+    write_string(&mut cert, rsa_signature_key(ca_public_key)?)?;
+
+    Ok((cert_type, cert))
+}
+
+/// Build an OpenSSH certificate for the subject key in `params`, signed by the RSA key `ca_key`
+/// in the HSM, whose PKCS#1 public key is `ca_public_key`. The result is formatted like a line
+/// from `authorized_keys`, exactly as `ssh-keygen -s` would write it.
+pub fn create_certificate(
+    hsm: &Hsm,
+    ca_key: u16,
+    ca_public_key: &[u8],
+    params: &SshCertificateParams,
+) -> Result<String, Error> {
+    if params.get_valid_before() <= params.get_valid_after() {
+        bail!("SSH certificate validity period is empty");
+    }
+    if params.get_principals().is_empty() {
+        // An empty principal list makes a certificate valid for *any* user or host.
+        bail!("SSH certificates must list at least one principal");
+    }
+
+    let nonce = rand::thread_rng().gen::<[u8; 32]>();
+    let (cert_type, mut cert) = certificate_body(ca_public_key, params, &nonce)?;
+
+    let mut hasher = Sha512::default();
+    hasher.input(&cert);
+    let digest: &[u8] = &hasher.result();
+    let signature = hsm.create_rsa_signature(digest, ca_key)?;
+    let mut signature_blob = Vec::new();
+    write_string(&mut signature_blob, "rsa-sha2-512")?;
+    write_string(&mut signature_blob, signature)?;
+    write_string(&mut cert, signature_blob)?;
+
+    Ok(format!("{} {} {}", cert_type, base64::encode(&cert), params.get_key_id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::ByteOrder;
+    use protobuf::RepeatedField;
+
+    use super::*;
+
+    // Generated by `ssh-keygen -s ca -I alice@example.com -n alice,root
+    // -V 20200101000000:20300101000000 -z 42 -O force-command=/bin/true user.pub`, with the CA's
+    // public key converted to PKCS#1.
+    const CA_PUBLIC_KEY: &[u8] = include_bytes!("../testdata/ssh/ca.der");
+    const USER_PUBLIC_KEY: &str = include_str!("../testdata/ssh/user.pub");
+    const USER_CERTIFICATE: &str = include_str!("../testdata/ssh/user-cert.pub");
+
+    fn option(name: &str, value: &str) -> SshCertificateOption {
+        let mut option = SshCertificateOption::new();
+        option.set_name(String::from(name));
+        option.set_value(String::from(value));
+        option
+    }
+
+    #[test]
+    fn mpint_encoding() {
+        let mut out = Vec::new();
+        write_mpint(&mut out, &BigUint::from(0u32)).unwrap();
+        assert_eq!(out, [0, 0, 0, 0]);
+
+        let mut out = Vec::new();
+        write_mpint(&mut out, &BigUint::from(0x7fu32)).unwrap();
+        assert_eq!(out, [0, 0, 0, 1, 0x7f]);
+
+        let mut out = Vec::new();
+        write_mpint(&mut out, &BigUint::from(0x80u32)).unwrap();
+        assert_eq!(out, [0, 0, 0, 2, 0, 0x80]);
+    }
+
+    #[test]
+    fn options_are_sorted() {
+        let mut out = Vec::new();
+        write_options(&mut out, &[option("b", ""), option("a", "x")]).unwrap();
+        assert_eq!(
+            out,
+            [
+                0, 0, 0, 23, // options
+                0, 0, 0, 1, b'a', 0, 0, 0, 5, 0, 0, 0, 1, b'x', // a=x
+                0, 0, 0, 1, b'b', 0, 0, 0, 0, // b
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_public_keys() {
+        assert!(parse_public_key(USER_PUBLIC_KEY).is_ok());
+        assert!(parse_public_key("").is_err());
+        assert!(parse_public_key("ssh-ed25519").is_err());
+        // The key type doesn't match the blob's.
+        let blob = USER_PUBLIC_KEY.split_whitespace().nth(1).unwrap();
+        assert!(parse_public_key(&format!("ssh-rsa {}", blob)).is_err());
+        // Certificates can't be certified.
+        assert!(parse_public_key(USER_CERTIFICATE).is_err());
+    }
+
+    #[test]
+    fn certificate_matches_ssh_keygen() {
+        let blob = USER_CERTIFICATE.split_whitespace().nth(1).unwrap();
+        let expected = base64::decode(blob).unwrap();
+        // ssh-keygen's nonce is the string after the certificate type.
+        let nonce_start = 4 + BigEndian::read_u32(&expected) as usize + 4;
+        let nonce = &expected[nonce_start..nonce_start + 32];
+
+        let mut params = SshCertificateParams::new();
+        params.set_public_key(String::from(USER_PUBLIC_KEY));
+        params.set_serial(42);
+        params.set_cert_type(SshCertificateParams_CertificateType::USER);
+        params.set_key_id(String::from("alice@example.com"));
+        params.set_principals(RepeatedField::from_vec(vec![
+            String::from("alice"),
+            String::from("root"),
+        ]));
+        params.set_valid_after(1_577_836_800);
+        params.set_valid_before(1_893_456_000);
+        params.set_critical_options(RepeatedField::from_vec(vec![
+            option("force-command", "/bin/true"),
+        ]));
+        params.set_extensions(RepeatedField::from_vec(vec![
+            option("permit-X11-forwarding", ""),
+            option("permit-agent-forwarding", ""),
+            option("permit-port-forwarding", ""),
+            option("permit-pty", ""),
+            option("permit-user-rc", ""),
+        ]));
+
+        let (cert_type, body) = certificate_body(CA_PUBLIC_KEY, &params, nonce).unwrap();
+        assert_eq!(cert_type, "ssh-ed25519-cert-v01@openssh.com");
+        assert_eq!(body[..], expected[..body.len()]);
+    }
+}
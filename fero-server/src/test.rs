@@ -1,9 +1,12 @@
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::os::unix::ffi::OsStrExt;
 use std::thread;
 use std::time::Duration;
 
+use diesel::Connection;
+use diesel::sqlite::SqliteConnection;
+use diesel_migrations::{find_migrations_directory, run_pending_migrations_in_directory};
 use failure::Error;
 use gpgme::{Context, CreateKeyFlags, ExportMode, Protocol};
 use libyubihsm::{ReturnCode, Yubihsm};
@@ -14,6 +17,7 @@ use secstr::SecStr;
 use tempfile::{NamedTempFile, TempDir};
 
 use database::Configuration;
+use database::models::UserKeyType;
 use fero_proto::fero::*;
 use hsm;
 use local;
@@ -50,6 +54,18 @@ fn do_provision(database_path: &str) -> Result<(), Error> {
     Ok(())
 }
 
+/// Create and migrate a database in `directory`, for tests which need a database but no device.
+pub(crate) fn migrated_database(directory: &TempDir) -> Result<Configuration, Error> {
+    let database_path_owned = directory.path().join("fero.db");
+    let database_path = database_path_owned.to_str().unwrap();
+    File::create(database_path)?;
+
+    let conn = SqliteConnection::establish(database_path)?;
+    run_pending_migrations_in_directory(&conn, &find_migrations_directory()?, &mut io::sink())?;
+
+    Ok(Configuration::new(database_path))
+}
+
 /// Attempt to reset the device at the conclusion of a test. This should not fail, as it is only
 /// called after the successful completion of a test.
 fn try_reset_device() -> bool {
@@ -130,7 +146,7 @@ fn setup_environment(
             File::open(gpg_key.path())?.read_to_end(&mut key_bytes)?;
             let key_id = local::find_keyid(&key_bytes)?;
 
-            local::store_user(&hsm, database_path, key_id, &key_bytes)?;
+            local::store_user(&hsm, database_path, key_id, UserKeyType::Pgp, &key_bytes)?;
             local::set_user_weight(database_path, key_id, &secret_name, 1)?;
 
             Ok(key_id)
@@ -0,0 +1,302 @@
+use chrono::prelude::*;
+use failure::Error;
+use sha2::{Digest, Sha256};
+use yasna::{self, DERWriter};
+use yasna::models::ObjectIdentifier;
+use yasna::tags::{TAG_BITSTRING, TAG_UTF8STRING};
+
+use cms::SignedData;
+use hsm::Hsm;
+use x509::{self, Certificate, DerElement};
+
+// See RFC 3161 for the format of everything in this module.
+const OID_TST_INFO: &[u64] = &[1, 2, 840, 113549, 1, 9, 16, 1, 4];
+const OID_TIME_STAMPING: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 3, 8];
+const DIGEST_ALGORITHMS: &[(&[u64], usize)] = &[
+    (&[2, 16, 840, 1, 101, 3, 4, 2, 1], 32),
+    (&[2, 16, 840, 1, 101, 3, 4, 2, 2], 48),
+    (&[2, 16, 840, 1, 101, 3, 4, 2, 3], 64),
+];
+
+const PKI_STATUS_GRANTED: u8 = 0;
+const PKI_STATUS_REJECTION: u8 = 2;
+
+// PKIFailureInfo bits
+const BAD_ALG: usize = 0;
+const BAD_REQUEST: usize = 2;
+const BAD_DATA_FORMAT: usize = 5;
+const UNACCEPTED_POLICY: usize = 15;
+const UNACCEPTED_EXTENSION: usize = 16;
+
+/// Why a timestamp request was rejected. Unlike other errors, these are reported to the client in
+/// a TimeStampResp rather than as an RPC failure, since that's what TSA clients expect.
+#[derive(Debug)]
+pub struct Rejection {
+    failure_info: usize,
+    reason: String,
+}
+
+impl Rejection {
+    fn new(failure_info: usize, reason: &str) -> Rejection {
+        Rejection {
+            failure_info,
+            reason: String::from(reason),
+        }
+    }
+}
+
+impl From<Error> for Rejection {
+    fn from(e: Error) -> Rejection {
+        Rejection::new(BAD_DATA_FORMAT, &format!("{}", e))
+    }
+}
+
+/// The parts of a TimeStampReq which are carried over into the TSTInfo, each as complete DER.
+pub struct TimestampRequest {
+    pub message_imprint: Vec<u8>,
+    pub nonce: Option<Vec<u8>>,
+    pub cert_req: bool,
+}
+
+impl TimestampRequest {
+    /// Parse a DER-encoded TimeStampReq, checking it against the TSA's `policy`.
+    pub fn from_der(der: &[u8], policy: &ObjectIdentifier) -> Result<TimestampRequest, Rejection> {
+        let fields = DerElement::parse_all(der)?.children()?;
+        if fields.len() < 2 || fields[0].raw != &[0x02, 0x01, 0x01][..] {
+            return Err(Rejection::new(BAD_REQUEST, "Unsupported TimeStampReq version"));
+        }
+
+        let imprint = fields[1].children()?;
+        let algorithm = match imprint.first() {
+            Some(algorithm) => algorithm.children()?,
+            None => return Err(Rejection::new(BAD_DATA_FORMAT, "Malformed messageImprint")),
+        };
+        let digest_length = match algorithm.first() {
+            Some(oid) => DIGEST_ALGORITHMS
+                .iter()
+                .find(|&&(algorithm, _)| oid.is_oid(algorithm))
+                .map(|&(_, length)| length),
+            None => None,
+        };
+        let digest_length =
+            digest_length.ok_or(Rejection::new(BAD_ALG, "Unsupported hash algorithm"))?;
+        match imprint.get(1) {
+            Some(digest) if digest.tag == 0x04 && digest.contents.len() == digest_length => {}
+            _ => return Err(Rejection::new(BAD_DATA_FORMAT, "Malformed messageImprint")),
+        }
+
+        let mut request = TimestampRequest {
+            message_imprint: fields[1].raw.to_vec(),
+            nonce: None,
+            cert_req: false,
+        };
+        for field in &fields[2..] {
+            match field.tag {
+                // reqPolicy
+                0x06 => {
+                    let requested = yasna::parse_der(field.raw, |reader| reader.read_oid())
+                        .map_err(|_| Rejection::new(BAD_DATA_FORMAT, "Malformed reqPolicy"))?;
+                    if requested != *policy {
+                        return Err(Rejection::new(UNACCEPTED_POLICY, "Unsupported policy"));
+                    }
+                }
+                0x02 => request.nonce = Some(field.raw.to_vec()),
+                0x01 => request.cert_req = field.contents != &[0][..],
+                0xa0 => {
+                    return Err(Rejection::new(UNACCEPTED_EXTENSION, "Extensions are unsupported"))
+                }
+                _ => return Err(Rejection::new(BAD_DATA_FORMAT, "Malformed TimeStampReq")),
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+/// Check that `certificate` may be used by a TSA, which requires that its only extended key usage
+/// is id-kp-timeStamping and that the extension is critical (RFC 3161 section 2.3).
+pub fn check_certificate(certificate: &Certificate) -> Result<(), Error> {
+    let usage = match certificate.extended_key_usage {
+        Some(ref usage) => usage,
+        None => bail!("TSA certificate has no extended key usage"),
+    };
+    if !usage.critical {
+        bail!("TSA certificate's extended key usage isn't critical");
+    }
+    if usage.purposes != [ObjectIdentifier::from_slice(OID_TIME_STAMPING)] {
+        bail!("TSA certificate's only extended key usage must be timeStamping");
+    }
+
+    Ok(())
+}
+
+/// Write a PKIFailureInfo BIT STRING with the single named bit `bit` set.
+fn write_failure_info(writer: DERWriter, bit: usize) {
+    let mut contents = vec![0u8; bit / 8 + 2];
+    contents[0] = 7 - (bit % 8) as u8;
+    contents[bit / 8 + 1] = 0x80 >> (bit % 8);
+
+    writer.write_tagged_implicit(TAG_BITSTRING, |writer| writer.write_bytes(&contents));
+}
+
+/// Build a TimeStampResp rejecting a request.
+pub fn rejection(rejection: &Rejection) -> Vec<u8> {
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_sequence(|writer| {
+                writer.next().write_u8(PKI_STATUS_REJECTION);
+                writer.next().write_sequence(|writer| {
+                    writer.next().write_tagged_implicit(TAG_UTF8STRING, |writer| {
+                        writer.write_bytes(rejection.reason.as_bytes())
+                    });
+                });
+                write_failure_info(writer.next(), rejection.failure_info);
+            });
+        });
+    })
+}
+
+/// Build a TimeStampResp granting `request`, with a token signed by the TSA certificate
+/// `certificate` whose key is `key` in the HSM.
+pub fn create_response(
+    hsm: &Hsm,
+    key: u16,
+    certificate: &Certificate,
+    policy: &ObjectIdentifier,
+    request: &TimestampRequest,
+    serial: i64,
+    time: DateTime<Utc>,
+) -> Result<Vec<u8>, Error> {
+    let tst_info = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_u8(1);
+            writer.next().write_oid(policy);
+            x509::write_der(writer.next(), &request.message_imprint);
+            writer.next().write_i64(serial);
+            x509::write_generalized_time(writer.next(), &time);
+            if let Some(ref nonce) = request.nonce {
+                x509::write_der(writer.next(), nonce);
+            }
+        });
+    });
+
+    let mut hasher = Sha256::default();
+    hasher.input(&tst_info);
+    let digest: &[u8] = &hasher.result();
+
+    let mut token = SignedData::new(certificate, OID_TST_INFO, digest)
+        .encapsulate(&tst_info)
+        .signing_certificate();
+    if request.cert_req {
+        token = token.certificate(&certificate.der);
+    }
+    let token = token.sign(hsm, key)?;
+
+    Ok(yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_sequence(|writer| {
+                writer.next().write_u8(PKI_STATUS_GRANTED);
+            });
+            x509::write_der(writer.next(), &token);
+        });
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Made by `openssl ts -query` over "hello", and TSA certificates made by `openssl req -x509`
+    // with various extended key usages.
+    const SHA256_REQUEST: &[u8] = include_bytes!("../testdata/tsa/sha256.tsq");
+    const SHA1_REQUEST: &[u8] = include_bytes!("../testdata/tsa/sha1.tsq");
+    const POLICY_REQUEST: &[u8] = include_bytes!("../testdata/tsa/policy.tsq");
+    const TSA_CERTIFICATE: &[u8] = include_bytes!("../testdata/tsa/tsa.der");
+    const NONCRITICAL_CERTIFICATE: &[u8] = include_bytes!("../testdata/tsa/noncritical.der");
+    const EXTRA_USAGE_CERTIFICATE: &[u8] = include_bytes!("../testdata/tsa/extra.der");
+    const PLAIN_CERTIFICATE: &[u8] = include_bytes!("../testdata/tsa/none.der");
+
+    fn policy() -> ObjectIdentifier {
+        ObjectIdentifier::from_slice(&[1, 2, 3, 4, 5])
+    }
+
+    fn failure_info(request: &[u8]) -> usize {
+        match TimestampRequest::from_der(request, &policy()) {
+            Ok(_) => panic!("Request wasn't rejected"),
+            Err(rejection) => rejection.failure_info,
+        }
+    }
+
+    #[test]
+    fn parse_request() {
+        let request = TimestampRequest::from_der(SHA256_REQUEST, &policy()).unwrap();
+        assert_eq!(request.message_imprint, &SHA256_REQUEST[5..56]);
+        assert_eq!(request.nonce, Some(SHA256_REQUEST[56..66].to_vec()));
+        assert!(request.cert_req);
+
+        let request = TimestampRequest::from_der(POLICY_REQUEST, &policy()).unwrap();
+        assert_eq!(request.nonce, None);
+        assert!(!request.cert_req);
+    }
+
+    #[test]
+    fn rejected_requests() {
+        assert_eq!(failure_info(SHA1_REQUEST), BAD_ALG);
+        assert_eq!(failure_info(&SHA256_REQUEST[..40]), BAD_DATA_FORMAT);
+
+        let mut version = SHA256_REQUEST.to_vec();
+        version[4] = 2;
+        assert_eq!(failure_info(&version), BAD_REQUEST);
+
+        let other_policy = ObjectIdentifier::from_slice(&[1, 2, 3, 4, 6]);
+        match TimestampRequest::from_der(POLICY_REQUEST, &other_policy) {
+            Err(rejection) => assert_eq!(rejection.failure_info, UNACCEPTED_POLICY),
+            Ok(_) => panic!("Request with the wrong policy wasn't rejected"),
+        }
+
+        // An empty [0] Extensions.
+        let mut extensions = SHA256_REQUEST.to_vec();
+        extensions[1] += 2;
+        extensions.extend_from_slice(&[0xa0, 0x00]);
+        assert_eq!(failure_info(&extensions), UNACCEPTED_EXTENSION);
+
+        // A digest of the wrong length for its algorithm.
+        let mut digest = SHA256_REQUEST.to_vec();
+        digest[1] -= 1;
+        digest[6] -= 1;
+        digest[23] -= 1;
+        digest.remove(24);
+        assert_eq!(failure_info(&digest), BAD_DATA_FORMAT);
+    }
+
+    #[test]
+    fn rejection_encoding() {
+        let response = rejection(&Rejection::new(UNACCEPTED_POLICY, "Unsupported policy"));
+        let status = DerElement::parse_all(&response).unwrap().children().unwrap();
+        assert_eq!(status.len(), 1);
+
+        let fields = status[0].children().unwrap();
+        assert_eq!(fields[0].raw, &[0x02, 0x01, PKI_STATUS_REJECTION][..]);
+        let text = fields[1].children().unwrap();
+        assert_eq!(text[0].tag, 0x0c);
+        assert_eq!(text[0].contents, b"Unsupported policy");
+        // Bit 15 is the last bit of the second byte, so no bits are unused.
+        assert_eq!(fields[2].raw, &[0x03, 0x03, 0x00, 0x00, 0x01][..]);
+
+        let response = rejection(&Rejection::new(BAD_ALG, ""));
+        let fields = DerElement::parse_all(&response).unwrap().children().unwrap()[0]
+            .children()
+            .unwrap();
+        assert_eq!(fields[2].raw, &[0x03, 0x02, 0x07, 0x80][..]);
+    }
+
+    #[test]
+    fn certificate_usage() {
+        check_certificate(&Certificate::from_der(TSA_CERTIFICATE).unwrap()).unwrap();
+        assert!(check_certificate(&Certificate::from_der(NONCRITICAL_CERTIFICATE).unwrap())
+            .is_err());
+        assert!(check_certificate(&Certificate::from_der(EXTRA_USAGE_CERTIFICATE).unwrap())
+            .is_err());
+        assert!(check_certificate(&Certificate::from_der(PLAIN_CERTIFICATE).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use base64;
+use chrono::prelude::*;
+use chrono::Duration;
+use failure::Error;
+use serde_json::{self, Value};
+use sha2::{Digest, Sha256};
+
+use database::models::TufRole;
+use fero_proto::hex;
+use hsm::Hsm;
+use x509;
+
+// See the TUF specification, particularly its "Document formats" section.
+
+/// The top-level roles whose metadata fero will sign.
+pub const ROLES: &[&str] = &["root", "targets", "snapshot", "timestamp"];
+
+const KEY_TYPE: &str = "rsa";
+const SCHEME: &str = "rsa-pkcs1v15-sha256";
+
+/// Canonical JSON has no floating-point numbers, which serde would otherwise happily round-trip.
+fn check_integers(value: &Value) -> Result<(), Error> {
+    match *value {
+        Value::Number(ref number) if !number.is_i64() && !number.is_u64() => {
+            bail!("Canonical JSON can't contain non-integer numbers")
+        }
+        Value::Array(ref values) => values.iter().map(check_integers).collect(),
+        Value::Object(ref map) => map.values().map(check_integers).collect(),
+        _ => Ok(()),
+    }
+}
+
+/// The fields of a role's `signed` metadata which are checked before signing it.
+pub struct Metadata {
+    pub role: String,
+    pub version: i64,
+    pub expires: DateTime<Utc>,
+}
+
+impl Metadata {
+    /// Parse the `signed` portion of a role's metadata, which must already be canonical JSON
+    /// since those are the exact bytes that get signed.
+    pub fn from_canonical_json(json: &[u8]) -> Result<Metadata, Error> {
+        let value: Value = serde_json::from_slice(json)?;
+        check_integers(&value)?;
+
+        // serde_json writes objects with sorted keys and no whitespace, so it produces canonical
+        // JSON, except that it escapes control characters which canonical JSON leaves alone.
+        if serde_json::to_vec(&value)? != json {
+            bail!("TUF metadata must be in canonical JSON form");
+        }
+
+        let role = value
+            .get("_type")
+            .and_then(Value::as_str)
+            .ok_or(format_err!("TUF metadata is missing its _type"))?;
+        let version = value
+            .get("version")
+            .and_then(Value::as_i64)
+            .ok_or(format_err!("TUF metadata is missing its version"))?;
+        let expires = value
+            .get("expires")
+            .and_then(Value::as_str)
+            .ok_or(format_err!("TUF metadata is missing its expiry"))?;
+
+        Ok(Metadata {
+            role: String::from(role),
+            version,
+            expires: DateTime::parse_from_rfc3339(expires)?.with_timezone(&Utc),
+        })
+    }
+
+    /// Check the metadata against the secret's policy for its role: the version must move
+    /// forward, and the expiry must be in the future but within the role's limit.
+    pub fn check(&self, policy: &TufRole, now: DateTime<Utc>) -> Result<(), Error> {
+        if self.version <= policy.version {
+            bail!(
+                "TUF {} version {} is not newer than version {}",
+                self.role,
+                self.version,
+                policy.version
+            );
+        }
+        if self.expires <= now {
+            bail!("TUF {} metadata has already expired", self.role);
+        }
+        if self.expires > now + Duration::days(i64::from(policy.max_expiry_days)) {
+            bail!(
+                "TUF {} metadata may expire at most {} days from now",
+                self.role,
+                policy.max_expiry_days
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The TUF public key object for a PKCS#1 RSAPublicKey, in canonical JSON. This is what goes in
+/// the `keys` of the delegating metadata.
+pub fn key(public_key: &[u8]) -> Result<String, Error> {
+    let spki = base64::encode(&x509::rsa_subject_public_key_info(public_key));
+    let mut pem = String::from("-----BEGIN PUBLIC KEY-----\n");
+    for line in spki.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str("-----END PUBLIC KEY-----\n");
+
+    Ok(serde_json::to_string(&json!({
+        "keytype": KEY_TYPE,
+        "keyval": { "public": pem },
+        "scheme": SCHEME,
+    }))?)
+}
+
+/// The TUF key ID of a canonical JSON key object, which is the hex SHA-256 of it.
+pub fn key_id(key: &str) -> String {
+    let mut hasher = Sha256::default();
+    hasher.input(key.as_bytes());
+    hex::encode(&hasher.result())
+}
+
+/// Sign canonical JSON `metadata` with `key` in the HSM, returning the hex signature.
+pub fn sign(hsm: &Hsm, key: u16, metadata: &[u8]) -> Result<String, Error> {
+    let mut hasher = Sha256::default();
+    hasher.input(metadata);
+
+    Ok(hex::encode(&hsm.create_rsa_signature(hasher.result(), key)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The RSA key from RFC 7638 section 3.1, and its SubjectPublicKeyInfo as written by
+    // `openssl rsa -pubout`.
+    const PUBLIC_KEY: &[u8] = include_bytes!("../testdata/jws/rfc7638.der");
+    const PUBLIC_KEY_PEM: &str = include_str!("../testdata/tuf/public.pem");
+
+    fn policy(version: i64, max_expiry_days: i32) -> TufRole {
+        TufRole {
+            id: 1,
+            secret_id: 1,
+            role: String::from("timestamp"),
+            max_expiry_days,
+            version,
+        }
+    }
+
+    #[test]
+    fn canonical_json() {
+        let metadata = Metadata::from_canonical_json(
+            br#"{"_type":"timestamp","expires":"2030-01-01T00:00:00Z","version":3}"#,
+        ).unwrap();
+        assert_eq!(metadata.role, "timestamp");
+        assert_eq!(metadata.version, 3);
+        assert_eq!(metadata.expires, Utc.ymd(2030, 1, 1).and_hms(0, 0, 0));
+
+        for json in &[
+            // Whitespace
+            &br#"{"_type":"timestamp", "expires":"2030-01-01T00:00:00Z","version":3}"#[..],
+            // Unsorted keys
+            br#"{"expires":"2030-01-01T00:00:00Z","_type":"timestamp","version":3}"#,
+            // Floating-point numbers
+            br#"{"_type":"timestamp","expires":"2030-01-01T00:00:00Z","version":3.5}"#,
+            br#"{"_type":"timestamp","expires":"2030-01-01T00:00:00Z","version":3,"x":[1.0]}"#,
+            // Missing or mistyped fields
+            br#"{"expires":"2030-01-01T00:00:00Z","version":3}"#,
+            br#"{"_type":"timestamp","version":3}"#,
+            br#"{"_type":"timestamp","expires":"2030-01-01T00:00:00Z","version":"3"}"#,
+            br#"{"_type":"timestamp","expires":"tomorrow","version":3}"#,
+        ] {
+            assert!(Metadata::from_canonical_json(json).is_err());
+        }
+    }
+
+    #[test]
+    fn policy_checks() {
+        let now = Utc.ymd(2029, 12, 25).and_hms(0, 0, 0);
+        let metadata = Metadata {
+            role: String::from("timestamp"),
+            version: 3,
+            expires: Utc.ymd(2030, 1, 1).and_hms(0, 0, 0),
+        };
+
+        metadata.check(&policy(2, 7), now).unwrap();
+        // Rollback and replay
+        assert!(metadata.check(&policy(3, 7), now).is_err());
+        assert!(metadata.check(&policy(4, 7), now).is_err());
+        // Too far in the future
+        assert!(metadata.check(&policy(2, 6), now).is_err());
+        // Already expired
+        assert!(metadata.check(&policy(2, 7), Utc.ymd(2030, 1, 1).and_hms(0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn keys() {
+        let key = key(PUBLIC_KEY).unwrap();
+        let value: Value = serde_json::from_str(&key).unwrap();
+        assert_eq!(value["keytype"], "rsa");
+        assert_eq!(value["scheme"], "rsa-pkcs1v15-sha256");
+        assert_eq!(value["keyval"]["public"], PUBLIC_KEY_PEM);
+
+        assert_eq!(
+            key_id(&key),
+            "a169ca63ad2f9addc0bfdf8dd66b195971ac02098446173001a4dc46e4b8d355"
+        );
+    }
+}
@@ -0,0 +1,961 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use failure::Error;
+use num::BigUint;
+use pem;
+use rand::{self, Rng};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use yasna::{self, DERWriter, Tag, TagClass};
+use yasna::models::ObjectIdentifier;
+use yasna::tags::{TAG_BITSTRING, TAG_GENERALIZEDTIME, TAG_UTCTIME};
+
+use fero_proto::rsa;
+
+use database::models::{CertificateProfile, RevokedCertificate};
+use hsm::Hsm;
+
+pub const OID_RSA_ENCRYPTION: &[u64] = &[1, 2, 840, 113549, 1, 1, 1];
+pub const OID_SHA256: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+pub const OID_SHA256_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 11];
+const OID_SHA384_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 12];
+const OID_SHA512_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 13];
+const OID_COMMON_NAME: &[u64] = &[2, 5, 4, 3];
+const OID_EXTENSION_REQUEST: &[u64] = &[1, 2, 840, 113549, 1, 9, 14];
+const OID_SUBJECT_KEY_IDENTIFIER: &[u64] = &[2, 5, 29, 14];
+const OID_KEY_USAGE: &[u64] = &[2, 5, 29, 15];
+const OID_SUBJECT_ALT_NAME: &[u64] = &[2, 5, 29, 17];
+const OID_BASIC_CONSTRAINTS: &[u64] = &[2, 5, 29, 19];
+const OID_CRL_NUMBER: &[u64] = &[2, 5, 29, 20];
+const OID_CRL_REASON: &[u64] = &[2, 5, 29, 21];
+const OID_AUTHORITY_KEY_IDENTIFIER: &[u64] = &[2, 5, 29, 35];
+const OID_EXTENDED_KEY_USAGE: &[u64] = &[2, 5, 29, 37];
+
+const KEY_USAGES: &[(&str, u16)] = &[
+    ("digital-signature", 1 << 0),
+    ("non-repudiation", 1 << 1),
+    ("key-encipherment", 1 << 2),
+    ("data-encipherment", 1 << 3),
+    ("key-agreement", 1 << 4),
+    ("key-cert-sign", 1 << 5),
+    ("crl-sign", 1 << 6),
+    ("encipher-only", 1 << 7),
+    ("decipher-only", 1 << 8),
+];
+
+const EXTENDED_KEY_USAGES: &[(&str, &str)] = &[
+    ("server-auth", "1.3.6.1.5.5.7.3.1"),
+    ("client-auth", "1.3.6.1.5.5.7.3.2"),
+    ("code-signing", "1.3.6.1.5.5.7.3.3"),
+    ("email-protection", "1.3.6.1.5.5.7.3.4"),
+    ("time-stamping", "1.3.6.1.5.5.7.3.8"),
+    ("ocsp-signing", "1.3.6.1.5.5.7.3.9"),
+];
+const EXTENDED_KEY_USAGE_SERVER_AUTH: &str = "1.3.6.1.5.5.7.3.1";
+
+/// How deeply constructed DER elements may nest, which bounds the recursion in checking them.
+const MAX_DER_DEPTH: usize = 32;
+
+/// A single DER element, borrowed from the buffer it was parsed out of. yasna wants to understand
+/// every field of the structures it reads, so this is used instead for picking apart certificates
+/// and requests whose contents we mostly just copy around.
+pub struct DerElement<'a> {
+    pub tag: u8,
+    pub contents: &'a [u8],
+    pub raw: &'a [u8],
+}
+
+impl<'a> DerElement<'a> {
+    /// Parse the first element out of `data`, returning it and whatever follows it. The contents
+    /// of constructed elements are checked to be well-formed too, so that `write_der` can copy
+    /// any element which parses.
+    pub fn parse(data: &'a [u8]) -> Result<(DerElement<'a>, &'a [u8]), Error> {
+        DerElement::parse_nested(data, 0)
+    }
+
+    fn parse_nested(data: &'a [u8], depth: usize) -> Result<(DerElement<'a>, &'a [u8]), Error> {
+        if data.len() < 2 {
+            bail!("Truncated DER element");
+        }
+
+        let tag = data[0];
+        if tag & 0x1f == 0x1f {
+            bail!("Multi-byte DER tags aren't supported");
+        }
+
+        let (length, header_length) = match data[1] {
+            length @ 0..=0x7f => (length as usize, 2),
+            0x80 => bail!("Indefinite lengths aren't allowed in DER"),
+            length_length => {
+                let length_length = (length_length & 0x7f) as usize;
+                if length_length > 4 || data.len() < 2 + length_length || data[2] == 0 {
+                    bail!("Malformed DER length");
+                }
+                let length = data[2..2 + length_length]
+                    .iter()
+                    .fold(0, |length, byte| (length << 8) | *byte as usize);
+                if length < 0x80 {
+                    bail!("Malformed DER length");
+                }
+                (length, 2 + length_length)
+            }
+        };
+
+        if data.len() - header_length < length {
+            bail!("Truncated DER element");
+        }
+
+        let end = header_length + length;
+        let element = DerElement {
+            tag,
+            contents: &data[header_length..end],
+            raw: &data[..end],
+        };
+        if element.is_constructed() {
+            if depth == MAX_DER_DEPTH {
+                bail!("DER elements are nested too deeply");
+            }
+            let mut rest = element.contents;
+            while !rest.is_empty() {
+                rest = DerElement::parse_nested(rest, depth + 1)?.1;
+            }
+        }
+
+        Ok((element, &data[end..]))
+    }
+
+    /// Parse `data`, which must consist of exactly one element.
+    pub fn parse_all(data: &'a [u8]) -> Result<DerElement<'a>, Error> {
+        let (element, rest) = DerElement::parse(data)?;
+        if !rest.is_empty() {
+            bail!("Trailing data after DER element");
+        }
+
+        Ok(element)
+    }
+
+    /// Parse the contents of a constructed element into its children.
+    pub fn children(&self) -> Result<Vec<DerElement<'a>>, Error> {
+        let mut children = Vec::new();
+        let mut rest = self.contents;
+        while !rest.is_empty() {
+            let (child, remaining) = DerElement::parse(rest)?;
+            children.push(child);
+            rest = remaining;
+        }
+
+        Ok(children)
+    }
+
+    pub fn is_constructed(&self) -> bool {
+        self.tag & 0x20 != 0
+    }
+
+    pub fn is_oid(&self, components: &[u64]) -> bool {
+        self.raw == &yasna::construct_der(|writer| {
+            writer.write_oid(&ObjectIdentifier::from_slice(components))
+        })[..]
+    }
+}
+
+/// Copy the DER element `der` into `writer`. yasna can't write pre-encoded DER, so the element is
+/// rebuilt piece by piece, which for valid DER gives back exactly the same bytes. Panics if `der`
+/// isn't a single element which `DerElement` parses.
+pub fn write_der(writer: DERWriter, der: &[u8]) {
+    let element = DerElement::parse_all(der).expect("write_der given malformed DER");
+    write_element(writer, &element);
+}
+
+fn write_element(writer: DERWriter, element: &DerElement) {
+    let tag = Tag {
+        tag_class: match element.tag >> 6 {
+            0 => TagClass::Universal,
+            1 => TagClass::Application,
+            2 => TagClass::ContextSpecific,
+            _ => TagClass::Private,
+        },
+        tag_number: u64::from(element.tag & 0x1f),
+    };
+
+    writer.write_tagged_implicit(tag, |writer| {
+        if element.is_constructed() {
+            // `parse` already checked the children, and the tag replaces SEQUENCE's.
+            let children = element.children().expect("write_der given malformed DER");
+            writer.write_sequence(|writer| {
+                for child in &children {
+                    write_element(writer.next(), child);
+                }
+            });
+        } else {
+            writer.write_bytes(element.contents);
+        }
+    });
+}
+
+/// Accept either PEM or DER input, returning DER.
+pub fn decode_pem_or_der(data: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.starts_with(b"-----BEGIN") {
+        match pem::parse(data) {
+            Ok(pem) => Ok(pem.contents),
+            Err(e) => bail!("Error parsing PEM: {}", e),
+        }
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+pub fn to_pem(tag: &str, der: &[u8]) -> String {
+    pem::encode(&pem::Pem {
+        tag: String::from(tag),
+        contents: der.to_vec(),
+    })
+}
+
+/// Find the value of the extension `oid` in an X.509 `Extensions` sequence.
+fn find_extension<'a>(extensions: &DerElement<'a>, oid: &[u64]) -> Result<Option<&'a [u8]>, Error> {
+    Ok(find_extension_with_criticality(extensions, oid)?.map(|(_, value)| value))
+}
+
+/// Find the extension `oid` in an X.509 `Extensions` sequence, returning whether it's critical
+/// along with its value.
+fn find_extension_with_criticality<'a>(
+    extensions: &DerElement<'a>,
+    oid: &[u64],
+) -> Result<Option<(bool, &'a [u8])>, Error> {
+    for extension in extensions.children()? {
+        let fields = extension.children()?;
+        let critical = match fields.get(1) {
+            // critical BOOLEAN DEFAULT FALSE
+            Some(critical) if fields.len() == 3 && critical.tag == 0x01 => {
+                critical.contents != &[0][..]
+            }
+            _ => false,
+        };
+        match (fields.first(), fields.last()) {
+            (Some(id), Some(value)) if id.is_oid(oid) => {
+                return Ok(Some((critical, value.contents)))
+            }
+            (Some(_), Some(_)) => {}
+            _ => bail!("Malformed X.509 extension"),
+        }
+    }
+
+    Ok(None)
+}
+
+/// The parts of an X.509 certificate which fero needs to issue things with it.
+pub struct Certificate {
+    pub der: Vec<u8>,
+    /// The complete DER INTEGER, not just its contents.
+    pub serial: Vec<u8>,
+    pub issuer: Vec<u8>,
+    pub subject: Vec<u8>,
+    pub subject_public_key_info: Vec<u8>,
+    pub subject_key_identifier: Option<Vec<u8>>,
+    pub extended_key_usage: Option<ExtendedKeyUsage>,
+}
+
+/// A certificate's extendedKeyUsage extension.
+pub struct ExtendedKeyUsage {
+    pub critical: bool,
+    pub purposes: Vec<ObjectIdentifier>,
+}
+
+impl Certificate {
+    pub fn from_der(der: &[u8]) -> Result<Certificate, Error> {
+        let certificate = DerElement::parse_all(der)?;
+        let tbs = certificate
+            .children()?
+            .into_iter()
+            .next()
+            .ok_or(format_err!("Malformed X.509 certificate"))?
+            .children()?;
+
+        // Skip the explicitly-tagged version, if there is one.
+        let tbs = match tbs.first() {
+            Some(version) if version.tag == 0xa0 => &tbs[1..],
+            _ => &tbs[..],
+        };
+        if tbs.len() < 6 {
+            bail!("Malformed X.509 certificate");
+        }
+
+        let extensions = match tbs[6..].iter().find(|field| field.tag == 0xa3) {
+            Some(extensions) => extensions.children()?.into_iter().next(),
+            None => None,
+        };
+        let (subject_key_identifier, extended_key_usage) = match extensions {
+            Some(ref extensions) => (
+                match find_extension(extensions, OID_SUBJECT_KEY_IDENTIFIER)? {
+                    Some(value) => Some(DerElement::parse_all(value)?.contents.to_vec()),
+                    None => None,
+                },
+                match find_extension_with_criticality(extensions, OID_EXTENDED_KEY_USAGE)? {
+                    Some((critical, value)) => Some(ExtendedKeyUsage {
+                        critical,
+                        purposes: DerElement::parse_all(value)?
+                            .children()?
+                            .iter()
+                            .map(|purpose| {
+                                yasna::parse_der(purpose.raw, |reader| reader.read_oid())
+                            })
+                            .collect::<Result<_, _>>()?,
+                    }),
+                    None => None,
+                },
+            ),
+            None => (None, None),
+        };
+
+        Ok(Certificate {
+            der: der.to_vec(),
+            serial: tbs[0].raw.to_vec(),
+            issuer: tbs[2].raw.to_vec(),
+            subject: tbs[4].raw.to_vec(),
+            subject_public_key_info: tbs[5].raw.to_vec(),
+            subject_key_identifier,
+            extended_key_usage,
+        })
+    }
+}
+
+/// The parts of a PKCS#10 certification request which are carried over into the certificate.
+pub struct CertificationRequest {
+    pub subject: Vec<u8>,
+    pub subject_public_key_info: Vec<u8>,
+    pub dns_names: Vec<String>,
+    /// The subject's commonName attributes, which TLS clients may still treat as DNS names.
+    pub common_names: Vec<String>,
+}
+
+impl CertificationRequest {
+    /// Parse a DER-encoded request, checking its self-signature to prove that the requester holds
+    /// the subject's private key. Only RSA keys are supported.
+    pub fn from_der(der: &[u8]) -> Result<CertificationRequest, Error> {
+        let request = DerElement::parse_all(der)?.children()?;
+        if request.len() != 3 {
+            bail!("Malformed certification request");
+        }
+        let info = request[0].children()?;
+        if info.len() < 3 {
+            bail!("Malformed certification request");
+        }
+        verify_request_signature(&request[0], &request[1], &request[2], &info[2])?;
+
+        let mut dns_names = Vec::new();
+        if let Some(attributes) = info.get(3) {
+            for attribute in attributes.children()? {
+                let fields = attribute.children()?;
+                if fields.len() != 2 || !fields[0].is_oid(OID_EXTENSION_REQUEST) {
+                    continue;
+                }
+
+                for extensions in fields[1].children()? {
+                    if let Some(names) = find_extension(&extensions, OID_SUBJECT_ALT_NAME)? {
+                        for name in DerElement::parse_all(names)?.children()? {
+                            match name.tag {
+                                // dNSName [2] IMPLICIT IA5String
+                                0x82 => dns_names.push(String::from_utf8(name.contents.to_vec())?),
+                                _ => bail!("Only DNS names are supported in subjectAltName"),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(CertificationRequest {
+            subject: info[1].raw.to_vec(),
+            subject_public_key_info: info[2].raw.to_vec(),
+            dns_names,
+            common_names: common_names(&info[1])?,
+        })
+    }
+}
+
+/// Check a request's signature over its CertificationRequestInfo `info`, using the key in its
+/// subjectPublicKeyInfo.
+fn verify_request_signature(
+    info: &DerElement,
+    algorithm: &DerElement,
+    signature: &DerElement,
+    subject_public_key_info: &DerElement,
+) -> Result<(), Error> {
+    let (digest_info_prefix, digest) = match algorithm.children()?.first() {
+        Some(oid) if oid.is_oid(OID_SHA256_WITH_RSA) => {
+            (rsa::SHA256_DIGEST_INFO_PREFIX, hash::<Sha256>(info.raw))
+        }
+        Some(oid) if oid.is_oid(OID_SHA384_WITH_RSA) => {
+            (rsa::SHA384_DIGEST_INFO_PREFIX, hash::<Sha384>(info.raw))
+        }
+        Some(oid) if oid.is_oid(OID_SHA512_WITH_RSA) => {
+            (rsa::SHA512_DIGEST_INFO_PREFIX, hash::<Sha512>(info.raw))
+        }
+        _ => bail!("Unsupported certification request signature algorithm"),
+    };
+
+    let fields = subject_public_key_info.children()?;
+    let public_key = match (fields.get(0), fields.get(1)) {
+        (Some(algorithm), Some(public_key)) if public_key.tag == 0x03 => {
+            match algorithm.children()?.first() {
+                Some(oid) if oid.is_oid(OID_RSA_ENCRYPTION) => {}
+                _ => bail!("Only RSA keys are supported in certification requests"),
+            }
+            bit_string_bytes(public_key)?
+        }
+        _ => bail!("Malformed subjectPublicKeyInfo"),
+    };
+    if signature.tag != 0x03 {
+        bail!("Malformed certification request");
+    }
+
+    rsa::verify(public_key, digest_info_prefix, &digest, bit_string_bytes(signature)?)
+        .map_err(|e| format_err!("Certification request signature is invalid: {}", e))
+}
+
+fn hash<D: Digest + Default>(data: &[u8]) -> Vec<u8> {
+    let mut hasher = D::default();
+    hasher.input(data);
+    hasher.result().to_vec()
+}
+
+/// The contents of a BIT STRING which has no unused bits.
+fn bit_string_bytes<'a>(bit_string: &DerElement<'a>) -> Result<&'a [u8], Error> {
+    match bit_string.contents.split_first() {
+        Some((&0, bytes)) => Ok(bytes),
+        _ => bail!("Malformed BIT STRING"),
+    }
+}
+
+/// Find the commonName attributes in an X.501 Name.
+fn common_names(name: &DerElement) -> Result<Vec<String>, Error> {
+    let mut common_names = Vec::new();
+    for relative_name in name.children()? {
+        for attribute in relative_name.children()? {
+            let fields = attribute.children()?;
+            if fields.len() != 2 {
+                bail!("Malformed X.501 name");
+            }
+            if !fields[0].is_oid(OID_COMMON_NAME) {
+                continue;
+            }
+
+            match fields[1].tag {
+                // UTF8String, PrintableString and IA5String
+                0x0c | 0x13 | 0x16 => {
+                    common_names.push(String::from_utf8(fields[1].contents.to_vec())?)
+                }
+                _ => bail!("Unsupported commonName string type"),
+            }
+        }
+    }
+
+    Ok(common_names)
+}
+
+pub fn parse_key_usage(name: &str) -> Result<u16, Error> {
+    KEY_USAGES
+        .iter()
+        .find(|&&(usage, _)| usage == name)
+        .map(|&(_, bit)| bit)
+        .ok_or(format_err!("Unknown key usage: {}", name))
+}
+
+/// Map an extended key usage name to its OID, passing dotted OIDs through as-is.
+pub fn parse_extended_key_usage(name: &str) -> Result<String, Error> {
+    if let Some(&(_, oid)) = EXTENDED_KEY_USAGES.iter().find(|&&(usage, _)| usage == name) {
+        return Ok(String::from(oid));
+    }
+
+    parse_oid(name)?;
+    Ok(String::from(name))
+}
+
+pub fn parse_oid(oid: &str) -> Result<ObjectIdentifier, Error> {
+    let components = oid.split('.')
+        .map(|component| component.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format_err!("Invalid OID: {}", oid))?;
+    if components.len() < 2 {
+        bail!("Invalid OID: {}", oid);
+    }
+
+    Ok(ObjectIdentifier::new(components))
+}
+
+pub fn write_algorithm(writer: DERWriter, oid: &[u64]) {
+    writer.write_sequence(|writer| {
+        writer.next().write_oid(&ObjectIdentifier::from_slice(oid));
+        writer.next().write_null();
+    });
+}
+
+/// Wrap a PKCS#1 RSAPublicKey in a SubjectPublicKeyInfo.
+pub fn rsa_subject_public_key_info(public_key: &[u8]) -> Vec<u8> {
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            write_algorithm(writer.next(), OID_RSA_ENCRYPTION);
+            write_bit_string(writer.next(), public_key);
+        });
+    })
+}
+
+/// Write a time as RFC 5280 requires: UTCTime through 2049, and GeneralizedTime afterwards.
+pub fn write_time(writer: DERWriter, time: &DateTime<Utc>) {
+    if time.year() < 2050 {
+        let time = time.format("%y%m%d%H%M%SZ").to_string();
+        writer.write_tagged_implicit(TAG_UTCTIME, |writer| writer.write_bytes(time.as_bytes()));
+    } else {
+        write_generalized_time(writer, time);
+    }
+}
+
+pub fn write_generalized_time(writer: DERWriter, time: &DateTime<Utc>) {
+    let time = time.format("%Y%m%d%H%M%SZ").to_string();
+    writer.write_tagged_implicit(TAG_GENERALIZEDTIME, |writer| writer.write_bytes(time.as_bytes()));
+}
+
+/// Write a DER BIT STRING from a little-endian set of named bits, as used by KeyUsage.
+fn write_named_bits(writer: DERWriter, bits: u16) {
+    // Named bit 0 is the most significant bit of the first byte.
+    let mut bytes = vec![0u8; 2];
+    for bit in 0..16 {
+        if bits & (1 << bit) != 0 {
+            bytes[bit / 8] |= 0x80 >> (bit % 8);
+        }
+    }
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+
+    let unused = bytes.last().map(|byte| byte.trailing_zeros() as u8).unwrap_or(0);
+    bytes.insert(0, unused);
+
+    writer.write_tagged_implicit(TAG_BITSTRING, |writer| writer.write_bytes(&bytes));
+}
+
+pub fn write_bit_string(writer: DERWriter, bytes: &[u8]) {
+    let mut contents = vec![0];
+    contents.extend_from_slice(bytes);
+
+    writer.write_tagged_implicit(TAG_BITSTRING, |writer| writer.write_bytes(&contents));
+}
+
+fn write_extension<F>(writer: DERWriter, oid: &[u64], critical: bool, value: F)
+where
+    F: FnOnce(DERWriter),
+{
+    writer.write_sequence(|writer| {
+        writer.next().write_oid(&ObjectIdentifier::from_slice(oid));
+        if critical {
+            writer.next().write_bool(true);
+        }
+        writer.next().write_bytes(&yasna::construct_der(value));
+    });
+}
+
+/// Derive a key identifier from the leftmost 160 bits of the SHA-256 hash of a
+/// subjectPublicKey, as allowed by RFC 7093.
+fn key_identifier(subject_public_key_info: &[u8]) -> Result<Vec<u8>, Error> {
+    let fields = DerElement::parse_all(subject_public_key_info)?.children()?;
+    let public_key = match fields.get(1) {
+        Some(public_key) if public_key.tag == 0x03 && !public_key.contents.is_empty() => {
+            &public_key.contents[1..]
+        }
+        _ => bail!("Malformed subjectPublicKeyInfo"),
+    };
+
+    let mut hasher = Sha256::default();
+    hasher.input(public_key);
+    let hash: &[u8] = &hasher.result();
+
+    Ok(hash[..20].to_vec())
+}
+
+/// Generate a random, positive 127-bit serial number.
+pub fn random_serial() -> BigUint {
+    let mut serial = rand::thread_rng().gen::<[u8; 16]>();
+    serial[0] &= 0x7f;
+
+    BigUint::from_bytes_be(&serial)
+}
+
+/// Wrap DER-encoded `tbs` data in a SEQUENCE with its sha256WithRSAEncryption signature by `key`,
+/// as is done for both certificates and CRLs.
+pub fn sign_tbs(hsm: &Hsm, key: u16, tbs: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut hasher = Sha256::default();
+    hasher.input(tbs);
+    let digest: &[u8] = &hasher.result();
+    let signature = hsm.create_rsa_signature(digest, key)?;
+
+    Ok(yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            write_der(writer.next(), tbs);
+            write_algorithm(writer.next(), OID_SHA256_WITH_RSA);
+            write_bit_string(writer.next(), &signature);
+        });
+    }))
+}
+
+fn check_dns_name(name: &str, suffixes: &[&str]) -> Result<(), Error> {
+    let name = name.as_bytes();
+    let allowed = suffixes.iter().any(|suffix| {
+        let suffix = suffix.as_bytes();
+        name.eq_ignore_ascii_case(suffix)
+            || (name.len() > suffix.len() + 1
+                && name[name.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+                && name[name.len() - suffix.len() - 1] == b'.')
+    });
+
+    if allowed {
+        Ok(())
+    } else {
+        bail!(
+            "DNS name {} is not allowed by the certificate profile",
+            String::from_utf8_lossy(name)
+        )
+    }
+}
+
+fn write_authority_key_identifier(writer: DERWriter, key_identifier: &[u8]) {
+    write_extension(writer, OID_AUTHORITY_KEY_IDENTIFIER, false, |writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_tagged_implicit(
+                Tag::context(0),
+                |writer| writer.write_bytes(key_identifier),
+            );
+        });
+    });
+}
+
+/// Check that `reason` is a CRLReason code which may appear in a CRL entry.
+pub fn check_crl_reason(reason: u32) -> Result<(), Error> {
+    match reason {
+        // 7 is unassigned, and removeFromCRL (8) only makes sense in delta CRLs.
+        0..=6 | 9 | 10 => Ok(()),
+        _ => bail!("Invalid CRL reason code: {}", reason),
+    }
+}
+
+/// Generate a CRL listing `revoked`, signed by the CA certificate `issuer` whose key is
+/// `issuer_key` in the HSM. Returns the DER-encoded CRL.
+pub fn generate_crl(
+    hsm: &Hsm,
+    issuer_key: u16,
+    issuer: &Certificate,
+    crl_number: i64,
+    revoked: &[RevokedCertificate],
+    now: DateTime<Utc>,
+    next_update: DateTime<Utc>,
+) -> Result<Vec<u8>, Error> {
+    let tbs = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            // Version 2
+            writer.next().write_u8(1);
+            write_algorithm(writer.next(), OID_SHA256_WITH_RSA);
+            write_der(writer.next(), &issuer.subject);
+            write_time(writer.next(), &now);
+            write_time(writer.next(), &next_update);
+            if !revoked.is_empty() {
+                writer.next().write_sequence(|writer| {
+                    for certificate in revoked {
+                        let serial = BigUint::from_bytes_be(&certificate.serial);
+                        let revocation_date = DateTime::from_utc(certificate.revocation_date, Utc);
+                        // CRLReason ::= ENUMERATED
+                        let reason = [0x0a, 0x01, certificate.reason as u8];
+
+                        writer.next().write_sequence(|writer| {
+                            writer.next().write_biguint(&serial);
+                            write_time(writer.next(), &revocation_date);
+                            // An unspecified reason is signalled by leaving the extension out.
+                            if certificate.reason != 0 {
+                                writer.next().write_sequence(|writer| {
+                                    write_extension(writer.next(), OID_CRL_REASON, false, |w| {
+                                        write_der(w, &reason)
+                                    });
+                                });
+                            }
+                        });
+                    }
+                });
+            }
+            writer.next().write_tagged(Tag::context(0), |writer| {
+                writer.write_sequence(|writer| {
+                    if let Some(ref authority_key_identifier) = issuer.subject_key_identifier {
+                        write_authority_key_identifier(writer.next(), authority_key_identifier);
+                    }
+                    write_extension(writer.next(), OID_CRL_NUMBER, false, |writer| {
+                        writer.write_i64(crl_number);
+                    });
+                });
+            });
+        });
+    });
+
+    sign_tbs(hsm, issuer_key, &tbs)
+}
+
+/// Check that the names in `request` are allowed by `profile`.
+fn check_request(
+    profile: &CertificateProfile,
+    request: &CertificationRequest,
+) -> Result<(), Error> {
+    let dns_suffixes = profile.dns_suffixes
+        .split(',')
+        .filter(|suffix| !suffix.is_empty())
+        .collect::<Vec<_>>();
+    for name in &request.dns_names {
+        check_dns_name(name, &dns_suffixes)?;
+    }
+
+    // TLS clients which predate subjectAltName match the server's name against its commonName,
+    // so in certificates which might authenticate a server it's held to the same rules.
+    let server_auth = profile.extended_key_usage.is_empty()
+        || profile.extended_key_usage
+            .split(',')
+            .any(|oid| oid == EXTENDED_KEY_USAGE_SERVER_AUTH);
+    if server_auth {
+        for name in &request.common_names {
+            check_dns_name(name, &dns_suffixes)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Issue a certificate for `request` from the CA certificate `issuer`, whose key is `issuer_key`
+/// in the HSM, applying `profile`. Returns the DER-encoded certificate.
+pub fn issue_certificate(
+    hsm: &Hsm,
+    issuer_key: u16,
+    issuer: &Certificate,
+    profile: &CertificateProfile,
+    request: &CertificationRequest,
+    now: DateTime<Utc>,
+) -> Result<Vec<u8>, Error> {
+    check_request(profile, request)?;
+
+    let extended_key_usages = profile.extended_key_usage
+        .split(',')
+        .filter(|oid| !oid.is_empty())
+        .map(parse_oid)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let path_length = match profile.path_length {
+        Some(path_length) if path_length < 0 => {
+            bail!("Certificate profile has a negative path length")
+        }
+        Some(path_length) => Some(path_length as u32),
+        None => None,
+    };
+    let not_after = now + Duration::days(i64::from(profile.validity_days));
+    let subject_key_identifier = key_identifier(&request.subject_public_key_info)?;
+    let serial = random_serial();
+
+    let tbs = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            // Version 3
+            writer.next().write_tagged(Tag::context(0), |writer| writer.write_u8(2));
+            writer.next().write_biguint(&serial);
+            write_algorithm(writer.next(), OID_SHA256_WITH_RSA);
+            write_der(writer.next(), &issuer.subject);
+            writer.next().write_sequence(|writer| {
+                write_time(writer.next(), &now);
+                write_time(writer.next(), &not_after);
+            });
+            write_der(writer.next(), &request.subject);
+            write_der(writer.next(), &request.subject_public_key_info);
+            writer.next().write_tagged(Tag::context(3), |writer| {
+                writer.write_sequence(|writer| {
+                    write_extension(writer.next(), OID_BASIC_CONSTRAINTS, true, |writer| {
+                        writer.write_sequence(|writer| {
+                            if profile.is_ca {
+                                writer.next().write_bool(true);
+                                if let Some(path_length) = path_length {
+                                    writer.next().write_u32(path_length);
+                                }
+                            }
+                        });
+                    });
+                    if profile.key_usage != 0 {
+                        write_extension(writer.next(), OID_KEY_USAGE, true, |writer| {
+                            write_named_bits(writer, profile.key_usage as u16);
+                        });
+                    }
+                    if !extended_key_usages.is_empty() {
+                        write_extension(writer.next(), OID_EXTENDED_KEY_USAGE, false, |writer| {
+                            writer.write_sequence(|writer| {
+                                for oid in &extended_key_usages {
+                                    writer.next().write_oid(oid);
+                                }
+                            });
+                        });
+                    }
+                    if !request.dns_names.is_empty() {
+                        write_extension(writer.next(), OID_SUBJECT_ALT_NAME, false, |writer| {
+                            writer.write_sequence(|writer| {
+                                for name in &request.dns_names {
+                                    writer.next().write_tagged_implicit(
+                                        Tag::context(2),
+                                        |writer| writer.write_bytes(name.as_bytes()),
+                                    );
+                                }
+                            });
+                        });
+                    }
+                    write_extension(writer.next(), OID_SUBJECT_KEY_IDENTIFIER, false, |writer| {
+                        writer.write_bytes(&subject_key_identifier);
+                    });
+                    if let Some(ref authority_key_identifier) = issuer.subject_key_identifier {
+                        write_authority_key_identifier(writer.next(), authority_key_identifier);
+                    }
+                });
+            });
+        });
+    });
+
+    sign_tbs(hsm, issuer_key, &tbs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Generated by `openssl req -new`, with an RSA and an ECDSA key respectively.
+    const RSA_REQUEST: &[u8] = include_bytes!("../testdata/x509/rsa.csr");
+    const ECDSA_REQUEST: &[u8] = include_bytes!("../testdata/x509/ecdsa.csr");
+
+    fn profile(extended_key_usage: &str, dns_suffixes: &str) -> CertificateProfile {
+        CertificateProfile {
+            id: 1,
+            secret_id: 1,
+            validity_days: 90,
+            key_usage: 0,
+            extended_key_usage: String::from(extended_key_usage),
+            dns_suffixes: String::from(dns_suffixes),
+            is_ca: false,
+            path_length: None,
+        }
+    }
+
+    #[test]
+    fn der_short_and_long_lengths() {
+        let (element, rest) = DerElement::parse(&[0x04, 0x02, 0xaa, 0xbb, 0xcc]).unwrap();
+        assert_eq!(element.tag, 0x04);
+        assert_eq!(element.contents, &[0xaa, 0xbb]);
+        assert_eq!(element.raw, &[0x04, 0x02, 0xaa, 0xbb]);
+        assert_eq!(rest, &[0xcc]);
+
+        let mut long = vec![0x04, 0x82, 0x01, 0x00];
+        long.extend_from_slice(&[0x55; 256]);
+        let element = DerElement::parse_all(&long).unwrap();
+        assert_eq!(element.contents.len(), 256);
+        assert_eq!(element.raw.len(), 260);
+    }
+
+    #[test]
+    fn der_malformed() {
+        // Empty and truncated input
+        assert!(DerElement::parse(&[]).is_err());
+        assert!(DerElement::parse(&[0x04]).is_err());
+        assert!(DerElement::parse(&[0x04, 0x02, 0xaa]).is_err());
+        assert!(DerElement::parse(&[0x04, 0x82, 0x01]).is_err());
+        assert!(DerElement::parse(&[0x04, 0x82, 0x01, 0x00, 0xaa]).is_err());
+        // Indefinite and oversized lengths
+        assert!(DerElement::parse(&[0x30, 0x80, 0x00, 0x00]).is_err());
+        assert!(DerElement::parse(&[0x04, 0x85, 0, 0, 0, 0, 1, 0xaa]).is_err());
+        // Lengths which aren't minimally encoded
+        assert!(DerElement::parse(&[0x04, 0x81, 0x01, 0xaa]).is_err());
+        assert!(DerElement::parse(&[0x04, 0x82, 0x00, 0x80]).is_err());
+        // Multi-byte tags
+        assert!(DerElement::parse(&[0x1f, 0x81, 0x00]).is_err());
+        // Constructed elements with malformed contents
+        assert!(DerElement::parse(&[0x30, 0x03, 0x04, 0x02, 0xaa]).is_err());
+        assert!(DerElement::parse(&[0xa0, 0x01, 0x00]).is_err());
+        // Trailing data
+        assert!(DerElement::parse_all(&[0x05, 0x00, 0x05, 0x00]).is_err());
+    }
+
+    #[test]
+    fn der_children() {
+        let sequence = DerElement::parse_all(&[0x30, 0x05, 0x02, 0x01, 0x05, 0x05, 0x00]).unwrap();
+        let children = sequence.children().unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].contents, &[0x05]);
+        assert_eq!(children[1].tag, 0x05);
+
+        let oid = yasna::construct_der(|writer| {
+            writer.write_oid(&ObjectIdentifier::from_slice(OID_COMMON_NAME))
+        });
+        let oid = DerElement::parse_all(&oid).unwrap();
+        assert!(oid.is_oid(OID_COMMON_NAME));
+        assert!(!oid.is_oid(OID_SUBJECT_ALT_NAME));
+    }
+
+    #[test]
+    fn der_nesting() {
+        let mut nested = vec![0x05, 0x00];
+        for _ in 0..MAX_DER_DEPTH {
+            let length = nested.len() as u8;
+            nested.splice(0..0, vec![0x30, length]);
+        }
+        assert!(DerElement::parse_all(&nested).is_ok());
+        nested.splice(0..0, vec![0x30, nested.len() as u8]);
+        assert!(DerElement::parse_all(&nested).is_err());
+    }
+
+    #[test]
+    fn der_copying() {
+        for der in &[RSA_REQUEST, ECDSA_REQUEST] {
+            assert_eq!(&yasna::construct_der(|writer| write_der(writer, der))[..], *der);
+        }
+    }
+
+    #[test]
+    fn parse_request() {
+        let request = CertificationRequest::from_der(RSA_REQUEST).unwrap();
+        assert_eq!(request.dns_names, vec!["www.example.com", "example.com"]);
+        assert_eq!(request.common_names, vec!["www.example.com"]);
+    }
+
+    #[test]
+    fn request_signature() {
+        // The first byte of the commonName's value
+        let mut tampered = RSA_REQUEST.to_vec();
+        assert_eq!(tampered[42], b'w');
+        tampered[42] = b'x';
+        assert!(CertificationRequest::from_der(&tampered).is_err());
+
+        let mut tampered = RSA_REQUEST.to_vec();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(CertificationRequest::from_der(&tampered).is_err());
+
+        assert!(CertificationRequest::from_der(ECDSA_REQUEST).is_err());
+    }
+
+    #[test]
+    fn dns_names() {
+        let suffixes = ["example.com"];
+        assert!(check_dns_name("example.com", &suffixes).is_ok());
+        assert!(check_dns_name("www.EXAMPLE.com", &suffixes).is_ok());
+        assert!(check_dns_name("badexample.com", &suffixes).is_err());
+        assert!(check_dns_name(".example.com", &suffixes).is_err());
+        assert!(check_dns_name("example.com.evil", &suffixes).is_err());
+        assert!(check_dns_name("example.com", &[]).is_err());
+    }
+
+    #[test]
+    fn request_names_against_profile() {
+        let request = CertificationRequest::from_der(RSA_REQUEST).unwrap();
+
+        assert!(check_request(&profile("", "example.com"), &request).is_ok());
+        assert!(check_request(&profile("", "example.org"), &request).is_err());
+
+        // The commonName isn't a DNS name unless the certificate can authenticate servers.
+        let mut request = request;
+        request.dns_names.clear();
+        assert!(check_request(&profile("", ""), &request).is_err());
+        assert!(check_request(&profile(EXTENDED_KEY_USAGE_SERVER_AUTH, ""), &request).is_err());
+        assert!(check_request(&profile("1.3.6.1.5.5.7.3.3", ""), &request).is_ok());
+    }
+}